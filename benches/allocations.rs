@@ -0,0 +1,52 @@
+use chest_royale_server_unhackable_trust::glub_server_bench::seeded_storage;
+use chest_royale_server_unhackable_trust::glub_server_storage::PlayerColor;
+use std::hint::black_box;
+
+// Every allocation in this binary is counted
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+/// Calls averaged over for each count
+const CALLS: u64 = 1000;
+
+// Heap allocations per call on the fog paths, counted rather than timed so the
+// numbers are exact and the same on every machine
+fn main() {
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let (mut storage, games) = seeded_storage(1);
+    let (game_id, player_id) = (games[0].game_id, games[0].white_player_id);
+
+    count("get_fogged_board/cached", || {
+        black_box(storage.get_fogged_board(game_id, player_id))
+    });
+    count("get_fogged_board/uncached", || {
+        storage.with_game_mut(game_id, |game_state| game_state.fogged_board_cache.clear());
+        black_box(storage.get_fogged_board(game_id, player_id))
+    });
+
+    let board = storage
+        .with_game(game_id, |game_state| game_state.board.clone())
+        .unwrap();
+    count("visible_mask", || {
+        black_box(board.visible_mask(&PlayerColor::White))
+    });
+    count("fogged_for", || {
+        black_box(board.fogged_for(&PlayerColor::White))
+    });
+}
+
+fn count<T>(name: &str, mut call: impl FnMut() -> T) {
+    // The first call may fill caches that later ones reuse
+    drop(call());
+    let before = dhat::HeapStats::get();
+    for _ in 0..CALLS {
+        drop(call());
+    }
+    let after = dhat::HeapStats::get();
+    println!(
+        "{:<28} {:>8.1} allocations {:>10.1} bytes per call",
+        name,
+        (after.total_blocks - before.total_blocks) as f64 / CALLS as f64,
+        (after.total_bytes - before.total_bytes) as f64 / CALLS as f64,
+    );
+}
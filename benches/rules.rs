@@ -0,0 +1,102 @@
+use chest_royale_server_unhackable_trust::glub_server::{ChestPiece, ExtendedBoard};
+use chest_royale_server_unhackable_trust::glub_server_array_board::ArrayBoard;
+use chest_royale_server_unhackable_trust::glub_server_bench::{
+    lone_piece_move, random_midgame, seeded_storage,
+};
+use chest_royale_server_unhackable_trust::glub_server_storage::{PlayerColor, TickShard};
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+/// Games ticked at once in the `increment_moves` case
+const TICKED_GAMES: u64 = 1000;
+
+fn visibility(c: &mut Criterion) {
+    let mut initial = ExtendedBoard::new();
+    initial.setup_initial_position();
+    let midgame = random_midgame(1);
+
+    let mut group = c.benchmark_group("visible_mask");
+    group.bench_function("initial", |b| {
+        b.iter(|| black_box(&initial).visible_mask(&PlayerColor::White))
+    });
+    group.bench_function("midgame", |b| {
+        b.iter(|| black_box(&midgame).visible_mask(&PlayerColor::White))
+    });
+    group.finish();
+
+    // The slot array the bitboards replaced, on the same positions
+    let (initial, midgame) = (
+        ArrayBoard::from_board(&initial),
+        ArrayBoard::from_board(&midgame),
+    );
+    let mut group = c.benchmark_group("get_visible_positions/array");
+    group.bench_function("initial", |b| {
+        b.iter(|| black_box(&initial).get_visible_positions(&PlayerColor::White))
+    });
+    group.bench_function("midgame", |b| {
+        b.iter(|| black_box(&midgame).get_visible_positions(&PlayerColor::White))
+    });
+    group.finish();
+}
+
+fn moves(c: &mut Criterion) {
+    let mut group = c.benchmark_group("make_move");
+    for piece in ChestPiece::ALL {
+        let (board, from, to) = lone_piece_move(piece);
+        group.bench_function(format!("{:?}", piece).to_lowercase(), |b| {
+            b.iter_batched_ref(
+                || board.clone(),
+                |board| board.make_move(from, to, &PlayerColor::White),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+
+    let midgame = random_midgame(1);
+    c.bench_function("all_legal_moves/midgame", |b| {
+        b.iter(|| black_box(&midgame).all_legal_moves(&PlayerColor::White, true))
+    });
+    let array = ArrayBoard::from_board(&midgame);
+    c.bench_function("all_legal_moves/array/midgame", |b| {
+        b.iter(|| black_box(&array).all_legal_moves(&PlayerColor::White, true))
+    });
+}
+
+fn storage(c: &mut Criterion) {
+    let (mut storage, games) = seeded_storage(TICKED_GAMES);
+
+    let mut group = c.benchmark_group("get_fogged_board");
+    let game = &games[0];
+    group.bench_function("cached", |b| {
+        b.iter(|| storage.get_fogged_board(game.game_id, game.white_player_id))
+    });
+    // Clearing the cache costs a lookup, small next to rebuilding the board
+    group.bench_function("uncached", |b| {
+        let mut next = 0;
+        b.iter(|| {
+            next = (next + 1) % games.len();
+            let game = &games[next];
+            storage.with_game_mut(game.game_id, |game_state| {
+                game_state.fogged_board_cache.clear()
+            });
+            storage.get_fogged_board(game.game_id, game.white_player_id)
+        })
+    });
+    group.finish();
+
+    let mut next = 0;
+    c.bench_function("game_lookup/1000_games", |b| {
+        b.iter(|| {
+            next = (next + 1) % games.len();
+            storage.with_game(games[next].game_id, |game_state| game_state.version)
+        })
+    });
+
+    c.bench_function("increment_moves/1000_games", |b| {
+        b.iter(|| storage.increment_moves(TickShard::ALL))
+    });
+}
+
+criterion_group!(benches, visibility, moves, storage);
+criterion_main!(benches);
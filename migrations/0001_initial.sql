@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS accounts (
+    player_name TEXT PRIMARY KEY NOT NULL,
+    stats TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS game_results (
+    game_id TEXT PRIMARY KEY NOT NULL,
+    archive TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS move_history (
+    game_id TEXT NOT NULL,
+    move_index INTEGER NOT NULL,
+    record TEXT NOT NULL,
+    PRIMARY KEY (game_id, move_index)
+);
+
+CREATE TABLE IF NOT EXISTS active_games (
+    game_id TEXT PRIMARY KEY NOT NULL,
+    state TEXT NOT NULL
+);
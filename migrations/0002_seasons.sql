@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS seasons (
+    position INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE,
+    archive TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS current_season (
+    id INTEGER PRIMARY KEY NOT NULL CHECK (id = 0),
+    name TEXT NOT NULL
+);
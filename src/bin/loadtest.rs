@@ -0,0 +1,70 @@
+use chest_royale_server_unhackable_trust::glub_server_config::Config;
+use chest_royale_server_unhackable_trust::glub_server_loadtest::{self, LoadTest};
+use chest_royale_server_unhackable_trust::serve_in_process;
+use clap::Parser;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Run simulated players against a server, print latency percentiles per
+/// endpoint and exit. Starts a server in this process unless --target is given.
+#[derive(Parser, Debug)]
+#[command(version, about = "Chest Royale load test")]
+struct LoadTestCli {
+    /// TOML config file for the in-process server, instead of the one named by
+    /// CHEST_CONFIG
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Simulated players
+    #[arg(long, default_value_t = 50)]
+    players: usize,
+    /// How long the test runs, e.g. 60s or 5m
+    #[arg(long, default_value = "30s", value_parser = humantime::parse_duration)]
+    duration: Duration,
+    /// URL of a running server to test, e.g. http://127.0.0.1:8080
+    #[arg(long, value_name = "URL")]
+    target: Option<String>,
+    /// Exit with an error if any endpoint's p99 latency is above this many
+    /// milliseconds
+    #[arg(long, value_name = "MS")]
+    max_p99_ms: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = LoadTestCli::parse();
+
+    // The in-process server's stop sender is held until the run ends, so its
+    // tick task keeps going
+    let (base_url, _in_process) = match &cli.target {
+        Some(target) => (target.trim_end_matches('/').to_string(), None),
+        None => {
+            let config = match Config::load(cli.config.as_deref()).and_then(|config| {
+                config.validate()?;
+                Ok(config)
+            }) {
+                Ok(config) => Arc::new(config),
+                Err(e) => {
+                    eprintln!("Configuration error: {}", e);
+                    std::process::exit(1);
+                }
+            };
+            match serve_in_process(config).await {
+                Ok((base_url, stop_sender)) => (base_url, Some(stop_sender)),
+                Err(e) => {
+                    eprintln!("Cannot start the server: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+
+    let passed = glub_server_loadtest::run(LoadTest {
+        base_url,
+        players: cli.players,
+        duration: cli.duration,
+        max_p99: cli.max_p99_ms.map(Duration::from_millis),
+    })
+    .await;
+    std::process::exit(if passed { 0 } else { 1 });
+}
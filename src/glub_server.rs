@@ -1,10 +1,27 @@
+use crate::glub_server_bitboard::{
+    Bitboard, SQUARE_COUNT, aligned, between, bit, color_index, diagonals, index, king_moves,
+    knight_moves, lines, pawn_attackers, pawn_pushers, piece_index, rectangle, sight, sight_line,
+    squares,
+};
 use crate::glub_server_storage::PlayerColor;
-use serde::Serialize;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fmt;
 use uuid::Uuid;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+/// Rows and columns on the board unless the rules ask for another size
+pub const BOARD_SIZE: usize = 8;
+
+/// Fewest rows or columns a board may have. Armies are laid out for eight
+/// columns, so no board is narrower.
+pub const MIN_BOARD_SIZE: usize = 8;
+
+/// Most rows or columns a board may have
+pub const MAX_BOARD_SIZE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
 pub enum ChestPiece {
+    #[default]
     Pawn,
     Scout,
     Rook,
@@ -14,194 +31,1032 @@ pub enum ChestPiece {
     King,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ExtendedSlot {
     pub piece: ChestPiece,
     pub color: PlayerColor,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// The board as one bitboard per piece type and color. It serializes as the
+/// array of slots, one row per rank, so snapshots and exports are unaffected.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "BoardSlots", into = "BoardSlots")]
 pub struct ExtendedBoard {
-    pub slots: [[Option<ExtendedSlot>; 8]; 8],
+    /// Indexed by color, then piece type
+    pieces: [[Bitboard; 7]; 2],
+    /// Impassable squares. Nothing may stand on or slide through a wall, and
+    /// walls block sight; knights and scouts still jump over them.
+    walls: Bitboard,
+    pawn_rules: PawnRules,
+    /// Zobrist hash of the pieces, kept up to date as they move
+    hash: u64,
+    /// Rows and columns
+    dims: (usize, usize),
+    /// The squares inside `dims`
+    on_board: Bitboard,
+}
+
+/// Zobrist keys indexed by color, piece type and square. They come from a fixed
+/// seed, so a position hashes the same on every run.
+static ZOBRIST_KEYS: [[[u64; SQUARE_COUNT]; 7]; 2] = zobrist_keys(0x2545_f491_4f6c_dd1d);
+
+// Fill the key table with splitmix64, at compile time
+const fn zobrist_keys(seed: u64) -> [[[u64; SQUARE_COUNT]; 7]; 2] {
+    let mut keys = [[[0; SQUARE_COUNT]; 7]; 2];
+    let mut state = seed;
+    let mut color = 0;
+    while color < 2 {
+        let mut piece = 0;
+        while piece < 7 {
+            let mut square = 0;
+            while square < SQUARE_COUNT {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                keys[color][piece][square] = z ^ (z >> 31);
+                square += 1;
+            }
+            piece += 1;
+        }
+        color += 1;
+    }
+    keys
+}
+
+// The key XORed into the hash while `slot` stands on `square`
+fn zobrist_key(slot: &ExtendedSlot, square: (usize, usize)) -> u64 {
+    ZOBRIST_KEYS[color_index(&slot.color)][piece_index(slot.piece)][index(square)]
+}
+
+/// Variant pawn moves on top of the standard one-step push and diagonal capture
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PawnRules {
+    /// Pawns may also step one square straight back onto an empty square
+    pub move_backward: bool,
+    /// Pawns may also capture the piece straight ahead of them
+    pub capture_forward: bool,
+}
+
+impl PawnRules {
+    pub fn is_standard(&self) -> bool {
+        *self == PawnRules::default()
+    }
+}
+
+/// A move packed into 16 bits: the origin square in the low byte and the
+/// destination in the high byte, squares numbered as in the bitboards. The
+/// game has no promotion, so nothing else needs a bit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Move(u16);
+
+const _: () = assert!(SQUARE_COUNT <= 256);
+
+impl Move {
+    /// None when either square is off the largest board. Boards turn away
+    /// squares past their own edges when the move is played.
+    pub fn new(from: (usize, usize), to: (usize, usize)) -> Option<Self> {
+        let packed = |(row, col): (usize, usize)| {
+            (row < MAX_BOARD_SIZE && col < MAX_BOARD_SIZE).then(|| index((row, col)) as u16)
+        };
+        Some(Move(packed(from)? | packed(to)? << 8))
+    }
+
+    pub fn from(self) -> (usize, usize) {
+        Self::square(self.0 & 0xff)
+    }
+
+    pub fn to(self) -> (usize, usize) {
+        Self::square(self.0 >> 8)
+    }
+
+    fn square(index: u16) -> (usize, usize) {
+        let index = index as usize;
+        (index / MAX_BOARD_SIZE, index % MAX_BOARD_SIZE)
+    }
+}
+
+/// Why the board refused a move. The text is only produced when a rejection
+/// is reported, so turning moves down doesn't allocate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    InvalidCoordinates,
+    Wall { square: (usize, usize) },
+    NoPiece { square: (usize, usize) },
+    NotYourPiece { color: PlayerColor },
+    InvalidPieceMove { piece: ChestPiece },
+    ScoutCapture,
+    OwnPiece { piece: ChestPiece },
+}
+
+impl fmt::Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MoveError::InvalidCoordinates => "Invalid coordinates",
+            MoveError::Wall { .. } => "That square is a wall",
+            MoveError::NoPiece { .. } => "No piece at source position",
+            MoveError::NotYourPiece { .. } => "Not your piece",
+            MoveError::InvalidPieceMove { .. } => "Invalid move for this piece",
+            MoveError::ScoutCapture => "Scouts cannot capture pieces",
+            MoveError::OwnPiece { .. } => "Cannot capture your own piece",
+        })
+    }
+}
+
+/// The pieces along a back rank from column 0 to 7, written as board string
+/// letters such as `RNBQKBSR`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct BackRank(pub [ChestPiece; 8]);
+
+impl BackRank {
+    /// The back rank pieces in any arrangement: the usual chess set with the
+    /// Scout in place of one knight
+    const PIECES: [ChestPiece; 8] = [
+        ChestPiece::Rook,
+        ChestPiece::Knight,
+        ChestPiece::Bishop,
+        ChestPiece::Queen,
+        ChestPiece::King,
+        ChestPiece::Bishop,
+        ChestPiece::Scout,
+        ChestPiece::Rook,
+    ];
+
+    /// A Chess960-style arrangement picked from `seed`: bishops on opposite
+    /// colored squares and the king between the rooks
+    pub fn random(seed: u64) -> Self {
+        let mut state = seed;
+        let mut pick = |choices: usize| {
+            state = splitmix64(state);
+            (state % choices as u64) as usize
+        };
+
+        let mut pieces = [None; 8];
+        pieces[pick(4) * 2] = Some(ChestPiece::Bishop);
+        pieces[pick(4) * 2 + 1] = Some(ChestPiece::Bishop);
+        for piece in [ChestPiece::Queen, ChestPiece::Knight, ChestPiece::Scout] {
+            let empty: Vec<usize> = (0..8).filter(|&col| pieces[col].is_none()).collect();
+            pieces[empty[pick(empty.len())]] = Some(piece);
+        }
+        // The last three squares take rook, king, rook in that order
+        let empty: Vec<usize> = (0..8).filter(|&col| pieces[col].is_none()).collect();
+        for (col, piece) in
+            empty
+                .into_iter()
+                .zip([ChestPiece::Rook, ChestPiece::King, ChestPiece::Rook])
+        {
+            pieces[col] = Some(piece);
+        }
+
+        BackRank(pieces.map(|piece| piece.unwrap_or_default()))
+    }
+
+    /// Check the rank holds the back rank pieces with the bishops on opposite
+    /// colors and the king between the rooks
+    pub fn validate(&self) -> Result<(), String> {
+        let mut sorted = self.0;
+        let mut expected = Self::PIECES;
+        sorted.sort_by_key(|&piece| piece_index(piece));
+        expected.sort_by_key(|&piece| piece_index(piece));
+        if sorted != expected {
+            return Err(format!("{} is not a full back rank", self));
+        }
+
+        let columns = |wanted| (0..8).filter(move |&col| self.0[col] == wanted);
+        let bishops: Vec<usize> = columns(ChestPiece::Bishop).collect();
+        if bishops[0] % 2 == bishops[1] % 2 {
+            return Err(format!("{} has both bishops on one color", self));
+        }
+        let rooks: Vec<usize> = columns(ChestPiece::Rook).collect();
+        let king = columns(ChestPiece::King).next().unwrap_or_default();
+        if !(rooks[0] < king && king < rooks[1]) {
+            return Err(format!("{} doesn't have the king between the rooks", self));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for BackRank {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0
+            .iter()
+            .try_for_each(|piece| write!(f, "{}", piece.letter()))
+    }
+}
+
+impl TryFrom<String> for BackRank {
+    type Error = String;
+
+    fn try_from(letters: String) -> Result<Self, String> {
+        let pieces: Vec<ChestPiece> = letters
+            .chars()
+            .map(|letter| {
+                ChestPiece::from_letter(letter)
+                    .ok_or_else(|| format!("Unknown piece letter {:?}", letter))
+            })
+            .collect::<Result<_, _>>()?;
+        let pieces: [ChestPiece; 8] = pieces
+            .try_into()
+            .map_err(|_| format!("A back rank has 8 pieces, got {:?}", letters))?;
+        Ok(BackRank(pieces))
+    }
+}
+
+impl From<BackRank> for String {
+    fn from(back_rank: BackRank) -> Self {
+        back_rank.to_string()
+    }
+}
+
+/// The pieces each side starts with and the squares they start on. Squares
+/// are given on an 8x8 board; a bigger board keeps white's half on its first
+/// rows and moves black's half to its last rows, both centered across the
+/// columns.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArmyComposition {
+    pub white: Vec<ArmyGroup>,
+    pub black: Vec<ArmyGroup>,
+}
+
+/// Every piece of one kind in an army, one on each of `squares`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArmyGroup {
+    pub piece: ChestPiece,
+    pub squares: Vec<(usize, usize)>,
+}
+
+impl ArmyComposition {
+    /// Armies of a back rank each with a row of pawns in front, white on rows 0
+    /// and 1 and black on rows 7 and 6
+    pub fn from_back_ranks(white: [ChestPiece; 8], black: [ChestPiece; 8]) -> Self {
+        let army = |pieces: [ChestPiece; 8], back_row: usize, pawn_row: usize| {
+            let mut groups: Vec<ArmyGroup> = Vec::new();
+            for (col, piece) in pieces.into_iter().enumerate() {
+                match groups.iter_mut().find(|group| group.piece == piece) {
+                    Some(group) => group.squares.push((back_row, col)),
+                    None => groups.push(ArmyGroup {
+                        piece,
+                        squares: vec![(back_row, col)],
+                    }),
+                }
+            }
+            groups.push(ArmyGroup {
+                piece: ChestPiece::Pawn,
+                squares: (0..8).map(|col| (pawn_row, col)).collect(),
+            });
+            groups
+        };
+        ArmyComposition {
+            white: army(white, 0, 1),
+            black: army(black, 7, 6),
+        }
+    }
+
+    /// Check each side has exactly one king and every piece has a square of
+    /// its own on the board
+    pub fn validate(&self) -> Result<(), String> {
+        let mut taken = BTreeSet::new();
+        for (color, groups) in [
+            (PlayerColor::White, &self.white),
+            (PlayerColor::Black, &self.black),
+        ] {
+            let kings: usize = groups
+                .iter()
+                .filter(|group| group.piece == ChestPiece::King)
+                .map(|group| group.squares.len())
+                .sum();
+            if kings != 1 {
+                return Err(format!("{:?} must have exactly one king", color));
+            }
+            for group in groups {
+                for &(row, col) in &group.squares {
+                    if row >= BOARD_SIZE || col >= BOARD_SIZE {
+                        return Err(format!("({}, {}) is off the board", row, col));
+                    }
+                    if !taken.insert((row, col)) {
+                        return Err(format!("Two pieces start on ({}, {})", row, col));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for ArmyComposition {
+    fn default() -> Self {
+        ArmyComposition::from_back_ranks(
+            [
+                ChestPiece::Rook,
+                ChestPiece::Knight,
+                ChestPiece::Bishop,
+                ChestPiece::Queen,
+                ChestPiece::King,
+                ChestPiece::Bishop,
+                ChestPiece::Scout,
+                ChestPiece::Rook,
+            ],
+            [
+                ChestPiece::Rook,
+                ChestPiece::Scout,
+                ChestPiece::Bishop,
+                ChestPiece::Queen,
+                ChestPiece::King,
+                ChestPiece::Bishop,
+                ChestPiece::Knight,
+                ChestPiece::Rook,
+            ],
+        )
+    }
+}
+
+/// Pieces one side gives up before the game starts, to even out a mismatch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Handicap {
+    NoQueen,
+    MinusRook,
+    MinusKnight,
+    MinusBishop,
+    PawnOdds,
+    /// Any other set of pieces, one entry per piece given up
+    Pieces(Vec<ChestPiece>),
+}
+
+impl Handicap {
+    pub fn pieces(&self) -> Vec<ChestPiece> {
+        match self {
+            Handicap::NoQueen => vec![ChestPiece::Queen],
+            Handicap::MinusRook => vec![ChestPiece::Rook],
+            Handicap::MinusKnight => vec![ChestPiece::Knight],
+            Handicap::MinusBishop => vec![ChestPiece::Bishop],
+            Handicap::PawnOdds => vec![ChestPiece::Pawn],
+            Handicap::Pieces(pieces) => pieces.clone(),
+        }
+    }
+
+    /// A handicap gives up at least one piece, and never the king
+    pub fn validate(&self) -> Result<(), String> {
+        let pieces = self.pieces();
+        if pieces.is_empty() {
+            return Err("A handicap must give up at least one piece".to_string());
+        }
+        if pieces.contains(&ChestPiece::King) {
+            return Err("A handicap can't give up the king".to_string());
+        }
+        Ok(())
+    }
+}
+
+// The serialized form of a board
+#[derive(Serialize, Deserialize)]
+struct BoardSlots {
+    slots: Vec<Vec<Option<ExtendedSlot>>>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    walls: BTreeSet<(usize, usize)>,
+    #[serde(default, skip_serializing_if = "PawnRules::is_standard")]
+    pawn_rules: PawnRules,
+}
+
+impl TryFrom<BoardSlots> for ExtendedBoard {
+    type Error = String;
+
+    fn try_from(stored: BoardSlots) -> Result<Self, String> {
+        let dims = (stored.slots.len(), stored.slots.first().map_or(0, Vec::len));
+        ExtendedBoard::check_dims(dims)?;
+        if stored.slots.iter().any(|cols| cols.len() != dims.1) {
+            return Err("Every row of a board needs the same number of squares".to_string());
+        }
+
+        let mut board = ExtendedBoard::with_dims(dims);
+        for (row, cols) in stored.slots.into_iter().enumerate() {
+            for (col, slot) in cols.into_iter().enumerate() {
+                board.set_slot((row, col), slot);
+            }
+        }
+        for (row, col) in stored.walls {
+            if !board.contains((row, col)) {
+                return Err(format!("Wall at ({}, {}) is off the board", row, col));
+            }
+            board.add_wall((row, col));
+        }
+        board.pawn_rules = stored.pawn_rules;
+
+        Ok(board)
+    }
+}
+
+impl From<ExtendedBoard> for BoardSlots {
+    fn from(board: ExtendedBoard) -> Self {
+        BoardSlots {
+            slots: board.slots(),
+            walls: board.walls(),
+            pawn_rules: board.pawn_rules,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Game {
     pub id: Uuid,
     pub player1_remaining_moves: u64,
     pub player1_move_increment_countdown: u64,
     pub player2_remaining_moves: u64,
     pub player2_move_increment_countdown: u64,
+    /// Set when a move point was due but the player was already at the cap
+    pub player1_at_cap: bool,
+    pub player2_at_cap: bool,
+    /// Points earned past the cap, towards a charged move
+    #[serde(default)]
+    pub player1_charge: u64,
+    #[serde(default)]
+    pub player2_charge: u64,
 }
 
 impl ExtendedBoard {
+    /// An empty board of the usual size
     pub fn new() -> Self {
+        Self::with_dims((BOARD_SIZE, BOARD_SIZE))
+    }
+
+    /// An empty board of `dims` rows and columns. Panics when `check_dims`
+    /// turns the size down.
+    pub fn with_dims(dims: (usize, usize)) -> Self {
+        Self::check_dims(dims).expect("board size out of range");
         Self {
-            slots: Default::default(),
+            pieces: [[Bitboard::EMPTY; 7]; 2],
+            walls: Bitboard::EMPTY,
+            pawn_rules: PawnRules::default(),
+            hash: 0,
+            dims,
+            on_board: rectangle(dims),
         }
     }
 
-    pub fn setup_initial_position(&mut self) {
-        // Clear the board first
-        self.slots = Default::default();
+    /// Check a board of `dims` rows and columns can be played on
+    pub fn check_dims((rows, cols): (usize, usize)) -> Result<(), String> {
+        let range = MIN_BOARD_SIZE..=MAX_BOARD_SIZE;
+        if !range.contains(&rows) || !range.contains(&cols) {
+            return Err(format!(
+                "A board has {} to {} rows and columns, got {}x{}",
+                MIN_BOARD_SIZE, MAX_BOARD_SIZE, rows, cols
+            ));
+        }
+        Ok(())
+    }
 
-        // Setup white pieces (bottom rows)
-        // Pawns on row 1
-        for col in 0..8 {
-            self.slots[1][col] = Some(ExtendedSlot {
-                piece: ChestPiece::Pawn,
-                color: PlayerColor::White,
-            });
+    /// Rows and columns
+    pub fn dims(&self) -> (usize, usize) {
+        self.dims
+    }
+
+    /// Whether `square` is on the board
+    pub fn contains(&self, square: (usize, usize)) -> bool {
+        square.0 < self.dims.0 && square.1 < self.dims.1
+    }
+
+    /// Every square on the board
+    pub fn all_squares(&self) -> Bitboard {
+        self.on_board
+    }
+
+    pub fn pawn_rules(&self) -> PawnRules {
+        self.pawn_rules
+    }
+
+    pub fn set_pawn_rules(&mut self, pawn_rules: PawnRules) {
+        self.pawn_rules = pawn_rules;
+    }
+
+    /// The piece on `square`, if any. Squares off the board are empty.
+    pub fn slot(&self, square: (usize, usize)) -> Option<ExtendedSlot> {
+        if !self.contains(square) {
+            return None;
         }
 
-        // Major pieces on row 0
-        let white_pieces = [
-            ChestPiece::Rook,
-            ChestPiece::Knight,
-            ChestPiece::Bishop,
-            ChestPiece::Queen,
-            ChestPiece::King,
-            ChestPiece::Bishop,
-            ChestPiece::Scout,
-            ChestPiece::Rook,
-        ];
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            for piece in ChestPiece::ALL {
+                if self.pieces[color_index(&color)][piece_index(piece)].contains(square) {
+                    return Some(ExtendedSlot { piece, color });
+                }
+            }
+        }
 
-        for (col, &piece) in white_pieces.iter().enumerate() {
-            self.slots[0][col] = Some(ExtendedSlot {
-                piece,
-                color: PlayerColor::White,
-            });
+        None
+    }
+
+    /// Put `slot` on `square`, replacing whatever stood there
+    pub fn set_slot(&mut self, square: (usize, usize), slot: Option<ExtendedSlot>) {
+        debug_assert!(self.contains(square), "{:?} is off the board", square);
+        if let Some(old) = self.slot(square) {
+            self.hash ^= zobrist_key(&old, square);
         }
+        let mask = bit(square);
+        for by_piece in &mut self.pieces {
+            for pieces in by_piece {
+                *pieces &= !mask;
+            }
+        }
+        if let Some(slot) = slot {
+            self.pieces[color_index(&slot.color)][piece_index(slot.piece)] |= mask;
+            self.hash ^= zobrist_key(&slot, square);
+        }
+    }
 
-        // Setup black pieces (top rows)
-        // Pawns on row 6
-        for col in 0..8 {
-            self.slots[6][col] = Some(ExtendedSlot {
-                piece: ChestPiece::Pawn,
-                color: PlayerColor::Black,
-            });
+    // No square holds two pieces, none stands on a wall or off the board, and
+    // the hash matches the pieces
+    fn is_consistent(&self) -> bool {
+        let mut seen = Bitboard::EMPTY;
+        for &pieces in self.pieces.iter().flatten() {
+            if !(seen & pieces).is_empty() {
+                return false;
+            }
+            seen |= pieces;
         }
+        (seen & self.walls).is_empty()
+            && ((seen | self.walls) & !self.on_board).is_empty()
+            && self.hash == self.zobrist_hash()
+    }
 
-        // Major pieces on row 7
-        let black_pieces = [
-            ChestPiece::Rook,
-            ChestPiece::Scout,
-            ChestPiece::Bishop,
-            ChestPiece::Queen,
-            ChestPiece::King,
-            ChestPiece::Bishop,
-            ChestPiece::Knight,
-            ChestPiece::Rook,
-        ];
+    /// The Zobrist hash worked out from scratch, one key per piece on the board
+    pub fn zobrist_hash(&self) -> u64 {
+        self.pieces()
+            .fold(0, |hash, (square, slot)| hash ^ zobrist_key(&slot, square))
+    }
 
-        for (col, &piece) in black_pieces.iter().enumerate() {
-            self.slots[7][col] = Some(ExtendedSlot {
-                piece,
-                color: PlayerColor::Black,
-            });
+    /// The board as rows of squares, indexed `[row][col]` with white's back
+    /// rank as row 0
+    pub fn slots(&self) -> Vec<Vec<Option<ExtendedSlot>>> {
+        (0..self.dims.0)
+            .map(|row| (0..self.dims.1).map(|col| self.slot((row, col))).collect())
+            .collect()
+    }
+
+    /// Every piece on the board with its square, row by row from white's back rank
+    pub fn pieces(&self) -> impl Iterator<Item = ((usize, usize), ExtendedSlot)> + '_ {
+        squares(self.occupied()).filter_map(|square| Some((square, self.slot(square)?)))
+    }
+
+    /// How many pieces of this type `color` has on the board
+    pub fn count(&self, color: &PlayerColor, piece: ChestPiece) -> u32 {
+        self.pieces[color_index(color)][piece_index(piece)].count_ones()
+    }
+
+    /// The walled squares
+    pub fn walls(&self) -> BTreeSet<(usize, usize)> {
+        squares(self.walls).collect()
+    }
+
+    pub fn has_walls(&self) -> bool {
+        !self.walls.is_empty()
+    }
+
+    pub fn is_wall(&self, square: (usize, usize)) -> bool {
+        self.contains(square) && self.walls.contains(square)
+    }
+
+    pub fn add_wall(&mut self, square: (usize, usize)) {
+        self.walls |= bit(square);
+    }
+
+    fn color_mask(&self, color: &PlayerColor) -> Bitboard {
+        self.pieces[color_index(color)]
+            .iter()
+            .fold(Bitboard::EMPTY, |mask, &pieces| mask | pieces)
+    }
+
+    fn occupied(&self) -> Bitboard {
+        self.color_mask(&PlayerColor::White) | self.color_mask(&PlayerColor::Black)
+    }
+
+    /// Wall off `count` random empty squares between the two armies. The same
+    /// seed always gives the same map. Squares next to a king and those in
+    /// `keep_clear` are left open.
+    pub fn place_random_walls(&mut self, count: usize, seed: u64, keep_clear: &[(usize, usize)]) {
+        let (rows, cols) = self.dims;
+        let mut candidates: Vec<(usize, usize)> = (2..rows - 2)
+            .flat_map(|row| (0..cols).map(move |col| (row, col)))
+            .filter(|&square| {
+                self.slot(square).is_none()
+                    && !self.beside_king(square)
+                    && !keep_clear.contains(&square)
+            })
+            .collect();
+
+        let mut state = seed;
+        let mut next = || {
+            state = splitmix64(state);
+            state
+        };
+
+        for _ in 0..count.min(candidates.len()) {
+            let index = (next() % candidates.len() as u64) as usize;
+            self.add_wall(candidates.swap_remove(index));
         }
     }
 
-    pub fn get_visible_positions(&self, player_color: &PlayerColor) -> HashSet<(usize, usize)> {
-        let mut visible = HashSet::new();
+    /// Check the walls leave a playable board: no wall under a piece and every
+    /// king with at least one open square next to it
+    pub fn validate_walls(&self) -> Result<(), String> {
+        if let Some((row, col)) = squares(self.walls & self.occupied()).next() {
+            return Err(format!("Wall at ({}, {}) covers a piece", row, col));
+        }
 
-        // Find all pieces belonging to the player
-        for row in 0..8 {
-            for col in 0..8 {
-                if let Some(slot) = &self.slots[row][col] {
-                    if slot.color == *player_color {
-                        // Add the piece's own position
-                        visible.insert((row, col));
-
-                        // Add positions this piece can see
-                        let sight_range = match slot.piece {
-                            ChestPiece::Scout => 3,
-                            _ => 1,
-                        };
-
-                        self.add_visible_positions(&mut visible, row, col, sight_range);
-                    }
-                }
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            if let Some(king) = self.find_king(&color)
+                && (king_moves(king) & self.on_board & !self.walls).is_empty()
+            {
+                return Err(format!("{:?} king is walled in", color));
             }
         }
 
-        visible
+        Ok(())
     }
 
-    fn add_visible_positions(
-        &self,
-        visible: &mut HashSet<(usize, usize)>,
-        center_row: usize,
-        center_col: usize,
-        range: usize,
-    ) {
-        let center_row = center_row as i32;
-        let center_col = center_col as i32;
-
-        for dr in -(range as i32)..=(range as i32) {
-            for dc in -(range as i32)..=(range as i32) {
-                let new_row = center_row + dr;
-                let new_col = center_col + dc;
-
-                if new_row >= 0 && new_row < 8 && new_col >= 0 && new_col < 8 {
-                    let distance = ((dr.abs() as f64).powi(2) + (dc.abs() as f64).powi(2)).sqrt();
-                    if distance <= range as f64 {
-                        visible.insert((new_row as usize, new_col as usize));
-                    }
+    fn beside_king(&self, square: (usize, usize)) -> bool {
+        let kings = self.pieces[0][piece_index(ChestPiece::King)]
+            | self.pieces[1][piece_index(ChestPiece::King)];
+        !(king_moves(square) & kings).is_empty()
+    }
+
+    /// Whether a wall stands strictly between the two squares
+    pub fn is_sight_blocked(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        self.has_walls() && !(sight_line(from, to) & self.walls).is_empty()
+    }
+
+    pub fn setup_initial_position(&mut self) {
+        self.setup_army(&ArmyComposition::default());
+    }
+
+    /// The starting position with both sides' back ranks in `back_rank`, black's
+    /// facing white's on the same columns
+    pub fn setup_position_with(&mut self, back_rank: &BackRank) {
+        self.setup_army(&ArmyComposition::from_back_ranks(back_rank.0, back_rank.0));
+    }
+
+    /// Take the pieces of `handicap` off `color`'s side, each from the lowest
+    /// square of its kind, so a rook comes off the queen's side and pawn odds
+    /// take the a-pawn
+    pub fn apply_handicap(
+        &mut self,
+        color: &PlayerColor,
+        handicap: &Handicap,
+    ) -> Result<(), String> {
+        handicap.validate()?;
+        for piece in handicap.pieces() {
+            let square = squares(self.pieces[color_index(color)][piece_index(piece)])
+                .next()
+                .ok_or_else(|| format!("{:?} has no {:?} to give up", color, piece))?;
+            self.set_slot(square, None);
+        }
+        Ok(())
+    }
+
+    /// Clear the board, keeping its size and the variant rules, and put both
+    /// armies on it
+    pub fn setup_army(&mut self, army: &ArmyComposition) {
+        *self = ExtendedBoard {
+            pawn_rules: self.pawn_rules,
+            ..ExtendedBoard::with_dims(self.dims)
+        };
+
+        // Move a square from the 8x8 layout onto this board
+        let (extra_rows, extra_cols) = (self.dims.0 - BOARD_SIZE, self.dims.1 - BOARD_SIZE);
+        let place = |(row, col): (usize, usize)| {
+            let row = if row < BOARD_SIZE / 2 {
+                row
+            } else {
+                row + extra_rows
+            };
+            (row, col + extra_cols / 2)
+        };
+
+        for (color, groups) in [
+            (PlayerColor::White, &army.white),
+            (PlayerColor::Black, &army.black),
+        ] {
+            for group in groups {
+                for &square in &group.squares {
+                    self.set_slot(
+                        place(square),
+                        Some(ExtendedSlot {
+                            piece: group.piece,
+                            color,
+                        }),
+                    );
                 }
             }
         }
     }
 
+    /// A copy of the board with every square `player_color` can't see emptied
+    pub fn fogged_for(&self, player_color: &PlayerColor) -> ExtendedBoard {
+        self.fogged_to(self.visible_mask(player_color))
+    }
+
+    /// A copy of the board with everything outside `visible` removed
+    pub fn fogged_to(&self, visible: Bitboard) -> ExtendedBoard {
+        // The wall map is no secret, so only the pieces are masked
+        let mut fogged = self.clone();
+        for by_piece in &mut fogged.pieces {
+            for pieces in by_piece {
+                *pieces &= visible;
+            }
+        }
+        fogged.hash = fogged.zobrist_hash();
+
+        fogged
+    }
+
+    /// Every square seen by a piece of `player_color`, including the pieces' own
+    pub fn visible_mask(&self, player_color: &PlayerColor) -> Bitboard {
+        let own = &self.pieces[color_index(player_color)];
+        ChestPiece::ALL
+            .into_iter()
+            .fold(Bitboard::EMPTY, |visible, piece| {
+                squares(own[piece_index(piece)]).fold(visible, |visible, square| {
+                    visible | self.visible_from(square, piece.default_sight())
+                })
+            })
+    }
+
+    /// Squares within `range` of `center` that no wall hides
+    pub fn visible_from(&self, center: (usize, usize), range: usize) -> Bitboard {
+        let in_range = sight(center, range) & self.on_board;
+        if !self.has_walls() {
+            return in_range;
+        }
+
+        squares(in_range)
+            .filter(|&square| self.is_sight_blocked(center, square))
+            .fold(in_range, |visible, square| visible & !bit(square))
+    }
+
     pub fn make_move(
         &mut self,
         from: (usize, usize),
         to: (usize, usize),
         player_color: &PlayerColor,
-    ) -> Result<(), String> {
-        let (from_row, from_col) = from;
-        let (to_row, to_col) = to;
+    ) -> Result<Option<ExtendedSlot>, MoveError> {
+        let mv = Move::new(from, to).ok_or(MoveError::InvalidCoordinates)?;
+        self.apply_move(mv, player_color)
+    }
+
+    /// Check `mv` for `player_color` and play it, handing back whatever was
+    /// captured
+    pub fn apply_move(
+        &mut self,
+        mv: Move,
+        player_color: &PlayerColor,
+    ) -> Result<Option<ExtendedSlot>, MoveError> {
+        let (from, to) = (mv.from(), mv.to());
+        if !self.contains(from) || !self.contains(to) {
+            return Err(MoveError::InvalidCoordinates);
+        }
 
-        // Validate coordinates
-        if from_row >= 8 || from_col >= 8 || to_row >= 8 || to_col >= 8 {
-            return Err("Invalid coordinates".to_string());
+        if self.is_wall(to) {
+            return Err(MoveError::Wall { square: to });
         }
 
         // Check if there's a piece at the from position
-        let piece_info = match &self.slots[from_row][from_col] {
-            Some(slot) => slot.clone(),
-            None => return Err("No piece at source position".to_string()),
-        };
+        let piece_info = self.slot(from).ok_or(MoveError::NoPiece { square: from })?;
 
         // Check if the piece belongs to the player
         if piece_info.color != *player_color {
-            return Err("Not your piece".to_string());
+            return Err(MoveError::NotYourPiece {
+                color: piece_info.color,
+            });
         }
 
         // Check if the move is valid for this piece type
         if !self.is_valid_move(&piece_info, from, to) {
-            return Err("Invalid move for this piece".to_string());
+            return Err(MoveError::InvalidPieceMove {
+                piece: piece_info.piece,
+            });
         }
 
+        let target = self.slot(to);
+
         // Special rule: Scouts cannot capture
-        if piece_info.piece == ChestPiece::Scout {
-            if self.slots[to_row][to_col].is_some() {
-                return Err("Scouts cannot capture pieces".to_string());
-            }
+        if piece_info.piece == ChestPiece::Scout && target.is_some() {
+            return Err(MoveError::ScoutCapture);
         }
 
         // Check if destination has own piece
-        if let Some(dest_piece) = &self.slots[to_row][to_col] {
-            if dest_piece.color == *player_color {
-                return Err("Cannot capture your own piece".to_string());
+        if let Some(dest_piece) = target
+            && dest_piece.color == *player_color
+        {
+            return Err(MoveError::OwnPiece {
+                piece: dest_piece.piece,
+            });
+        }
+
+        // Execute the move by flipping the two squares on the mover's
+        // bitboard and clearing the captured piece's. The hash follows: the
+        // captured piece comes out at `to`, and the mover out at `from` and
+        // in at `to`.
+        if let Some(captured) = target {
+            self.pieces[color_index(&captured.color)][piece_index(captured.piece)] &= !bit(to);
+            self.hash ^= zobrist_key(&captured, to);
+        }
+        self.pieces[color_index(player_color)][piece_index(piece_info.piece)] ^=
+            bit(from) | bit(to);
+        self.hash ^= zobrist_key(&piece_info, from) ^ zobrist_key(&piece_info, to);
+        debug_assert!(
+            self.is_consistent(),
+            "board out of sync after {:?}",
+            (from, to)
+        );
+
+        Ok(target)
+    }
+
+    /// Set off the blast of a capture on `square` in an atomic game: the piece
+    /// that captured goes, and so does every piece other than a pawn on the
+    /// eight squares around it. Returns what the blast took out.
+    pub fn explode(&mut self, square: (usize, usize)) -> Vec<((usize, usize), ExtendedSlot)> {
+        let pawns = self.pieces[0][piece_index(ChestPiece::Pawn)]
+            | self.pieces[1][piece_index(ChestPiece::Pawn)];
+        let blast = bit(square) | king_moves(square) & self.occupied() & !pawns;
+        let destroyed: Vec<_> = squares(blast)
+            .filter_map(|square| self.slot(square).map(|slot| (square, slot)))
+            .collect();
+        for (square, slot) in &destroyed {
+            self.hash ^= zobrist_key(slot, *square);
+        }
+        for color in &mut self.pieces {
+            for pieces in color {
+                *pieces &= !blast;
             }
         }
+        debug_assert!(self.is_consistent(), "board out of sync after {:?}", square);
 
-        // Execute the move
-        self.slots[from_row][from_col] = None;
-        self.slots[to_row][to_col] = Some(piece_info);
+        destroyed
+    }
 
-        Ok(())
+    /// Whether the move from `from` to `to` is a capture whose blast, in an
+    /// atomic game, would take out `color`'s own king. A king can never
+    /// capture, since the capturing piece always goes.
+    pub fn blast_hits_own_king(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+        color: &PlayerColor,
+    ) -> bool {
+        let captures = self.slot(to).is_some_and(|slot| slot.color != *color);
+        captures
+            && self
+                .find_king(color)
+                .is_some_and(|king| king == from || king_moves(to).contains(king))
+    }
+
+    /// Total material value of the pieces `color` has on the board
+    pub fn material(&self, color: &PlayerColor) -> u32 {
+        ChestPiece::ALL
+            .into_iter()
+            .map(|piece| self.count(color, piece) * piece.value())
+            .sum()
+    }
+
+    /// Whether `color` still has the pieces to force a win.
+    /// Any pawn, rook or queen is enough, as are two minor pieces. Scouts can't
+    /// capture, so they never deliver the final blow and don't count.
+    pub fn has_sufficient_material(&self, color: &PlayerColor) -> bool {
+        let count = |piece| self.count(color, piece);
+        count(ChestPiece::Pawn) + count(ChestPiece::Rook) + count(ChestPiece::Queen) > 0
+            || count(ChestPiece::Knight) + count(ChestPiece::Bishop) >= 2
+    }
+
+    /// Where the king of `color` stands, if it is still on the board
+    pub fn find_king(&self, color: &PlayerColor) -> Option<(usize, usize)> {
+        squares(self.pieces[color_index(color)][piece_index(ChestPiece::King)]).next()
+    }
+
+    /// Zobrist hash of the piece placement, used to detect repeated positions.
+    /// It is updated move by move rather than worked out each time.
+    pub fn position_key(&self) -> u64 {
+        self.hash
+    }
+
+    /// Whether any piece of `by_color` could capture on `square`
+    pub fn is_square_attacked(&self, square: (usize, usize), by_color: &PlayerColor) -> bool {
+        !self.attackers(square, by_color).is_empty()
+    }
+
+    /// The squares of every piece of `by_color` that could capture on `square`
+    pub fn attackers(&self, square: (usize, usize), by_color: &PlayerColor) -> Bitboard {
+        if !self.contains(square) {
+            return Bitboard::EMPTY;
+        }
+
+        // Scouts cannot capture, so they never attack
+        let attackers = &self.pieces[color_index(by_color)];
+        let of = |piece| attackers[piece_index(piece)];
+        let mut found = of(ChestPiece::Knight) & knight_moves(square)
+            | of(ChestPiece::King) & king_moves(square)
+            | of(ChestPiece::Pawn) & pawn_attackers(square, by_color);
+        if self.pawn_rules.capture_forward {
+            found |= of(ChestPiece::Pawn) & pawn_pushers(square, by_color);
+        }
+
+        let sliders = (of(ChestPiece::Rook) | of(ChestPiece::Queen)) & lines(square)
+            | (of(ChestPiece::Bishop) | of(ChestPiece::Queen)) & diagonals(square);
+        let blockers = self.occupied() | self.walls;
+        for from in squares(sliders) {
+            if (between(from, square) & blockers).is_empty() {
+                found |= bit(from);
+            }
+        }
+        found
+    }
+
+    /// Whether the king of `color` is attacked
+    pub fn is_in_check(&self, color: &PlayerColor) -> bool {
+        self.find_king(color)
+            .is_some_and(|king| self.is_square_attacked(king, &color.opponent()))
+    }
+
+    /// The squares of the pieces giving check to `color`
+    pub fn checkers(&self, color: &PlayerColor) -> Vec<(usize, usize)> {
+        self.find_king(color).map_or_else(Vec::new, |king| {
+            squares(self.attackers(king, &color.opponent())).collect()
+        })
+    }
+
+    /// Whether `color` is in check with no move that gets it out
+    pub fn is_checkmated(&self, color: &PlayerColor) -> bool {
+        self.is_in_check(color) && self.all_legal_moves(color, true).is_empty()
+    }
+
+    /// Whether the king of `color` stands on `square`
+    pub fn holds_king_of(&self, square: (usize, usize), color: &PlayerColor) -> bool {
+        self.contains(square)
+            && self.pieces[color_index(color)][piece_index(ChestPiece::King)].contains(square)
+    }
+
+    /// Whether making this move would leave the mover's own king attacked
+    pub fn leaves_king_in_check(
+        &self,
+        from: (usize, usize),
+        to: (usize, usize),
+        player_color: &PlayerColor,
+    ) -> bool {
+        let mut after = self.clone();
+        after.make_move(from, to, player_color).is_ok() && after.is_in_check(player_color)
+    }
+
+    /// Every square the piece on `from` may move to.
+    /// With `check_rules`, moves that leave the own king attacked are excluded.
+    pub fn legal_destinations(
+        &self,
+        from: (usize, usize),
+        check_rules: bool,
+    ) -> Vec<(usize, usize)> {
+        let Some(slot) = self.slot(from) else {
+            return Vec::new();
+        };
+
+        squares(self.reachable_from(&slot, from))
+            .filter(|&to| {
+                let mut after = self.clone();
+                after.make_move(from, to, &slot.color).is_ok()
+                    && !(check_rules && after.is_in_check(&slot.color))
+            })
+            .collect()
+    }
+
+    // Every square the piece could move to on an empty board, a superset of its
+    // legal moves that spares trying the rest
+    fn reachable_from(&self, slot: &ExtendedSlot, from: (usize, usize)) -> Bitboard {
+        self.on_board
+            & match slot.piece {
+                // One step forward, back or diagonally covers every pawn variant
+                ChestPiece::Pawn | ChestPiece::King => king_moves(from),
+                ChestPiece::Scout => sight(from, 2) & !bit(from),
+                ChestPiece::Rook => lines(from),
+                ChestPiece::Knight => knight_moves(from),
+                ChestPiece::Bishop => diagonals(from),
+                ChestPiece::Queen => lines(from) | diagonals(from),
+            }
+    }
+
+    /// Every legal `(from, to)` move available to `color`
+    pub fn all_legal_moves(
+        &self,
+        color: &PlayerColor,
+        check_rules: bool,
+    ) -> Vec<((usize, usize), (usize, usize))> {
+        let mut moves = Vec::new();
+        for from in squares(self.color_mask(color)) {
+            for to in self.legal_destinations(from, check_rules) {
+                moves.push((from, to));
+            }
+        }
+
+        moves
     }
 
     fn is_valid_move(
@@ -222,15 +1077,21 @@ impl ExtendedBoard {
                 } else {
                     -1
                 };
+                let occupied = self.occupied().contains(to);
 
-                // Forward move
+                // Forward move, or a capture straight ahead where allowed
                 if dc == 0 && dr == forward {
-                    return self.slots[to.0][to.1].is_none();
+                    return !occupied || self.pawn_rules.capture_forward;
+                }
+
+                // Backward step where allowed, never a capture
+                if dc == 0 && dr == -forward && self.pawn_rules.move_backward {
+                    return !occupied;
                 }
 
                 // Diagonal capture
                 if dc.abs() == 1 && dr == forward {
-                    return self.slots[to.0][to.1].is_some();
+                    return occupied;
                 }
 
                 false
@@ -238,8 +1099,7 @@ impl ExtendedBoard {
 
             ChestPiece::Scout => {
                 // Scouts can move 1 or 2 tiles in any direction
-                let distance = ((dr.abs() as f64).powi(2) + (dc.abs() as f64).powi(2)).sqrt();
-                distance <= 2.0 && distance >= 1.0
+                (1..=4).contains(&(dr * dr + dc * dc))
             }
 
             ChestPiece::Rook => (dr == 0 || dc == 0) && self.is_path_clear(from, to),
@@ -259,39 +1119,47 @@ impl ExtendedBoard {
     }
 
     fn is_path_clear(&self, from: (usize, usize), to: (usize, usize)) -> bool {
-        let (from_row, from_col) = (from.0 as i32, from.1 as i32);
-        let (to_row, to_col) = (to.0 as i32, to.1 as i32);
-
-        let dr = (to_row - from_row).signum();
-        let dc = (to_col - from_col).signum();
-
-        let mut current_row = from_row + dr;
-        let mut current_col = from_col + dc;
-
-        while current_row != to_row || current_col != to_col {
-            if current_row < 0 || current_row >= 8 || current_col < 0 || current_col >= 8 {
-                return false;
-            }
+        from == to
+            || (aligned(from, to)
+                && (between(from, to) & (self.occupied() | self.walls)).is_empty())
+    }
+}
 
-            if self.slots[current_row as usize][current_col as usize].is_some() {
-                return false;
-            }
+impl ChestPiece {
+    /// Every piece type, in bitboard order
+    pub const ALL: [ChestPiece; 7] = [
+        ChestPiece::Pawn,
+        ChestPiece::Scout,
+        ChestPiece::Rook,
+        ChestPiece::Knight,
+        ChestPiece::Bishop,
+        ChestPiece::Queen,
+        ChestPiece::King,
+    ];
 
-            current_row += dr;
-            current_col += dc;
+    /// Material value in pawns. The king is priceless and counts as zero.
+    pub fn value(&self) -> u32 {
+        match self {
+            ChestPiece::Pawn => 1,
+            ChestPiece::Scout => 2,
+            ChestPiece::Knight => 3,
+            ChestPiece::Bishop => 3,
+            ChestPiece::Rook => 5,
+            ChestPiece::Queen => 9,
+            ChestPiece::King => 0,
         }
-
-        true
     }
-}
 
-// Additional trait implementations
-impl Default for ChestPiece {
-    fn default() -> Self {
-        ChestPiece::Pawn
+    /// How many squares around itself the piece reveals through the fog
+    pub fn default_sight(&self) -> usize {
+        match self {
+            ChestPiece::Scout => 3,
+            _ => 1,
+        }
     }
 }
 
+// Additional trait implementations
 impl std::fmt::Display for ChestPiece {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let symbol = match self {
@@ -307,6 +1175,138 @@ impl std::fmt::Display for ChestPiece {
     }
 }
 
+impl ChestPiece {
+    /// Read a board string letter: P, S, R, N, B, Q or K in either case
+    pub fn from_letter(letter: char) -> Option<Self> {
+        match letter.to_ascii_uppercase() {
+            'P' => Some(ChestPiece::Pawn),
+            'S' => Some(ChestPiece::Scout),
+            'R' => Some(ChestPiece::Rook),
+            'N' => Some(ChestPiece::Knight),
+            'B' => Some(ChestPiece::Bishop),
+            'Q' => Some(ChestPiece::Queen),
+            'K' => Some(ChestPiece::King),
+            _ => None,
+        }
+    }
+
+    /// The uppercase board string letter for this piece
+    pub fn letter(&self) -> char {
+        match self {
+            ChestPiece::Pawn => 'P',
+            ChestPiece::Scout => 'S',
+            ChestPiece::Rook => 'R',
+            ChestPiece::Knight => 'N',
+            ChestPiece::Bishop => 'B',
+            ChestPiece::Queen => 'Q',
+            ChestPiece::King => 'K',
+        }
+    }
+}
+
+impl ExtendedSlot {
+    /// Color initial and piece letter, e.g. `wR` or `bK`
+    pub fn code(&self) -> String {
+        let color = match self.color {
+            PlayerColor::White => 'w',
+            PlayerColor::Black => 'b',
+        };
+        format!("{}{}", color, self.piece.letter())
+    }
+}
+
+impl ExtendedBoard {
+    /// The code of every square's piece, empty for an empty square, indexed like
+    /// `slots` (white's back rank is row 0)
+    pub fn piece_codes(&self) -> Vec<Vec<String>> {
+        self.slots()
+            .into_iter()
+            .map(|cols| {
+                cols.into_iter()
+                    .map(|slot| slot.as_ref().map(ExtendedSlot::code).unwrap_or_default())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Parse one line per row, black's back rank (the last row) first, each
+    /// line as long as the first. Boards of 8 to 12 rows and columns are read.
+    /// Uppercase letters are white pieces, lowercase black, `.` an empty square
+    /// and `#` a wall.
+    /// Blank lines and surrounding whitespace are ignored.
+    pub fn from_board_string(board: &str) -> Result<Self, String> {
+        let lines: Vec<&str> = board
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect();
+        let dims = (
+            lines.len(),
+            lines.first().map_or(0, |line| line.chars().count()),
+        );
+        Self::check_dims(dims)?;
+
+        let mut parsed = ExtendedBoard::with_dims(dims);
+        for (index, line) in lines.iter().enumerate() {
+            let row = dims.0 - 1 - index;
+            let squares: Vec<char> = line.chars().collect();
+            if squares.len() != dims.1 {
+                return Err(format!(
+                    "Row {} has {} squares, expected {}",
+                    row,
+                    squares.len(),
+                    dims.1
+                ));
+            }
+
+            for (col, &square) in squares.iter().enumerate() {
+                if square == '.' {
+                    continue;
+                }
+                if square == '#' {
+                    parsed.add_wall((row, col));
+                    continue;
+                }
+                let piece = ChestPiece::from_letter(square)
+                    .ok_or_else(|| format!("Unknown piece {:?} at ({}, {})", square, row, col))?;
+                let color = if square.is_ascii_uppercase() {
+                    PlayerColor::White
+                } else {
+                    PlayerColor::Black
+                };
+                parsed.set_slot((row, col), Some(ExtendedSlot { piece, color }));
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl ExtendedBoard {
+    /// The board in the format read by `from_board_string`
+    pub fn to_board_string(&self) -> String {
+        let mut board = String::new();
+        for row in (0..self.dims.0).rev() {
+            for col in 0..self.dims.1 {
+                board.push(match self.slot((row, col)) {
+                    Some(slot) if slot.color == PlayerColor::White => slot.piece.letter(),
+                    Some(slot) => slot.piece.letter().to_ascii_lowercase(),
+                    None if self.is_wall((row, col)) => '#',
+                    None => '.',
+                });
+            }
+            board.push('\n');
+        }
+        board
+    }
+}
+
+impl Default for ExtendedBoard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Game {
     pub fn new() -> Self {
         Game {
@@ -315,6 +1315,10 @@ impl Game {
             player1_move_increment_countdown: 3,
             player2_remaining_moves: 1,
             player2_move_increment_countdown: 3,
+            player1_at_cap: false,
+            player2_at_cap: false,
+            player1_charge: 0,
+            player2_charge: 0,
         }
     }
 }
@@ -330,3 +1334,444 @@ impl std::fmt::Display for Game {
         write!(f, "Game({})", self.id)
     }
 }
+
+/// One step of splitmix64, enough for shuffling a handful of squares
+fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white(piece: ChestPiece) -> Option<ExtendedSlot> {
+        Some(ExtendedSlot {
+            piece,
+            color: PlayerColor::White,
+        })
+    }
+
+    // Plays `moves` random legal moves from the starting position, preferring
+    // captures so the midgame thins out, and hands each position to `visit`
+    // with the square just moved to and whatever was captured there
+    fn random_game(
+        seed: u64,
+        moves: usize,
+        mut visit: impl FnMut(&mut ExtendedBoard, (usize, usize), Option<ExtendedSlot>),
+    ) {
+        let mut board = ExtendedBoard::new();
+        board.setup_initial_position();
+        let mut state = seed;
+        let mut color = PlayerColor::White;
+        for _ in 0..moves {
+            let legal = board.all_legal_moves(&color, false);
+            let captures: Vec<_> = legal
+                .iter()
+                .copied()
+                .filter(|&(_, to)| board.slot(to).is_some())
+                .collect();
+            let choices = if captures.is_empty() { legal } else { captures };
+            if choices.is_empty() {
+                break;
+            }
+            state = splitmix64(state);
+            let (from, to) = choices[(state % choices.len() as u64) as usize];
+            let captured = board.make_move(from, to, &color).unwrap();
+            visit(&mut board, to, captured);
+            color = color.opponent();
+        }
+    }
+
+    #[test]
+    fn piece_sets_stay_in_step_through_captures() {
+        for seed in 0..20 {
+            random_game(seed, 80, |board, _, _| {
+                assert!(board.is_consistent());
+                let scanned: Vec<_> = board
+                    .slots()
+                    .into_iter()
+                    .enumerate()
+                    .flat_map(|(row, slots)| {
+                        slots
+                            .into_iter()
+                            .enumerate()
+                            .filter_map(move |(col, slot)| Some(((row, col), slot?)))
+                    })
+                    .collect();
+                assert_eq!(board.pieces().collect::<Vec<_>>(), scanned);
+            });
+        }
+    }
+
+    #[test]
+    fn the_running_hash_matches_one_worked_out_from_scratch() {
+        for seed in 0..20 {
+            let mut explosions = 0;
+            random_game(seed, 100, |board, to, captured| {
+                assert_eq!(board.position_key(), board.zobrist_hash());
+                // Every other capture blows up as it would in an atomic game
+                if captured.is_some() && seed % 2 == 0 {
+                    board.explode(to);
+                    explosions += 1;
+                    assert_eq!(board.position_key(), board.zobrist_hash());
+                }
+            });
+            assert!(
+                seed % 2 == 1 || explosions > 0,
+                "seed {} never exploded",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn legal_destinations_match_trying_every_square() {
+        for seed in 0..10 {
+            random_game(seed, 30, |board, _, _| {
+                for (from, slot) in board.pieces() {
+                    for check_rules in [false, true] {
+                        let brute: Vec<_> = squares(board.all_squares())
+                            .filter(|&to| {
+                                let mut after = board.clone();
+                                after.make_move(from, to, &slot.color).is_ok()
+                                    && !(check_rules && after.is_in_check(&slot.color))
+                            })
+                            .collect();
+                        assert_eq!(board.legal_destinations(from, check_rules), brute);
+                    }
+                }
+            });
+        }
+    }
+
+    #[test]
+    fn random_back_ranks_keep_the_constraints_for_every_seed() {
+        let mut seen = BTreeSet::new();
+        for seed in 0..2000 {
+            let back_rank = BackRank::random(seed);
+            assert_eq!(back_rank, BackRank::random(seed));
+            assert_eq!(back_rank.validate(), Ok(()), "seed {}", seed);
+
+            let cols = |wanted| (0..8).filter(move |&col| back_rank.0[col] == wanted);
+            let bishops: Vec<usize> = cols(ChestPiece::Bishop).collect();
+            let rooks: Vec<usize> = cols(ChestPiece::Rook).collect();
+            let king: Vec<usize> = cols(ChestPiece::King).collect();
+            assert_eq!(bishops.len(), 2);
+            assert_ne!(bishops[0] % 2, bishops[1] % 2, "{}", back_rank);
+            assert_eq!((rooks.len(), king.len()), (2, 1));
+            assert!(rooks[0] < king[0] && king[0] < rooks[1], "{}", back_rank);
+            for piece in [ChestPiece::Queen, ChestPiece::Knight, ChestPiece::Scout] {
+                assert_eq!(cols(piece).count(), 1, "{}", back_rank);
+            }
+
+            // Black's rank mirrors white's across the board
+            let mut board = ExtendedBoard::new();
+            board.setup_position_with(&back_rank);
+            for col in 0..8 {
+                let (white, black) = (board.slot((0, col)).unwrap(), board.slot((7, col)).unwrap());
+                assert_eq!(
+                    (white.piece, white.color),
+                    (back_rank.0[col], PlayerColor::White)
+                );
+                assert_eq!(
+                    (black.piece, black.color),
+                    (back_rank.0[col], PlayerColor::Black)
+                );
+            }
+            seen.insert(back_rank.to_string());
+        }
+        // Far from every seed giving the same few ranks
+        assert!(seen.len() > 500, "only {} arrangements", seen.len());
+    }
+
+    #[test]
+    fn back_ranks_breaking_the_constraints_are_refused() {
+        use ChestPiece::*;
+        for pieces in [
+            [Rook, Bishop, Knight, Bishop, King, Queen, Scout, Rook],
+            [King, Rook, Bishop, Queen, Rook, Bishop, Scout, Knight],
+            [Rook, Knight, Bishop, Queen, King, Bishop, Knight, Rook],
+        ] {
+            assert!(BackRank(pieces).validate().is_err(), "{:?}", pieces);
+        }
+    }
+
+    #[test]
+    fn board_strings_of_any_allowed_size_round_trip() {
+        for dims in [(8, 8), (10, 10), (12, 12), (8, 12)] {
+            let mut board = ExtendedBoard::with_dims(dims);
+            board.set_slot((0, 0), white(ChestPiece::King));
+            board.set_slot((dims.0 - 1, dims.1 - 1), white(ChestPiece::Rook));
+            board.add_wall((dims.0 / 2, dims.1 - 1));
+
+            let parsed = ExtendedBoard::from_board_string(&board.to_board_string()).unwrap();
+            assert_eq!(parsed, board);
+            assert_eq!(parsed.dims(), dims);
+
+            let json = serde_json::to_string(&board).unwrap();
+            let restored: ExtendedBoard = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored, board);
+            assert_eq!(restored.position_key(), board.position_key());
+        }
+    }
+
+    #[test]
+    fn sizes_outside_the_limits_are_rejected() {
+        for dims in [(7, 8), (8, 7), (13, 12), (12, 13)] {
+            assert!(ExtendedBoard::check_dims(dims).is_err(), "{:?}", dims);
+        }
+
+        let ragged = "........\n".repeat(7) + ".........\n";
+        assert!(ExtendedBoard::from_board_string(&ragged).is_err());
+
+        let stored = serde_json::json!({ "slots": vec![vec![None::<ExtendedSlot>; 8]; 13] });
+        assert!(serde_json::from_value::<ExtendedBoard>(stored).is_err());
+    }
+
+    #[test]
+    fn the_army_keeps_to_the_middle_of_a_bigger_board() {
+        let mut board = ExtendedBoard::with_dims((10, 12));
+        board.setup_initial_position();
+
+        assert_eq!(board.find_king(&PlayerColor::White), Some((0, 6)));
+        assert_eq!(board.find_king(&PlayerColor::Black), Some((9, 6)));
+        assert_eq!(board.slot((1, 2)), white(ChestPiece::Pawn));
+        assert_eq!(board.slot((1, 1)), None);
+        assert_eq!(board.slot((1, 10)), None);
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            assert_eq!(board.count(&color, ChestPiece::Pawn), 8);
+            assert_eq!(board.material(&color), 38);
+        }
+    }
+
+    #[test]
+    fn pieces_stop_at_the_edge_of_their_board() {
+        let mut board = ExtendedBoard::with_dims((10, 10));
+        board.set_slot((0, 0), white(ChestPiece::Queen));
+        board.set_slot((9, 9), white(ChestPiece::Knight));
+
+        // Nine squares each way, less the knight's corner
+        let queen = board.legal_destinations((0, 0), false);
+        assert_eq!(queen.len(), 26);
+        assert!(queen.contains(&(0, 9)) && queen.contains(&(9, 0)));
+        assert_eq!(
+            board.legal_destinations((9, 9), false),
+            vec![(7, 8), (8, 7)]
+        );
+
+        // The largest board has squares past this one's edge
+        assert_eq!(
+            board.make_move((0, 0), (0, 10), &PlayerColor::White),
+            Err(MoveError::InvalidCoordinates)
+        );
+        assert!(board.slot((0, 10)).is_none());
+
+        let visible = board.visible_mask(&PlayerColor::White);
+        assert!((visible & !board.all_squares()).is_empty());
+        assert!(visible.contains((8, 9)) && visible.contains((1, 0)));
+    }
+
+    #[test]
+    fn piece_values_and_sight_ranges() {
+        let values = [
+            (ChestPiece::Pawn, 1),
+            (ChestPiece::Scout, 2),
+            (ChestPiece::Rook, 5),
+            (ChestPiece::Knight, 3),
+            (ChestPiece::Bishop, 3),
+            (ChestPiece::Queen, 9),
+            (ChestPiece::King, 0),
+        ];
+        assert_eq!(values.len(), ChestPiece::ALL.len());
+        for (piece, value) in values {
+            assert_eq!(piece.value(), value, "{:?}", piece);
+        }
+
+        for piece in ChestPiece::ALL {
+            let sight = if piece == ChestPiece::Scout { 3 } else { 1 };
+            assert_eq!(piece.default_sight(), sight, "{:?}", piece);
+        }
+
+        // A lone scout sees three squares out and no further
+        let mut board = ExtendedBoard::new();
+        board.set_slot((3, 3), white(ChestPiece::Scout));
+        let visible = board.visible_mask(&PlayerColor::White);
+        assert!(visible.contains((6, 3)) && visible.contains((3, 0)));
+        assert!(!visible.contains((7, 3)) && !visible.contains((3, 7)));
+    }
+
+    #[test]
+    fn pawns_step_back_and_capture_ahead_only_under_their_flags() {
+        let mut board = ExtendedBoard::new();
+        board.set_slot((3, 3), white(ChestPiece::Pawn));
+        board.set_slot(
+            (4, 3),
+            Some(ExtendedSlot {
+                piece: ChestPiece::Knight,
+                color: PlayerColor::Black,
+            }),
+        );
+        let white_pawn = Err(MoveError::InvalidPieceMove {
+            piece: ChestPiece::Pawn,
+        });
+
+        let mut standard = board.clone();
+        assert_eq!(
+            standard.make_move((3, 3), (2, 3), &PlayerColor::White),
+            white_pawn
+        );
+        assert_eq!(
+            standard.make_move((3, 3), (4, 3), &PlayerColor::White),
+            white_pawn
+        );
+
+        let mut relaxed = board.clone();
+        relaxed.set_pawn_rules(PawnRules {
+            move_backward: true,
+            capture_forward: true,
+        });
+        assert_eq!(
+            relaxed.make_move((3, 3), (2, 3), &PlayerColor::White),
+            Ok(None)
+        );
+        assert_eq!(
+            relaxed.make_move((2, 3), (3, 3), &PlayerColor::White),
+            Ok(None)
+        );
+        let captured = relaxed.make_move((3, 3), (4, 3), &PlayerColor::White);
+        assert_eq!(
+            captured.unwrap().map(|slot| slot.piece),
+            Some(ChestPiece::Knight)
+        );
+
+        // A backward step is never a capture
+        relaxed.set_slot(
+            (3, 3),
+            Some(ExtendedSlot {
+                piece: ChestPiece::Rook,
+                color: PlayerColor::Black,
+            }),
+        );
+        assert_eq!(
+            relaxed.make_move((4, 3), (3, 3), &PlayerColor::White),
+            white_pawn
+        );
+    }
+
+    #[test]
+    fn a_rook_cannot_slide_through_or_onto_a_wall() {
+        let mut board = ExtendedBoard::from_board_string(
+            "....k...
+             ........
+             ........
+             ........
+             R..#....
+             ........
+             ........
+             ....K...",
+        )
+        .unwrap();
+
+        assert!(
+            board
+                .make_move((3, 0), (3, 5), &PlayerColor::White)
+                .is_err()
+        );
+        assert_eq!(
+            board.make_move((3, 0), (3, 3), &PlayerColor::White),
+            Err(MoveError::Wall { square: (3, 3) })
+        );
+        assert!(
+            board
+                .make_move((3, 0), (3, 7), &PlayerColor::White)
+                .is_err()
+        );
+        assert_eq!(
+            board.make_move((3, 0), (3, 2), &PlayerColor::White),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn a_wall_hides_what_lies_beyond_it() {
+        let open = "....k...
+                    ........
+                    ........
+                    ........
+                    S.p.....
+                    ........
+                    ........
+                    ....K...";
+        let board = ExtendedBoard::from_board_string(open).unwrap();
+        assert!(board.visible_mask(&PlayerColor::White).contains((3, 2)));
+
+        let walled = ExtendedBoard::from_board_string(&open.replace("S.p", "S#p")).unwrap();
+        let visible = walled.visible_mask(&PlayerColor::White);
+        assert!(walled.is_sight_blocked((3, 0), (3, 2)));
+        assert!(!visible.contains((3, 2)), "the pawn is behind the wall");
+        assert!(
+            visible.contains((1, 0)),
+            "squares the wall doesn't cover stay in sight"
+        );
+    }
+
+    #[test]
+    fn random_walls_never_shut_a_king_in() {
+        // Kings in the middle of the board, where walls can reach them
+        let centred = "........
+                       ........
+                       ........
+                       ...k....
+                       ....K...
+                       ........
+                       ........
+                       ........";
+        for seed in 0..200 {
+            let mut board = ExtendedBoard::new();
+            board.setup_initial_position();
+            board.place_random_walls(24, seed, &[]);
+            assert_eq!(board.walls().len(), 24);
+            assert_eq!(board.validate_walls(), Ok(()), "seed {}", seed);
+
+            let mut board = ExtendedBoard::from_board_string(centred).unwrap();
+            board.place_random_walls(64, seed, &[]);
+            assert_eq!(board.validate_walls(), Ok(()), "seed {}", seed);
+            for color in [PlayerColor::White, PlayerColor::Black] {
+                let king = board.find_king(&color).unwrap();
+                assert!(
+                    squares(king_moves(king)).all(|square| !board.is_wall(square)),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+
+        // A hand-made map that does shut one in is refused
+        let mut cornered = ExtendedBoard::from_board_string(
+            "....k...
+             ........
+             ........
+             ........
+             ........
+             ........
+             ##......
+             K#......",
+        )
+        .unwrap();
+        assert_eq!(
+            cornered.validate_walls(),
+            Err("White king is walled in".to_string())
+        );
+        cornered.set_slot((6, 6), white(ChestPiece::Rook));
+        cornered.add_wall((6, 6));
+        assert!(
+            cornered
+                .validate_walls()
+                .unwrap_err()
+                .contains("covers a piece")
+        );
+    }
+}
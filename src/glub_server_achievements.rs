@@ -0,0 +1,52 @@
+use crate::glub_server::ChestPiece;
+use crate::glub_server_storage::{GameResult, GameState, PlayerColor, PlayerStats};
+use serde::{Deserialize, Serialize};
+
+/// One-time awards evaluated when a game finishes
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Achievement {
+    FirstWin,
+    Flawless,
+    LastKingStanding,
+}
+
+impl Achievement {
+    /// Every achievement, in the order they are evaluated
+    pub const ALL: [Achievement; 3] = [
+        Achievement::FirstWin,
+        Achievement::Flawless,
+        Achievement::LastKingStanding,
+    ];
+
+    /// Whether the player of `color` earned this achievement in the finished game.
+    /// `stats` already includes the finished game.
+    pub fn is_earned(
+        &self,
+        color: &PlayerColor,
+        result: &GameResult,
+        game_state: &GameState,
+        stats: &PlayerStats,
+    ) -> bool {
+        if result.winner.as_ref() != Some(color) {
+            return false;
+        }
+
+        match self {
+            Achievement::FirstWin => stats.wins == 1,
+
+            // Won without losing a single piece
+            Achievement::Flawless => !game_state
+                .captured_pieces
+                .iter()
+                .any(|captured| captured.color == *color),
+
+            // Won with nothing but the king left on the board
+            Achievement::LastKingStanding => game_state
+                .board
+                .pieces()
+                .filter(|(_, slot)| slot.color == *color)
+                .all(|(_, slot)| slot.piece == ChestPiece::King),
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use crate::glub_server::ChestPiece;
+use crate::glub_server_config::Config;
+use crate::glub_server_logging::rotate_numbered;
+use crate::glub_server_storage::{GameEndReason, GameState, PlayerColor};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+/// One line of the analytics log, written when a game ends. Players appear
+/// only by id.
+#[derive(Serialize, Clone, Debug)]
+pub struct GameAnalytics {
+    pub game_id: Uuid,
+    /// "matchmaking", "tournament", "practice", "bot" or "seeded"
+    pub mode: &'static str,
+    pub fog_enabled: bool,
+    pub reason: GameEndReason,
+    /// `None` for a draw
+    pub winner: Option<PlayerColor>,
+    pub duration_seconds: u64,
+    pub white_player_id: Uuid,
+    pub black_player_id: Uuid,
+    pub total_moves: usize,
+    pub moves_by_piece: BTreeMap<String, u64>,
+    /// Pieces taken, by the type of the captured piece
+    pub captures_by_piece: BTreeMap<String, u64>,
+}
+
+impl GameAnalytics {
+    pub fn from_game(game_state: &GameState, duration_seconds: u64) -> Option<Self> {
+        let result = game_state.result.as_ref()?;
+        let mode = if game_state.is_solo() {
+            "practice"
+        } else if game_state.bot_color.is_some() {
+            "bot"
+        } else if game_state.unrated {
+            "seeded"
+        } else if game_state.tournament_id.is_some() {
+            "tournament"
+        } else {
+            "matchmaking"
+        };
+        let (white, black) = if game_state.player1.color == PlayerColor::White {
+            (&game_state.player1, &game_state.player2)
+        } else {
+            (&game_state.player2, &game_state.player1)
+        };
+
+        let mut moves_by_piece = BTreeMap::new();
+        let mut captures_by_piece = BTreeMap::new();
+        for record in &game_state.history {
+            *moves_by_piece.entry(piece_name(record.piece)).or_default() += 1;
+            if let Some(captured) = record.captured {
+                *captures_by_piece.entry(piece_name(captured)).or_default() += 1;
+            }
+        }
+
+        Some(Self {
+            game_id: game_state.game.id,
+            mode,
+            fog_enabled: game_state.rules.fog_enabled,
+            reason: result.reason,
+            winner: result.winner,
+            duration_seconds,
+            white_player_id: white.id,
+            black_player_id: black.id,
+            total_moves: game_state.history.len(),
+            moves_by_piece,
+            captures_by_piece,
+        })
+    }
+}
+
+fn piece_name(piece: ChestPiece) -> String {
+    format!("{:?}", piece).to_lowercase()
+}
+
+pub type AnalyticsSender = mpsc::UnboundedSender<GameAnalytics>;
+
+/// Start the analytics writer if `config` names a log, returning where to send
+/// records
+pub fn start_analytics(config: &Config) -> Option<AnalyticsSender> {
+    let path = config.analytics_path.clone()?;
+    let (analytics, records) = mpsc::unbounded_channel();
+    tokio::spawn(run_analytics_writer(
+        path,
+        config.analytics_max_bytes,
+        config.analytics_keep_files,
+        records,
+    ));
+    Some(analytics)
+}
+
+/// Append every record received to `path` as a JSON line. Once the file would
+/// grow past `max_bytes` it is rotated to `path.1`, `path.2` and so on, keeping
+/// `keep_files` old files. Runs on a blocking thread so slow disks never hold
+/// up gameplay.
+pub async fn run_analytics_writer(
+    path: PathBuf,
+    max_bytes: u64,
+    keep_files: u32,
+    mut records: mpsc::UnboundedReceiver<GameAnalytics>,
+) {
+    let writer = tokio::task::spawn_blocking(move || {
+        while let Some(record) = records.blocking_recv() {
+            if let Err(e) = append(&path, max_bytes, keep_files, &record) {
+                warn!(
+                    "Failed to write analytics for game {}: {}",
+                    record.game_id, e
+                );
+            }
+        }
+    });
+    let _ = writer.await;
+}
+
+fn append(
+    path: &Path,
+    max_bytes: u64,
+    keep_files: u32,
+    record: &GameAnalytics,
+) -> Result<(), String> {
+    let mut line = serde_json::to_string(record).map_err(|e| e.to_string())?;
+    line.push('\n');
+
+    let size = std::fs::metadata(path).map_or(0, |metadata| metadata.len());
+    if size > 0 && size + line.len() as u64 > max_bytes {
+        rotate_numbered(path, keep_files as usize).map_err(|e| e.to_string())?;
+    }
+
+    let mut file: File = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| e.to_string())?;
+    file.write_all(line.as_bytes()).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_storage::{GameStorage, TickShard};
+    use std::time::Duration;
+
+    // The rook takes the pawn, then black resigns
+    fn play_scripted_game(storage: &mut GameStorage) -> crate::SeededGame {
+        let game = storage
+            .seed_game(
+                "....k...
+                 ........
+                 ........
+                 ........
+                 p.......
+                 ........
+                 ........
+                 R...K...",
+                "white".to_string(),
+                "black".to_string(),
+                None,
+            )
+            .unwrap();
+        for _ in 0..3 {
+            storage.increment_moves(TickShard::ALL);
+        }
+        let moved = storage
+            .make_move(
+                game.game_id,
+                crate::MoveRequest {
+                    player_id: game.white_player_id,
+                    from: (0, 0),
+                    to: (3, 0),
+                    use_charge: false,
+                },
+            )
+            .unwrap();
+        assert!(moved.success, "{}", moved.message);
+        storage.quit(game.black_player_id).unwrap();
+        game
+    }
+
+    fn analytics_config(dir: &Path, enabled: bool) -> Config {
+        Config {
+            analytics_path: enabled.then(|| dir.join("analytics.jsonl")),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_finished_game_is_written_as_one_json_line() {
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = analytics_config(&dir, true);
+        let analytics = start_analytics(&config).expect("a path enables analytics");
+        let mut storage = GameStorage::new().with_analytics(analytics);
+
+        let game = play_scripted_game(&mut storage);
+        let path = dir.join("analytics.jsonl");
+        let mut written = String::new();
+        for _ in 0..100 {
+            written = std::fs::read_to_string(&path).unwrap_or_default();
+            if written.ends_with('\n') {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 1, "{}", written);
+        let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        let fields: Vec<&str> = line
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "black_player_id",
+                "captures_by_piece",
+                "duration_seconds",
+                "fog_enabled",
+                "game_id",
+                "mode",
+                "moves_by_piece",
+                "reason",
+                "total_moves",
+                "white_player_id",
+                "winner",
+            ]
+        );
+        assert_eq!(line["game_id"], game.game_id.to_string());
+        assert_eq!(line["white_player_id"], game.white_player_id.to_string());
+        assert_eq!(line["black_player_id"], game.black_player_id.to_string());
+        assert_eq!(line["mode"], "seeded");
+        assert_eq!(line["winner"], "white");
+        assert_eq!(line["total_moves"], 1);
+        assert_eq!(line["moves_by_piece"], serde_json::json!({ "rook": 1 }));
+        assert_eq!(line["captures_by_piece"], serde_json::json!({ "pawn": 1 }));
+        assert!(line["duration_seconds"].is_u64());
+        assert_eq!(line["reason"], "resigned");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn nothing_is_written_without_an_analytics_path() {
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = analytics_config(&dir, false);
+        assert!(start_analytics(&config).is_none());
+
+        let mut storage = GameStorage::with_config(&config);
+        play_scripted_game(&mut storage);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,514 @@
+use crate::glub_server::{ChestPiece, ExtendedBoard, ExtendedSlot, PawnRules};
+use crate::glub_server_storage::PlayerColor;
+use std::collections::HashSet;
+
+/// The board as a grid of slots, worked square by square the way it was before
+/// `ExtendedBoard` moved to bitboards. Nothing in the server plays on it: it is
+/// the reference the bitboard rules are checked against, and the baseline the
+/// benchmarks compare them with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArrayBoard {
+    slots: Vec<Vec<Option<ExtendedSlot>>>,
+    walls: HashSet<(usize, usize)>,
+    pawn_rules: PawnRules,
+}
+
+impl ArrayBoard {
+    /// The same position as `board`, pieces, walls and pawn rules alike
+    pub fn from_board(board: &ExtendedBoard) -> Self {
+        Self {
+            slots: board.slots(),
+            walls: board.walls().into_iter().collect(),
+            pawn_rules: board.pawn_rules(),
+        }
+    }
+
+    pub fn slots(&self) -> &[Vec<Option<ExtendedSlot>>] {
+        &self.slots
+    }
+
+    fn rows(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn cols(&self) -> usize {
+        self.slots.first().map_or(0, Vec::len)
+    }
+
+    fn contains(&self, (row, col): (usize, usize)) -> bool {
+        row < self.rows() && col < self.cols()
+    }
+
+    fn all_squares(&self) -> impl Iterator<Item = (usize, usize)> + use<> {
+        let cols = self.cols();
+        (0..self.rows()).flat_map(move |row| (0..cols).map(move |col| (row, col)))
+    }
+
+    fn slot(&self, (row, col): (usize, usize)) -> Option<&ExtendedSlot> {
+        self.slots[row][col].as_ref()
+    }
+
+    /// Whether a wall stands strictly between the two squares
+    pub fn is_sight_blocked(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        if self.walls.is_empty() {
+            return false;
+        }
+
+        let dr = to.0 as f64 - from.0 as f64;
+        let dc = to.1 as f64 - from.1 as f64;
+        let steps = 2 * (dr.abs().max(dc.abs()) as usize);
+        (1..steps).any(|step| {
+            let t = step as f64 / steps as f64;
+            let square = (
+                (from.0 as f64 + dr * t).round() as usize,
+                (from.1 as f64 + dc * t).round() as usize,
+            );
+            square != from && square != to && self.walls.contains(&square)
+        })
+    }
+
+    pub fn get_visible_positions(&self, player_color: &PlayerColor) -> HashSet<(usize, usize)> {
+        let mut visible = HashSet::new();
+
+        // Find all pieces belonging to the player
+        for square in self.all_squares() {
+            if let Some(slot) = self.slot(square)
+                && slot.color == *player_color
+            {
+                // Add the piece's own position
+                visible.insert(square);
+
+                // Add positions this piece can see
+                self.add_visible_positions(&mut visible, square, slot.piece.default_sight());
+            }
+        }
+
+        visible
+    }
+
+    /// Add every square within `range` of the center to `visible`, unless a wall
+    /// blocks the view
+    pub fn add_visible_positions(
+        &self,
+        visible: &mut HashSet<(usize, usize)>,
+        center: (usize, usize),
+        range: usize,
+    ) {
+        let (center_row, center_col) = (center.0 as i32, center.1 as i32);
+
+        for dr in -(range as i32)..=(range as i32) {
+            for dc in -(range as i32)..=(range as i32) {
+                let new_row = center_row + dr;
+                let new_col = center_col + dc;
+
+                if (0..self.rows() as i32).contains(&new_row)
+                    && (0..self.cols() as i32).contains(&new_col)
+                {
+                    let distance = ((dr.abs() as f64).powi(2) + (dc.abs() as f64).powi(2)).sqrt();
+                    let square = (new_row as usize, new_col as usize);
+                    if distance <= range as f64 && !self.is_sight_blocked(center, square) {
+                        visible.insert(square);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn make_move(
+        &mut self,
+        from: (usize, usize),
+        to: (usize, usize),
+        player_color: &PlayerColor,
+    ) -> Result<Option<ExtendedSlot>, String> {
+        // Validate coordinates
+        if !self.contains(from) || !self.contains(to) {
+            return Err("Invalid coordinates".to_string());
+        }
+
+        if self.walls.contains(&to) {
+            return Err("That square is a wall".to_string());
+        }
+
+        // Check if there's a piece at the from position
+        let piece_info = match self.slot(from) {
+            Some(slot) => *slot,
+            None => return Err("No piece at source position".to_string()),
+        };
+
+        // Check if the piece belongs to the player
+        if piece_info.color != *player_color {
+            return Err("Not your piece".to_string());
+        }
+
+        // Check if the move is valid for this piece type
+        if !self.is_valid_move(&piece_info, from, to) {
+            return Err("Invalid move for this piece".to_string());
+        }
+
+        // Special rule: Scouts cannot capture
+        if piece_info.piece == ChestPiece::Scout && self.slot(to).is_some() {
+            return Err("Scouts cannot capture pieces".to_string());
+        }
+
+        // Check if destination has own piece
+        if let Some(dest_piece) = self.slot(to)
+            && dest_piece.color == *player_color
+        {
+            return Err("Cannot capture your own piece".to_string());
+        }
+
+        // Execute the move, handing back whatever was captured
+        self.slots[from.0][from.1] = None;
+        Ok(self.slots[to.0][to.1].replace(piece_info))
+    }
+
+    /// Where the king of `color` stands, if it is still on the board
+    pub fn find_king(&self, color: &PlayerColor) -> Option<(usize, usize)> {
+        self.all_squares().find(|&square| {
+            self.slot(square)
+                .is_some_and(|slot| slot.piece == ChestPiece::King && slot.color == *color)
+        })
+    }
+
+    /// Whether any piece of `by_color` could capture on `square`
+    pub fn is_square_attacked(&self, square: (usize, usize), by_color: &PlayerColor) -> bool {
+        self.all_squares().any(|from| {
+            self.slot(from).is_some_and(|slot| {
+                slot.color == *by_color
+                    // Scouts cannot capture, so they never attack
+                    && slot.piece != ChestPiece::Scout
+                    && from != square
+                    && self.is_valid_capture(slot, from, square)
+            })
+        })
+    }
+
+    /// Whether the king of `color` is attacked
+    pub fn is_in_check(&self, color: &PlayerColor) -> bool {
+        self.find_king(color)
+            .is_some_and(|king| self.is_square_attacked(king, &color.opponent()))
+    }
+
+    /// Every square the piece on `from` may move to.
+    /// With `check_rules`, moves that leave the own king attacked are excluded.
+    pub fn legal_destinations(
+        &self,
+        from: (usize, usize),
+        check_rules: bool,
+    ) -> Vec<(usize, usize)> {
+        let Some(color) = self.slot(from).map(|slot| slot.color) else {
+            return Vec::new();
+        };
+
+        self.all_squares()
+            .filter(|&to| {
+                let mut after = self.clone();
+                after.make_move(from, to, &color).is_ok()
+                    && !(check_rules && after.is_in_check(&color))
+            })
+            .collect()
+    }
+
+    /// Every legal `(from, to)` move available to `color`
+    pub fn all_legal_moves(
+        &self,
+        color: &PlayerColor,
+        check_rules: bool,
+    ) -> Vec<((usize, usize), (usize, usize))> {
+        let mut moves = Vec::new();
+        for from in self.all_squares() {
+            if self.slot(from).is_some_and(|slot| slot.color == *color) {
+                for to in self.legal_destinations(from, check_rules) {
+                    moves.push((from, to));
+                }
+            }
+        }
+
+        moves
+    }
+
+    // A capture on `to` is a valid move onto an occupied square
+    fn is_valid_capture(
+        &self,
+        piece_info: &ExtendedSlot,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> bool {
+        let dr = to.0 as i32 - from.0 as i32;
+        let dc = to.1 as i32 - from.1 as i32;
+
+        match piece_info.piece {
+            // Pawns capture diagonally, and straight ahead where allowed, whether
+            // or not the square is occupied yet
+            ChestPiece::Pawn => {
+                let forward = if piece_info.color == PlayerColor::White {
+                    1
+                } else {
+                    -1
+                };
+                dr == forward && (dc.abs() == 1 || dc == 0 && self.pawn_rules.capture_forward)
+            }
+            _ => self.is_valid_move(piece_info, from, to),
+        }
+    }
+
+    fn is_valid_move(
+        &self,
+        piece_info: &ExtendedSlot,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> bool {
+        let dr = to.0 as i32 - from.0 as i32;
+        let dc = to.1 as i32 - from.1 as i32;
+
+        match piece_info.piece {
+            ChestPiece::Pawn => {
+                let forward = if piece_info.color == PlayerColor::White {
+                    1
+                } else {
+                    -1
+                };
+                let occupied = self.slot(to).is_some();
+
+                // Forward move, or a capture straight ahead where allowed
+                if dc == 0 && dr == forward {
+                    return !occupied || self.pawn_rules.capture_forward;
+                }
+
+                // Backward step where allowed, never a capture
+                if dc == 0 && dr == -forward && self.pawn_rules.move_backward {
+                    return !occupied;
+                }
+
+                // Diagonal capture
+                if dc.abs() == 1 && dr == forward {
+                    return occupied;
+                }
+
+                false
+            }
+
+            ChestPiece::Scout => {
+                // Scouts can move 1 or 2 tiles in any direction
+                let distance = ((dr.abs() as f64).powi(2) + (dc.abs() as f64).powi(2)).sqrt();
+                (1.0..=2.0).contains(&distance)
+            }
+
+            ChestPiece::Rook => (dr == 0 || dc == 0) && self.is_path_clear(from, to),
+
+            ChestPiece::Knight => {
+                (dr.abs() == 2 && dc.abs() == 1) || (dr.abs() == 1 && dc.abs() == 2)
+            }
+
+            ChestPiece::Bishop => dr.abs() == dc.abs() && self.is_path_clear(from, to),
+
+            ChestPiece::Queen => {
+                (dr == 0 || dc == 0 || dr.abs() == dc.abs()) && self.is_path_clear(from, to)
+            }
+
+            ChestPiece::King => dr.abs() <= 1 && dc.abs() <= 1 && (dr != 0 || dc != 0),
+        }
+    }
+
+    fn is_path_clear(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let (from_row, from_col) = (from.0 as i32, from.1 as i32);
+        let (to_row, to_col) = (to.0 as i32, to.1 as i32);
+
+        let dr = (to_row - from_row).signum();
+        let dc = (to_col - from_col).signum();
+
+        let mut current_row = from_row + dr;
+        let mut current_col = from_col + dc;
+
+        while current_row != to_row || current_col != to_col {
+            let square = (current_row as usize, current_col as usize);
+            if !self.contains(square) {
+                return false;
+            }
+
+            if self.slot(square).is_some() || self.walls.contains(&square) {
+                return false;
+            }
+
+            current_row += dr;
+            current_col += dc;
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_bench::splitmix64;
+    use crate::glub_server_bitboard::squares;
+
+    /// Random positions compared per property
+    const BOARDS: u64 = 300;
+
+    /// Random moves tried on each of them
+    const MOVES: usize = 60;
+
+    // The next number below `below` from a splitmix64 stream
+    fn draw(state: &mut u64, below: usize) -> usize {
+        *state = splitmix64(*state);
+        (*state % below as u64) as usize
+    }
+
+    // A reproducible random position on a board of one of the allowed shapes:
+    // one king a side, a scatter of other pieces and, on most boards, walls
+    fn random_board(seed: u64) -> ExtendedBoard {
+        let mut state = seed;
+        let next = &mut state;
+
+        let dims = [(8, 8), (10, 10), (8, 12), (12, 12)][draw(next, 4)];
+        let mut board = ExtendedBoard::with_dims(dims);
+        board.set_pawn_rules(PawnRules {
+            move_backward: draw(next, 4) == 0,
+            capture_forward: draw(next, 4) == 0,
+        });
+        let random_square = |board: &ExtendedBoard, next: &mut u64| loop {
+            let square = (draw(next, dims.0), draw(next, dims.1));
+            if board.slot(square).is_none() && !board.is_wall(square) {
+                break square;
+            }
+        };
+
+        for color in colors() {
+            let king = random_square(&board, next);
+            board.set_slot(
+                king,
+                Some(ExtendedSlot {
+                    piece: ChestPiece::King,
+                    color,
+                }),
+            );
+        }
+        for _ in 0..draw(next, 24) {
+            let square = random_square(&board, next);
+            let piece = ChestPiece::ALL[draw(next, 6)];
+            let color = colors()[draw(next, 2)];
+            board.set_slot(square, Some(ExtendedSlot { piece, color }));
+        }
+        for _ in 0..draw(next, 8) {
+            let square = random_square(&board, next);
+            board.add_wall(square);
+        }
+
+        board
+    }
+
+    fn colors() -> [PlayerColor; 2] {
+        [PlayerColor::White, PlayerColor::Black]
+    }
+
+    #[test]
+    fn random_boards_have_no_kings_missing_or_doubled() {
+        for seed in 0..BOARDS {
+            let board = random_board(seed);
+            for color in colors() {
+                assert_eq!(board.count(&color, ChestPiece::King), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn both_boards_see_the_same_squares() {
+        for seed in 0..BOARDS {
+            let board = random_board(seed);
+            let array = ArrayBoard::from_board(&board);
+            for color in colors() {
+                let visible: HashSet<_> = squares(board.visible_mask(&color)).collect();
+                assert_eq!(
+                    visible,
+                    array.get_visible_positions(&color),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn both_boards_attack_the_same_squares() {
+        for seed in 0..BOARDS {
+            let board = random_board(seed);
+            let array = ArrayBoard::from_board(&board);
+            for square in squares(board.all_squares()) {
+                for color in colors() {
+                    assert_eq!(
+                        board.is_square_attacked(square, &color),
+                        array.is_square_attacked(square, &color),
+                        "seed {}, {:?} attacked by {:?}",
+                        seed,
+                        square,
+                        color
+                    );
+                }
+            }
+            for color in colors() {
+                assert_eq!(board.is_in_check(&color), array.is_in_check(&color));
+            }
+        }
+    }
+
+    #[test]
+    fn both_boards_allow_the_same_moves() {
+        for seed in 0..BOARDS {
+            let board = random_board(seed);
+            let array = ArrayBoard::from_board(&board);
+            for color in colors() {
+                for check_rules in [false, true] {
+                    assert_eq!(
+                        board.all_legal_moves(&color, check_rules),
+                        array.all_legal_moves(&color, check_rules),
+                        "seed {}, {:?}, check rules {}",
+                        seed,
+                        color,
+                        check_rules
+                    );
+                }
+            }
+        }
+    }
+
+    // Random moves, legal or not, are played on both boards; the boards agree
+    // on which go through, what they capture and where everything ends up
+    #[test]
+    fn both_boards_play_moves_alike() {
+        for seed in 0..BOARDS {
+            let mut board = random_board(seed);
+            let mut array = ArrayBoard::from_board(&board);
+            let (rows, cols) = board.dims();
+            let mut state = seed;
+            let mut next = |below| draw(&mut state, below);
+
+            for _ in 0..MOVES {
+                let color = colors()[next(2)];
+                // From any piece, the mover's or not, to one of its moves half
+                // the time and otherwise anywhere up to a row and column past
+                // the edge
+                let pieces: Vec<_> = board.pieces().map(|(square, _)| square).collect();
+                let from = pieces[next(pieces.len())];
+                let moves = board.legal_destinations(from, false);
+                let to = if !moves.is_empty() && next(2) == 0 {
+                    moves[next(moves.len())]
+                } else {
+                    (next(rows + 1), next(cols + 1))
+                };
+                let played = board.make_move(from, to, &color);
+                let reference = array.make_move(from, to, &color);
+                assert_eq!(
+                    played.ok(),
+                    reference.ok(),
+                    "seed {}, {:?} to {:?}",
+                    seed,
+                    from,
+                    to
+                );
+                assert_eq!(board.slots(), array.slots(), "seed {}", seed);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::SeededGame;
+use crate::glub_server::{ChestPiece, ExtendedBoard, ExtendedSlot};
+use crate::glub_server_storage::{GameStorage, PlayerColor};
+
+/// Random moves played from the starting position to reach a midgame
+const MIDGAME_PLIES: usize = 30;
+
+/// Storage holding one seeded game per midgame from seeds `0..games`, for
+/// benchmarking storage-level paths. The games are listed in seed order.
+pub fn seeded_storage(games: u64) -> (GameStorage, Vec<SeededGame>) {
+    let mut storage = GameStorage::new();
+    let seeded = (0..games)
+        .map(|seed| {
+            storage
+                .seed_game(
+                    &random_midgame(seed).to_board_string(),
+                    "white".to_string(),
+                    "black".to_string(),
+                    None,
+                )
+                .expect("seeded midgames are valid")
+        })
+        .collect();
+    (storage, seeded)
+}
+
+/// A reproducible midgame: the starting position after a run of random moves
+/// picked from `seed`. Kings are never taken, so the position stays playable.
+pub fn random_midgame(seed: u64) -> ExtendedBoard {
+    let mut board = ExtendedBoard::new();
+    board.setup_initial_position();
+
+    let mut state = seed;
+    let mut color = PlayerColor::White;
+    for _ in 0..MIDGAME_PLIES {
+        let moves: Vec<_> = board
+            .all_legal_moves(&color, false)
+            .into_iter()
+            .filter(|&(_, to)| !board.holds_king_of(to, &color.opponent()))
+            .collect();
+        if moves.is_empty() {
+            break;
+        }
+
+        state = splitmix64(state);
+        let (from, to) = moves[(state % moves.len() as u64) as usize];
+        let _ = board.make_move(from, to, &color);
+        color = color.opponent();
+    }
+
+    board
+}
+
+/// A white `piece` on an open board with a legal move for it, plus both kings
+pub fn lone_piece_move(piece: ChestPiece) -> (ExtendedBoard, (usize, usize), (usize, usize)) {
+    let mut board = ExtendedBoard::new();
+    for (square, color) in [((0, 0), PlayerColor::White), ((7, 7), PlayerColor::Black)] {
+        board.set_slot(
+            square,
+            Some(ExtendedSlot {
+                piece: ChestPiece::King,
+                color,
+            }),
+        );
+    }
+    if piece == ChestPiece::King {
+        return (board, (0, 0), (1, 1));
+    }
+
+    let from = (3, 3);
+    let to = match piece {
+        ChestPiece::Pawn => (4, 3),
+        ChestPiece::Scout => (5, 3),
+        ChestPiece::Rook => (3, 7),
+        ChestPiece::Knight => (5, 4),
+        ChestPiece::Bishop | ChestPiece::Queen | ChestPiece::King => (6, 6),
+    };
+    board.set_slot(
+        from,
+        Some(ExtendedSlot {
+            piece,
+            color: PlayerColor::White,
+        }),
+    );
+    (board, from, to)
+}
+
+pub fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
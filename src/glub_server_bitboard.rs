@@ -0,0 +1,329 @@
+use crate::glub_server::{ChestPiece, MAX_BOARD_SIZE};
+use crate::glub_server_storage::PlayerColor;
+use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use std::sync::OnceLock;
+
+/// Squares on the largest board, and so bits in a `Bitboard`
+pub const SQUARE_COUNT: usize = MAX_BOARD_SIZE * MAX_BOARD_SIZE;
+
+const WORDS: usize = SQUARE_COUNT.div_ceil(64);
+
+/// A set of squares, one bit per square: bit `row * MAX_BOARD_SIZE + col`,
+/// counting from the low bit of the first word. Every board size uses the
+/// layout of the largest board, so one set of move and sight tables serves
+/// them all; a smaller board masks off the squares past its edges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Bitboard([u64; WORDS]);
+
+impl Bitboard {
+    pub const EMPTY: Bitboard = Bitboard([0; WORDS]);
+
+    pub fn is_empty(self) -> bool {
+        self == Self::EMPTY
+    }
+
+    pub fn count_ones(self) -> u32 {
+        self.0.iter().map(|word| word.count_ones()).sum()
+    }
+
+    pub fn contains(self, square: (usize, usize)) -> bool {
+        !(self & bit(square)).is_empty()
+    }
+}
+
+macro_rules! bitwise {
+    ($op:ident, $method:ident, $assign:ident, $assign_method:ident) => {
+        impl $op for Bitboard {
+            type Output = Bitboard;
+
+            fn $method(self, other: Bitboard) -> Bitboard {
+                Bitboard(std::array::from_fn(|word| {
+                    self.0[word].$method(other.0[word])
+                }))
+            }
+        }
+
+        impl $assign for Bitboard {
+            fn $assign_method(&mut self, other: Bitboard) {
+                *self = (*self).$method(other);
+            }
+        }
+    };
+}
+
+bitwise!(BitAnd, bitand, BitAndAssign, bitand_assign);
+bitwise!(BitOr, bitor, BitOrAssign, bitor_assign);
+bitwise!(BitXor, bitxor, BitXorAssign, bitxor_assign);
+
+impl Not for Bitboard {
+    type Output = Bitboard;
+
+    fn not(self) -> Bitboard {
+        Bitboard(self.0.map(|word| !word))
+    }
+}
+
+/// Sight ranges beyond this already cover the largest board from any square
+const MAX_SIGHT_RANGE: usize = 16;
+
+/// The bit for one square
+pub fn bit(square: (usize, usize)) -> Bitboard {
+    let index = index(square);
+    let mut mask = Bitboard::EMPTY;
+    mask.0[index / 64] = 1 << (index % 64);
+    mask
+}
+
+/// The squares in a set, white's back rank first and left to right within a row
+pub fn squares(mask: Bitboard) -> impl Iterator<Item = (usize, usize)> {
+    let mut words = mask.0;
+    let mut word = 0;
+    std::iter::from_fn(move || {
+        while *words.get(word)? == 0 {
+            word += 1;
+        }
+        let index = word * 64 + words[word].trailing_zeros() as usize;
+        words[word] &= words[word] - 1;
+        Some((index / MAX_BOARD_SIZE, index % MAX_BOARD_SIZE))
+    })
+}
+
+/// Every square of a board with `rows` rows and `cols` columns
+pub fn rectangle((rows, cols): (usize, usize)) -> Bitboard {
+    (0..rows)
+        .flat_map(|row| (0..cols).map(move |col| (row, col)))
+        .fold(Bitboard::EMPTY, |mask, square| mask | bit(square))
+}
+
+/// The position of a square's bit, below `SQUARE_COUNT`
+pub fn index(square: (usize, usize)) -> usize {
+    debug_assert!(square.0 < MAX_BOARD_SIZE && square.1 < MAX_BOARD_SIZE);
+    square.0 * MAX_BOARD_SIZE + square.1
+}
+
+pub fn color_index(color: &PlayerColor) -> usize {
+    match color {
+        PlayerColor::White => 0,
+        PlayerColor::Black => 1,
+    }
+}
+
+pub fn piece_index(piece: ChestPiece) -> usize {
+    piece as usize
+}
+
+/// Squares strictly between two squares on a shared rank, file or diagonal;
+/// empty when they share none
+pub fn between(from: (usize, usize), to: (usize, usize)) -> Bitboard {
+    tables().between[index(from) * SQUARE_COUNT + index(to)]
+}
+
+/// Whether two different squares share a rank, file or diagonal
+pub fn aligned(from: (usize, usize), to: (usize, usize)) -> bool {
+    (tables().lines[index(from)] | tables().diagonals[index(from)]).contains(to)
+}
+
+/// Other squares on the same rank or file
+pub fn lines(square: (usize, usize)) -> Bitboard {
+    tables().lines[index(square)]
+}
+
+/// Other squares on the same diagonals
+pub fn diagonals(square: (usize, usize)) -> Bitboard {
+    tables().diagonals[index(square)]
+}
+
+pub fn knight_moves(square: (usize, usize)) -> Bitboard {
+    tables().knight[index(square)]
+}
+
+pub fn king_moves(square: (usize, usize)) -> Bitboard {
+    tables().king[index(square)]
+}
+
+/// Squares a pawn of `color` would have to stand on to capture on `square`
+pub fn pawn_attackers(square: (usize, usize), color: &PlayerColor) -> Bitboard {
+    let row = match color {
+        PlayerColor::White => square.0.checked_sub(1),
+        PlayerColor::Black => Some(square.0 + 1).filter(|&row| row < MAX_BOARD_SIZE),
+    };
+    let Some(row) = row else {
+        return Bitboard::EMPTY;
+    };
+    [
+        square.1.checked_sub(1),
+        Some(square.1 + 1).filter(|&col| col < MAX_BOARD_SIZE),
+    ]
+    .into_iter()
+    .flatten()
+    .fold(Bitboard::EMPTY, |mask, col| mask | bit((row, col)))
+}
+
+/// The square a pawn of `color` would have to stand on to step forward onto
+/// `square`
+pub fn pawn_pushers(square: (usize, usize), color: &PlayerColor) -> Bitboard {
+    let row = match color {
+        PlayerColor::White => square.0.checked_sub(1),
+        PlayerColor::Black => Some(square.0 + 1).filter(|&row| row < MAX_BOARD_SIZE),
+    };
+    row.map_or(Bitboard::EMPTY, |row| bit((row, square.1)))
+}
+
+/// Squares within `range` of `square`, measured as a straight line between
+/// square centers
+pub fn sight(square: (usize, usize), range: usize) -> Bitboard {
+    tables().sight[range.min(MAX_SIGHT_RANGE) * SQUARE_COUNT + index(square)]
+}
+
+/// Squares a line of sight from one square to another passes through, not
+/// counting either end
+pub fn sight_line(from: (usize, usize), to: (usize, usize)) -> Bitboard {
+    tables().sight_lines[index(from) * SQUARE_COUNT + index(to)]
+}
+
+struct Tables {
+    between: Vec<Bitboard>,
+    lines: [Bitboard; SQUARE_COUNT],
+    diagonals: [Bitboard; SQUARE_COUNT],
+    knight: [Bitboard; SQUARE_COUNT],
+    king: [Bitboard; SQUARE_COUNT],
+    sight: Vec<Bitboard>,
+    sight_lines: Vec<Bitboard>,
+}
+
+fn tables() -> &'static Tables {
+    static TABLES: OnceLock<Tables> = OnceLock::new();
+    TABLES.get_or_init(build_tables)
+}
+
+// Every square of the largest board. The tables cover all of them, and
+// boards drop whatever lies past their own edges.
+fn all_squares() -> impl Iterator<Item = (usize, usize)> {
+    (0..MAX_BOARD_SIZE).flat_map(|row| (0..MAX_BOARD_SIZE).map(move |col| (row, col)))
+}
+
+// The squares at the given offsets from `square` that are on the largest board
+fn offsets(square: (usize, usize), deltas: &[(i32, i32)]) -> Bitboard {
+    deltas
+        .iter()
+        .map(|&(dr, dc)| (square.0 as i32 + dr, square.1 as i32 + dc))
+        .filter(|&(row, col)| {
+            (0..MAX_BOARD_SIZE as i32).contains(&row) && (0..MAX_BOARD_SIZE as i32).contains(&col)
+        })
+        .fold(Bitboard::EMPTY, |mask, (row, col)| {
+            mask | bit((row as usize, col as usize))
+        })
+}
+
+fn build_tables() -> Tables {
+    let mut tables = Tables {
+        between: vec![Bitboard::EMPTY; SQUARE_COUNT * SQUARE_COUNT],
+        lines: [Bitboard::EMPTY; SQUARE_COUNT],
+        diagonals: [Bitboard::EMPTY; SQUARE_COUNT],
+        knight: [Bitboard::EMPTY; SQUARE_COUNT],
+        king: [Bitboard::EMPTY; SQUARE_COUNT],
+        sight: vec![Bitboard::EMPTY; (MAX_SIGHT_RANGE + 1) * SQUARE_COUNT],
+        sight_lines: vec![Bitboard::EMPTY; SQUARE_COUNT * SQUARE_COUNT],
+    };
+
+    for from in all_squares() {
+        let i = index(from);
+        tables.knight[i] = offsets(
+            from,
+            &[
+                (1, 2),
+                (2, 1),
+                (2, -1),
+                (1, -2),
+                (-1, -2),
+                (-2, -1),
+                (-2, 1),
+                (-1, 2),
+            ],
+        );
+        tables.king[i] = offsets(
+            from,
+            &[
+                (1, 0),
+                (1, 1),
+                (0, 1),
+                (-1, 1),
+                (-1, 0),
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+            ],
+        );
+
+        for to in all_squares() {
+            if to == from {
+                continue;
+            }
+            let dr = to.0 as i32 - from.0 as i32;
+            let dc = to.1 as i32 - from.1 as i32;
+            let j = index(to);
+
+            if dr == 0 || dc == 0 {
+                tables.lines[i] |= bit(to);
+            } else if dr.abs() == dc.abs() {
+                tables.diagonals[i] |= bit(to);
+            }
+            if dr == 0 || dc == 0 || dr.abs() == dc.abs() {
+                let (step_r, step_c) = (dr.signum(), dc.signum());
+                let (mut row, mut col) = (from.0 as i32 + step_r, from.1 as i32 + step_c);
+                while (row, col) != (to.0 as i32, to.1 as i32) {
+                    tables.between[i * SQUARE_COUNT + j] |= bit((row as usize, col as usize));
+                    row += step_r;
+                    col += step_c;
+                }
+            }
+
+            // Sample the line between the two centers twice per square crossed
+            let steps = 2 * dr.unsigned_abs().max(dc.unsigned_abs()) as usize;
+            for step in 1..steps {
+                let t = step as f64 / steps as f64;
+                let square = (
+                    (from.0 as f64 + dr as f64 * t).round() as usize,
+                    (from.1 as f64 + dc as f64 * t).round() as usize,
+                );
+                if square != from && square != to {
+                    tables.sight_lines[i * SQUARE_COUNT + j] |= bit(square);
+                }
+            }
+        }
+
+        // Compare squared distances so the disc needs no square roots
+        for range in 0..=MAX_SIGHT_RANGE {
+            for to in all_squares() {
+                let dr = to.0.abs_diff(from.0);
+                let dc = to.1.abs_diff(from.1);
+                if dr * dr + dc * dc <= range * range {
+                    tables.sight[range * SQUARE_COUNT + i] |= bit(to);
+                }
+            }
+        }
+    }
+
+    tables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sight_matches_the_floating_point_circle() {
+        for range in 1..=4 {
+            for from in all_squares() {
+                let circle = all_squares()
+                    .filter(|to| {
+                        let dr = to.0 as f64 - from.0 as f64;
+                        let dc = to.1 as f64 - from.1 as f64;
+                        (dr * dr + dc * dc).sqrt() <= range as f64
+                    })
+                    .fold(Bitboard::EMPTY, |mask, to| mask | bit(to));
+                assert_eq!(sight(from, range), circle, "{:?} at range {}", from, range);
+            }
+        }
+    }
+}
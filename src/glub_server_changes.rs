@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+use uuid::Uuid;
+
+/// Changes a slow subscriber may fall behind by before it starts missing some
+const CHANGE_BUFFER: usize = 1024;
+
+/// A game moved on: a move was made, a draw was offered or answered, or the
+/// game ended
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameChange {
+    pub game_id: Uuid,
+    pub version: u64,
+    /// The server instance that made the change
+    pub origin: Uuid,
+}
+
+/// Carries game changes to every waiting request. Changes always reach this
+/// instance; with Redis they also reach every other instance sharing it.
+#[derive(Clone, Debug)]
+pub struct ChangeBus {
+    instance: Uuid,
+    local: broadcast::Sender<GameChange>,
+    /// Set once this instance starts shutting down; never shared with others
+    shutdown: Arc<watch::Sender<bool>>,
+    #[cfg(feature = "redis")]
+    remote: Option<redis_backed::RedisPublisher>,
+}
+
+impl Default for ChangeBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChangeBus {
+    pub fn new() -> Self {
+        let (local, _) = broadcast::channel(CHANGE_BUFFER);
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            instance: Uuid::new_v4(),
+            local,
+            shutdown: Arc::new(shutdown),
+            #[cfg(feature = "redis")]
+            remote: None,
+        }
+    }
+
+    /// A second instance on this bus, for servers sharing one process. Each
+    /// sees the other's changes, but shuts down on its own.
+    pub fn new_instance(&self) -> Self {
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            instance: Uuid::new_v4(),
+            local: self.local.clone(),
+            shutdown: Arc::new(shutdown),
+            #[cfg(feature = "redis")]
+            remote: self.remote.clone(),
+        }
+    }
+
+    /// Identifies this instance, so subscribers can tell changes made elsewhere
+    pub fn instance(&self) -> Uuid {
+        self.instance
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<GameChange> {
+        self.local.subscribe()
+    }
+
+    /// Watch for this instance shutting down. A receiver taken after the
+    /// shutdown still sees it.
+    pub fn subscribe_shutdown(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+
+    /// Tell every waiting request that this instance is going away, so it can
+    /// answer its client instead of being cut off when the drain runs out
+    pub fn shut_down(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Announce a change. Nobody listening is not an error.
+    pub fn publish(&self, game_id: Uuid, version: u64) {
+        let change = GameChange {
+            game_id,
+            version,
+            origin: self.instance,
+        };
+        let _ = self.local.send(change);
+
+        #[cfg(feature = "redis")]
+        if let Some(remote) = &self.remote {
+            remote.publish(&change);
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_backed {
+    use super::*;
+    use redis::Commands;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use tracing::warn;
+
+    const CHANGES_CHANNEL: &str = "chest:changes";
+
+    /// Wait before resubscribing after the change feed drops
+    const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+    /// Hands changes to a thread that publishes them, so callers holding the
+    /// storage lock never wait on Redis
+    #[derive(Clone)]
+    pub struct RedisPublisher {
+        outbox: mpsc::Sender<GameChange>,
+    }
+
+    impl std::fmt::Debug for RedisPublisher {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("RedisPublisher").finish()
+        }
+    }
+
+    impl RedisPublisher {
+        pub fn publish(&self, change: &GameChange) {
+            let _ = self.outbox.send(*change);
+        }
+    }
+
+    // Publish queued changes one by one for as long as the bus exists
+    fn publish_changes(mut connection: redis::Connection, outbox: mpsc::Receiver<GameChange>) {
+        for change in outbox {
+            let Ok(payload) = serde_json::to_string(&change) else {
+                continue;
+            };
+            if let Err(e) = connection.publish::<_, _, ()>(CHANGES_CHANNEL, payload) {
+                warn!(
+                    "Redis unreachable, change to game {} stays on this instance: {}",
+                    change.game_id, e
+                );
+            }
+        }
+    }
+
+    impl ChangeBus {
+        /// Also share changes through Redis pub/sub. Changes published by other
+        /// instances are handed to this instance's subscribers.
+        pub fn with_redis(mut self, url: &str) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            let connection = client.get_connection().map_err(|e| e.to_string())?;
+            let (outbox, queued) = mpsc::channel();
+            std::thread::spawn(move || publish_changes(connection, queued));
+            self.remote = Some(RedisPublisher { outbox });
+
+            let instance = self.instance;
+            let local = self.local.clone();
+            std::thread::spawn(move || {
+                loop {
+                    if let Err(e) = forward_remote_changes(&client, instance, &local) {
+                        warn!("Lost the Redis change feed, resubscribing: {}", e);
+                    }
+                    std::thread::sleep(RESUBSCRIBE_DELAY);
+                }
+            });
+
+            Ok(self)
+        }
+    }
+
+    // Relay changes made by other instances until the subscription fails
+    fn forward_remote_changes(
+        client: &redis::Client,
+        instance: Uuid,
+        local: &broadcast::Sender<GameChange>,
+    ) -> redis::RedisResult<()> {
+        let mut connection = client.get_connection()?;
+        let mut pubsub = connection.as_pubsub();
+        pubsub.subscribe(CHANGES_CHANNEL)?;
+
+        loop {
+            let payload: String = pubsub.get_message()?.get_payload()?;
+            if let Ok(change) = serde_json::from_str::<GameChange>(&payload)
+                && change.origin != instance
+            {
+                let _ = local.send(change);
+            }
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::glub_server_config::{BindAddress, Config};
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Command-line flags. Anything given here beats the environment and the config file.
+#[derive(Parser, Debug)]
+#[command(version, about = "Chest Royale game server")]
+pub struct Cli {
+    /// TOML config file, instead of the one named by CHEST_CONFIG
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on: an IP address, ip:port, or unix:<path> for a Unix
+    /// domain socket. Repeat to listen on several.
+    #[arg(long, value_name = "ADDRESS")]
+    pub bind: Vec<BindAddress>,
+    /// Keep running if some bind addresses fail, as long as one works
+    #[arg(long)]
+    pub best_effort_bind: bool,
+    #[arg(long)]
+    pub port: Option<u16>,
+    /// Length of one game tick in milliseconds
+    #[arg(long, value_name = "MS")]
+    pub tick_ms: Option<u64>,
+    /// error, warn, info, debug or trace
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<String>,
+    /// Print the resolved configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+impl Cli {
+    /// Defaults, then the config file, then the environment, then these flags
+    pub fn resolve(&self) -> Result<Config, String> {
+        let mut config = Config::load(self.config.as_deref())?;
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Override settings with every flag that was given
+    pub fn apply(&self, config: &mut Config) {
+        if !self.bind.is_empty() {
+            config.bind = self.bind.clone();
+        }
+        if self.best_effort_bind {
+            config.best_effort_bind = true;
+        }
+        if let Some(port) = self.port {
+            config.port = port;
+        }
+        if let Some(tick_ms) = self.tick_ms {
+            config.tick_ms = tick_ms;
+        }
+        if let Some(log_level) = &self.log_level {
+            config.log_level = log_level.clone();
+        }
+    }
+}
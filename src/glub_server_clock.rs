@@ -0,0 +1,135 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Source of time for game timers, swappable so timing rules can be driven manually
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> Instant;
+    /// Calendar time, for showing when something happened; timers use `now`
+    fn wall_time(&self) -> SystemTime;
+}
+
+/// The real monotonic clock
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock that only moves when told to
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<(Instant, SystemTime)>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self {
+            now: Mutex::new((Instant::now(), SystemTime::now())),
+        }
+    }
+
+    /// Move both the monotonic and the wall clock forward
+    pub fn advance(&self, by: Duration) {
+        let mut now = self.now.lock().unwrap();
+        now.0 += by;
+        now.1 += by;
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        self.now.lock().unwrap().0
+    }
+
+    fn wall_time(&self) -> SystemTime {
+        self.now.lock().unwrap().1
+    }
+}
+
+/// ISO 8601 in UTC to the second, e.g. `2024-05-01T12:30:00Z`
+pub fn format_wall_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+/// Serialize an `Instant` as the number of seconds that have passed since it,
+/// so a timestamp keeps its age across a save and reload
+pub mod instant_as_age {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::{Duration, Instant};
+
+    pub fn serialize<S: Serializer>(instant: &Instant, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(instant.elapsed().as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Instant, D::Error> {
+        let age = f64::deserialize(deserializer)?;
+        let age = Duration::try_from_secs_f64(age).unwrap_or_default();
+        let now = Instant::now();
+        Ok(now.checked_sub(age).unwrap_or(now))
+    }
+}
+
+/// Serialize a `SystemTime` as an ISO 8601 string in UTC
+pub mod wall_time_iso8601 {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use std::time::SystemTime;
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format_wall_time(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        humantime::parse_rfc3339_weak(&text).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use std::time::UNIX_EPOCH;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Stamped {
+        #[serde(with = "wall_time_iso8601")]
+        at: SystemTime,
+    }
+
+    #[test]
+    fn wall_time_is_written_as_iso_8601_to_the_second() {
+        let at = UNIX_EPOCH + Duration::from_millis(1_714_566_600_750);
+        assert_eq!(format_wall_time(at), "2024-05-01T12:30:00Z");
+
+        let json = serde_json::to_string(&Stamped { at }).unwrap();
+        assert_eq!(json, r#"{"at":"2024-05-01T12:30:00Z"}"#);
+        let read: Stamped = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.at, UNIX_EPOCH + Duration::from_secs(1_714_566_600));
+        assert!(serde_json::from_str::<Stamped>(r#"{"at":"yesterday"}"#).is_err());
+    }
+
+    #[test]
+    fn a_manual_clock_moves_both_times_together() {
+        let clock = ManualClock::new();
+        let (now, wall_time) = (clock.now(), clock.wall_time());
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - now, Duration::from_secs(90));
+        assert_eq!(
+            clock.wall_time().duration_since(wall_time).unwrap(),
+            Duration::from_secs(90)
+        );
+    }
+}
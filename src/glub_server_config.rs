@@ -0,0 +1,957 @@
+use crate::glub_server::{ExtendedBoard, MAX_BOARD_SIZE, MIN_BOARD_SIZE};
+use crate::glub_server_storage::{GameRules, KingRule, Strictness, centre_hill};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing_subscriber::filter::LevelFilter;
+
+/// Shown instead of secrets when the configuration is printed
+const REDACTED: &str = "<redacted>";
+
+/// Server settings. Built from the defaults, then the TOML file named by
+/// `--config` or `CHEST_CONFIG`, then `CHEST_*` environment variables, then
+/// command-line flags.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses the server listens on: IP addresses (served on `port`), socket
+    /// addresses such as `[::]:3000`, or `unix:` and a socket path. A single
+    /// address may be given on its own.
+    #[serde(deserialize_with = "one_or_many")]
+    pub bind: Vec<BindAddress>,
+    /// Keep running on the addresses that could be bound instead of exiting
+    /// when one fails
+    pub best_effort_bind: bool,
+    /// TCP port for bind addresses that don't name one
+    pub port: u16,
+    /// Permissions given to a Unix socket, e.g. `0o660`
+    pub socket_mode: u32,
+    /// Most verbose log level shown: error, warn, info, debug or trace
+    pub log_level: String,
+    /// Most verbose log level written to the log file
+    pub log_file_level: String,
+    /// When the log file is rotated
+    pub log_file_rotation: LogRotation,
+    /// Size at which the log file is rotated with `size` rotation
+    pub log_file_max_bytes: u64,
+    /// Rotated log files kept beside the current one
+    pub log_file_keep: usize,
+    /// Write the log file as one JSON object per line
+    pub log_file_json: bool,
+    /// Length of one game tick; move points and presence are checked every tick
+    pub tick_ms: u64,
+    /// Groups games are split into for the tick. One group is handled every
+    /// tick_ms / tick_shards, so the lock is never held for all games at once.
+    pub tick_shards: u64,
+    /// Seconds between move point grants
+    pub move_increment_seconds: u64,
+    /// Maximum number of move points a player can bank
+    pub max_stored_moves: u64,
+    /// Seconds of silence before a player is flagged as idle in the event log
+    pub presence_warning_seconds: u64,
+    /// Seconds a finished game stays in memory before it is moved to the archive
+    pub archive_grace_seconds: u64,
+    /// Seconds a finished game stays in memory when no archive is configured;
+    /// 0 keeps finished games forever
+    pub finished_retention_seconds: u64,
+    /// Seconds without a move after which a game is ended with no winner; 0 disables
+    pub stale_game_seconds: u64,
+    /// Most games in progress at once; new joins get a 503 beyond this
+    pub max_active_games: usize,
+    /// Seconds without a finished tick before the server reports itself not ready
+    pub heartbeat_timeout_seconds: u64,
+    /// Seconds between checkpoints of active games to the database
+    pub checkpoint_interval_seconds: u64,
+    /// Time allowed for open requests and pending writes once a shutdown starts
+    pub shutdown_deadline_seconds: u64,
+    /// Largest request body accepted by default
+    pub body_limit_bytes: usize,
+    /// Game archives carry a full move history, so imports get more room
+    pub import_body_limit_bytes: usize,
+    /// Longest accepted player name, in characters
+    pub max_player_name_len: usize,
+    /// How long a spectator code works after it is handed out; 0 keeps it for
+    /// the whole game
+    pub spectator_code_ttl_seconds: u64,
+    /// How long a private lobby waits for the invited player, and how long its
+    /// code keeps leading to the game after that
+    pub lobby_ttl_seconds: u64,
+    /// Longest a client may hold a wait request open
+    pub max_wait_seconds: u64,
+    /// Requests still running after this long are cut off with 408, so a stalled
+    /// handler can't keep the storage lock forever
+    pub request_timeout_seconds: u64,
+    /// Compress responses with gzip or brotli when the client accepts it
+    pub compress_responses: bool,
+    /// How long a single webhook delivery attempt may take
+    pub webhook_timeout_seconds: u64,
+    /// Delivery attempts per game summary before it is dropped
+    pub webhook_max_attempts: u32,
+    /// Size at which the analytics log is rotated
+    pub analytics_max_bytes: u64,
+    /// Rotated analytics logs kept beside the current one; 0 keeps none
+    pub analytics_keep_files: u32,
+    /// Rules every new game starts with
+    pub rules: GameRules,
+    /// Token required in the `x-admin-token` header; admin endpoints are off without one
+    pub admin_token: Option<String>,
+    pub database_url: Option<String>,
+    pub redis_url: Option<String>,
+    /// Where active games are saved on shutdown and loaded from on start
+    pub snapshot_path: Option<PathBuf>,
+    /// Where finished games are written once evicted from memory
+    pub archive_dir: Option<PathBuf>,
+    /// Receives a summary of every finished game
+    pub webhook_url: Option<String>,
+    /// Where a JSON line is appended for every finished game
+    pub analytics_path: Option<PathBuf>,
+    /// Also log to this file, alongside stdout
+    pub log_file: Option<PathBuf>,
+}
+
+/// How the log file is split up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogRotation {
+    /// A new file every day, named after the date
+    #[default]
+    Daily,
+    /// A new file every hour
+    Hourly,
+    /// Once the file reaches `log_file_max_bytes`, numbered `.1`, `.2` and so on
+    Size,
+    /// A single file that keeps growing
+    Never,
+}
+
+impl FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "daily" => Ok(LogRotation::Daily),
+            "hourly" => Ok(LogRotation::Hourly),
+            "size" => Ok(LogRotation::Size),
+            "never" => Ok(LogRotation::Never),
+            _ => Err(format!(
+                "must be daily, hourly, size or never, got {:?}",
+                value
+            )),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec![BindAddress::Ip(IpAddr::V4(Ipv4Addr::UNSPECIFIED))],
+            best_effort_bind: false,
+            port: 3000,
+            socket_mode: 0o660,
+            log_level: "info".to_string(),
+            log_file_level: "info".to_string(),
+            log_file_rotation: LogRotation::default(),
+            log_file_max_bytes: 10 * 1024 * 1024,
+            log_file_keep: 7,
+            log_file_json: false,
+            tick_ms: 1000,
+            tick_shards: 4,
+            move_increment_seconds: 3,
+            max_stored_moves: 5,
+            presence_warning_seconds: 30,
+            archive_grace_seconds: 120,
+            finished_retention_seconds: 3600,
+            stale_game_seconds: 1800,
+            max_active_games: 1000,
+            heartbeat_timeout_seconds: 10,
+            checkpoint_interval_seconds: 30,
+            shutdown_deadline_seconds: 30,
+            body_limit_bytes: 16 * 1024,
+            import_body_limit_bytes: 4 * 1024 * 1024,
+            max_player_name_len: 32,
+            max_wait_seconds: 25,
+            spectator_code_ttl_seconds: 0,
+            lobby_ttl_seconds: 30 * 60,
+            request_timeout_seconds: 30,
+            compress_responses: true,
+            webhook_timeout_seconds: 5,
+            webhook_max_attempts: 4,
+            analytics_max_bytes: 10 * 1024 * 1024,
+            analytics_keep_files: 5,
+            rules: GameRules::default(),
+            admin_token: None,
+            database_url: None,
+            redis_url: None,
+            snapshot_path: None,
+            archive_dir: None,
+            webhook_url: None,
+            analytics_path: None,
+            log_file: None,
+        }
+    }
+}
+
+impl Config {
+    /// Defaults, then `file` (or the one named by `CHEST_CONFIG`), then the
+    /// environment. The result still needs `validate`.
+    pub fn load(file: Option<&Path>) -> Result<Self, String> {
+        let env_file = std::env::var_os("CHEST_CONFIG").map(PathBuf::from);
+        let mut config = match file.or(env_file.as_deref()) {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.apply_env(|name| std::env::var(name).ok())?;
+        Ok(config)
+    }
+
+    /// Settings from a TOML file; anything it leaves out keeps its default
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read config file {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))
+    }
+
+    /// Override settings from environment variables, looked up through `lookup`
+    pub fn apply_env(&mut self, lookup: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        if let Some(value) = lookup("CHEST_BIND") {
+            self.bind = value
+                .split(',')
+                .map(|address| address.trim().parse())
+                .collect::<Result<_, String>>()
+                .map_err(|e| format!("CHEST_BIND has an invalid address: {}", e))?;
+        }
+        env_flag(
+            &lookup,
+            "CHEST_BEST_EFFORT_BIND",
+            &mut self.best_effort_bind,
+        )?;
+        env_value(&lookup, "CHEST_PORT", &mut self.port)?;
+        if let Some(value) = lookup("CHEST_SOCKET_MODE") {
+            self.socket_mode = u32::from_str_radix(value.trim().trim_start_matches("0o"), 8)
+                .map_err(|_| format!("CHEST_SOCKET_MODE must be octal, got {:?}", value))?;
+        }
+        env_string_value(&lookup, "CHEST_LOG_LEVEL", &mut self.log_level);
+        env_string_value(&lookup, "CHEST_LOG_FILE_LEVEL", &mut self.log_file_level);
+        if let Some(value) = lookup("CHEST_LOG_FILE_ROTATION") {
+            self.log_file_rotation = value
+                .parse()
+                .map_err(|e| format!("CHEST_LOG_FILE_ROTATION {}", e))?;
+        }
+        env_value(
+            &lookup,
+            "CHEST_LOG_FILE_MAX_BYTES",
+            &mut self.log_file_max_bytes,
+        )?;
+        env_value(&lookup, "CHEST_LOG_FILE_KEEP", &mut self.log_file_keep)?;
+        env_flag(&lookup, "CHEST_LOG_FILE_JSON", &mut self.log_file_json)?;
+        env_value(&lookup, "CHEST_TICK_MS", &mut self.tick_ms)?;
+        env_value(&lookup, "CHEST_TICK_SHARDS", &mut self.tick_shards)?;
+        env_value(
+            &lookup,
+            "CHEST_MOVE_INCREMENT_SECONDS",
+            &mut self.move_increment_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_MAX_STORED_MOVES",
+            &mut self.max_stored_moves,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_PRESENCE_WARNING_SECONDS",
+            &mut self.presence_warning_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_ARCHIVE_GRACE_SECONDS",
+            &mut self.archive_grace_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_FINISHED_RETENTION_SECONDS",
+            &mut self.finished_retention_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_STALE_GAME_SECONDS",
+            &mut self.stale_game_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_MAX_ACTIVE_GAMES",
+            &mut self.max_active_games,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_HEARTBEAT_TIMEOUT_SECONDS",
+            &mut self.heartbeat_timeout_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_CHECKPOINT_INTERVAL_SECONDS",
+            &mut self.checkpoint_interval_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_SHUTDOWN_DEADLINE_SECONDS",
+            &mut self.shutdown_deadline_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_BODY_LIMIT_BYTES",
+            &mut self.body_limit_bytes,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_IMPORT_BODY_LIMIT_BYTES",
+            &mut self.import_body_limit_bytes,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_MAX_PLAYER_NAME_LEN",
+            &mut self.max_player_name_len,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_MAX_WAIT_SECONDS",
+            &mut self.max_wait_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_SPECTATOR_CODE_TTL_SECONDS",
+            &mut self.spectator_code_ttl_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_LOBBY_TTL_SECONDS",
+            &mut self.lobby_ttl_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_REQUEST_TIMEOUT_SECONDS",
+            &mut self.request_timeout_seconds,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_COMPRESS_RESPONSES",
+            &mut self.compress_responses,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_WEBHOOK_TIMEOUT_SECONDS",
+            &mut self.webhook_timeout_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_WEBHOOK_MAX_ATTEMPTS",
+            &mut self.webhook_max_attempts,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_ANALYTICS_MAX_BYTES",
+            &mut self.analytics_max_bytes,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_ANALYTICS_KEEP_FILES",
+            &mut self.analytics_keep_files,
+        )?;
+
+        env_flag(
+            &lookup,
+            "CHEST_FIRST_BLOOD_BONUS",
+            &mut self.rules.first_blood_bonus,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_ABANDON_AFTER_SECONDS",
+            &mut self.rules.abandon_after_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_INTRO_REVEAL_SECONDS",
+            &mut self.rules.intro_reveal_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_MIN_MOVE_INTERVAL_MS",
+            &mut self.rules.min_move_interval_millis,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_CHARGE_CAPACITY",
+            &mut self.rules.charge_capacity,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_FREEZE_ON_DRAW_OFFER",
+            &mut self.rules.freeze_on_draw_offer,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_SCOUT_BEACON_SECONDS",
+            &mut self.rules.scout_beacon_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_CAPTURE_PULSE_RADIUS",
+            &mut self.rules.capture_pulse_radius,
+        )?;
+        env_value(&lookup, "CHEST_WALL_COUNT", &mut self.rules.wall_count)?;
+        env_value(
+            &lookup,
+            "CHEST_BOT_MOVE_INTERVAL_MILLIS",
+            &mut self.rules.bot_move_interval_millis,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_REQUIRE_READY",
+            &mut self.rules.require_ready,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_PAWNS_MOVE_BACKWARD",
+            &mut self.rules.pawns_move_backward,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_PAWNS_CAPTURE_FORWARD",
+            &mut self.rules.pawns_capture_forward,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_NO_CAPTURE_DRAW_MOVES",
+            &mut self.rules.no_capture_draw_moves,
+        )?;
+        env_flag(
+            &lookup,
+            "CHEST_PAWN_MOVES_RESET_NO_CAPTURE_DRAW",
+            &mut self.rules.pawn_moves_reset_no_capture_draw,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_OPENING_GRACE_MOVES",
+            &mut self.rules.opening_grace_moves,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_REVEAL_POWERUP_COST",
+            &mut self.rules.reveal_powerup_cost,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_REVEAL_POWERUP_SECONDS",
+            &mut self.rules.reveal_powerup_seconds,
+        )?;
+        env_value(
+            &lookup,
+            "CHEST_PLACEMENT_SECONDS",
+            &mut self.rules.placement_seconds,
+        )?;
+        env_flag(&lookup, "CHEST_THREE_CHECK", &mut self.rules.three_check)?;
+        env_flag(&lookup, "CHEST_ATOMIC", &mut self.rules.atomic)?;
+        env_value(&lookup, "CHEST_BOARD_SIZE", &mut self.rules.board_size)?;
+        let mut king_of_the_hill = false;
+        env_flag(&lookup, "CHEST_KING_OF_THE_HILL", &mut king_of_the_hill)?;
+        if king_of_the_hill {
+            self.rules.hill_squares = centre_hill(self.rules.board_size).to_vec();
+        }
+        env_value(
+            &lookup,
+            "CHEST_HILL_HOLD_SECONDS",
+            &mut self.rules.hill_hold_seconds,
+        )?;
+        if lookup("CHEST_WALL_SEED").is_some() {
+            let mut seed = 0;
+            env_value(&lookup, "CHEST_WALL_SEED", &mut seed)?;
+            self.rules.wall_seed = Some(seed);
+        }
+        env_flag(
+            &lookup,
+            "CHEST_RANDOM_BACK_RANK",
+            &mut self.rules.random_back_rank,
+        )?;
+        if lookup("CHEST_BACK_RANK_SEED").is_some() {
+            let mut seed = 0;
+            env_value(&lookup, "CHEST_BACK_RANK_SEED", &mut seed)?;
+            self.rules.back_rank_seed = Some(seed);
+        }
+        if let Some(value) = lookup("CHEST_KING_RULE") {
+            self.rules.king_rule = match value.as_str() {
+                "capture_king" => KingRule::CaptureKing,
+                "checkmate" => KingRule::Checkmate,
+                _ => {
+                    return Err(format!(
+                        "CHEST_KING_RULE must be capture_king or checkmate, got {:?}",
+                        value
+                    ));
+                }
+            };
+        }
+        if let Some(value) = lookup("CHEST_STRICTNESS") {
+            self.rules.strictness = match value.as_str() {
+                "lenient" => Strictness::Lenient,
+                "standard" => Strictness::Standard,
+                "strict" => Strictness::Strict,
+                _ => {
+                    return Err(format!(
+                        "CHEST_STRICTNESS must be lenient, standard or strict, got {:?}",
+                        value
+                    ));
+                }
+            };
+        }
+
+        env_string(&lookup, "CHEST_ADMIN_TOKEN", &mut self.admin_token);
+        env_string(&lookup, "CHEST_DATABASE_URL", &mut self.database_url);
+        env_string(&lookup, "CHEST_REDIS_URL", &mut self.redis_url);
+        env_string(&lookup, "CHEST_WEBHOOK_URL", &mut self.webhook_url);
+        if let Some(path) = lookup("CHEST_SNAPSHOT_PATH") {
+            self.snapshot_path = Some(PathBuf::from(path));
+        }
+        if let Some(path) = lookup("CHEST_ARCHIVE_DIR") {
+            self.archive_dir = Some(PathBuf::from(path));
+        }
+        if let Some(path) = lookup("CHEST_ANALYTICS_PATH") {
+            self.analytics_path = Some(PathBuf::from(path));
+        }
+        if let Some(path) = lookup("CHEST_LOG_FILE") {
+            self.log_file = Some(PathBuf::from(path));
+        }
+
+        Ok(())
+    }
+
+    /// Reject settings the server can't run with, naming the offending field
+    pub fn validate(&self) -> Result<(), String> {
+        let at_least_one = [
+            ("port", self.port as u64),
+            ("tick_ms", self.tick_ms),
+            ("tick_shards", self.tick_shards),
+            ("move_increment_seconds", self.move_increment_seconds),
+            ("max_stored_moves", self.max_stored_moves),
+            (
+                "checkpoint_interval_seconds",
+                self.checkpoint_interval_seconds,
+            ),
+            ("body_limit_bytes", self.body_limit_bytes as u64),
+            (
+                "import_body_limit_bytes",
+                self.import_body_limit_bytes as u64,
+            ),
+            ("max_player_name_len", self.max_player_name_len as u64),
+            ("max_active_games", self.max_active_games as u64),
+            ("lobby_ttl_seconds", self.lobby_ttl_seconds),
+            ("heartbeat_timeout_seconds", self.heartbeat_timeout_seconds),
+            ("request_timeout_seconds", self.request_timeout_seconds),
+            ("webhook_timeout_seconds", self.webhook_timeout_seconds),
+            ("webhook_max_attempts", self.webhook_max_attempts as u64),
+            ("analytics_max_bytes", self.analytics_max_bytes),
+            ("log_file_max_bytes", self.log_file_max_bytes),
+            ("log_file_keep", self.log_file_keep as u64),
+            (
+                "rules.abandon_after_seconds",
+                self.rules.abandon_after_seconds,
+            ),
+        ];
+        for (field, value) in at_least_one {
+            if value == 0 {
+                return Err(format!("{} must be at least 1", field));
+            }
+        }
+
+        for (field, level) in [
+            ("log_level", &self.log_level),
+            ("log_file_level", &self.log_file_level),
+        ] {
+            if level.parse::<LevelFilter>().is_err() {
+                return Err(format!(
+                    "{} must be one of error, warn, info, debug or trace, got {:?}",
+                    field, level
+                ));
+            }
+        }
+        if self.bind.is_empty() {
+            return Err("bind must list at least one address".to_string());
+        }
+        if self.socket_mode > 0o777 {
+            return Err(format!(
+                "socket_mode must be at most 0o777, got {:#o}",
+                self.socket_mode
+            ));
+        }
+        // Long polls must be able to run their course
+        if self.request_timeout_seconds <= self.max_wait_seconds {
+            return Err(format!(
+                "request_timeout_seconds ({}) must be longer than max_wait_seconds ({})",
+                self.request_timeout_seconds, self.max_wait_seconds
+            ));
+        }
+        let size = self.rules.board_size;
+        if ExtendedBoard::check_dims((size, size)).is_err() {
+            return Err(format!(
+                "rules.board_size must be {} to {}, got {}",
+                MIN_BOARD_SIZE, MAX_BOARD_SIZE, size
+            ));
+        }
+        // Walls only go on the empty middle rows, four of them on the usual board
+        if self.rules.wall_count > 32 {
+            return Err(format!(
+                "rules.wall_count must be at most 32, got {}",
+                self.rules.wall_count
+            ));
+        }
+        if let Some(&(row, col)) = self
+            .rules
+            .hill_squares
+            .iter()
+            .find(|(row, col)| *row >= size || *col >= size)
+        {
+            return Err(format!(
+                "rules.hill_squares must be on the board, got ({}, {})",
+                row, col
+            ));
+        }
+        // A blast can take a king, which checkmate games promise never happens
+        if self.rules.atomic && self.rules.king_rule == KingRule::Checkmate {
+            return Err(
+                "rules.atomic can't be combined with rules.king_rule = checkmate".to_string(),
+            );
+        }
+        if let Some(army) = &self.rules.army {
+            army.validate()
+                .map_err(|e| format!("rules.army is not a valid army: {}", e))?;
+        }
+        if self.tick_ms > 60_000 {
+            return Err("tick_ms must be at most 60000".to_string());
+        }
+        if self.tick_shards > self.tick_ms {
+            return Err("tick_shards must be at most tick_ms".to_string());
+        }
+        if self.move_increment_seconds * 1000 < self.tick_ms {
+            return Err("move_increment_seconds must be at least one tick long".to_string());
+        }
+        if self.heartbeat_timeout_seconds * 1000 <= self.tick_ms {
+            return Err("heartbeat_timeout_seconds must be longer than one tick".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn log_level(&self) -> LevelFilter {
+        self.log_level.parse().unwrap_or(LevelFilter::INFO)
+    }
+
+    pub fn log_file_level(&self) -> LevelFilter {
+        self.log_file_level.parse().unwrap_or(LevelFilter::INFO)
+    }
+
+    pub fn tick(&self) -> Duration {
+        Duration::from_millis(self.tick_ms)
+    }
+
+    /// Whole ticks in `seconds`, never fewer than one
+    pub fn ticks_in(&self, seconds: u64) -> u64 {
+        (seconds * 1000 / self.tick_ms).max(1)
+    }
+
+    /// Rules for new games, with the server-wide economy settings applied
+    pub fn game_rules(&self) -> GameRules {
+        GameRules {
+            max_stored_moves: self.max_stored_moves,
+            move_increment_ticks: self.ticks_in(self.move_increment_seconds),
+            ..self.rules.clone()
+        }
+    }
+
+    /// A copy safe to print, with secrets and credentials hidden
+    pub fn redacted(&self) -> Self {
+        let hide = |value: &Option<String>| value.as_ref().map(|_| REDACTED.to_string());
+        Self {
+            admin_token: hide(&self.admin_token),
+            database_url: hide(&self.database_url),
+            redis_url: hide(&self.redis_url),
+            webhook_url: hide(&self.webhook_url),
+            ..self.clone()
+        }
+    }
+}
+
+/// Where the server listens: an IP address combined with `Config::port`, a full
+/// socket address, or a Unix domain socket written as `unix:/run/chest/chest.sock`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BindAddress {
+    Ip(IpAddr),
+    Socket(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl BindAddress {
+    /// The TCP address to bind, or `None` for a Unix socket
+    pub fn socket_addr(&self, default_port: u16) -> Option<SocketAddr> {
+        match self {
+            BindAddress::Ip(ip) => Some(SocketAddr::new(*ip, default_port)),
+            BindAddress::Socket(address) => Some(*address),
+            BindAddress::Unix(_) => None,
+        }
+    }
+}
+
+// Accept either a single bind address or a list of them
+fn one_or_many<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<BindAddress>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(BindAddress),
+        Many(Vec<BindAddress>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(address) => vec![address],
+        OneOrMany::Many(addresses) => addresses,
+    })
+}
+
+impl FromStr for BindAddress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: needs a socket path".to_string());
+            }
+            return Ok(BindAddress::Unix(PathBuf::from(path)));
+        }
+        if let Ok(address) = value.parse() {
+            return Ok(BindAddress::Socket(address));
+        }
+        value.parse().map(BindAddress::Ip).map_err(|_| {
+            format!(
+                "{:?} is not an IP address, socket address or unix:<path>",
+                value
+            )
+        })
+    }
+}
+
+impl TryFrom<String> for BindAddress {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<BindAddress> for String {
+    fn from(address: BindAddress) -> Self {
+        address.to_string()
+    }
+}
+
+impl std::fmt::Display for BindAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindAddress::Ip(ip) => write!(f, "{}", ip),
+            BindAddress::Socket(address) => write!(f, "{}", address),
+            BindAddress::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+fn env_value<T: FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    target: &mut T,
+) -> Result<(), String> {
+    if let Some(value) = lookup(name) {
+        *target = value
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} has an invalid value {:?}", name, value))?;
+    }
+    Ok(())
+}
+
+// Booleans accept "1"/"0" as well as "true"/"false"
+fn env_flag(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    target: &mut bool,
+) -> Result<(), String> {
+    if let Some(value) = lookup(name) {
+        *target = match value.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" => true,
+            "0" | "false" => false,
+            _ => return Err(format!("{} must be true or false, got {:?}", name, value)),
+        };
+    }
+    Ok(())
+}
+
+fn env_string_value(lookup: &impl Fn(&str) -> Option<String>, name: &str, target: &mut String) {
+    if let Some(value) = lookup(name) {
+        *target = value;
+    }
+}
+
+fn env_string(lookup: &impl Fn(&str) -> Option<String>, name: &str, target: &mut Option<String>) {
+    if let Some(value) = lookup(name) {
+        *target = Some(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_cli::Cli;
+    use clap::Parser;
+    use std::collections::HashMap;
+
+    // An environment holding just `vars`
+    fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    // A config file in the temp directory, removed when dropped
+    struct ConfigFile(PathBuf);
+
+    impl ConfigFile {
+        fn new(contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("chest-{}.toml", uuid::Uuid::new_v4()));
+            std::fs::write(&path, contents).expect("temp dir is writable");
+            Self(path)
+        }
+    }
+
+    impl Drop for ConfigFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn file_beats_defaults_env_beats_file_and_flags_beat_env() {
+        let file = ConfigFile::new("port = 4000\ntick_ms = 500\nmax_stored_moves = 7\n");
+        let mut config = Config::from_file(&file.0).unwrap();
+        assert_eq!(config.port, 4000);
+        assert_eq!(config.tick_ms, 500);
+        assert_eq!(config.max_wait_seconds, Config::default().max_wait_seconds);
+
+        config
+            .apply_env(env(&[
+                ("CHEST_PORT", "5000"),
+                ("CHEST_TICK_MS", "250"),
+                ("CHEST_LOBBY_TTL_SECONDS", "600"),
+            ]))
+            .unwrap();
+        assert_eq!(config.port, 5000);
+        assert_eq!(config.tick_ms, 250);
+        assert_eq!(config.lobby_ttl_seconds, 600);
+        assert_eq!(config.max_stored_moves, 7);
+
+        let cli = Cli::try_parse_from(["chest", "--port", "6000"]).unwrap();
+        cli.apply(&mut config);
+        assert_eq!(config.port, 6000);
+        assert_eq!(config.tick_ms, 250);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn unset_variables_leave_settings_alone() {
+        let mut config = Config::default();
+        config.apply_env(env(&[])).unwrap();
+        assert_eq!(config.port, Config::default().port);
+        assert_eq!(config.tick_ms, Config::default().tick_ms);
+    }
+
+    #[test]
+    fn the_hill_sits_in_the_middle_of_the_configured_board() {
+        let mut config = Config::default();
+        config
+            .apply_env(env(&[
+                ("CHEST_BOARD_SIZE", "10"),
+                ("CHEST_KING_OF_THE_HILL", "true"),
+            ]))
+            .unwrap();
+        assert_eq!(config.rules.board_size, 10);
+        assert_eq!(config.rules.hill_squares, [(4, 4), (4, 5), (5, 4), (5, 5)]);
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn invalid_environment_values_name_the_variable() {
+        for (name, value) in [
+            ("CHEST_TICK_MS", "fast"),
+            ("CHEST_PORT", "70000"),
+            ("CHEST_COMPRESS_RESPONSES", "maybe"),
+            ("CHEST_SOCKET_MODE", "rw-rw----"),
+        ] {
+            let error = Config::default()
+                .apply_env(env(&[(name, value)]))
+                .unwrap_err();
+            assert!(error.contains(name), "{:?} doesn't name {}", error, name);
+        }
+    }
+
+    // A setting's name, and how to break it
+    type BrokenSetting = (&'static str, fn(&mut Config));
+
+    #[test]
+    fn invalid_settings_name_the_field() {
+        let cases: [BrokenSetting; 9] = [
+            ("tick_ms", |config| config.tick_ms = 0),
+            ("lobby_ttl_seconds", |config| config.lobby_ttl_seconds = 0),
+            ("max_stored_moves", |config| config.max_stored_moves = 0),
+            ("log_level", |config| config.log_level = "loud".to_string()),
+            ("request_timeout_seconds", |config| {
+                config.request_timeout_seconds = config.max_wait_seconds
+            }),
+            ("rules.wall_count", |config| config.rules.wall_count = 40),
+            ("rules.board_size", |config| config.rules.board_size = 13),
+            ("rules.hill_squares", |config| {
+                config.rules.board_size = 8;
+                config.rules.hill_squares = vec![(9, 9)];
+            }),
+            ("rules.atomic", |config| {
+                config.rules.atomic = true;
+                config.rules.king_rule = KingRule::Checkmate;
+            }),
+        ];
+        for (field, break_it) in cases {
+            let mut config = Config::default();
+            break_it(&mut config);
+            let error = config.validate().unwrap_err();
+            assert!(error.contains(field), "{:?} doesn't name {}", error, field);
+        }
+    }
+
+    #[test]
+    fn unknown_fields_in_the_file_are_rejected() {
+        let file = ConfigFile::new("prot = 4000\n");
+        let error = Config::from_file(&file.0).unwrap_err();
+        assert!(error.contains("prot"), "{:?}", error);
+    }
+
+    #[test]
+    fn printing_hides_secrets() {
+        let config = Config {
+            admin_token: Some("hunter2".to_string()),
+            database_url: Some("sqlite://games.db".to_string()),
+            ..Config::default()
+        };
+        let printed = toml::to_string(&config.redacted()).unwrap();
+        assert!(!printed.contains("hunter2"));
+        assert!(!printed.contains("games.db"));
+        assert!(printed.contains(REDACTED));
+    }
+}
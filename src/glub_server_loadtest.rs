@@ -0,0 +1,337 @@
+use crate::glub_server::{ExtendedBoard, ExtendedSlot};
+use crate::glub_server_bench::splitmix64;
+use crate::glub_server_storage::PlayerColor;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Pause between one player's polls, about what a client refreshing its board
+/// would do
+const POLL_EVERY: Duration = Duration::from_millis(250);
+
+/// Give up on a request after this long; it counts as an error
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One load test run
+pub struct LoadTest {
+    /// Server to test, e.g. `http://127.0.0.1:8080`
+    pub base_url: String,
+    pub players: usize,
+    pub duration: Duration,
+    /// Fail the run if any endpoint's p99 latency is above this
+    pub max_p99: Option<Duration>,
+}
+
+/// Latencies and failures for one endpoint
+#[derive(Default)]
+struct EndpointSamples {
+    latencies: Vec<Duration>,
+    /// Requests that failed to complete or got a 4xx or 5xx status
+    errors: u64,
+}
+
+#[derive(Clone, Default)]
+struct Samples(Arc<Mutex<BTreeMap<&'static str, EndpointSamples>>>);
+
+impl Samples {
+    fn record(&self, endpoint: &'static str, elapsed: Duration, ok: bool) {
+        if let Ok(mut samples) = self.0.lock() {
+            let samples = samples.entry(endpoint).or_default();
+            samples.latencies.push(elapsed);
+            if !ok {
+                samples.errors += 1;
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Joined {
+    player_id: Uuid,
+}
+
+#[derive(Deserialize)]
+struct CurrentGameResponse {
+    game: Option<CurrentGame>,
+}
+
+#[derive(Deserialize)]
+struct CurrentGame {
+    game_id: Uuid,
+    your_color: PlayerColor,
+    remaining_moves: u64,
+}
+
+// The parts of a player's board the simulated clients use
+#[derive(Deserialize)]
+struct BoardView {
+    slots: Vec<Vec<Option<ExtendedSlot>>>,
+    dims: (usize, usize),
+    #[serde(default)]
+    walls: Vec<(usize, usize)>,
+}
+
+/// Run `players` simulated clients against the server until `duration` is up,
+/// then print p50/p95/p99 latency and error counts per endpoint. Returns false
+/// when an endpoint is over the p99 ceiling.
+pub async fn run(test: LoadTest) -> bool {
+    let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Cannot create HTTP client: {}", e);
+            return false;
+        }
+    };
+    let samples = Samples::default();
+    let deadline = Instant::now() + test.duration;
+
+    println!(
+        "Running {} players against {} for {}",
+        test.players,
+        test.base_url,
+        humantime::format_duration(test.duration)
+    );
+    let mut players = tokio::task::JoinSet::new();
+    for index in 0..test.players {
+        let player = Player {
+            client: client.clone(),
+            base_url: test.base_url.clone(),
+            name: format!("load{:05}", index),
+            samples: samples.clone(),
+            rng: splitmix64(index as u64),
+        };
+        players.spawn(player.play_until(deadline));
+    }
+    while players.join_next().await.is_some() {}
+
+    report(&samples, test.max_p99)
+}
+
+// One simulated client: queue, poll the game and board, and move whenever it
+// has a point to spend
+struct Player {
+    client: reqwest::Client,
+    base_url: String,
+    name: String,
+    samples: Samples,
+    rng: u64,
+}
+
+impl Player {
+    async fn play_until(mut self, deadline: Instant) {
+        let mut player_id = None;
+        // Set once the player has been matched; losing the game after that
+        // means it finished
+        let mut matched = false;
+
+        while Instant::now() < deadline {
+            let Some(id) = player_id else {
+                let body = json!({ "player_name": self.name });
+                player_id = self
+                    .request::<Joined>("POST /join_queue", "/join_queue", Some(body))
+                    .await
+                    .map(|joined| joined.player_id);
+                tokio::time::sleep(POLL_EVERY).await;
+                continue;
+            };
+
+            let current = self
+                .request::<CurrentGameResponse>(
+                    "GET /players/{player_id}/current_game",
+                    &format!("/players/{}/current_game", id),
+                    None,
+                )
+                .await;
+            match current {
+                Some(CurrentGameResponse { game: Some(game) }) => {
+                    matched = true;
+                    self.play(id, game).await;
+                }
+                // The game is over, so queue again
+                Some(CurrentGameResponse { game: None }) if matched => {
+                    player_id = None;
+                    matched = false;
+                }
+                Some(CurrentGameResponse { game: None }) => {
+                    self.request::<serde_json::Value>("GET /queue/status", "/queue/status", None)
+                        .await;
+                }
+                None => {}
+            }
+            tokio::time::sleep(POLL_EVERY).await;
+        }
+    }
+
+    // Fetch the board and make a move if there's a point for one. The move is
+    // picked from the pieces in sight with the server's own rules, so it may
+    // still be turned down by a piece hidden in the fog.
+    async fn play(&mut self, player_id: Uuid, game: CurrentGame) {
+        let Some(view) = self
+            .request::<BoardView>(
+                "GET /game/{game_id}/board/{player_id}",
+                &format!("/game/{}/board/{}", game.game_id, player_id),
+                None,
+            )
+            .await
+        else {
+            return;
+        };
+        if game.remaining_moves == 0 {
+            return;
+        }
+
+        if ExtendedBoard::check_dims(view.dims).is_err() {
+            return;
+        }
+        let mut board = ExtendedBoard::with_dims(view.dims);
+        for (row, cols) in view.slots.into_iter().enumerate() {
+            for (col, slot) in cols.into_iter().enumerate() {
+                board.set_slot((row, col), slot);
+            }
+        }
+        for wall in view.walls {
+            board.add_wall(wall);
+        }
+        let moves = board.all_legal_moves(&game.your_color, false);
+        if moves.is_empty() {
+            return;
+        }
+        self.rng = splitmix64(self.rng);
+        let (from, to) = moves[(self.rng % moves.len() as u64) as usize];
+
+        let body = json!({ "player_id": player_id, "from": from, "to": to });
+        self.request::<serde_json::Value>(
+            "POST /game/{game_id}/move",
+            &format!("/game/{}/move", game.game_id),
+            Some(body),
+        )
+        .await;
+    }
+
+    // Send one request and time it. The body is parsed for the caller when the
+    // status is a success.
+    async fn request<T: serde::de::DeserializeOwned>(
+        &self,
+        endpoint: &'static str,
+        path: &str,
+        body: Option<serde_json::Value>,
+    ) -> Option<T> {
+        let url = format!("{}{}", self.base_url, path);
+        let request = match body {
+            Some(body) => self.client.post(url).json(&body),
+            None => self.client.get(url),
+        };
+
+        let started = Instant::now();
+        let response = request.send().await;
+        let elapsed = started.elapsed();
+
+        let response = match response {
+            Ok(response) if response.status().is_success() => response,
+            _ => {
+                self.samples.record(endpoint, elapsed, false);
+                return None;
+            }
+        };
+        self.samples.record(endpoint, elapsed, true);
+        response.json().await.ok()
+    }
+}
+
+// Print one line per endpoint and check the p99 ceiling
+fn report(samples: &Samples, max_p99: Option<Duration>) -> bool {
+    let Ok(mut samples) = samples.0.lock() else {
+        return false;
+    };
+
+    println!(
+        "{:<40} {:>9} {:>7} {:>9} {:>9} {:>9}",
+        "endpoint", "requests", "errors", "p50 ms", "p95 ms", "p99 ms"
+    );
+    let mut passed = true;
+    for (endpoint, endpoint_samples) in samples.iter_mut() {
+        endpoint_samples.latencies.sort_unstable();
+        let latencies = &endpoint_samples.latencies;
+        let p99 = percentile(latencies, 99);
+        println!(
+            "{:<40} {:>9} {:>7} {:>9.1} {:>9.1} {:>9.1}",
+            endpoint,
+            latencies.len(),
+            endpoint_samples.errors,
+            millis(percentile(latencies, 50)),
+            millis(percentile(latencies, 95)),
+            millis(p99),
+        );
+
+        if let Some(max_p99) = max_p99
+            && p99 > max_p99
+        {
+            println!(
+                "{} p99 of {:.1}ms is over the {:.1}ms ceiling",
+                endpoint,
+                millis(p99),
+                millis(max_p99)
+            );
+            passed = false;
+        }
+    }
+
+    passed
+}
+
+// Nearest-rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_config::Config;
+
+    #[test]
+    fn the_p99_ceiling_fails_only_the_slow_endpoints() {
+        let samples = Samples::default();
+        for millis in 1..=100 {
+            samples.record("board", Duration::from_millis(millis), true);
+        }
+        samples.record("move", Duration::from_millis(5), false);
+
+        assert_eq!(
+            percentile(&samples.0.lock().unwrap()["board"].latencies, 99),
+            Duration::from_millis(99)
+        );
+        assert!(report(&samples, None));
+        assert!(report(&samples, Some(Duration::from_millis(99))));
+        assert!(!report(&samples, Some(Duration::from_millis(98))));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn a_small_run_against_an_in_process_server_passes() {
+        let config = Config {
+            tick_ms: 100,
+            ..Config::default()
+        };
+        let (base_url, _stop) = crate::serve_in_process(Arc::new(config)).await.unwrap();
+
+        let passed = run(LoadTest {
+            base_url,
+            players: 4,
+            duration: Duration::from_secs(2),
+            max_p99: Some(Duration::from_secs(1)),
+        })
+        .await;
+        assert!(passed);
+    }
+}
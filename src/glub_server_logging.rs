@@ -0,0 +1,235 @@
+use crate::glub_server_config::{Config, LogRotation};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::prelude::*;
+
+/// Install the stdout logger and, when a log file is configured, a file logger
+/// with its own level and format. Log lines reach the file through a background
+/// writer; the returned guard flushes it when dropped, so hold it until exit.
+pub fn init_logging(config: &Config) -> Result<Option<WorkerGuard>, String> {
+    let (subscriber, guard) = logging_subscriber(config)?;
+    subscriber.init();
+    Ok(guard)
+}
+
+// The loggers `config` asks for, not yet installed
+fn logging_subscriber(
+    config: &Config,
+) -> Result<(impl tracing::Subscriber + Send + Sync, Option<WorkerGuard>), String> {
+    let stdout = tracing_subscriber::fmt::layer().with_filter(config.log_level());
+
+    let Some(path) = &config.log_file else {
+        return Ok((tracing_subscriber::registry().with(None).with(stdout), None));
+    };
+
+    let (writer, guard) = match config.log_file_rotation {
+        LogRotation::Size => tracing_appender::non_blocking(SizeRotatingFile::new(
+            path.clone(),
+            config.log_file_max_bytes,
+            config.log_file_keep,
+        )),
+        rotation => {
+            tracing_appender::non_blocking(rolling_appender(path, rotation, config.log_file_keep)?)
+        }
+    };
+
+    let file = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(false);
+    let file = if config.log_file_json {
+        file.json().with_filter(config.log_file_level()).boxed()
+    } else {
+        file.with_filter(config.log_file_level()).boxed()
+    };
+
+    let subscriber = tracing_subscriber::registry().with(Some(file)).with(stdout);
+    Ok((subscriber, Some(guard)))
+}
+
+// Time-based rotation names each file after `path` plus the period it covers
+fn rolling_appender(
+    path: &Path,
+    rotation: LogRotation,
+    keep: usize,
+) -> Result<RollingFileAppender, String> {
+    let directory = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let prefix = path
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file path", path.display()))?;
+    let rotation = match rotation {
+        LogRotation::Daily => Rotation::DAILY,
+        LogRotation::Hourly => Rotation::HOURLY,
+        LogRotation::Size | LogRotation::Never => Rotation::NEVER,
+    };
+
+    RollingFileAppender::builder()
+        .rotation(rotation)
+        .filename_prefix(prefix.to_string_lossy())
+        .max_log_files(keep + 1)
+        .build(directory)
+        .map_err(|e| e.to_string())
+}
+
+/// A file that is moved aside to `path.1` once it would grow past `max_bytes`,
+/// keeping `keep` old files
+pub struct SizeRotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl SizeRotatingFile {
+    pub fn new(path: PathBuf, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            path,
+            max_bytes,
+            keep,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn open(&mut self) -> std::io::Result<&mut File> {
+        if self.file.is_none() {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        Ok(self.file.as_mut().expect("log file was just opened"))
+    }
+}
+
+impl Write for SizeRotatingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.open()?;
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_bytes {
+            self.file = None;
+            rotate_numbered(&self.path, self.keep)?;
+            self.open()?;
+        }
+
+        let file = self.open()?;
+        file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Shift `path.N-1` to `path.N` and so on down to `path` to `path.1`, dropping
+/// the oldest. With `keep` at zero the file is simply removed.
+pub fn rotate_numbered(path: &Path, keep: usize) -> std::io::Result<()> {
+    let numbered = |n: usize| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    };
+
+    if keep == 0 {
+        return std::fs::remove_file(path);
+    }
+    for n in (1..keep).rev() {
+        let from = numbered(n);
+        if from.exists() {
+            std::fs::rename(&from, numbered(n + 1))?;
+        }
+    }
+    std::fs::rename(path, numbered(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file_config(dir: &Path, rotation: LogRotation, json: bool) -> Config {
+        Config {
+            log_level: "off".to_string(),
+            log_file_level: "debug".to_string(),
+            log_file: Some(dir.join("chest.log")),
+            log_file_rotation: rotation,
+            log_file_json: json,
+            ..Config::default()
+        }
+    }
+
+    // Log a few events through the loggers `config` asks for, then flush them
+    fn log_through(config: &Config) {
+        let (subscriber, guard) = logging_subscriber(config).unwrap();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(game_id = 7, "game started");
+            tracing::debug!("move points granted");
+            tracing::trace!("too detailed for the file");
+        });
+        drop(guard);
+    }
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn events_reach_the_log_file_at_its_own_level() {
+        let dir = temp_dir();
+        log_through(&file_config(&dir, LogRotation::Size, false));
+
+        let written = std::fs::read_to_string(dir.join("chest.log")).unwrap();
+        assert!(written.contains("game started"), "{}", written);
+        assert!(written.contains("game_id=7"), "{}", written);
+        assert!(written.contains("move points granted"), "{}", written);
+        assert!(!written.contains("too detailed"), "{}", written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_json_file_format_writes_one_object_per_event() {
+        let dir = temp_dir();
+        log_through(&file_config(&dir, LogRotation::Never, true));
+
+        let written = std::fs::read_to_string(dir.join("chest.log")).unwrap();
+        let events: Vec<serde_json::Value> = written
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2, "{}", written);
+        assert_eq!(events[0]["fields"]["message"], "game started");
+        assert_eq!(events[0]["fields"]["game_id"], 7);
+        assert_eq!(events[0]["level"], "INFO");
+        assert_eq!(events[1]["fields"]["message"], "move points granted");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn daily_files_are_named_after_the_log_path() {
+        let dir = temp_dir();
+        log_through(&file_config(&dir, LogRotation::Daily, false));
+
+        let files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(files.len(), 1, "{:?}", files);
+        assert!(files[0].starts_with("chest.log."), "{:?}", files);
+        let written = std::fs::read_to_string(dir.join(&files[0])).unwrap();
+        assert!(written.contains("game started"), "{}", written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
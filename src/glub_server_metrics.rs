@@ -0,0 +1,132 @@
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds of the latency histogram buckets, in milliseconds. Slower requests
+/// land in a final open-ended bucket.
+pub const LATENCY_BUCKETS_MILLIS: [u64; 9] = [1, 5, 10, 25, 50, 100, 250, 1000, 5000];
+
+/// Request counts, status classes and latencies per route, shared by every request
+#[derive(Clone, Default)]
+pub struct RouteMetrics {
+    routes: Arc<Mutex<BTreeMap<String, RouteStats>>>,
+}
+
+/// Numbers for one route, keyed by method and route pattern such as
+/// `POST /game/{game_id}/move`
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct RouteStats {
+    pub requests: u64,
+    /// Responses with a 4xx or 5xx status
+    pub errors: u64,
+    pub error_rate: f64,
+    /// Responses per status class, e.g. `"2xx"`
+    pub status_classes: BTreeMap<String, u64>,
+    pub latency: LatencyHistogram,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LatencyHistogram {
+    /// Requests per bucket; buckets are not cumulative
+    pub buckets: Vec<LatencyBucket>,
+    pub total_millis: f64,
+    pub max_millis: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LatencyBucket {
+    /// `None` for the bucket holding everything slower than the last bound
+    pub le_millis: Option<u64>,
+    pub count: u64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        let buckets = LATENCY_BUCKETS_MILLIS
+            .iter()
+            .map(|&bound| Some(bound))
+            .chain([None])
+            .map(|le_millis| LatencyBucket {
+                le_millis,
+                count: 0,
+            })
+            .collect();
+        Self {
+            buckets,
+            total_millis: 0.0,
+            max_millis: 0.0,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub fn observe(&mut self, elapsed: Duration) {
+        let millis = elapsed.as_secs_f64() * 1000.0;
+        self.total_millis += millis;
+        self.max_millis = self.max_millis.max(millis);
+        if let Some(bucket) = self
+            .buckets
+            .iter_mut()
+            .find(|bucket| bucket.le_millis.is_none_or(|bound| millis <= bound as f64))
+        {
+            bucket.count += 1;
+        }
+    }
+}
+
+impl RouteMetrics {
+    pub fn record(&self, route: String, status: StatusCode, elapsed: Duration) {
+        let Ok(mut routes) = self.routes.lock() else {
+            return;
+        };
+        let stats = routes.entry(route).or_default();
+
+        stats.requests += 1;
+        if status.is_client_error() || status.is_server_error() {
+            stats.errors += 1;
+        }
+        stats.error_rate = stats.errors as f64 / stats.requests as f64;
+        *stats
+            .status_classes
+            .entry(format!("{}xx", status.as_u16() / 100))
+            .or_default() += 1;
+        stats.latency.observe(elapsed);
+    }
+
+    /// A copy of the current numbers, sorted by route
+    pub fn snapshot(&self) -> BTreeMap<String, RouteStats> {
+        self.routes
+            .lock()
+            .map(|routes| routes.clone())
+            .unwrap_or_default()
+    }
+}
+
+/// Middleware recording every matched request. Must be added with `route_layer`
+/// so the route pattern is known; unmatched URLs are not recorded, which keeps
+/// the number of keys bounded.
+pub async fn track_metrics(
+    State(metrics): State<RouteMetrics>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| format!("{} {}", request.method(), path.as_str()));
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    if let Some(route) = route {
+        metrics.record(route, response.status(), started.elapsed());
+    }
+    response
+}
@@ -0,0 +1,591 @@
+use crate::glub_server_seasons::SeasonArchive;
+use crate::glub_server_storage::{GameArchive, GameState, PlayerStats};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Archive files larger than this are treated as corrupt rather than read
+const MAX_ARCHIVE_FILE_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Changes `GameStorage` hands off to be written outside the storage lock
+#[derive(Debug)]
+pub enum PersistEvent {
+    AccountUpdated {
+        player_name: String,
+        stats: PlayerStats,
+    },
+    GameFinished {
+        archive: Box<GameArchive>,
+    },
+    /// Full set of unfinished games; anything not listed is no longer active
+    Checkpoint {
+        games: Vec<GameState>,
+    },
+    /// A season rollover: the closed season, the one now running and every
+    /// account after its reset, written together so a crash can't half-apply it
+    SeasonClosed {
+        archive: Box<SeasonArchive>,
+        current_season: String,
+        accounts: HashMap<String, PlayerStats>,
+    },
+}
+
+pub type PersistSender = mpsc::UnboundedSender<PersistEvent>;
+
+/// What a persistence backend hands back at startup
+#[derive(Debug, Default)]
+pub struct RestoredState {
+    pub accounts: HashMap<String, PlayerStats>,
+    pub active_games: Vec<GameState>,
+    /// Closed seasons, oldest first
+    pub seasons: Vec<SeasonArchive>,
+    /// `None` until the first rollover
+    pub current_season: Option<String>,
+}
+
+/// Everything written to the shutdown snapshot file
+#[derive(Serialize, Debug)]
+pub struct StorageSnapshot {
+    pub accounts: HashMap<String, PlayerStats>,
+    pub games: Vec<GameState>,
+    pub seasons: Vec<SeasonArchive>,
+    pub current_season: String,
+}
+
+// Games are read one by one so a single bad entry doesn't sink the whole file
+#[derive(Deserialize)]
+struct RawSnapshot {
+    #[serde(default)]
+    accounts: HashMap<String, PlayerStats>,
+    #[serde(default)]
+    games: Vec<serde_json::Value>,
+    #[serde(default)]
+    seasons: Vec<SeasonArchive>,
+    #[serde(default)]
+    current_season: Option<String>,
+}
+
+/// Write the snapshot next to `path` first and rename it into place, so a
+/// crash mid-write never leaves a truncated file behind
+pub fn write_snapshot(path: &Path, snapshot: &StorageSnapshot) -> Result<(), String> {
+    let json = serde_json::to_vec(snapshot).map_err(|e| e.to_string())?;
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, json).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+}
+
+/// Read a snapshot file, skipping games that no longer deserialize.
+/// A missing file restores nothing.
+pub fn read_snapshot(path: &Path) -> Result<RestoredState, String> {
+    let json = match std::fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(RestoredState::default());
+        }
+        Err(e) => return Err(e.to_string()),
+    };
+    let raw: RawSnapshot = serde_json::from_slice(&json).map_err(|e| e.to_string())?;
+
+    let mut active_games = Vec::with_capacity(raw.games.len());
+    for game in raw.games {
+        match serde_json::from_value(game) {
+            Ok(game_state) => active_games.push(game_state),
+            Err(e) => warn!("Discarding unreadable saved game: {}", e),
+        }
+    }
+
+    Ok(RestoredState {
+        accounts: raw.accounts,
+        active_games,
+        seasons: raw.seasons,
+        current_season: raw.current_season,
+    })
+}
+
+/// Finished games on disk, one JSON file per game named after its id.
+/// Lookups open a single file and never scan the directory.
+///
+/// Records are queued rather than written by the caller, so evicting a game
+/// under the storage lock does no file I/O. A queued record is served from
+/// memory until the writer has it on disk, and stays there if the write fails.
+#[derive(Debug, Clone)]
+pub struct ArchiveStore {
+    dir: PathBuf,
+    pending: Arc<DashMap<Uuid, Arc<GameArchive>>>,
+    queued: mpsc::UnboundedSender<Uuid>,
+}
+
+/// Ids of queued records, for [`ArchiveStore::run_writer`]
+pub type ArchiveQueue = mpsc::UnboundedReceiver<Uuid>;
+
+impl ArchiveStore {
+    pub fn open(dir: impl Into<PathBuf>) -> Result<(Self, ArchiveQueue), String> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        let (queued, queue) = mpsc::unbounded_channel();
+        Ok((
+            Self {
+                dir,
+                pending: Arc::new(DashMap::new()),
+                queued,
+            },
+            queue,
+        ))
+    }
+
+    /// Hand a game's record to the writer, replacing any earlier copy
+    pub fn queue(&self, archive: GameArchive) {
+        let game_id = archive.game_id;
+        self.pending.insert(game_id, Arc::new(archive));
+        let _ = self.queued.send(game_id);
+    }
+
+    /// Write queued records until every store handle is gone
+    pub async fn run_writer(self, mut queue: ArchiveQueue) {
+        // Let go of this handle's sender, so the loop ends with the last store
+        let Self {
+            dir,
+            pending,
+            queued,
+        } = self;
+        drop(queued);
+        while let Some(game_id) = queue.recv().await {
+            let Some(archive) = pending.get(&game_id).map(|entry| Arc::clone(entry.value())) else {
+                continue;
+            };
+            let path = archive_path(&dir, game_id);
+            let written = tokio::task::spawn_blocking(move || write_archive(&path, &archive))
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|written| written);
+            match written {
+                Ok(()) => {
+                    pending.remove(&game_id);
+                }
+                Err(e) => warn!(
+                    "Failed to archive game {}, keeping it in memory: {}",
+                    game_id, e
+                ),
+            }
+        }
+    }
+
+    /// The archived record for a game, or `None` if it was never archived.
+    /// Reads the disk, so call it off the storage lock.
+    pub fn read(&self, game_id: Uuid) -> Result<Option<GameArchive>, String> {
+        if let Some(archive) = self.pending.get(&game_id) {
+            return Ok(Some(GameArchive::clone(archive.value())));
+        }
+
+        let path = archive_path(&self.dir, game_id);
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.to_string()),
+        };
+        if size > MAX_ARCHIVE_FILE_BYTES {
+            return Err("Archived game is too large".to_string());
+        }
+
+        let json = std::fs::read(&path).map_err(|e| e.to_string())?;
+        serde_json::from_slice(&json)
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+}
+
+fn archive_path(dir: &Path, game_id: Uuid) -> PathBuf {
+    dir.join(format!("{}.json", game_id))
+}
+
+// Write next to `path` and rename into place, as with snapshots
+fn write_archive(path: &Path, archive: &GameArchive) -> Result<(), String> {
+    let json = serde_json::to_vec(archive).map_err(|e| e.to_string())?;
+    let tmp_path = path.with_extension("tmp");
+    std::fs::write(&tmp_path, json).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp_path, path).map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStore;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::*;
+    use sqlx::sqlite::{SqliteConnectOptions, SqlitePool};
+    use std::str::FromStr;
+
+    /// SQLite-backed store for accounts, finished games and active game checkpoints
+    #[derive(Debug, Clone)]
+    pub struct SqliteStore {
+        pool: SqlitePool,
+    }
+
+    impl SqliteStore {
+        /// Open (creating if needed) the database and bring its schema up to date
+        pub async fn connect(url: &str) -> Result<Self, String> {
+            let options = SqliteConnectOptions::from_str(url)
+                .map_err(|e| e.to_string())?
+                .create_if_missing(true);
+            let pool = SqlitePool::connect_with(options)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            sqlx::migrate!("./migrations")
+                .run(&pool)
+                .await
+                .map_err(|e| e.to_string())?;
+
+            Ok(Self { pool })
+        }
+
+        /// Accounts and the last checkpoint of every active game
+        pub async fn load(&self) -> Result<RestoredState, String> {
+            let mut restored = RestoredState::default();
+
+            let accounts: Vec<(String, String)> =
+                sqlx::query_as("SELECT player_name, stats FROM accounts")
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            for (player_name, stats) in accounts {
+                match serde_json::from_str(&stats) {
+                    Ok(stats) => {
+                        restored.accounts.insert(player_name, stats);
+                    }
+                    Err(e) => warn!("Skipping unreadable account {}: {}", player_name, e),
+                }
+            }
+
+            let games: Vec<(String, String)> =
+                sqlx::query_as("SELECT game_id, state FROM active_games")
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            for (game_id, state) in games {
+                match serde_json::from_str(&state) {
+                    Ok(state) => restored.active_games.push(state),
+                    Err(e) => warn!("Skipping unreadable game {}: {}", game_id, e),
+                }
+            }
+
+            let seasons: Vec<(String, String)> =
+                sqlx::query_as("SELECT name, archive FROM seasons ORDER BY position")
+                    .fetch_all(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+            for (name, archive) in seasons {
+                match serde_json::from_str(&archive) {
+                    Ok(archive) => restored.seasons.push(archive),
+                    Err(e) => warn!("Skipping unreadable season {}: {}", name, e),
+                }
+            }
+
+            restored.current_season =
+                sqlx::query_scalar("SELECT name FROM current_season WHERE id = 0")
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+            Ok(restored)
+        }
+
+        /// Apply events until every sender is gone
+        pub async fn run_writer(self, mut events: mpsc::UnboundedReceiver<PersistEvent>) {
+            while let Some(event) = events.recv().await {
+                if let Err(e) = self.write(event).await {
+                    warn!("Persistence error: {}", e);
+                }
+            }
+        }
+
+        async fn write(&self, event: PersistEvent) -> Result<(), String> {
+            match event {
+                PersistEvent::AccountUpdated { player_name, stats } => {
+                    sqlx::query(
+                        "INSERT INTO accounts (player_name, stats) VALUES (?, ?)
+                         ON CONFLICT(player_name) DO UPDATE SET stats = excluded.stats",
+                    )
+                    .bind(player_name)
+                    .bind(to_json(&stats)?)
+                    .execute(&self.pool)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                }
+                PersistEvent::GameFinished { archive } => {
+                    let game_id = archive.game_id.to_string();
+                    let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+                    for (index, record) in archive.history.iter().enumerate() {
+                        sqlx::query(
+                            "INSERT OR REPLACE INTO move_history (game_id, move_index, record)
+                             VALUES (?, ?, ?)",
+                        )
+                        .bind(&game_id)
+                        .bind(index as i64)
+                        .bind(to_json(record)?)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    }
+
+                    sqlx::query(
+                        "INSERT OR REPLACE INTO game_results (game_id, archive) VALUES (?, ?)",
+                    )
+                    .bind(&game_id)
+                    .bind(to_json(&archive)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                    sqlx::query("DELETE FROM active_games WHERE game_id = ?")
+                        .bind(&game_id)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| e.to_string())?;
+
+                    tx.commit().await.map_err(|e| e.to_string())?;
+                }
+                PersistEvent::Checkpoint { games } => {
+                    let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+                    sqlx::query("DELETE FROM active_games")
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    for game_state in &games {
+                        sqlx::query("INSERT INTO active_games (game_id, state) VALUES (?, ?)")
+                            .bind(game_state.game.id.to_string())
+                            .bind(to_json(game_state)?)
+                            .execute(&mut *tx)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+
+                    tx.commit().await.map_err(|e| e.to_string())?;
+                }
+                PersistEvent::SeasonClosed {
+                    archive,
+                    current_season,
+                    accounts,
+                } => {
+                    let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+                    sqlx::query(
+                        "INSERT INTO seasons (position, name, archive)
+                         VALUES ((SELECT COALESCE(MAX(position), -1) + 1 FROM seasons), ?, ?)",
+                    )
+                    .bind(&archive.name)
+                    .bind(to_json(&archive)?)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                    sqlx::query(
+                        "INSERT INTO current_season (id, name) VALUES (0, ?)
+                         ON CONFLICT(id) DO UPDATE SET name = excluded.name",
+                    )
+                    .bind(current_season)
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                    for (player_name, stats) in &accounts {
+                        sqlx::query(
+                            "INSERT INTO accounts (player_name, stats) VALUES (?, ?)
+                             ON CONFLICT(player_name) DO UPDATE SET stats = excluded.stats",
+                        )
+                        .bind(player_name)
+                        .bind(to_json(stats)?)
+                        .execute(&mut *tx)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                    }
+
+                    tx.commit().await.map_err(|e| e.to_string())?;
+                }
+            }
+
+            Ok(())
+        }
+    }
+
+    fn to_json<T: serde::Serialize>(value: &T) -> Result<String, String> {
+        serde_json::to_string(value).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_storage::{GameMode, GameStorage};
+
+    // Storage with one finished game between ann and bob, so both have accounts
+    fn storage_after_a_game(persist: Option<PersistSender>) -> GameStorage {
+        let mut storage = GameStorage::new();
+        if let Some(persist) = persist {
+            storage = storage.with_persistence(persist);
+        }
+        let ann = storage
+            .join_queue("ann".to_string(), GameMode::Realtime)
+            .unwrap();
+        storage
+            .join_queue("bob".to_string(), GameMode::Realtime)
+            .unwrap();
+        storage.quit(ann.player_id).unwrap();
+        storage
+    }
+
+    fn temp_path(extension: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("chest-royale-{}.{}", Uuid::new_v4(), extension))
+    }
+
+    #[test]
+    fn a_snapshot_keeps_closed_seasons_and_reset_ratings() {
+        let mut storage = storage_after_a_game(None);
+        let bob_before = storage.get_player_stats("bob").unwrap().rating;
+        storage.rollover_season(Some("Spring".to_string())).unwrap();
+        let bob_after = storage.get_player_stats("bob").unwrap().rating;
+        assert!(bob_after < bob_before);
+
+        let path = temp_path("json");
+        write_snapshot(&path, &storage.snapshot()).unwrap();
+        let mut restored = GameStorage::new();
+        restored.restore(read_snapshot(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(restored.get_player_stats("bob").unwrap().rating, bob_after);
+        let seasons = restored.get_player_seasons("bob").unwrap();
+        assert_eq!(seasons.len(), 1);
+        assert_eq!(seasons[0].rating, bob_before);
+        assert_eq!(
+            restored.rollover_season(None),
+            Ok("Spring".to_string()),
+            "the season running at the snapshot is still running"
+        );
+    }
+
+    #[test]
+    fn a_game_continues_after_a_snapshot_reload() {
+        let mut storage = GameStorage::new();
+        let white = storage
+            .join_queue("ann".to_string(), GameMode::Realtime)
+            .unwrap()
+            .player_id;
+        let joined = storage
+            .join_queue("bob".to_string(), GameMode::Realtime)
+            .unwrap();
+        let (game_id, black) = (joined.game_id.unwrap(), joined.player_id);
+        let moved = storage
+            .make_move(
+                game_id,
+                crate::MoveRequest {
+                    player_id: white,
+                    from: (1, 4),
+                    to: (2, 4),
+                    use_charge: false,
+                },
+            )
+            .unwrap();
+        assert!(moved.success);
+
+        let path = temp_path("json");
+        write_snapshot(&path, &storage.snapshot()).unwrap();
+        let mut restored = GameStorage::new();
+        restored.restore(read_snapshot(&path).unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        let board = restored.get_fogged_board(game_id, white).unwrap();
+        assert!(board.slots[1][4].is_none());
+        assert!(board.slots[2][4].is_some());
+        // Black still has the move point it started with
+        let moved = restored
+            .make_move(
+                game_id,
+                crate::MoveRequest {
+                    player_id: black,
+                    from: (6, 4),
+                    to: (5, 4),
+                    use_charge: false,
+                },
+            )
+            .unwrap();
+        assert!(moved.success, "{}", moved.message);
+    }
+
+    #[test]
+    fn unreadable_games_in_a_snapshot_are_skipped() {
+        let storage = storage_after_a_game(None);
+        let mut snapshot = serde_json::to_value(storage.snapshot()).unwrap();
+        snapshot["games"] = serde_json::json!([{ "not": "a game" }]);
+
+        let path = temp_path("json");
+        std::fs::write(&path, snapshot.to_string()).unwrap();
+        let restored = read_snapshot(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert!(restored.active_games.is_empty());
+        assert!(restored.accounts.contains_key("ann"));
+        assert!(
+            read_snapshot(&temp_path("json"))
+                .unwrap()
+                .accounts
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_archives_are_written_by_the_writer() {
+        let dir = temp_path("archive");
+        let (store, queue) = ArchiveStore::open(&dir).unwrap();
+        let writer = tokio::spawn(store.clone().run_writer(queue));
+
+        let storage = storage_after_a_game(None);
+        let game_id = storage.finished_game_ids()[0];
+        store.queue(storage.export_game(game_id).unwrap());
+        assert!(
+            store.read(game_id).unwrap().is_some(),
+            "served while queued"
+        );
+        drop(store);
+        drop(storage);
+        writer.await.unwrap();
+
+        let (reopened, _) = ArchiveStore::open(&dir).unwrap();
+        let archived = reopened.read(game_id).unwrap().unwrap();
+        assert_eq!(archived.game_id, game_id);
+        assert!(reopened.read(Uuid::new_v4()).unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn a_season_rollover_survives_a_restart() {
+        let path = temp_path("db");
+        let url = format!("sqlite://{}", path.display());
+        let store = SqliteStore::connect(&url).await.unwrap();
+
+        let (persist, events) = mpsc::unbounded_channel();
+        let mut storage = storage_after_a_game(Some(persist));
+        let bob_before = storage.get_player_stats("bob").unwrap().rating;
+        storage.rollover_season(Some("Spring".to_string())).unwrap();
+        let bob_after = storage.get_player_stats("bob").unwrap().rating;
+        // The writer finishes once the storage, the last sender, is gone
+        drop(storage);
+        store.run_writer(events).await;
+
+        let reopened = SqliteStore::connect(&url).await.unwrap();
+        let mut restored = GameStorage::new();
+        restored.restore(reopened.load().await.unwrap());
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(restored.get_player_stats("bob").unwrap().rating, bob_after);
+        let seasons = restored.get_player_seasons("bob").unwrap();
+        assert_eq!(seasons.len(), 1);
+        assert_eq!(seasons[0].season, "Season 1");
+        assert_eq!(seasons[0].rating, bob_before);
+        assert_eq!(restored.rollover_season(None), Ok("Spring".to_string()));
+    }
+}
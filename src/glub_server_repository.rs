@@ -0,0 +1,686 @@
+use crate::glub_server_storage::{GameMode, GameState, QueuedPlayer};
+use foldhash::HashMap;
+use std::fmt::Debug;
+use uuid::Uuid;
+
+/// Where games and the matchmaking queue are kept. `GameStorage` only reaches its
+/// games through this trait, so the containers can change without touching game logic.
+pub trait GameRepository: Send + Sync + Debug {
+    fn get(&self, game_id: Uuid) -> Option<&GameState>;
+
+    /// Changes made through the returned reference are the update
+    fn get_mut(&mut self, game_id: Uuid) -> Option<&mut GameState>;
+
+    /// Add a game, replacing any game with the same id
+    fn insert(&mut self, game_state: GameState);
+
+    fn remove(&mut self, game_id: Uuid) -> Option<GameState>;
+
+    fn contains(&self, game_id: Uuid) -> bool {
+        self.get(game_id).is_some()
+    }
+
+    /// Every game, finished or not, in no particular order. Games are changed
+    /// one at a time through `get_mut`, so a repository knows which to write out.
+    fn games(&self) -> Box<dyn Iterator<Item = &GameState> + '_>;
+
+    /// Games that have not reached a result yet
+    fn active_games(&self) -> Box<dyn Iterator<Item = &GameState> + '_> {
+        Box::new(
+            self.games()
+                .filter(|game_state| game_state.result.is_none()),
+        )
+    }
+
+    fn push_queued(&mut self, player: QueuedPlayer);
+
+    /// Take the next player waiting for a game in `mode`, if anyone is
+    fn pop_queued(&mut self, mode: GameMode) -> Option<QueuedPlayer>;
+
+    /// Take a specific player out of the queue
+    fn remove_queued(&mut self, player_id: Uuid) -> Option<QueuedPlayer>;
+
+    fn queued(&self) -> Box<dyn Iterator<Item = &QueuedPlayer> + '_>;
+
+    fn queue_len(&self) -> usize {
+        self.queued().count()
+    }
+
+    /// Write out the changes made to one game through `get_mut`. Repositories
+    /// that live entirely in memory have nothing to do.
+    fn save(&mut self, _game_id: Uuid) -> Result<(), SaveError> {
+        Ok(())
+    }
+
+    /// Write out every change made through `get_mut`, reporting each game that
+    /// couldn't be written
+    fn flush(&mut self) -> Result<(), Vec<SaveError>> {
+        Ok(())
+    }
+
+    /// Pick up changes another instance made to a game. Repositories owned by a
+    /// single process have nothing to do.
+    fn refresh(&mut self, _game_id: Uuid) {}
+
+    /// Check that the backing store can be reached
+    fn check_health(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Why changes to games were not written out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SaveError {
+    /// Another instance changed the game first. The local copy now holds
+    /// their version, so the change is lost and has to be made again.
+    Conflict(Uuid),
+    /// The write didn't go through; the change is kept and tried again later
+    Failed(String),
+}
+
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Conflict(game_id) => {
+                write!(f, "game {} was changed by another instance", game_id)
+            }
+            SaveError::Failed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Games in a `HashMap` and the queue in a `Vec`, all in process memory. Game
+/// ids are random, so the maps use foldhash's seeded hasher instead of SipHash.
+#[derive(Debug, Default)]
+pub struct InMemoryRepository {
+    games: HashMap<Uuid, GameState>,
+    queue: Vec<QueuedPlayer>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl GameRepository for InMemoryRepository {
+    fn get(&self, game_id: Uuid) -> Option<&GameState> {
+        self.games.get(&game_id)
+    }
+
+    fn get_mut(&mut self, game_id: Uuid) -> Option<&mut GameState> {
+        self.games.get_mut(&game_id)
+    }
+
+    fn insert(&mut self, game_state: GameState) {
+        self.games.insert(game_state.game.id, game_state);
+    }
+
+    fn remove(&mut self, game_id: Uuid) -> Option<GameState> {
+        self.games.remove(&game_id)
+    }
+
+    fn contains(&self, game_id: Uuid) -> bool {
+        self.games.contains_key(&game_id)
+    }
+
+    fn games(&self) -> Box<dyn Iterator<Item = &GameState> + '_> {
+        Box::new(self.games.values())
+    }
+
+    fn push_queued(&mut self, player: QueuedPlayer) {
+        self.queue.push(player);
+    }
+
+    // Matches the most recent arrival, as the queue always has
+    fn pop_queued(&mut self, mode: GameMode) -> Option<QueuedPlayer> {
+        let index = self.queue.iter().rposition(|player| player.mode == mode)?;
+        Some(self.queue.remove(index))
+    }
+
+    fn remove_queued(&mut self, player_id: Uuid) -> Option<QueuedPlayer> {
+        let index = self
+            .queue
+            .iter()
+            .position(|player| player.id == player_id)?;
+        Some(self.queue.remove(index))
+    }
+
+    fn queued(&self) -> Box<dyn Iterator<Item = &QueuedPlayer> + '_> {
+        Box::new(self.queue.iter())
+    }
+
+    fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_backed::RedisRepository;
+
+#[cfg(feature = "redis")]
+mod redis_backed {
+    use super::*;
+    use redis::{Commands, Connection};
+    use std::collections::HashSet;
+    use std::hash::{DefaultHasher, Hash, Hasher};
+    use tracing::warn;
+
+    const GAME_IDS_KEY: &str = "chest:games";
+    const QUEUE_KEY: &str = "chest:queue";
+    const TURN_BASED_QUEUE_KEY: &str = "chest:queue:turn_based";
+
+    fn game_key(game_id: Uuid) -> String {
+        format!("chest:game:{}", game_id)
+    }
+
+    // Each mode has a list of its own, so matching stays a single atomic pop
+    fn queue_key(mode: GameMode) -> &'static str {
+        match mode {
+            GameMode::Realtime => QUEUE_KEY,
+            GameMode::TurnBased => TURN_BASED_QUEUE_KEY,
+        }
+    }
+
+    // Tells one stored copy of a game from another, whatever changed in it
+    fn digest(json: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        json.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // Redis calls block the thread. On the multi-threaded runtime the worker's
+    // other tasks are handed to the rest of the pool while it waits.
+    fn blocking<T>(f: impl FnOnce() -> T) -> T {
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == tokio::runtime::RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(f)
+            }
+            _ => f(),
+        }
+    }
+
+    /// Games and the queue kept in Redis, so a restarted or second instance can
+    /// pick them up.
+    ///
+    /// Games are served from a local copy loaded at startup. Changes are written
+    /// back under `WATCH`, and only if the stored game is still the copy this
+    /// instance last read or wrote. When another instance got there first the
+    /// write fails with [`SaveError::Conflict`] and the local copy is replaced
+    /// with theirs. Queue operations go straight to a Redis list, so two
+    /// instances never match the same waiting player.
+    pub struct RedisRepository {
+        connection: Connection,
+        games: HashMap<Uuid, GameState>,
+        /// Digest of each game's JSON as last read from or written to Redis
+        stored_digests: HashMap<Uuid, u64>,
+        /// Games handed out mutably since they were last written
+        dirty: HashSet<Uuid>,
+        queue: Vec<QueuedPlayer>,
+    }
+
+    impl std::fmt::Debug for RedisRepository {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("RedisRepository")
+                .field("games", &self.games.len())
+                .field("dirty", &self.dirty.len())
+                .field("queue", &self.queue.len())
+                .finish()
+        }
+    }
+
+    impl RedisRepository {
+        /// Connect and load every stored game and the queue
+        pub fn connect(url: &str) -> Result<Self, String> {
+            let client = redis::Client::open(url).map_err(|e| e.to_string())?;
+            let connection = blocking(|| client.get_connection()).map_err(|e| e.to_string())?;
+
+            let mut repository = Self {
+                connection,
+                games: HashMap::default(),
+                stored_digests: HashMap::default(),
+                dirty: HashSet::new(),
+                queue: Vec::new(),
+            };
+
+            let game_ids: Vec<String> = repository
+                .redis(|connection| connection.smembers(GAME_IDS_KEY))
+                .map_err(|e| e.to_string())?;
+            for game_id in game_ids {
+                match Uuid::parse_str(&game_id) {
+                    Ok(game_id) => repository.reload(game_id)?,
+                    Err(_) => warn!("Skipping unreadable game id {} in Redis", game_id),
+                }
+            }
+            repository.refresh_queue()?;
+
+            Ok(repository)
+        }
+
+        fn redis<T>(&mut self, f: impl FnOnce(&mut Connection) -> T) -> T {
+            let connection = &mut self.connection;
+            blocking(|| f(connection))
+        }
+
+        fn fetch(&mut self, game_id: Uuid) -> Result<Option<String>, String> {
+            self.redis(|connection| connection.get(game_key(game_id)))
+                .map_err(|e| e.to_string())
+        }
+
+        // Make `json` the local copy of a game, dropping any unsaved changes
+        fn load(&mut self, game_id: Uuid, json: Option<String>) {
+            self.dirty.remove(&game_id);
+            let Some(json) = json else {
+                self.stored_digests.remove(&game_id);
+                self.games.remove(&game_id);
+                return;
+            };
+            match serde_json::from_str::<GameState>(&json) {
+                Ok(game_state) => {
+                    self.stored_digests.insert(game_id, digest(&json));
+                    self.games.insert(game_id, game_state);
+                }
+                Err(e) => warn!("Skipping unreadable game {} in Redis: {}", game_id, e),
+            }
+        }
+
+        // Replace the local copy of a game with what Redis holds
+        fn reload(&mut self, game_id: Uuid) -> Result<(), String> {
+            let json = self.fetch(game_id)?;
+            self.load(game_id, json);
+            Ok(())
+        }
+
+        fn refresh_queue(&mut self) -> Result<(), String> {
+            let mut queue = Vec::new();
+            for key in [QUEUE_KEY, TURN_BASED_QUEUE_KEY] {
+                let entries: Vec<String> = self
+                    .redis(|connection| connection.lrange(key, 0, -1))
+                    .map_err(|e| e.to_string())?;
+                queue.extend(
+                    entries
+                        .iter()
+                        .filter_map(|entry| serde_json::from_str::<QueuedPlayer>(entry).ok()),
+                );
+            }
+            queue.sort_by_key(|player| player.joined_at);
+            self.queue = queue;
+            Ok(())
+        }
+
+        /// Write one game if Redis still holds the copy we last saw. On a
+        /// conflict the local copy is reloaded before returning.
+        fn write_game(&mut self, game_id: Uuid) -> Result<(), SaveError> {
+            let Some(game_state) = self.games.get(&game_id) else {
+                return Ok(());
+            };
+            let json =
+                serde_json::to_string(game_state).map_err(|e| SaveError::Failed(e.to_string()))?;
+            let written = digest(&json);
+            let known = self.stored_digests.get(&game_id).copied();
+            // Touched but not changed
+            if known == Some(written) {
+                return Ok(());
+            }
+
+            let key = game_key(game_id);
+            let stored = self.redis(|connection| -> redis::RedisResult<bool> {
+                redis::cmd("WATCH").arg(&key).exec(connection)?;
+                let stored: Option<String> = connection.get(&key)?;
+                if stored.as_deref().map(digest) != known {
+                    redis::cmd("UNWATCH").exec(connection)?;
+                    return Ok(false);
+                }
+
+                // A nil reply means the key changed between WATCH and EXEC
+                let reply: redis::Value = redis::pipe()
+                    .atomic()
+                    .set(&key, &json)
+                    .sadd(GAME_IDS_KEY, game_id.to_string())
+                    .query(connection)?;
+                Ok(reply != redis::Value::Nil)
+            });
+
+            match stored {
+                Ok(true) => {
+                    self.stored_digests.insert(game_id, written);
+                    Ok(())
+                }
+                Ok(false) => {
+                    self.reload(game_id).map_err(SaveError::Failed)?;
+                    Err(SaveError::Conflict(game_id))
+                }
+                Err(e) => Err(SaveError::Failed(e.to_string())),
+            }
+        }
+    }
+
+    impl GameRepository for RedisRepository {
+        fn get(&self, game_id: Uuid) -> Option<&GameState> {
+            self.games.get(&game_id)
+        }
+
+        fn get_mut(&mut self, game_id: Uuid) -> Option<&mut GameState> {
+            let game_state = self.games.get_mut(&game_id)?;
+            self.dirty.insert(game_id);
+            Some(game_state)
+        }
+
+        fn insert(&mut self, game_state: GameState) {
+            let game_id = game_state.game.id;
+            self.dirty.insert(game_id);
+            self.games.insert(game_id, game_state);
+        }
+
+        fn remove(&mut self, game_id: Uuid) -> Option<GameState> {
+            let removed: redis::RedisResult<()> = self.redis(|connection| {
+                redis::pipe()
+                    .atomic()
+                    .del(game_key(game_id))
+                    .srem(GAME_IDS_KEY, game_id.to_string())
+                    .query(connection)
+            });
+            if let Err(e) = removed {
+                warn!("Redis unreachable, game {} left in Redis: {}", game_id, e);
+            }
+
+            self.dirty.remove(&game_id);
+            self.stored_digests.remove(&game_id);
+            self.games.remove(&game_id)
+        }
+
+        fn contains(&self, game_id: Uuid) -> bool {
+            self.games.contains_key(&game_id)
+        }
+
+        fn games(&self) -> Box<dyn Iterator<Item = &GameState> + '_> {
+            Box::new(self.games.values())
+        }
+
+        // Unsaved local changes are kept unless another instance has written
+        // the game since, in which case theirs wins
+        fn refresh(&mut self, game_id: Uuid) {
+            let fetched = self.fetch(game_id);
+            match fetched {
+                Ok(json) => {
+                    let stored = json.as_deref().map(digest);
+                    if stored != self.stored_digests.get(&game_id).copied() {
+                        self.load(game_id, json);
+                    }
+                }
+                Err(e) => warn!("Redis unreachable, game {} may be stale: {}", game_id, e),
+            }
+        }
+
+        fn push_queued(&mut self, player: QueuedPlayer) {
+            let pushed = serde_json::to_string(&player)
+                .map_err(|e| e.to_string())
+                .and_then(|json| {
+                    self.redis(|connection| {
+                        connection.rpush::<_, _, ()>(queue_key(player.mode), json)
+                    })
+                    .map_err(|e| e.to_string())
+                });
+            if let Err(e) = pushed {
+                warn!(
+                    "Redis unreachable, {} queued on this instance only: {}",
+                    player.name, e
+                );
+            }
+            self.queue.push(player);
+        }
+
+        // Popped atomically in Redis so no two instances match the same player
+        fn pop_queued(&mut self, mode: GameMode) -> Option<QueuedPlayer> {
+            match self
+                .redis(|connection| connection.rpop::<_, Option<String>>(queue_key(mode), None))
+            {
+                Ok(entry) => {
+                    let player: Option<QueuedPlayer> =
+                        entry.and_then(|entry| serde_json::from_str(&entry).ok());
+                    if let Some(player) = &player {
+                        self.queue.retain(|queued| queued.id != player.id);
+                    }
+                    player
+                }
+                Err(e) => {
+                    warn!(
+                        "Redis unreachable, matching from this instance's queue: {}",
+                        e
+                    );
+                    let index = self.queue.iter().rposition(|player| player.mode == mode)?;
+                    Some(self.queue.remove(index))
+                }
+            }
+        }
+
+        fn remove_queued(&mut self, player_id: Uuid) -> Option<QueuedPlayer> {
+            // LREM needs the exact stored entry, so find it first
+            for key in [QUEUE_KEY, TURN_BASED_QUEUE_KEY] {
+                let removed = self.redis(|connection| -> redis::RedisResult<()> {
+                    let entries: Vec<String> = connection.lrange(key, 0, -1)?;
+                    for entry in entries {
+                        let matches = serde_json::from_str::<QueuedPlayer>(&entry)
+                            .is_ok_and(|player| player.id == player_id);
+                        if matches {
+                            connection.lrem::<_, _, ()>(key, 1, entry)?;
+                        }
+                    }
+                    Ok(())
+                });
+                if let Err(e) = removed {
+                    warn!("Redis unreachable, queue entry not removed: {}", e);
+                }
+            }
+
+            let index = self
+                .queue
+                .iter()
+                .position(|player| player.id == player_id)?;
+            Some(self.queue.remove(index))
+        }
+
+        fn queued(&self) -> Box<dyn Iterator<Item = &QueuedPlayer> + '_> {
+            Box::new(self.queue.iter())
+        }
+
+        fn queue_len(&self) -> usize {
+            self.queue.len()
+        }
+
+        fn check_health(&mut self) -> Result<(), String> {
+            self.redis(|connection| redis::cmd("PING").query::<String>(connection))
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        }
+
+        fn save(&mut self, game_id: Uuid) -> Result<(), SaveError> {
+            if !self.dirty.contains(&game_id) {
+                return Ok(());
+            }
+            // A failed write stays dirty, to be tried again on the next flush
+            self.write_game(game_id)?;
+            self.dirty.remove(&game_id);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Vec<SaveError>> {
+            let dirty: Vec<Uuid> = self.dirty.iter().copied().collect();
+            let mut errors: Vec<SaveError> = dirty
+                .into_iter()
+                .filter_map(|game_id| self.save(game_id).err())
+                .collect();
+
+            if let Err(e) = self.refresh_queue() {
+                errors.push(SaveError::Failed(format!("queue not refreshed: {}", e)));
+            }
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_storage::{GameEndReason, GameResult, GameStorage};
+
+    // Two games from a real storage, the second one finished
+    fn sample_games() -> (GameState, GameState) {
+        let mut storage = GameStorage::new();
+        let mut game_ids = Vec::new();
+        for _ in 0..2 {
+            storage
+                .join_queue("ann".to_string(), GameMode::Realtime)
+                .unwrap();
+            let joined = storage
+                .join_queue("bob".to_string(), GameMode::Realtime)
+                .unwrap();
+            game_ids.push(joined.game_id.unwrap());
+        }
+        let game = |game_id| storage.with_game(game_id, GameState::clone).unwrap();
+        let (active, mut finished) = (game(game_ids[0]), game(game_ids[1]));
+        finished.result = Some(GameResult {
+            winner: None,
+            reason: GameEndReason::Abandoned,
+        });
+        (active, finished)
+    }
+
+    fn queued(name: &str, mode: GameMode) -> QueuedPlayer {
+        QueuedPlayer {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            joined_at: std::time::Instant::now(),
+            mode,
+        }
+    }
+
+    // What every repository has to do, checked through the trait object
+    // `GameStorage` holds
+    fn check_contract(repository: &mut dyn GameRepository) {
+        let (active, finished) = sample_games();
+        let (active_id, finished_id) = (active.game.id, finished.game.id);
+        repository.insert(active);
+        repository.insert(finished);
+        assert!(repository.contains(active_id));
+        assert_eq!(repository.games().count(), 2);
+        let active_ids: Vec<Uuid> = repository
+            .active_games()
+            .map(|game_state| game_state.game.id)
+            .collect();
+        assert_eq!(active_ids, vec![active_id]);
+
+        repository.get_mut(active_id).unwrap().version = 41;
+        assert_eq!(repository.save(active_id), Ok(()));
+        assert_eq!(repository.get(active_id).unwrap().version, 41);
+        repository.get_mut(active_id).unwrap().version = 42;
+        assert_eq!(repository.flush(), Ok(()));
+        assert_eq!(repository.get(active_id).unwrap().version, 42);
+
+        assert_eq!(
+            repository
+                .remove(finished_id)
+                .map(|game_state| game_state.game.id),
+            Some(finished_id)
+        );
+        assert!(repository.get(finished_id).is_none());
+        assert!(repository.remove(finished_id).is_none());
+
+        let (first, second, turn_based) = (
+            queued("first", GameMode::Realtime),
+            queued("second", GameMode::Realtime),
+            queued("patient", GameMode::TurnBased),
+        );
+        let (second_id, turn_based_id) = (second.id, turn_based.id);
+        repository.push_queued(first);
+        repository.push_queued(turn_based);
+        repository.push_queued(second);
+        assert_eq!(repository.queue_len(), 3);
+        assert_eq!(
+            repository
+                .pop_queued(GameMode::Realtime)
+                .map(|player| player.id),
+            Some(second_id),
+            "the latest arrival is matched first"
+        );
+        assert_eq!(
+            repository
+                .remove_queued(turn_based_id)
+                .map(|player| player.name),
+            Some("patient".to_string())
+        );
+        assert!(repository.pop_queued(GameMode::TurnBased).is_none());
+        assert_eq!(repository.queued().count(), 1);
+        assert!(repository.check_health().is_ok());
+
+        // Leave a shared store as it was found
+        let first_id = repository.queued().next().unwrap().id;
+        repository.remove_queued(first_id);
+        repository.remove(active_id);
+    }
+
+    #[test]
+    fn the_in_memory_repository_keeps_the_contract() {
+        let mut repository: Box<dyn GameRepository> = Box::new(InMemoryRepository::new());
+        check_contract(repository.as_mut());
+    }
+
+    // Redis tests run only against a server named in REDIS_URL, which should be
+    // a database nothing else uses, e.g. redis://127.0.0.1/15
+    #[cfg(feature = "redis")]
+    fn redis() -> Option<RedisRepository> {
+        let url = std::env::var("REDIS_URL").ok()?;
+        Some(RedisRepository::connect(&url).expect("REDIS_URL is reachable"))
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn the_redis_repository_keeps_the_contract() {
+        let Some(repository) = redis() else {
+            return;
+        };
+        let mut repository: Box<dyn GameRepository> = Box::new(repository);
+        check_contract(repository.as_mut());
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn a_stale_instance_loses_its_write_and_picks_up_the_winner() {
+        let (Some(mut first), Some(mut second)) = (redis(), redis()) else {
+            return;
+        };
+        let (game, _) = sample_games();
+        let game_id = game.game.id;
+        first.insert(game);
+        assert_eq!(first.save(game_id), Ok(()));
+        second.refresh(game_id);
+
+        // A move point tick changes the game without bumping its version
+        first.get_mut(game_id).unwrap().game.player1_remaining_moves += 1;
+        assert_eq!(first.save(game_id), Ok(()));
+        second
+            .get_mut(game_id)
+            .unwrap()
+            .game
+            .player2_remaining_moves += 1;
+        assert_eq!(second.save(game_id), Err(SaveError::Conflict(game_id)));
+
+        let (theirs, ours) = (first.get(game_id).unwrap(), second.get(game_id).unwrap());
+        assert_eq!(
+            ours.game.player1_remaining_moves,
+            theirs.game.player1_remaining_moves
+        );
+        assert_eq!(
+            ours.game.player2_remaining_moves,
+            theirs.game.player2_remaining_moves
+        );
+        // Nothing left to write once the winner's copy is loaded
+        assert_eq!(second.flush(), Ok(()));
+
+        first.remove(game_id);
+    }
+}
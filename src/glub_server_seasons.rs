@@ -0,0 +1,88 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Rating every new account starts from, and the point soft resets pull towards
+pub const BASE_RATING: f64 = 1200.0;
+
+/// How far a single game can move a rating
+pub const RATING_K_FACTOR: f64 = 32.0;
+
+/// Share of the distance from the base rating kept across a season reset
+pub const SEASON_RESET_RETENTION: f64 = 0.5;
+
+/// Results counted since the current season started
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SeasonStats {
+    pub games_played: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+}
+
+/// One account's standing when a season closed
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SeasonEntry {
+    pub rating: f64,
+    pub stats: SeasonStats,
+}
+
+/// A closed season, frozen at the moment of the rollover
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeasonArchive {
+    pub name: String,
+    pub entries: HashMap<String, SeasonEntry>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LeaderboardEntry {
+    pub player_name: String,
+    pub rating: f64,
+    pub stats: SeasonStats,
+}
+
+impl SeasonArchive {
+    /// Rough number of bytes the archive takes up in memory
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>()
+            + self.name.capacity()
+            + self
+                .entries
+                .keys()
+                .map(|player_name| {
+                    size_of::<String>() + size_of::<SeasonEntry>() + player_name.capacity()
+                })
+                .sum::<usize>()
+    }
+
+    /// Accounts that played during the season, best rating first
+    pub fn leaderboard(&self) -> Vec<LeaderboardEntry> {
+        let mut leaderboard: Vec<LeaderboardEntry> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.stats.games_played > 0)
+            .map(|(player_name, entry)| LeaderboardEntry {
+                player_name: player_name.clone(),
+                rating: entry.rating,
+                stats: entry.stats.clone(),
+            })
+            .collect();
+
+        leaderboard.sort_by(|a, b| {
+            b.rating
+                .total_cmp(&a.rating)
+                .then_with(|| a.player_name.cmp(&b.player_name))
+        });
+        leaderboard
+    }
+}
+
+/// Elo update for a player scoring `score` (1 win, 0.5 draw, 0 loss) against `opponent`
+pub fn updated_rating(rating: f64, opponent: f64, score: f64) -> f64 {
+    let expected = 1.0 / (1.0 + 10f64.powf((opponent - rating) / 400.0));
+    rating + RATING_K_FACTOR * (score - expected)
+}
+
+/// Squash a rating towards the base for the start of a new season
+pub fn soft_reset(rating: f64) -> f64 {
+    BASE_RATING + (rating - BASE_RATING) * SEASON_RESET_RETENTION
+}
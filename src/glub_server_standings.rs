@@ -0,0 +1,181 @@
+use crate::glub_server_storage::{PlayerColor, PlayerStats};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Games and points (1 a win, 0.5 a draw) with each color
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ColorRecord {
+    pub white_games: u64,
+    pub white_points: f64,
+    pub black_games: u64,
+    pub black_points: f64,
+}
+
+impl ColorRecord {
+    pub fn record(&mut self, color: PlayerColor, points: f64) {
+        match color {
+            PlayerColor::White => {
+                self.white_games += 1;
+                self.white_points += points;
+            }
+            PlayerColor::Black => {
+                self.black_games += 1;
+                self.black_points += points;
+            }
+        }
+    }
+
+    /// Average of the scoring rate with white and with black, so a player isn't
+    /// ahead just for having had white more often. A color never played counts
+    /// as an even score.
+    pub fn balanced_score(&self) -> f64 {
+        let rate = |games: u64, points: f64| {
+            if games == 0 {
+                0.5
+            } else {
+                points / games as f64
+            }
+        };
+        (rate(self.white_games, self.white_points) + rate(self.black_games, self.black_points))
+            / 2.0
+    }
+}
+
+/// Results against one opponent
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct HeadToHead {
+    pub games: u64,
+    pub points: f64,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct StandingsEntry {
+    /// 1 for the top player; every player gets a distinct rank
+    pub rank: usize,
+    pub player_name: String,
+    pub rating: f64,
+    pub games_played: u64,
+    /// Points against the other players on exactly the same rating, 0 when
+    /// nobody shares it
+    pub head_to_head_points: f64,
+    pub color_balanced_score: f64,
+}
+
+/// Every account with a rated game, by rating. Players on the same rating are
+/// split by their points against each other, then by color-balanced score,
+/// then by name, so the order never depends on hash order.
+pub fn standings(accounts: &HashMap<String, PlayerStats>) -> Vec<StandingsEntry> {
+    let mut players: Vec<(&String, &PlayerStats)> = accounts
+        .iter()
+        .filter(|(_, stats)| stats.games_played > 0)
+        .collect();
+    players.sort_by(|(_, a), (_, b)| b.rating.total_cmp(&a.rating));
+
+    let mut standings = Vec::with_capacity(players.len());
+    for tied in players.chunk_by(|(_, a), (_, b)| a.rating == b.rating) {
+        let mut group: Vec<StandingsEntry> = tied
+            .iter()
+            .map(|(player_name, stats)| StandingsEntry {
+                rank: 0,
+                player_name: player_name.to_string(),
+                rating: stats.rating,
+                games_played: stats.games_played,
+                head_to_head_points: tied
+                    .iter()
+                    .filter_map(|(opponent, _)| stats.head_to_head.get(*opponent))
+                    .fold(0.0, |points, record| points + record.points),
+                color_balanced_score: stats.color_record.balanced_score(),
+            })
+            .collect();
+        group.sort_by(|a, b| {
+            b.head_to_head_points
+                .total_cmp(&a.head_to_head_points)
+                .then_with(|| b.color_balanced_score.total_cmp(&a.color_balanced_score))
+                .then_with(|| a.player_name.cmp(&b.player_name))
+        });
+        standings.extend(group);
+    }
+
+    for (index, entry) in standings.iter_mut().enumerate() {
+        entry.rank = index + 1;
+    }
+    standings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(rating: f64, head_to_head: &[(&str, f64)], color_record: ColorRecord) -> PlayerStats {
+        PlayerStats {
+            rating,
+            games_played: 4,
+            color_record,
+            head_to_head: head_to_head
+                .iter()
+                .map(|&(opponent, points)| (opponent.to_string(), HeadToHead { games: 2, points }))
+                .collect(),
+            ..PlayerStats::default()
+        }
+    }
+
+    fn even() -> ColorRecord {
+        ColorRecord {
+            white_games: 2,
+            white_points: 1.0,
+            black_games: 2,
+            black_points: 1.0,
+        }
+    }
+
+    #[test]
+    fn ties_split_by_head_to_head_then_color_balance_then_name() {
+        let accounts = HashMap::from([
+            ("top".to_string(), player(1600.0, &[], even())),
+            // Ann beat Bob, who has the better color record
+            (
+                "bob".to_string(),
+                player(1500.0, &[("ann", 0.5)], {
+                    let mut record = even();
+                    record.black_points = 2.0;
+                    record
+                }),
+            ),
+            ("ann".to_string(), player(1500.0, &[("bob", 1.5)], even())),
+            // Even against each other, so black points decide
+            ("cat".to_string(), player(1400.0, &[("dan", 1.0)], even())),
+            (
+                "dan".to_string(),
+                player(1400.0, &[("cat", 1.0)], {
+                    let mut record = even();
+                    record.black_points = 1.5;
+                    record
+                }),
+            ),
+            // Level on everything but the name
+            ("fay".to_string(), player(1300.0, &[], even())),
+            ("eve".to_string(), player(1300.0, &[], even())),
+            ("new".to_string(), PlayerStats::default()),
+        ]);
+
+        let standings = standings(&accounts);
+        let order: Vec<(usize, &str)> = standings
+            .iter()
+            .map(|entry| (entry.rank, entry.player_name.as_str()))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                (1, "top"),
+                (2, "ann"),
+                (3, "bob"),
+                (4, "dan"),
+                (5, "cat"),
+                (6, "eve"),
+                (7, "fay"),
+            ]
+        );
+        assert_eq!(standings[1].head_to_head_points, 1.5);
+        assert_eq!(standings[3].color_balanced_score, 0.625);
+    }
+}
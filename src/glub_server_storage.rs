@@ -1,273 +1,7524 @@
 use crate::glub_server::*;
-use serde::Serialize;
-use std::collections::HashMap;
+use crate::glub_server_achievements::*;
+use crate::glub_server_analytics::*;
+use crate::glub_server_bitboard::{Bitboard, bit, sight, squares};
+use crate::glub_server_changes::*;
+use crate::glub_server_clock::*;
+use crate::glub_server_config::Config;
+use crate::glub_server_metrics::LatencyHistogram;
+use crate::glub_server_persistence::*;
+use crate::glub_server_repository::*;
+use crate::glub_server_seasons::*;
+use crate::glub_server_standings::{ColorRecord, HeadToHead, StandingsEntry, standings};
+use crate::glub_server_tournament::*;
+use crate::glub_server_webhook::*;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// Moves without a capture or pawn move before a strict game is drawn
+pub const STRICT_FIFTY_MOVE_LIMIT: u32 = 100;
+
+/// How many times a position may occur before a strict game is drawn
+pub const STRICT_REPETITION_LIMIT: u32 = 3;
+
+/// How many recent time-to-match samples per mode feed the queue wait estimate
+pub const WAIT_SAMPLE_WINDOW: usize = 50;
+
 #[derive(Debug)]
 pub struct GameStorage {
-    games: HashMap<Uuid, GameState>,
-    queue: Vec<QueuedPlayer>,
+    /// Games and the matchmaking queue
+    repository: Box<dyn GameRepository>,
+    /// Most recent time-to-match durations for each mode, oldest first
+    recent_waits: HashMap<GameMode, VecDeque<Duration>>,
+    default_rules: GameRules,
+    accounts: HashMap<String, PlayerStats>,
+    /// Every game each known player has been part of
+    player_games: HashMap<Uuid, Vec<Uuid>>,
+    /// Sightings of players noted under the read lock, keyed by game and
+    /// player, waiting to be folded into their games on the next presence check
+    presence: DashMap<(Uuid, Uuid), std::time::Instant>,
+    tournaments: HashMap<Uuid, Tournament>,
+    /// Short codes for sharing a game with spectators
+    spectator_codes: HashMap<String, SpectatorCode>,
+    /// How long a spectator code stays valid; zero keeps it for the whole game
+    spectator_code_ttl: Duration,
+    /// How long a private lobby waits for the invited player, and how long its
+    /// code keeps leading to the game after that
+    lobby_ttl: Duration,
+    /// Private games by invite code, until they expire
+    lobbies: HashMap<String, Lobby>,
+    /// Closed seasons, oldest first
+    seasons: Vec<SeasonArchive>,
+    current_season: String,
+    clock: Arc<dyn Clock>,
+    /// Where durable changes are sent when persistence is enabled
+    persist: Option<PersistSender>,
+    archive: Option<ArchiveStore>,
+    /// Where finished game summaries are sent when a webhook is configured
+    webhook: Option<WebhookSender>,
+    /// Where finished game analytics are sent when the analytics log is enabled
+    analytics: Option<AnalyticsSender>,
+    /// Wakes requests waiting for a game to change, here and on other instances
+    changes: ChangeBus,
+    /// Set while the server drains for shutdown; no new games are started
+    maintenance: bool,
+    /// Set by an operator to freeze every game: moves are refused and no move
+    /// points are granted, but games can still be read
+    paused: bool,
+    /// Time between calls to `increment_moves`
+    tick: Duration,
+    /// Silence before a player is flagged as idle
+    presence_warning: Duration,
+    /// How long a finished game stays in memory before it is archived
+    archive_grace: Duration,
+    /// How long a finished game stays in memory when there is no archive to move
+    /// it to; zero keeps it forever
+    finished_retention: Duration,
+    /// Time without a move after which a game is ended; zero never ends one
+    stale_after: Duration,
+    /// Most games that may be in progress at once
+    max_active_games: usize,
+    /// Games without a result, kept up to date as games start and finish and
+    /// shared with the tick task so it can leave the lock alone when idle
+    active_game_count: Arc<AtomicUsize>,
+    /// Ids of games without a result by tick shard, kept alongside the count
+    /// so a shard's pass only looks at its own games
+    shard_games: Vec<BTreeSet<Uuid>>,
+    /// When the tick task last finished a tick
+    heartbeat: Option<std::time::Instant>,
+    /// Silence from the tick task after which the server is no longer ready
+    heartbeat_timeout: Duration,
+    /// How long each tick took to run, for diagnostics
+    tick_timings: LatencyHistogram,
+    last_tick: Option<Duration>,
+    /// Board fetches served from a game's fogged board cache, and those that
+    /// had to compute it. Atomic so fetches served under the read lock count too.
+    fogged_board_hits: AtomicU64,
+    fogged_board_misses: AtomicU64,
 }
 
-#[derive(Debug)]
+/// The name bots play under
+pub const BOT_NAME: &str = "Bot";
+
+/// Characters used in spectator codes, leaving out look-alikes such as 0 and O
+const SPECTATOR_CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const SPECTATOR_CODE_LEN: usize = 6;
+
+/// Tries at a move whose save keeps losing to another instance's change to the
+/// same game
+const MOVE_SAVE_ATTEMPTS: usize = 3;
+
+/// The four middle squares of a board `size` squares across, the usual hill
+/// for king of the hill. On an odd size the hill sits just below and left of
+/// the middle.
+pub fn centre_hill(size: usize) -> [(usize, usize); 4] {
+    let low = (size - 1) / 2;
+    [
+        (low, low),
+        (low, low + 1),
+        (low + 1, low),
+        (low + 1, low + 1),
+    ]
+}
+
+/// Checks a side gives to win a three-check game
+pub const THREE_CHECK_WINS: u32 = 3;
+
+/// How long ago the opponent last moved is rounded down to one of these many
+/// seconds, so the board tells a player the opponent is around without timing
+/// each of their moves
+const ACTIVITY_BUCKETS: [u64; 7] = [0, 5, 10, 30, 60, 120, 300];
+
+/// The game a spectator code leads to
+#[derive(Debug, Clone)]
+pub struct SpectatorCode {
+    pub game_id: Uuid,
+    /// `None` when codes don't expire
+    pub expires_at: Option<std::time::Instant>,
+}
+
+/// A private game waiting for the invited player
+#[derive(Debug, Clone)]
+pub struct Lobby {
+    pub host: QueuedPlayer,
+    /// Where the game starts instead of the standard position
+    pub position: Option<ExtendedBoard>,
+    /// Ranked lobbies play the standard position and count towards accounts
+    pub ranked: bool,
+    /// Material one side gives up at the start
+    pub handicap: Option<HandicapOdds>,
+    pub expires_at: std::time::Instant,
+    /// Set once the invited player has joined
+    pub game_id: Option<Uuid>,
+}
+
+/// The pieces one side of a private game gave up before it started
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HandicapOdds {
+    pub color: PlayerColor,
+    pub handicap: Handicap,
+}
+
+/// Optional rules applied to each new game
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameRules {
+    /// Grant a bonus move point to the first player to capture a piece
+    pub first_blood_bonus: bool,
+    /// Seconds without any request from a player before they forfeit
+    pub abandon_after_seconds: u64,
+    /// Only show a player their own move points in the game status
+    pub hide_opponent_economy: bool,
+    /// Hide squares outside each player's sight
+    pub fog_enabled: bool,
+    pub strictness: Strictness,
+    /// Seconds after the game starts during which spectators see the whole board
+    pub intro_reveal_seconds: u64,
+    /// Shortest gap allowed between two moves by the same player, so banked
+    /// points can't all be spent in one burst
+    pub min_move_interval_millis: u64,
+    /// Maximum number of move points a player can bank
+    pub max_stored_moves: u64,
+    /// Ticks between move point grants
+    pub move_increment_ticks: u64,
+    /// Seconds a Scout's owner keeps sight of each square the Scout moves onto;
+    /// 0 disables beacons
+    pub scout_beacon_seconds: u64,
+    /// Reject moves from both players while a draw offer is pending, instead of
+    /// letting the offering player's next move withdraw it
+    pub freeze_on_draw_offer: bool,
+    /// Move points earned at the cap that fill a player's charge meter. A full
+    /// meter buys one free move that ignores the move interval; 0 disables charge.
+    pub charge_capacity: u64,
+    /// Squares around a capture revealed to both players on their next board
+    /// fetch; 0 disables the pulse
+    pub capture_pulse_radius: u64,
+    pub king_rule: KingRule,
+    /// Random wall squares placed between the armies; 0 disables walls
+    pub wall_count: u64,
+    /// Seed for the wall map; without one each game gets its own map
+    pub wall_seed: Option<u64>,
+    /// Shuffle the back ranks Chess960-style, the same for both sides
+    pub random_back_rank: bool,
+    /// Seed for the shuffled back rank; without one each game gets its own
+    pub back_rank_seed: Option<u64>,
+    /// The pieces each side starts with and where; the usual army when unset.
+    /// A shuffled back rank only applies to the usual army.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub army: Option<ArmyComposition>,
+    /// Shortest gap between two bot moves, so a bot spends its points at a human
+    /// pace instead of the moment they arrive
+    pub bot_move_interval_millis: u64,
+    /// Pawns may also step one square straight back
+    pub pawns_move_backward: bool,
+    /// Pawns may also capture straight ahead
+    pub pawns_capture_forward: bool,
+    /// Hold the game in setup, with no moves and no move points, until both
+    /// players have said they are ready
+    pub require_ready: bool,
+    /// Moves by either player without a capture after which the game is drawn;
+    /// 0 disables the draw
+    pub no_capture_draw_moves: u32,
+    /// A pawn move also restarts the no-capture count, as in chess
+    pub pawn_moves_reset_no_capture_draw: bool,
+    /// Move points both players must have banked before the first move of the
+    /// game is accepted; 0 lets play start at once
+    pub opening_grace_moves: u64,
+    /// Move points a player spends to see the whole board for a few seconds;
+    /// 0 disables the reveal powerup
+    pub reveal_powerup_cost: u64,
+    /// Seconds a bought reveal lasts
+    pub reveal_powerup_seconds: u64,
+    /// Seconds each player has to arrange their own two home ranks in secret
+    /// before play starts; 0 starts from the usual setup
+    pub placement_seconds: u64,
+    /// Squares a king wins on by staying there for `hill_hold_seconds`; empty
+    /// disables king of the hill
+    pub hill_squares: Vec<(usize, usize)>,
+    /// Seconds a king has to stay on the hill to win, so a king can't win by
+    /// stepping on and off it in one burst of moves
+    pub hill_hold_seconds: u64,
+    /// Giving check three times wins the game
+    pub three_check: bool,
+    /// Every capture explodes, taking the capturing piece and every piece
+    /// other than a pawn next to it off the board
+    pub atomic: bool,
+    pub mode: GameMode,
+    /// Rows and columns on the board. Armies keep to the middle eight columns
+    /// of a wider board, and each side to its own edge of a longer one.
+    pub board_size: usize,
+}
+
+impl GameRules {
+    pub fn pawn_rules(&self) -> PawnRules {
+        PawnRules {
+            move_backward: self.pawns_move_backward,
+            capture_forward: self.pawns_capture_forward,
+        }
+    }
+
+    /// Whether moves may not leave the own king in check. Checkmate games always
+    /// apply check rules, whatever their strictness.
+    pub fn check_rules(&self) -> bool {
+        self.strictness.check_rules() || self.king_rule == KingRule::Checkmate
+    }
+
+    /// Refuse combinations of rules that can't be played together. A blast
+    /// takes kings off the board, which checkmate games promise never happens.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.atomic && self.king_rule == KingRule::Checkmate {
+            return Err("Atomic games can't be won by checkmate".to_string());
+        }
+        Ok(())
+    }
+}
+
+// Median of some durations in seconds, `None` without any
+fn median_seconds(waits: impl Iterator<Item = Duration>) -> Option<f64> {
+    let mut waits: Vec<Duration> = waits.collect();
+    waits.sort();
+    match waits.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(waits[len / 2].as_secs_f64()),
+        len => Some((waits[len / 2 - 1] + waits[len / 2]).as_secs_f64() / 2.0),
+    }
+}
+
+/// How the right to move is handed out
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    /// Each player moves whenever they have a move point
+    #[default]
+    Realtime,
+    /// Players take turns one move at a time, white first. Move points are
+    /// neither granted nor spent.
+    TurnBased,
+}
+
+impl GameMode {
+    pub const ALL: [GameMode; 2] = [GameMode::Realtime, GameMode::TurnBased];
+
+    /// `rules` with this mode's preset applied. Turn-based games drop the rules
+    /// built on the move point economy.
+    pub fn apply(self, rules: GameRules) -> GameRules {
+        match self {
+            GameMode::Realtime => GameRules {
+                mode: self,
+                ..rules
+            },
+            GameMode::TurnBased => GameRules {
+                mode: self,
+                first_blood_bonus: false,
+                min_move_interval_millis: 0,
+                charge_capacity: 0,
+                opening_grace_moves: 0,
+                reveal_powerup_cost: 0,
+                ..rules
+            },
+        }
+    }
+}
+
+/// How a game is won over the board
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KingRule {
+    /// Take the opponent's king
+    #[default]
+    CaptureKing,
+    /// Leave the opponent in check with no move out of it; kings are never taken
+    Checkmate,
+}
+
+/// How much of classic chess law is enforced on top of the base piece rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Strictness {
+    /// Piece movement rules only
+    #[default]
+    Lenient,
+    /// Moves may not leave the own king in check
+    Standard,
+    /// Check rules plus stalemate, threefold repetition, fifty-move and
+    /// insufficient material draws
+    Strict,
+}
+
+impl Strictness {
+    pub fn check_rules(&self) -> bool {
+        *self != Strictness::Lenient
+    }
+
+    pub fn draw_rules(&self) -> bool {
+        *self == Strictness::Strict
+    }
+}
+
+impl Default for GameRules {
+    fn default() -> Self {
+        Self {
+            first_blood_bonus: false,
+            abandon_after_seconds: 90,
+            hide_opponent_economy: true,
+            fog_enabled: true,
+            strictness: Strictness::default(),
+            intro_reveal_seconds: 0,
+            min_move_interval_millis: 0,
+            max_stored_moves: 5,
+            move_increment_ticks: 3,
+            scout_beacon_seconds: 0,
+            freeze_on_draw_offer: false,
+            charge_capacity: 0,
+            capture_pulse_radius: 0,
+            king_rule: KingRule::default(),
+            wall_count: 0,
+            wall_seed: None,
+            random_back_rank: false,
+            back_rank_seed: None,
+            army: None,
+            bot_move_interval_millis: 2000,
+            pawns_move_backward: false,
+            pawns_capture_forward: false,
+            require_ready: false,
+            no_capture_draw_moves: 0,
+            pawn_moves_reset_no_capture_draw: false,
+            opening_grace_moves: 0,
+            reveal_powerup_cost: 0,
+            reveal_powerup_seconds: 3,
+            placement_seconds: 0,
+            hill_squares: Vec::new(),
+            hill_hold_seconds: 3,
+            three_check: false,
+            atomic: false,
+            mode: GameMode::Realtime,
+            board_size: BOARD_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct QueuedPlayer {
     pub id: Uuid,
     pub name: String,
+    #[serde(with = "instant_as_age")]
+    pub joined_at: std::time::Instant,
+    /// Players are only matched with others who asked for the same mode
+    #[serde(default)]
+    pub mode: GameMode,
 }
 
-#[derive(Debug)]
+/// A game in memory. Presence timers are not persisted and restart from the moment
+/// a game is loaded; the game's age is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
     pub game: Game,
     pub board: ExtendedBoard,
+    /// Bumped on every successful move
+    pub version: u64,
     pub player1: PlayerInfo,
     pub player2: PlayerInfo,
+    /// Drives timers; its age survives a snapshot
+    #[serde(with = "instant_as_age")]
     pub created_at: std::time::Instant,
+    /// When the game started by the calendar, for display
+    #[serde(with = "wall_time_iso8601", default = "std::time::SystemTime::now")]
+    pub started_at: std::time::SystemTime,
+    /// The last move by either player, or the start of the game
+    #[serde(with = "instant_as_age", default = "std::time::Instant::now")]
+    pub last_activity: std::time::Instant,
+    pub rules: GameRules,
+    pub first_blood_awarded: bool,
+    pub captured_pieces: Vec<ExtendedSlot>,
+    pub history: Vec<MoveRecord>,
+    /// How often each position has occurred, keyed by `ExtendedBoard::position_key`
+    pub position_counts: HashMap<u64, u32>,
+    /// Moves since the last capture or pawn move
+    pub halfmove_clock: u32,
+    /// Moves since the last capture, for `GameRules::no_capture_draw_moves`
+    #[serde(default)]
+    pub moves_since_capture: u32,
+    pub result: Option<GameResult>,
+    /// The tournament this game decides a match in, if any
+    pub tournament_id: Option<Uuid>,
+    /// The color that offered a draw, until the opponent answers
+    pub draw_offer: Option<PlayerColor>,
+    /// Started from an arbitrary position; the result leaves accounts alone
+    #[serde(default)]
+    pub unrated: bool,
+    /// The position a seeded game started from
+    #[serde(default)]
+    pub start_board: Option<ExtendedBoard>,
+    /// The custom position a private lobby started the game from, as a board
+    /// string
+    #[serde(default)]
+    pub start_position: Option<String>,
+    /// The color the server plays in a game against a bot
+    #[serde(default)]
+    pub bot_color: Option<PlayerColor>,
+    /// Whose move it is in a turn-based game; `None` in a realtime one
+    #[serde(default)]
+    pub turn: Option<PlayerColor>,
+    /// The shuffled back rank both sides started with, when the rules asked for one
+    #[serde(default)]
+    pub back_rank: Option<BackRank>,
+    /// The material one side gave up in a handicap game
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handicap: Option<HandicapOdds>,
+    /// Set once the placement phase is over, or straight away for games that
+    /// don't have one
+    #[serde(default)]
+    pub placement_done: bool,
+    /// Arrangements submitted during the placement phase, put on the board
+    /// when it ends
+    #[serde(default)]
+    pub placements: HashMap<PlayerColor, Vec<OccupiedSquare>>,
+    /// Ids that held a seat in this game before it was reassigned
+    #[serde(default)]
+    pub removed_players: BTreeSet<Uuid>,
+    /// Whether each player has said they are ready, for games that require it
+    #[serde(default)]
+    pub player1_ready: bool,
+    #[serde(default)]
+    pub player2_ready: bool,
+    /// Checks each player has given, counted in three-check games
+    #[serde(default)]
+    pub player1_checks: u32,
+    #[serde(default)]
+    pub player2_checks: u32,
+    pub events: Vec<GameEvent>,
+    #[serde(skip, default = "std::time::Instant::now")]
+    pub player1_last_seen: std::time::Instant,
+    #[serde(skip, default = "std::time::Instant::now")]
+    pub player2_last_seen: std::time::Instant,
+    pub player1_idle_warned: bool,
+    pub player2_idle_warned: bool,
+    #[serde(skip)]
+    pub player1_last_move_at: Option<std::time::Instant>,
+    #[serde(skip)]
+    pub player2_last_move_at: Option<std::time::Instant>,
+    /// When the game reached its result, for eviction to the archive
+    #[serde(skip)]
+    pub finished_at: Option<std::time::Instant>,
+    /// Legal moves per color, keyed by the board version and number of active
+    /// beacons and capture pulses they were computed with
+    #[serde(skip)]
+    pub legal_moves_cache: HashMap<PlayerColor, ((u64, usize), Vec<LegalMove>)>,
+    /// The board each color was last served, keyed like `legal_moves_cache`
+    #[serde(skip)]
+    pub fogged_board_cache: HashMap<PlayerColor, ((u64, usize), Arc<FoggedBoard>)>,
+    /// Squares each color can still see after a Scout moved through them
+    #[serde(skip)]
+    pub beacons: HashMap<PlayerColor, Vec<Beacon>>,
+    /// Capture squares each color hasn't fetched its board since
+    #[serde(skip)]
+    pub capture_pulses: HashMap<PlayerColor, Vec<(usize, usize)>>,
+    /// When each color's bought full-board reveal runs out
+    #[serde(skip)]
+    pub reveals: HashMap<PlayerColor, std::time::Instant>,
+    /// Kings standing on the hill, by color
+    #[serde(skip)]
+    pub hill_holds: HashMap<PlayerColor, HillHold>,
 }
 
-#[derive(Debug, Clone)]
-pub struct PlayerInfo {
-    pub id: Uuid,
-    pub name: String,
-    pub color: PlayerColor,
+/// Temporary sight of one square, left behind by a Scout
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Beacon {
+    pub position: (usize, usize),
+    pub expires_at: std::time::Instant,
 }
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum PlayerColor {
-    White,
-    Black,
+/// A king on the hill, since it last stepped onto it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HillHold {
+    pub since: std::time::Instant,
+    /// Whether the opponent has been told, which waits until they can see it
+    pub announced: bool,
 }
 
-#[derive(Serialize)]
-pub struct FoggedBoard {
-    pub slots: [[Option<VisibleSlot>; 8]; 8],
-    pub your_color: PlayerColor,
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LegalMove {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
 }
 
-#[derive(Serialize, Clone, Debug)]
-pub struct VisibleSlot {
-    pub piece: ChestPiece,
+/// Notable things that happened during a game, in order
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum GameEvent {
+    PlayerIdle {
+        color: PlayerColor,
+    },
+    PlayerReturned {
+        color: PlayerColor,
+    },
+    MovePointCapped {
+        color: PlayerColor,
+    },
+    PlayerReady {
+        color: PlayerColor,
+    },
+    DrawOffered {
+        color: PlayerColor,
+    },
+    /// The offer was declined by the opponent or withdrawn by `color`
+    DrawOfferClosed {
+        color: PlayerColor,
+    },
+    /// `color` sent in its arrangement for the placement phase
+    PlacementSubmitted {
+        color: PlayerColor,
+    },
+    /// The placement phase is over and the arrangements are on the board
+    PlacementOver,
+    /// An operator gave `color`'s seat to a new player
+    PlayerReassigned {
+        color: PlayerColor,
+    },
+    /// `color` spent move points on a full-board reveal
+    RevealPowerupUsed {
+        color: PlayerColor,
+    },
+    /// `color`'s king is on the hill where its opponent can see it
+    KingOnHill {
+        color: PlayerColor,
+    },
+    /// A capture on `square` exploded in an atomic game. `destroyed` lists
+    /// every piece lost, the captured one first, so each player learns of
+    /// losses the fog hid from them.
+    Explosion {
+        square: (usize, usize),
+        destroyed: Vec<OccupiedSquare>,
+    },
+    /// `color` gave check in a three-check game, its `checks`th. `from` are
+    /// the checking pieces' squares, given to the defender even under fog.
+    CheckGiven {
+        color: PlayerColor,
+        checks: u32,
+        from: Vec<(usize, usize)>,
+    },
+    GameOver {
+        result: GameResult,
+    },
+}
+
+/// A player's answer in the draw offer flow
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DrawAction {
+    /// Propose a draw; accepts the opponent's offer if one is pending
+    Offer,
+    Accept,
+    /// Turn down the opponent's offer or withdraw your own
+    Decline,
+}
+
+/// A single successful move, in the order it was played
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MoveRecord {
     pub color: PlayerColor,
+    pub piece: ChestPiece,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub captured: Option<ChestPiece>,
+    /// What the capture's blast took out besides the captured piece, in atomic
+    /// games
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exploded: Vec<OccupiedSquare>,
 }
 
-impl GameStorage {
-    pub fn new() -> Self {
-        Self {
-            games: HashMap::new(),
-            queue: Vec::new(),
+/// Everything needed to reconstruct a finished game elsewhere
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct GameArchive {
+    pub game_id: Uuid,
+    pub player1: PlayerInfo,
+    pub player2: PlayerInfo,
+    pub rules: GameRules,
+    pub history: Vec<MoveRecord>,
+    pub final_board: ExtendedBoard,
+    /// Where the game started, when not from the standard position
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_board: Option<ExtendedBoard>,
+    /// The shuffled back rank the game started with, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub back_rank: Option<BackRank>,
+    /// The material one side gave up, in a handicap game
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub handicap: Option<HandicapOdds>,
+    pub result: GameResult,
+}
+
+impl GameArchive {
+    /// Status of an archived game, which only keeps its result
+    pub fn status(self, player_id: Option<Uuid>) -> Result<crate::GameStatus, String> {
+        if let Some(player_id) = player_id
+            && self.player1.id != player_id
+            && self.player2.id != player_id
+        {
+            return Err("Player not in this game".to_string());
         }
-    }
 
-    pub fn join_queue(&mut self, player_name: String) -> Result<crate::JoinQueueResponse, String> {
-        let player_id = Uuid::new_v4();
+        Ok(crate::GameStatus {
+            game_id: self.game_id,
+            player1_moves: None,
+            player2_moves: None,
+            player1_at_cap: None,
+            player2_at_cap: None,
+            player1_charge: None,
+            player2_charge: None,
+            current_turn: None,
+            result: Some(self.result),
+            phase: GamePhase::Finished,
+            in_check: None,
+            view_hash: None,
+            back_rank: self.back_rank,
+            handicap: self.handicap,
+            start_position: None,
+            draw_offer: None,
+            player1_checks: None,
+            player2_checks: None,
+            created_at: None,
+            age_seconds: None,
+        })
+    }
 
-        // Check if there's already a player waiting
-        if let Some(waiting_player) = self.queue.pop() {
-            // Create a new game with both players
-            let game_id = self.create_game(
-                waiting_player,
-                QueuedPlayer {
-                    id: player_id,
-                    name: player_name,
-                },
-            )?;
+    pub fn start_board(self) -> crate::StartBoard {
+        start_board_of(self.game_id, self.start_board, &self.rules)
+    }
 
-            Ok(crate::JoinQueueResponse {
-                player_id,
-                game_id: Some(game_id),
-                message: "Game started!".to_string(),
-            })
-        } else {
-            // Add to queue
-            self.queue.push(QueuedPlayer {
-                id: player_id,
-                name: player_name,
-            });
+    pub fn move_history(&self, since: usize) -> crate::MoveHistory {
+        history_since(self.game_id, &self.history, since)
+    }
 
-            Ok(crate::JoinQueueResponse {
-                player_id,
-                game_id: None,
-                message: "Added to queue, waiting for opponent...".to_string(),
-            })
-        }
+    pub fn verify(self) -> crate::VerifyGameResponse {
+        verify_replay(
+            self.game_id,
+            &self.history,
+            self.start_board,
+            &self.final_board,
+            &self.rules,
+        )
     }
+}
 
-    fn create_game(
-        &mut self,
-        player1: QueuedPlayer,
-        player2: QueuedPlayer,
-    ) -> Result<Uuid, String> {
-        let game_id = Uuid::new_v4();
+// The board a game started from, the standard position unless it had its own
+fn start_board_of(
+    game_id: Uuid,
+    start_board: Option<ExtendedBoard>,
+    rules: &GameRules,
+) -> crate::StartBoard {
+    let custom = start_board.is_some();
+    let mut board = start_board.unwrap_or_else(|| {
         let mut board = ExtendedBoard::new();
         board.setup_initial_position();
+        board
+    });
+    board.set_pawn_rules(rules.pawn_rules());
 
-        let game_state = GameState {
-            game: Game {
-                id: game_id,
-                player1_remaining_moves: 1,          // Start with 1 move
-                player1_move_increment_countdown: 3, // 3 seconds until next move point
-                player2_remaining_moves: 1,
-                player2_move_increment_countdown: 3,
-            },
-            board,
-            player1: PlayerInfo {
-                id: player1.id,
-                name: player1.name,
-                color: PlayerColor::White,
-            },
-            player2: PlayerInfo {
-                id: player2.id,
-                name: player2.name,
-                color: PlayerColor::Black,
-            },
-            created_at: std::time::Instant::now(),
-        };
-
-        self.games.insert(game_id, game_state);
-        Ok(game_id)
+    crate::StartBoard {
+        game_id,
+        board,
+        custom,
     }
+}
 
-    pub fn get_fogged_board(&self, game_id: Uuid, player_id: Uuid) -> Result<FoggedBoard, String> {
-        let game_state = self.games.get(&game_id).ok_or("Game not found")?;
+fn history_since(game_id: Uuid, history: &[MoveRecord], since: usize) -> crate::MoveHistory {
+    crate::MoveHistory {
+        game_id,
+        total: history.len(),
+        moves: history.iter().skip(since).cloned().collect(),
+    }
+}
 
-        let player_color = if game_state.player1.id == player_id {
-            game_state.player1.color.clone()
-        } else if game_state.player2.id == player_id {
-            game_state.player2.color.clone()
+fn verify_replay(
+    game_id: Uuid,
+    history: &[MoveRecord],
+    start_board: Option<ExtendedBoard>,
+    final_board: &ExtendedBoard,
+    rules: &GameRules,
+) -> crate::VerifyGameResponse {
+    let outcome = replay_history(history, start_board, rules).and_then(|board| {
+        if board == *final_board {
+            Ok(())
         } else {
-            return Err("Player not in this game".to_string());
-        };
+            Err("Replayed board does not match the stored board".to_string())
+        }
+    });
 
-        let visible_positions = game_state.board.get_visible_positions(&player_color);
-        let mut fogged_slots: [[Option<VisibleSlot>; 8]; 8] = Default::default();
+    crate::VerifyGameResponse {
+        game_id,
+        moves_checked: history.len(),
+        valid: outcome.is_ok(),
+        error: outcome.err(),
+    }
+}
 
-        for row in 0..8 {
-            for col in 0..8 {
-                if visible_positions.contains(&(row, col)) {
-                    if let Some(piece_info) = &game_state.board.slots[row][col] {
-                        fogged_slots[row][col] = Some(VisibleSlot {
-                            piece: piece_info.piece,
-                            color: piece_info.color.clone(),
-                        });
-                    }
-                }
-            }
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct GameResult {
+    /// `None` means the game was drawn
+    pub winner: Option<PlayerColor>,
+    pub reason: GameEndReason,
+}
+
+/// One of the groups games are split into for the tick, picked by game id so a
+/// game always lands in the same group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TickShard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl TickShard {
+    /// Every game at once
+    pub const ALL: TickShard = TickShard { index: 0, count: 1 };
+
+    /// The shard of `count` that `game_id` falls in
+    pub fn of(game_id: Uuid, count: u64) -> TickShard {
+        TickShard {
+            index: (game_id.as_u128() % count as u128) as u64,
+            count,
         }
+    }
 
-        Ok(FoggedBoard {
-            slots: fogged_slots,
-            your_color: player_color,
-        })
+    pub fn contains(&self, game_id: Uuid) -> bool {
+        game_id.as_u128() % self.count as u128 == self.index as u128
     }
 
-    pub fn make_move(
-        &mut self,
-        game_id: Uuid,
-        move_req: crate::MoveRequest,
-    ) -> Result<crate::MoveResponse, String> {
-        let game_state = self.games.get_mut(&game_id).ok_or("Game not found")?;
+    /// Whether this group finishes a full tick
+    pub fn is_last(&self) -> bool {
+        self.index + 1 == self.count
+    }
+}
 
-        let (is_player1, remaining_moves) = if game_state.player1.id == move_req.player_id {
-            (true, game_state.game.player1_remaining_moves)
-        } else if game_state.player2.id == move_req.player_id {
-            (false, game_state.game.player2_remaining_moves)
-        } else {
-            return Err("Player not in this game".to_string());
-        };
+/// Where a game is in its life
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    /// Each player arranging their home ranks where the other can't see
+    Placement,
+    /// Waiting for both players to be ready
+    Setup,
+    Playing,
+    Finished,
+}
 
-        if remaining_moves == 0 {
-            return Ok(crate::MoveResponse {
-                success: false,
-                message: "No moves remaining".to_string(),
-                remaining_moves: 0,
-            });
-        }
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GameEndReason {
+    KingCaptured,
+    Checkmate,
+    Abandoned,
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    /// The configured number of moves passed without a capture
+    NoCaptureLimit,
+    InsufficientMaterial,
+    Resigned,
+    DrawAgreed,
+    /// Nobody moved for too long
+    Inactive,
+    /// A king stayed on the hill long enough
+    HillControlled,
+    /// One side gave check three times
+    ThreeChecks,
+    /// The blast of a capture took out a king
+    Explosion,
+}
 
-        let player_color = if is_player1 {
-            &game_state.player1.color
-        } else {
-            &game_state.player2.color
-        };
+/// Per-account record, keyed by player name
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlayerStats {
+    pub rating: f64,
+    /// Results since the current season started; the other counters are all-time
+    pub season: SeasonStats,
+    pub games_played: u64,
+    pub wins: u64,
+    pub losses: u64,
+    pub draws: u64,
+    pub current_win_streak: u64,
+    pub best_win_streak: u64,
+    pub achievements: Vec<Achievement>,
+    #[serde(default)]
+    pub color_record: ColorRecord,
+    /// Results against each opponent, by name
+    #[serde(default)]
+    pub head_to_head: BTreeMap<String, HeadToHead>,
+}
 
-        // Validate and execute the move
-        match game_state
-            .board
-            .make_move(move_req.from, move_req.to, player_color)
-        {
-            Ok(_) => {
-                // Consume a move point
-                if is_player1 {
-                    game_state.game.player1_remaining_moves -= 1;
-                } else {
-                    game_state.game.player2_remaining_moves -= 1;
-                }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerInfo {
+    pub id: Uuid,
+    pub name: String,
+    pub color: PlayerColor,
+}
 
-                let remaining = if is_player1 {
-                    game_state.game.player1_remaining_moves
-                } else {
-                    game_state.game.player2_remaining_moves
-                };
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlayerColor {
+    White,
+    Black,
+}
 
-                Ok(crate::MoveResponse {
-                    success: true,
-                    message: "Move successful".to_string(),
-                    remaining_moves: remaining,
-                })
-            }
-            Err(e) => Ok(crate::MoveResponse {
-                success: false,
-                message: e,
-                remaining_moves: remaining_moves,
-            }),
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FoggedBoard {
+    pub slots: Vec<Vec<Option<VisibleSlot>>>,
+    /// Rows and columns, so clients needn't assume an 8x8 board
+    pub dims: (usize, usize),
+    pub your_color: PlayerColor,
+    /// Impassable squares, always visible
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub walls: BTreeSet<(usize, usize)>,
+    /// Changes only when what the player sees changes, unlike the board version
+    pub view_hash: String,
+    /// Roughly how many seconds ago the opponent last moved, or none before
+    /// their first move. Left out of `view_hash`.
+    pub opponent_last_active: Option<u64>,
+}
+
+impl FoggedBoard {
+    pub fn new(
+        slots: Vec<Vec<Option<VisibleSlot>>>,
+        your_color: PlayerColor,
+        walls: BTreeSet<(usize, usize)>,
+        opponent_last_active: Option<u64>,
+    ) -> Self {
+        Self {
+            view_hash: view_hash(&slots, &walls),
+            dims: (slots.len(), slots.first().map_or(0, Vec::len)),
+            slots,
+            your_color,
+            walls,
+            opponent_last_active,
         }
     }
+}
 
-    pub fn get_game_status(&self, game_id: Uuid) -> Result<crate::GameStatus, String> {
-        let game_state = self.games.get(&game_id).ok_or("Game not found")?;
+/// Hex digest of a player's view, as 16 characters so JavaScript clients can
+/// compare it without losing precision
+pub fn view_hash(slots: &[Vec<Option<VisibleSlot>>], walls: &BTreeSet<(usize, usize)>) -> String {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    slots.hash(&mut hasher);
+    walls.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
 
-        Ok(crate::GameStatus {
-            game_id,
-            player1_moves: game_state.game.player1_remaining_moves,
-            player2_moves: game_state.game.player2_remaining_moves,
-            current_turn: None, // In this system, both players can move simultaneously
-        })
+// The back rank and pawn rank each color starts on, on a board of `rows` rows
+fn home_rows(color: PlayerColor, rows: usize) -> [usize; 2] {
+    match color {
+        PlayerColor::White => [0, 1],
+        PlayerColor::Black => [rows - 1, rows - 2],
     }
+}
 
-    pub fn increment_moves(&mut self) {
-        for game_state in self.games.values_mut() {
-            // Player 1 move increment
-            if game_state.game.player1_move_increment_countdown > 0 {
-                game_state.game.player1_move_increment_countdown -= 1;
-            } else {
-                game_state.game.player1_remaining_moves = std::cmp::min(
-                    game_state.game.player1_remaining_moves + 1,
-                    5, // Max 5 moves stored
-                );
-                game_state.game.player1_move_increment_countdown = 3; // Reset to 3 seconds
-            }
+// A short code that is easy to read out, for spectator codes and lobbies
+fn random_code() -> String {
+    let mut bits = Uuid::new_v4().as_u128();
+    (0..SPECTATOR_CODE_LEN)
+        .map(|_| {
+            let index = (bits % SPECTATOR_CODE_ALPHABET.len() as u128) as usize;
+            bits /= SPECTATOR_CODE_ALPHABET.len() as u128;
+            SPECTATOR_CODE_ALPHABET[index] as char
+        })
+        .collect()
+}
 
-            // Player 2 move increment
-            if game_state.game.player2_move_increment_countdown > 0 {
-                game_state.game.player2_move_increment_countdown -= 1;
-            } else {
-                game_state.game.player2_remaining_moves = std::cmp::min(
-                    game_state.game.player2_remaining_moves + 1,
-                    5, // Max 5 moves stored
-                );
-                game_state.game.player2_move_increment_countdown = 3; // Reset to 3 seconds
-            }
+// What any board a game starts from must satisfy: one king a side, and walls
+// that neither cover a piece nor box in a king
+fn validate_start_board(board: &ExtendedBoard) -> Result<(), String> {
+    board.validate_walls()?;
+    for color in [PlayerColor::White, PlayerColor::Black] {
+        let kings = board.count(&color, ChestPiece::King);
+        if kings != 1 {
+            return Err(format!("{:?} must have exactly one king", color));
         }
     }
+    Ok(())
 }
 
-// Implement Serialize for PlayerColor
-impl Serialize for PlayerColor {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        match self {
-            PlayerColor::White => serializer.serialize_str("white"),
-            PlayerColor::Black => serializer.serialize_str("black"),
-        }
+/// The board as seen from outside the game
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpectatorBoard {
+    pub slots: Vec<Vec<Option<VisibleSlot>>>,
+    /// Whether the pieces are hidden until the game ends
+    pub fogged: bool,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub walls: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct VisibleSlot {
+    pub piece: ChestPiece,
+    pub color: PlayerColor,
+}
+
+/// A visible piece and where it stands, for the compact board format and custom
+/// starting positions
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OccupiedSquare {
+    pub row: usize,
+    pub col: usize,
+    pub piece: ChestPiece,
+    pub color: PlayerColor,
+}
+
+// The pieces a blast took out, as recorded in the move history
+fn blast_victims(destroyed: Vec<((usize, usize), ExtendedSlot)>) -> Vec<OccupiedSquare> {
+    destroyed
+        .into_iter()
+        .map(|((row, col), slot)| OccupiedSquare {
+            row,
+            col,
+            piece: slot.piece,
+            color: slot.color,
+        })
+        .collect()
+}
+
+/// Replay recorded moves from `start_board`, or the standard position, checking
+/// that each one was legal and moved and captured what its record says
+pub fn replay_history(
+    history: &[MoveRecord],
+    start_board: Option<ExtendedBoard>,
+    rules: &GameRules,
+) -> Result<ExtendedBoard, String> {
+    let check_rules = rules.check_rules();
+    let mut board = start_board.unwrap_or_else(|| {
+        let mut board = ExtendedBoard::new();
+        board.setup_initial_position();
+        board
+    });
+    board.set_pawn_rules(rules.pawn_rules());
+
+    for (index, record) in history.iter().enumerate() {
+        let number = index + 1;
+        if check_rules && board.leaves_king_in_check(record.from, record.to, &record.color) {
+            return Err(format!(
+                "Move {} leaves the {:?} king in check",
+                number, record.color
+            ));
+        }
+        if rules.atomic && board.blast_hits_own_king(record.from, record.to, &record.color) {
+            return Err(format!(
+                "Move {} blows up the {:?} king",
+                number, record.color
+            ));
+        }
+
+        let captured = board
+            .make_move(record.from, record.to, &record.color)
+            .map_err(|e| format!("Move {} is illegal: {}", number, e))?;
+
+        let moved = board.slot(record.to).map(|slot| slot.piece);
+        if moved != Some(record.piece) {
+            return Err(format!(
+                "Move {} moved {:?}, but {:?} was recorded",
+                number, moved, record.piece
+            ));
+        }
+        let captured = captured.map(|slot| slot.piece);
+        if captured != record.captured {
+            return Err(format!(
+                "Move {} captured {:?}, but {:?} was recorded",
+                number, captured, record.captured
+            ));
+        }
+        let exploded = if rules.atomic && captured.is_some() {
+            blast_victims(board.explode(record.to))
+        } else {
+            Vec::new()
+        };
+        if exploded != record.exploded {
+            return Err(format!(
+                "Move {} blew up {:?}, but {:?} was recorded",
+                number, exploded, record.exploded
+            ));
+        }
+        let king_fell = captured == Some(ChestPiece::King)
+            || exploded
+                .iter()
+                .any(|victim| victim.piece == ChestPiece::King);
+        if king_fell && number < history.len() {
+            return Err(format!(
+                "Moves recorded after the king fell on move {}",
+                number
+            ));
+        }
+    }
+
+    Ok(board)
+}
+
+/// The occupied squares of a board grid, row by row
+pub fn occupied_squares(slots: &[Vec<Option<VisibleSlot>>]) -> Vec<OccupiedSquare> {
+    let mut squares = Vec::new();
+    for (row, cols) in slots.iter().enumerate() {
+        for (col, slot) in cols.iter().enumerate() {
+            if let Some(slot) = slot {
+                squares.push(OccupiedSquare {
+                    row,
+                    col,
+                    piece: slot.piece,
+                    color: slot.color,
+                });
+            }
+        }
+    }
+    squares
+}
+
+impl GameStorage {
+    pub fn new() -> Self {
+        Self::with_config(&Config::default())
+    }
+
+    pub fn with_config(config: &Config) -> Self {
+        Self {
+            repository: Box::new(InMemoryRepository::new()),
+            recent_waits: HashMap::new(),
+            default_rules: config.game_rules(),
+            accounts: HashMap::new(),
+            player_games: HashMap::new(),
+            presence: DashMap::new(),
+            tournaments: HashMap::new(),
+            spectator_codes: HashMap::new(),
+            spectator_code_ttl: Duration::from_secs(config.spectator_code_ttl_seconds),
+            lobby_ttl: Duration::from_secs(config.lobby_ttl_seconds),
+            lobbies: HashMap::new(),
+            seasons: Vec::new(),
+            current_season: "Season 1".to_string(),
+            clock: Arc::new(SystemClock),
+            persist: None,
+            archive: None,
+            webhook: None,
+            analytics: None,
+            changes: ChangeBus::new(),
+            maintenance: false,
+            paused: false,
+            tick: config.tick(),
+            presence_warning: Duration::from_secs(config.presence_warning_seconds),
+            archive_grace: Duration::from_secs(config.archive_grace_seconds),
+            finished_retention: Duration::from_secs(config.finished_retention_seconds),
+            stale_after: Duration::from_secs(config.stale_game_seconds),
+            max_active_games: config.max_active_games,
+            active_game_count: Arc::new(AtomicUsize::new(0)),
+            shard_games: vec![BTreeSet::new(); config.tick_shards.max(1) as usize],
+            heartbeat: None,
+            tick_timings: LatencyHistogram::default(),
+            last_tick: None,
+            fogged_board_hits: AtomicU64::new(0),
+            fogged_board_misses: AtomicU64::new(0),
+            heartbeat_timeout: Duration::from_secs(config.heartbeat_timeout_seconds),
+        }
+    }
+
+    /// Share game changes with other instances
+    pub fn with_changes(mut self, changes: ChangeBus) -> Self {
+        self.changes = changes;
+        self
+    }
+
+    pub fn changes(&self) -> &ChangeBus {
+        &self.changes
+    }
+
+    // Tell waiting requests that a game moved on
+    fn publish_change(&self, game_id: Uuid) {
+        if let Some(game_state) = self.repository.get(game_id) {
+            self.changes.publish(game_id, game_state.version);
+        }
+    }
+
+    /// Reload a game another instance changed
+    pub fn refresh_game(&mut self, game_id: Uuid) {
+        self.repository.refresh(game_id);
+        self.recount_active_games();
+    }
+
+    /// Keep games and the queue somewhere other than process memory
+    pub fn with_repository(mut self, repository: Box<dyn GameRepository>) -> Self {
+        self.repository = repository;
+        self.recount_active_games();
+        self
+    }
+
+    /// The number of games without a result, readable without the storage lock
+    pub fn active_game_count(&self) -> Arc<AtomicUsize> {
+        Arc::clone(&self.active_game_count)
+    }
+
+    // Count and index unfinished games from scratch, for when games arrive in
+    // bulk
+    fn recount_active_games(&mut self) {
+        for games in &mut self.shard_games {
+            games.clear();
+        }
+        let game_ids: Vec<Uuid> = self
+            .repository
+            .active_games()
+            .map(|game_state| game_state.game.id)
+            .collect();
+        self.active_game_count
+            .store(game_ids.len(), Ordering::Relaxed);
+        for game_id in game_ids {
+            self.shard_games_of(game_id).insert(game_id);
+        }
+    }
+
+    // The indexed games in the tick shard `game_id` falls in
+    fn shard_games_of(&mut self, game_id: Uuid) -> &mut BTreeSet<Uuid> {
+        let shard = TickShard::of(game_id, self.shard_games.len() as u64);
+        &mut self.shard_games[shard.index as usize]
+    }
+
+    /// Send account updates, finished games and checkpoints to a persistence writer
+    pub fn with_persistence(mut self, persist: PersistSender) -> Self {
+        self.persist = Some(persist);
+        self
+    }
+
+    /// Move finished games out of memory into an on-disk archive
+    pub fn with_archive(mut self, archive: ArchiveStore) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Post a summary of every finished game through a webhook
+    pub fn with_webhook(mut self, webhook: WebhookSender) -> Self {
+        self.webhook = Some(webhook);
+        self
+    }
+
+    /// Append a record of every finished game to the analytics log
+    pub fn with_analytics(mut self, analytics: AnalyticsSender) -> Self {
+        self.analytics = Some(analytics);
+        self
+    }
+
+    /// Stop sending to the persistence and archive writers so they can finish
+    /// what they have queued
+    pub fn stop_persistence(&mut self) {
+        self.persist = None;
+        self.archive = None;
+    }
+
+    pub fn set_maintenance(&mut self, maintenance: bool) {
+        self.maintenance = maintenance;
+    }
+
+    pub fn in_maintenance(&self) -> bool {
+        self.maintenance
+    }
+
+    /// Freeze or unfreeze every game. Time spent paused doesn't count towards
+    /// ending a game for inactivity.
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused && !paused {
+            let now = self.clock.now();
+            for game_id in self.active_game_ids() {
+                self.with_game_mut(game_id, |game_state| game_state.last_activity = now);
+            }
+        }
+        self.paused = paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Called by the tick task after every tick with how long the tick took, so
+    /// a stuck task shows up in readiness
+    pub fn record_heartbeat(&mut self, tick_took: Duration) {
+        self.heartbeat = Some(self.clock.now());
+        self.tick_timings.observe(tick_took);
+        self.last_tick = Some(tick_took);
+    }
+
+    /// What is taking up memory: counts and rough sizes per category, the
+    /// `top` games with the most events and history, and tick timings
+    pub fn diagnostics(&self, top: usize) -> crate::Diagnostics {
+        let mut categories = BTreeMap::new();
+        let mut add = |name: &str, count: usize, approx_bytes: usize| {
+            categories.insert(
+                name.to_string(),
+                crate::CategoryUsage {
+                    count,
+                    approx_bytes,
+                },
+            );
+        };
+
+        let (finished, active): (Vec<&GameState>, Vec<&GameState>) = self
+            .repository
+            .games()
+            .partition(|game_state| game_state.result.is_some());
+        for (name, games) in [
+            ("active_games", &active),
+            ("finished_games_awaiting_archive", &finished),
+        ] {
+            add(
+                name,
+                games.len(),
+                games
+                    .iter()
+                    .map(|game_state| game_state.approx_bytes())
+                    .sum(),
+            );
+        }
+        let games = || active.iter().chain(&finished);
+        add(
+            "events",
+            games().map(|game_state| game_state.events.len()).sum(),
+            games().map(|game_state| game_state.events_bytes()).sum(),
+        );
+        add(
+            "history",
+            games().map(|game_state| game_state.history.len()).sum(),
+            games().map(|game_state| game_state.history_bytes()).sum(),
+        );
+        add(
+            "legal_moves_cache",
+            games()
+                .map(|game_state| game_state.legal_moves_cache.len())
+                .sum(),
+            games()
+                .map(|game_state| game_state.legal_moves_cache_bytes())
+                .sum(),
+        );
+        add(
+            "fogged_board_cache",
+            games()
+                .map(|game_state| game_state.fogged_board_cache.len())
+                .sum(),
+            games()
+                .map(|game_state| game_state.fogged_board_cache_bytes())
+                .sum(),
+        );
+        add(
+            "queue",
+            self.repository.queue_len(),
+            self.repository
+                .queued()
+                .map(QueuedPlayer::approx_bytes)
+                .sum(),
+        );
+        add(
+            "accounts",
+            self.accounts.len(),
+            self.accounts
+                .iter()
+                .map(|(player_name, stats)| player_name.capacity() + stats.approx_bytes())
+                .sum(),
+        );
+        add(
+            "player_games",
+            self.player_games.len(),
+            self.player_games
+                .values()
+                .map(|game_ids| {
+                    size_of::<(Uuid, Vec<Uuid>)>() + game_ids.capacity() * size_of::<Uuid>()
+                })
+                .sum(),
+        );
+        add(
+            "pending_presence",
+            self.presence.len(),
+            self.presence.len() * size_of::<((Uuid, Uuid), std::time::Instant)>(),
+        );
+        add(
+            "spectator_codes",
+            self.spectator_codes.len(),
+            self.spectator_codes
+                .keys()
+                .map(|code| size_of::<(String, SpectatorCode)>() + code.capacity())
+                .sum(),
+        );
+        add(
+            "lobbies",
+            self.lobbies.len(),
+            self.lobbies
+                .iter()
+                .map(|(code, lobby)| {
+                    size_of::<(String, Lobby)>() + code.capacity() + lobby.host.name.capacity()
+                })
+                .sum(),
+        );
+        add(
+            "tournaments",
+            self.tournaments.len(),
+            self.tournaments
+                .values()
+                .map(Tournament::approx_bytes)
+                .sum(),
+        );
+        add(
+            "seasons",
+            self.seasons.len(),
+            self.seasons.iter().map(SeasonArchive::approx_bytes).sum(),
+        );
+        add(
+            "recent_waits",
+            self.recent_waits.values().map(VecDeque::len).sum(),
+            self.recent_waits
+                .values()
+                .map(|waits| waits.capacity() * size_of::<Duration>())
+                .sum(),
+        );
+
+        let mut largest_games: Vec<crate::GameFootprint> = games()
+            .map(|game_state| crate::GameFootprint {
+                game_id: game_state.game.id,
+                finished: game_state.result.is_some(),
+                events: game_state.events.len(),
+                history: game_state.history.len(),
+                approx_bytes: game_state.approx_bytes(),
+            })
+            .collect();
+        largest_games.sort_by(|a, b| {
+            (b.events + b.history)
+                .cmp(&(a.events + a.history))
+                .then(b.approx_bytes.cmp(&a.approx_bytes))
+                .then(a.game_id.cmp(&b.game_id))
+        });
+        largest_games.truncate(top);
+
+        crate::Diagnostics {
+            categories,
+            largest_games,
+            ticks: crate::TickTimings {
+                last_millis: self
+                    .last_tick
+                    .map(|tick_took| tick_took.as_secs_f64() * 1000.0),
+                latency: self.tick_timings.clone(),
+            },
+            fogged_board_cache: crate::CacheCounters {
+                hits: self.fogged_board_hits.load(Ordering::Relaxed),
+                misses: self.fogged_board_misses.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    /// Whether the server can take traffic, naming the first component that
+    /// can't when it isn't
+    pub fn readiness(&mut self) -> Result<(), crate::NotReady> {
+        let not_ready = |component: &str, reason: String| crate::NotReady {
+            component: component.to_string(),
+            reason,
+        };
+
+        if self.maintenance {
+            return Err(not_ready(
+                "maintenance",
+                "draining for shutdown".to_string(),
+            ));
+        }
+        match self.heartbeat {
+            None => return Err(not_ready("tick", "no tick has run yet".to_string())),
+            Some(heartbeat) => {
+                let silent = self.clock.now().saturating_duration_since(heartbeat);
+                if silent > self.heartbeat_timeout {
+                    return Err(not_ready(
+                        "tick",
+                        format!("last tick {} seconds ago", silent.as_secs()),
+                    ));
+                }
+            }
+        }
+        self.repository
+            .check_health()
+            .map_err(|e| not_ready("repository", e))
+    }
+
+    /// Games in progress against the configured ceiling
+    pub fn occupancy(&self) -> crate::Occupancy {
+        crate::Occupancy {
+            active_games: self.active_game_count.load(Ordering::Relaxed),
+            max_active_games: self.max_active_games,
+        }
+    }
+
+    // New matchmaking and practice games are refused at the ceiling. Tournament
+    // rounds are already promised to their players and are let through.
+    fn ensure_capacity(&self) -> Result<(), String> {
+        if self.occupancy().is_full() {
+            return Err("Server is full".to_string());
+        }
+        Ok(())
+    }
+
+    /// Load accounts, seasons and active games saved by a previous run
+    pub fn restore(&mut self, restored: RestoredState) {
+        self.accounts.extend(restored.accounts);
+        self.seasons.extend(restored.seasons);
+        if let Some(current_season) = restored.current_season {
+            self.current_season = current_season;
+        }
+
+        let now = self.clock.now();
+        for mut game_state in restored.active_games {
+            let game_id = game_state.game.id;
+            if let Err(e) = game_state.validate() {
+                warn!("Discarding saved game {}: {}", game_id, e);
+                continue;
+            }
+            if self.repository.contains(game_id) {
+                continue;
+            }
+
+            game_state.player1_last_seen = now;
+            game_state.player2_last_seen = now;
+
+            self.player_games
+                .entry(game_state.player1.id)
+                .or_default()
+                .push(game_id);
+            if !game_state.is_solo() {
+                self.player_games
+                    .entry(game_state.player2.id)
+                    .or_default()
+                    .push(game_id);
+            }
+            self.repository.insert(game_state);
+            self.issue_spectator_code(game_id);
+        }
+        self.recount_active_games();
+    }
+
+    /// Accounts, seasons and unfinished games, for writing to a snapshot file
+    pub fn snapshot(&self) -> StorageSnapshot {
+        StorageSnapshot {
+            accounts: self.accounts.clone(),
+            games: self.repository.active_games().cloned().collect(),
+            seasons: self.seasons.clone(),
+            current_season: self.current_season.clone(),
+        }
+    }
+
+    /// Hand the current state of every unfinished game to the persistence writer
+    pub fn checkpoint(&self) {
+        let Some(persist) = &self.persist else {
+            return;
+        };
+
+        let games = self.repository.active_games().cloned().collect();
+        let _ = persist.send(PersistEvent::Checkpoint { games });
+    }
+
+    /// Replace the clock used for all game timers
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Match the player with the latest arrival waiting for the same mode, or
+    /// queue them. Taking the waiting player and creating their game happen in
+    /// this one call, so however joins are locked around it, no waiting player
+    /// can be matched twice; if the game can't be created they go back in line.
+    pub fn join_queue(
+        &mut self,
+        player_name: String,
+        mode: GameMode,
+    ) -> Result<crate::JoinQueueResponse, String> {
+        self.ensure_capacity()?;
+        let player_id = Uuid::new_v4();
+        let now = self.clock.now();
+
+        // Check if there's already a player waiting
+        if let Some(waiting_player) = self.repository.pop_queued(mode) {
+            let waited = now.saturating_duration_since(waiting_player.joined_at);
+
+            // Create a new game with both players
+            let game_id = match self.create_game(
+                waiting_player.clone(),
+                QueuedPlayer {
+                    id: player_id,
+                    name: player_name,
+                    joined_at: now,
+                    mode,
+                },
+                mode.apply(self.default_rules.clone()),
+            ) {
+                Ok(game_id) => game_id,
+                Err(e) => {
+                    self.repository.push_queued(waiting_player);
+                    return Err(e);
+                }
+            };
+            self.record_wait(mode, waited);
+
+            Ok(crate::JoinQueueResponse {
+                player_id,
+                game_id: Some(game_id),
+                message: "Game started!".to_string(),
+            })
+        } else {
+            // Add to queue
+            self.player_games.entry(player_id).or_default();
+            self.repository.push_queued(QueuedPlayer {
+                id: player_id,
+                name: player_name,
+                joined_at: now,
+                mode,
+            });
+
+            Ok(crate::JoinQueueResponse {
+                player_id,
+                game_id: None,
+                message: "Added to queue, waiting for opponent...".to_string(),
+            })
+        }
+    }
+
+    /// Start a practice game where one player controls both colors
+    pub fn start_solo_game(
+        &mut self,
+        player_name: String,
+        fog_enabled: bool,
+        mode: GameMode,
+    ) -> Result<crate::JoinQueueResponse, String> {
+        self.ensure_capacity()?;
+        let player_id = Uuid::new_v4();
+        let now = self.clock.now();
+        let rules = mode.apply(GameRules {
+            fog_enabled,
+            ..self.default_rules.clone()
+        });
+
+        let game_id = self.create_game(
+            QueuedPlayer {
+                id: player_id,
+                name: player_name.clone(),
+                joined_at: now,
+                mode,
+            },
+            QueuedPlayer {
+                id: player_id,
+                name: player_name,
+                joined_at: now,
+                mode,
+            },
+            rules,
+        )?;
+
+        Ok(crate::JoinQueueResponse {
+            player_id,
+            game_id: Some(game_id),
+            message: "Practice game started!".to_string(),
+        })
+    }
+
+    /// Start an unrated game against a bot, which plays black and opens as soon
+    /// as it has a move point
+    pub fn start_bot_game(
+        &mut self,
+        player_name: String,
+        fog_enabled: bool,
+        mode: GameMode,
+    ) -> Result<crate::JoinQueueResponse, String> {
+        self.ensure_capacity()?;
+        let player_id = Uuid::new_v4();
+        let now = self.clock.now();
+        let rules = mode.apply(GameRules {
+            fog_enabled,
+            ..self.default_rules.clone()
+        });
+
+        let game_id = self.create_game(
+            QueuedPlayer {
+                id: player_id,
+                name: player_name,
+                joined_at: now,
+                mode,
+            },
+            QueuedPlayer {
+                id: Uuid::new_v4(),
+                name: BOT_NAME.to_string(),
+                joined_at: now,
+                mode,
+            },
+            rules,
+        )?;
+        self.with_game_mut(game_id, |game_state| {
+            game_state.unrated = true;
+            game_state.bot_color = Some(game_state.player2.color);
+            game_state.player2_ready = true;
+        });
+        self.flush();
+
+        Ok(crate::JoinQueueResponse {
+            player_id,
+            game_id: Some(game_id),
+            message: "Bot game started!".to_string(),
+        })
+    }
+
+    /// Let every bot in `shard` whose move interval has passed spend a move
+    /// point. Bots play through `make_move` like anyone else, so they can never
+    /// spend points they don't have, and they stop once their game is over.
+    pub fn play_bot_moves(&mut self, shard: TickShard) {
+        let game_ids = self.active_game_ids_in(shard);
+        self.play_bot_moves_for(&game_ids);
+    }
+
+    fn play_bot_moves_for(&mut self, game_ids: &[Uuid]) {
+        if self.paused {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut decisions = Vec::new();
+        for &game_id in game_ids {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+            let Some(bot_color) = game_state.bot_color else {
+                continue;
+            };
+            if game_state.phase() != GamePhase::Playing {
+                continue;
+            }
+            let is_player1 = game_state.player1.color == bot_color;
+            let (bot_id, remaining, last_move_at) = if is_player1 {
+                (
+                    game_state.player1.id,
+                    game_state.game.player1_remaining_moves,
+                    game_state.player1_last_move_at,
+                )
+            } else {
+                (
+                    game_state.player2.id,
+                    game_state.game.player2_remaining_moves,
+                    game_state.player2_last_move_at,
+                )
+            };
+
+            // Nobody is there to answer a draw offer, so turn it down
+            let declines_draw = game_state
+                .draw_offer
+                .as_ref()
+                .is_some_and(|offered_by| *offered_by != bot_color);
+
+            let interval = Duration::from_millis(game_state.rules.bot_move_interval_millis);
+            let rested =
+                last_move_at.is_none_or(|at| now.saturating_duration_since(at) >= interval);
+            let may_move = game_state
+                .turn
+                .map_or(remaining > 0, |turn| turn == bot_color);
+            let choice = if may_move && rested {
+                let moves = game_state.legal_moves_for(bot_color, now);
+                choose_bot_move(&game_state.board, &moves, game_id, game_state.version)
+            } else {
+                None
+            };
+            decisions.push((game_id, bot_id, declines_draw, choice));
+        }
+
+        for (game_id, bot_id, declines_draw, choice) in decisions {
+            if declines_draw {
+                let _ = self.respond_to_draw(game_id, bot_id, DrawAction::Decline);
+            }
+            if let Some(chosen) = choice {
+                let _ = self.make_move(
+                    game_id,
+                    crate::MoveRequest {
+                        player_id: bot_id,
+                        from: chosen.from,
+                        to: chosen.to,
+                        use_charge: false,
+                    },
+                );
+            }
+        }
+    }
+
+    fn record_wait(&mut self, mode: GameMode, waited: Duration) {
+        let waits = self
+            .recent_waits
+            .entry(mode)
+            .or_insert_with(|| VecDeque::with_capacity(WAIT_SAMPLE_WINDOW));
+        if waits.len() == WAIT_SAMPLE_WINDOW {
+            waits.pop_front();
+        }
+        waits.push_back(waited);
+    }
+
+    /// Take a player out of everything: leave the queue and resign any game in
+    /// progress. Calling it again changes nothing.
+    pub fn quit(&mut self, player_id: Uuid) -> Result<crate::QuitResponse, String> {
+        let game_ids = self
+            .player_games
+            .get(&player_id)
+            .cloned()
+            .ok_or("Player not found")?;
+
+        let left_queue = self.repository.remove_queued(player_id).is_some();
+
+        let mut resigned_games = Vec::new();
+        for game_id in game_ids {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+            if game_state.result.is_some() {
+                continue;
+            }
+
+            let color = if game_state.player1.id == player_id {
+                game_state.player1.color
+            } else {
+                game_state.player2.color
+            };
+            // Nobody wins a practice game the player walked away from
+            let result = GameResult {
+                winner: (!game_state.is_solo()).then(|| color.opponent()),
+                reason: GameEndReason::Resigned,
+            };
+            game_state.events.push(GameEvent::GameOver { result });
+            game_state.result = Some(result);
+
+            self.record_finished_game(game_id);
+            resigned_games.push(game_id);
+        }
+        self.flush();
+
+        Ok(crate::QuitResponse {
+            left_queue,
+            resigned_games,
+        })
+    }
+
+    /// Give a seat in a game in progress to a new player, for when the one
+    /// holding it can't carry on. The old id is dropped from the game and told
+    /// so on its next request.
+    pub fn reassign_player(
+        &mut self,
+        game_id: Uuid,
+        color: PlayerColor,
+        player_name: String,
+    ) -> Result<crate::ReassignResponse, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+        if game_state.result.is_some() {
+            return Err("Game is already over".to_string());
+        }
+        if game_state.is_solo() || game_state.bot_color == Some(color) {
+            return Err("Only a human opponent's seat can be reassigned".to_string());
+        }
+
+        let player_id = Uuid::new_v4();
+        let (seat, last_seen, warned) = if game_state.player1.color == color {
+            (
+                &mut game_state.player1,
+                &mut game_state.player1_last_seen,
+                &mut game_state.player1_idle_warned,
+            )
+        } else {
+            (
+                &mut game_state.player2,
+                &mut game_state.player2_last_seen,
+                &mut game_state.player2_idle_warned,
+            )
+        };
+        let removed_id = std::mem::replace(&mut seat.id, player_id);
+        seat.name = player_name;
+        // The new player starts with a clean presence record
+        *last_seen = now;
+        *warned = false;
+        game_state.removed_players.insert(removed_id);
+        game_state
+            .events
+            .push(GameEvent::PlayerReassigned { color });
+
+        if let Some(game_ids) = self.player_games.get_mut(&removed_id) {
+            game_ids.retain(|id| *id != game_id);
+            if game_ids.is_empty() {
+                self.player_games.remove(&removed_id);
+            }
+        }
+        self.player_games
+            .entry(player_id)
+            .or_default()
+            .push(game_id);
+        self.presence.remove(&(game_id, removed_id));
+        info!("Reassigned {:?} in game {}", color, game_id);
+
+        self.flush();
+        self.publish_change(game_id);
+        Ok(crate::ReassignResponse { player_id })
+    }
+
+    /// Send in the player's arrangement of their own two home ranks during the
+    /// placement phase. It must use the pieces those ranks hold now, and it stays
+    /// off the board until both players have placed or time runs out.
+    /// Sending another before then replaces it.
+    pub fn submit_placement(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+        pieces: Vec<crate::PlacedPiece>,
+    ) -> Result<crate::PlacementResponse, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+        let color = if game_state.player1.id == player_id {
+            game_state.player1.color
+        } else if game_state.player2.id == player_id {
+            game_state.player2.color
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+        if game_state.phase() != GamePhase::Placement {
+            return Err("Game is not in placement".to_string());
+        }
+
+        let squares: Vec<OccupiedSquare> = pieces
+            .into_iter()
+            .map(|placed| OccupiedSquare {
+                row: placed.row,
+                col: placed.col,
+                piece: placed.piece,
+                color,
+            })
+            .collect();
+        game_state.validate_placement(color, &squares)?;
+        game_state.placements.insert(color, squares);
+        game_state
+            .events
+            .push(GameEvent::PlacementSubmitted { color });
+
+        // A bot keeps the usual setup
+        let all_placed = [PlayerColor::White, PlayerColor::Black]
+            .iter()
+            .all(|color| {
+                game_state.bot_color.as_ref() == Some(color)
+                    || game_state.placements.contains_key(color)
+            });
+        if all_placed {
+            game_state.finish_placement(now);
+            info!("Both players placed in game {}", game_id);
+        }
+        let phase = game_state.phase();
+
+        self.flush();
+        self.publish_change(game_id);
+        Ok(crate::PlacementResponse { phase })
+    }
+
+    /// Whether the player held a seat in the game before it was reassigned
+    pub fn was_removed(&self, game_id: Uuid, player_id: Uuid) -> bool {
+        self.repository
+            .get(game_id)
+            .is_some_and(|game_state| game_state.removed_players.contains(&player_id))
+    }
+
+    /// Offer, accept or decline a draw. Practice games can't be drawn by agreement.
+    pub fn respond_to_draw(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+        action: DrawAction,
+    ) -> Result<crate::DrawOfferResponse, String> {
+        if self.paused {
+            return Err("Server paused".to_string());
+        }
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+
+        let color = if game_state.player1.id == player_id {
+            game_state.player1.color
+        } else if game_state.player2.id == player_id {
+            game_state.player2.color
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+        if game_state.is_solo() {
+            return Err("Practice games can't be drawn by agreement".to_string());
+        }
+        if game_state.result.is_some() {
+            return Err("Game is over".to_string());
+        }
+
+        let pending = game_state.draw_offer;
+        let opponent_offered = pending.as_ref() == Some(&color.opponent());
+        match action {
+            DrawAction::Offer if opponent_offered => self.accept_draw(game_id),
+            DrawAction::Offer => {
+                if pending.is_none() {
+                    game_state.draw_offer = Some(color);
+                    game_state.events.push(GameEvent::DrawOffered { color });
+                }
+            }
+            DrawAction::Accept if opponent_offered => self.accept_draw(game_id),
+            DrawAction::Accept => return Err("No draw offer to accept".to_string()),
+            DrawAction::Decline => {
+                let Some(offered_by) = pending else {
+                    return Err("No draw offer to decline".to_string());
+                };
+                game_state.draw_offer = None;
+                game_state
+                    .events
+                    .push(GameEvent::DrawOfferClosed { color: offered_by });
+            }
+        }
+        self.flush();
+        self.publish_change(game_id);
+
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        Ok(crate::DrawOfferResponse {
+            draw_offer: game_state.draw_offer,
+            result: game_state.result,
+        })
+    }
+
+    /// Record that a player is ready to start. Once both are, the game leaves
+    /// setup and move points start to accrue. Saying so again changes nothing.
+    pub fn mark_ready(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+    ) -> Result<crate::ReadyResponse, String> {
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+        if game_state.player1.id != player_id && game_state.player2.id != player_id {
+            return Err("Player not in this game".to_string());
+        }
+
+        // In practice games one player answers for both sides
+        let mut newly_ready = Vec::new();
+        if game_state.player1.id == player_id && !game_state.player1_ready {
+            game_state.player1_ready = true;
+            newly_ready.push(game_state.player1.color);
+        }
+        if game_state.player2.id == player_id && !game_state.player2_ready {
+            game_state.player2_ready = true;
+            newly_ready.push(game_state.player2.color);
+        }
+
+        let phase = game_state.phase();
+        if !newly_ready.is_empty() {
+            for color in newly_ready {
+                game_state.events.push(GameEvent::PlayerReady { color });
+            }
+            if phase == GamePhase::Playing {
+                info!("Game {} started, both players ready", game_id);
+            }
+            self.flush();
+            self.publish_change(game_id);
+        }
+
+        Ok(crate::ReadyResponse { phase })
+    }
+
+    /// Spend move points on the reveal powerup: the player sees the whole board
+    /// until it runs out, then has to buy another
+    pub fn buy_reveal(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+    ) -> Result<crate::RevealResponse, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+        let is_player1 = if game_state.player1.id == player_id {
+            true
+        } else if game_state.player2.id == player_id {
+            false
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+        let color = if is_player1 {
+            game_state.player1.color
+        } else {
+            game_state.player2.color
+        };
+
+        let cost = game_state.rules.reveal_powerup_cost;
+        if cost == 0 || !game_state.rules.fog_enabled {
+            return Err("The reveal powerup is off in this game".to_string());
+        }
+        if game_state.phase() != GamePhase::Playing {
+            return Err("Game is not in progress".to_string());
+        }
+        if game_state.revealing(&color, now) {
+            return Err("A reveal is already running".to_string());
+        }
+        let (remaining_moves, at_cap) = if is_player1 {
+            (
+                &mut game_state.game.player1_remaining_moves,
+                &mut game_state.game.player1_at_cap,
+            )
+        } else {
+            (
+                &mut game_state.game.player2_remaining_moves,
+                &mut game_state.game.player2_at_cap,
+            )
+        };
+        if *remaining_moves < cost {
+            return Err(format!("The reveal costs {} move points", cost));
+        }
+        *remaining_moves -= cost;
+        *at_cap = false;
+        let remaining_moves = *remaining_moves;
+
+        let seconds = game_state.rules.reveal_powerup_seconds;
+        game_state
+            .reveals
+            .insert(color, now + Duration::from_secs(seconds));
+        game_state.legal_moves_cache.remove(&color);
+        game_state.fogged_board_cache.remove(&color);
+        game_state
+            .events
+            .push(GameEvent::RevealPowerupUsed { color });
+        info!("{:?} bought a reveal in game {}", color, game_id);
+
+        self.flush();
+        self.publish_change(game_id);
+        Ok(crate::RevealResponse {
+            remaining_moves,
+            reveal_seconds: seconds,
+        })
+    }
+
+    /// Open a private lobby for the host to share by its code. A custom starting
+    /// position or a handicap is checked now, and only unranked lobbies may have
+    /// one.
+    pub fn create_lobby(
+        &mut self,
+        player_name: String,
+        position: Option<crate::StartPosition>,
+        handicap: Option<HandicapOdds>,
+        ranked: bool,
+        mode: GameMode,
+    ) -> Result<crate::LobbyCreated, String> {
+        let position = position.map(|position| position.to_board()).transpose()?;
+        if let Some(board) = &position {
+            if ranked {
+                return Err("Ranked games start from the standard position".to_string());
+            }
+            validate_start_board(board)?;
+        }
+        if let Some(odds) = &handicap {
+            if ranked {
+                return Err("Handicap games can't be ranked".to_string());
+            }
+            if position.is_some() {
+                return Err("A handicap can't be given on a custom position".to_string());
+            }
+            // Try it on the army the game will start with
+            let mut board = ExtendedBoard::new();
+            match &mode.apply(self.default_rules.clone()).army {
+                Some(army) => board.setup_army(army),
+                None => board.setup_initial_position(),
+            }
+            board.apply_handicap(&odds.color, &odds.handicap)?;
+        }
+
+        let now = self.clock.now();
+        let player_id = Uuid::new_v4();
+        let code = loop {
+            let code = random_code();
+            if !self.lobbies.contains_key(&code) {
+                break code;
+            }
+        };
+        self.lobbies.insert(
+            code.clone(),
+            Lobby {
+                host: QueuedPlayer {
+                    id: player_id,
+                    name: player_name,
+                    joined_at: now,
+                    mode,
+                },
+                position,
+                ranked,
+                handicap,
+                expires_at: now + self.lobby_ttl,
+                game_id: None,
+            },
+        );
+        info!("Lobby {} opened", code);
+
+        Ok(crate::LobbyCreated { code, player_id })
+    }
+
+    /// What an invited player is about to join. Codes are not case sensitive.
+    pub fn get_lobby(&self, code: &str) -> Result<crate::LobbyInfo, String> {
+        let code = code.to_ascii_uppercase();
+        let lobby = self
+            .lobbies
+            .get(&code)
+            .filter(|lobby| lobby.expires_at > self.clock.now())
+            .ok_or("Lobby not found")?;
+
+        Ok(crate::LobbyInfo {
+            host_name: lobby.host.name.clone(),
+            ranked: lobby.ranked,
+            mode: lobby.host.mode,
+            start_position: lobby.position.as_ref().map(ExtendedBoard::to_board_string),
+            handicap: lobby.handicap.clone(),
+            game_id: lobby.game_id,
+            code,
+        })
+    }
+
+    /// Join a lobby as the invited player, starting the game with the host as
+    /// white. Games from unranked lobbies leave accounts alone.
+    pub fn join_lobby(
+        &mut self,
+        code: &str,
+        player_name: String,
+    ) -> Result<crate::JoinQueueResponse, String> {
+        self.ensure_capacity()?;
+        let now = self.clock.now();
+        let code = code.to_ascii_uppercase();
+        let lobby = self
+            .lobbies
+            .get(&code)
+            .filter(|lobby| lobby.expires_at > now && lobby.game_id.is_none())
+            .ok_or("Lobby not found")?
+            .clone();
+
+        let player_id = Uuid::new_v4();
+        let guest = QueuedPlayer {
+            id: player_id,
+            name: player_name,
+            joined_at: now,
+            mode: lobby.host.mode,
+        };
+        let rules = lobby.host.mode.apply(self.default_rules.clone());
+        let game_id = match lobby.position {
+            Some(board) => {
+                let game_id = self.create_game_on_board(lobby.host, guest, rules, board)?;
+                self.with_game_mut(game_id, |game_state| {
+                    game_state.start_board = Some(game_state.board.clone());
+                    game_state.start_position = Some(game_state.board.to_board_string());
+                });
+                game_id
+            }
+            None => self.create_game_with_handicap(lobby.host, guest, rules, lobby.handicap)?,
+        };
+        if !lobby.ranked {
+            self.with_game_mut(game_id, |game_state| game_state.unrated = true);
+        }
+        self.flush();
+
+        if let Some(lobby) = self.lobbies.get_mut(&code) {
+            lobby.game_id = Some(game_id);
+        }
+        info!("Lobby {} started game {}", code, game_id);
+
+        Ok(crate::JoinQueueResponse {
+            player_id,
+            game_id: Some(game_id),
+            message: "Private game started!".to_string(),
+        })
+    }
+
+    // End the game as a draw both players agreed to
+    fn accept_draw(&mut self, game_id: Uuid) {
+        let Some(game_state) = self.repository.get_mut(game_id) else {
+            return;
+        };
+        let result = GameResult {
+            winner: None,
+            reason: GameEndReason::DrawAgreed,
+        };
+        game_state.draw_offer = None;
+        game_state.events.push(GameEvent::GameOver { result });
+        game_state.result = Some(result);
+        self.record_finished_game(game_id);
+    }
+
+    /// Who is waiting and how long a new player can expect to wait, overall
+    /// and for each mode
+    pub fn get_queue_status(&self) -> crate::QueueStatus {
+        let now = self.clock.now();
+        let waited =
+            |player: &QueuedPlayer| now.saturating_duration_since(player.joined_at).as_secs();
+
+        let modes: Vec<crate::ModeQueueStatus> = GameMode::ALL
+            .into_iter()
+            .map(|mode| {
+                let waits = self.recent_waits.get(&mode);
+                crate::ModeQueueStatus {
+                    mode,
+                    players_waiting: self
+                        .repository
+                        .queued()
+                        .filter(|player| player.mode == mode)
+                        .count(),
+                    longest_wait_seconds: self
+                        .repository
+                        .queued()
+                        .filter(|player| player.mode == mode)
+                        .map(waited)
+                        .max(),
+                    median_wait_seconds: median_seconds(waits.into_iter().flatten().copied()),
+                    sample_size: waits.map_or(0, VecDeque::len),
+                }
+            })
+            .collect();
+
+        let median_wait_seconds = median_seconds(self.recent_waits.values().flatten().copied());
+        let message = match median_wait_seconds {
+            Some(_) => "Estimate based on recent matches".to_string(),
+            None => "No recent matches to estimate from".to_string(),
+        };
+
+        crate::QueueStatus {
+            players_waiting: self.repository.queue_len(),
+            longest_wait_seconds: self.repository.queued().map(waited).max(),
+            median_wait_seconds,
+            sample_size: modes.iter().map(|mode| mode.sample_size).sum(),
+            message,
+            modes,
+        }
+    }
+
+    fn create_game(
+        &mut self,
+        player1: QueuedPlayer,
+        player2: QueuedPlayer,
+        rules: GameRules,
+    ) -> Result<Uuid, String> {
+        self.create_game_with_handicap(player1, player2, rules, None)
+    }
+
+    // Create a game with `handicap`'s pieces taken off the board before it
+    // starts, so a handicap that can't be given leaves no game behind
+    fn create_game_with_handicap(
+        &mut self,
+        player1: QueuedPlayer,
+        player2: QueuedPlayer,
+        rules: GameRules,
+        handicap: Option<HandicapOdds>,
+    ) -> Result<Uuid, String> {
+        let back_rank = (rules.random_back_rank && rules.army.is_none()).then(|| {
+            BackRank::random(rules.back_rank_seed.unwrap_or_else(|| {
+                let (high, low) = Uuid::new_v4().as_u64_pair();
+                high ^ low
+            }))
+        });
+        let dims = (rules.board_size, rules.board_size);
+        ExtendedBoard::check_dims(dims)?;
+        let mut board = ExtendedBoard::with_dims(dims);
+        match (&back_rank, &rules.army) {
+            (Some(back_rank), _) => board.setup_position_with(back_rank),
+            (None, Some(army)) => board.setup_army(army),
+            (None, None) => board.setup_initial_position(),
+        }
+        // Both players see the missing pieces from their first board, and
+        // replays start without them
+        if let Some(odds) = &handicap {
+            board.apply_handicap(&odds.color, &odds.handicap)?;
+        }
+        let standard_start = back_rank.is_none()
+            && rules.army.is_none()
+            && rules.board_size == BOARD_SIZE
+            && handicap.is_none();
+        // Practice games have no one to hide an arrangement from
+        let placement = rules.placement_seconds > 0 && player1.id != player2.id;
+
+        let game_id = self.create_game_on_board(player1, player2, rules, board)?;
+        if !standard_start || placement {
+            self.with_game_mut(game_id, |game_state| {
+                game_state.placement_done = !placement;
+                // Replays start from the recorded board instead of the standard one
+                if !standard_start && game_state.start_board.is_none() {
+                    game_state.start_board = Some(game_state.board.clone());
+                }
+                game_state.back_rank = back_rank;
+                game_state.handicap = handicap;
+            });
+            self.flush();
+        }
+        Ok(game_id)
+    }
+
+    /// Start a game from a board string, to reproduce a reported position.
+    /// Seeded games are unrated.
+    pub fn seed_game(
+        &mut self,
+        board: &str,
+        white_name: String,
+        black_name: String,
+        rules: Option<GameRules>,
+    ) -> Result<crate::SeededGame, String> {
+        let board = ExtendedBoard::from_board_string(board)?;
+        validate_start_board(&board)?;
+
+        let now = self.clock.now();
+        let rules = rules.unwrap_or_else(|| self.default_rules.clone());
+        rules.validate()?;
+        let white = QueuedPlayer {
+            id: Uuid::new_v4(),
+            name: white_name,
+            joined_at: now,
+            mode: rules.mode,
+        };
+        let black = QueuedPlayer {
+            id: Uuid::new_v4(),
+            name: black_name,
+            joined_at: now,
+            mode: rules.mode,
+        };
+        let (white_player_id, black_player_id) = (white.id, black.id);
+
+        let game_id = self.create_game_on_board(white, black, rules, board)?;
+        self.with_game_mut(game_id, |game_state| {
+            game_state.unrated = true;
+            game_state.start_board = Some(game_state.board.clone());
+        });
+        self.flush();
+
+        Ok(crate::SeededGame {
+            game_id,
+            white_player_id,
+            black_player_id,
+        })
+    }
+
+    fn create_game_on_board(
+        &mut self,
+        player1: QueuedPlayer,
+        player2: QueuedPlayer,
+        rules: GameRules,
+        mut board: ExtendedBoard,
+    ) -> Result<Uuid, String> {
+        let game_id = Uuid::new_v4();
+        let now = self.clock.now();
+        board.set_pawn_rules(rules.pawn_rules());
+        // Boards that bring their own walls keep them
+        let mut start_board = None;
+        if rules.wall_count > 0 && !board.has_walls() {
+            let seed = rules.wall_seed.unwrap_or_else(|| {
+                let (high, low) = game_id.as_u64_pair();
+                high ^ low
+            });
+            board.place_random_walls(rules.wall_count as usize, seed, &rules.hill_squares);
+            board.validate_walls()?;
+            start_board = Some(board.clone());
+        }
+        let position_counts = HashMap::from([(board.position_key(), 1)]);
+        let turn = (rules.mode == GameMode::TurnBased).then_some(PlayerColor::White);
+
+        let game_state = GameState {
+            game: Game {
+                id: game_id,
+                player1_remaining_moves: 1, // Start with 1 move
+                player1_move_increment_countdown: rules.move_increment_ticks,
+                player2_remaining_moves: 1,
+                player2_move_increment_countdown: rules.move_increment_ticks,
+                player1_at_cap: false,
+                player2_at_cap: false,
+                player1_charge: 0,
+                player2_charge: 0,
+            },
+            board,
+            version: 0,
+            player1: PlayerInfo {
+                id: player1.id,
+                name: player1.name,
+                color: PlayerColor::White,
+            },
+            player2: PlayerInfo {
+                id: player2.id,
+                name: player2.name,
+                color: PlayerColor::Black,
+            },
+            created_at: now,
+            started_at: self.clock.wall_time(),
+            last_activity: now,
+            rules,
+            first_blood_awarded: false,
+            captured_pieces: Vec::new(),
+            history: Vec::new(),
+            position_counts,
+            halfmove_clock: 0,
+            moves_since_capture: 0,
+            result: None,
+            tournament_id: None,
+            draw_offer: None,
+            unrated: false,
+            start_board,
+            start_position: None,
+            bot_color: None,
+            turn,
+            back_rank: None,
+            handicap: None,
+            placement_done: true,
+            placements: HashMap::new(),
+            removed_players: BTreeSet::new(),
+            player1_ready: false,
+            player2_ready: false,
+            player1_checks: 0,
+            player2_checks: 0,
+            events: Vec::new(),
+            player1_last_seen: now,
+            player2_last_seen: now,
+            player1_idle_warned: false,
+            player2_idle_warned: false,
+            player1_last_move_at: None,
+            player2_last_move_at: None,
+            finished_at: None,
+            legal_moves_cache: HashMap::new(),
+            fogged_board_cache: HashMap::new(),
+            beacons: HashMap::new(),
+            capture_pulses: HashMap::new(),
+            reveals: HashMap::new(),
+            hill_holds: HashMap::new(),
+        };
+
+        self.player_games
+            .entry(player1.id)
+            .or_default()
+            .push(game_id);
+        self.player_games
+            .entry(player2.id)
+            .or_default()
+            .push(game_id);
+        self.repository.insert(game_state);
+        self.active_game_count.fetch_add(1, Ordering::Relaxed);
+        self.shard_games_of(game_id).insert(game_id);
+        self.issue_spectator_code(game_id);
+        Ok(game_id)
+    }
+
+    /// The unfinished game a player is currently in, if any
+    pub fn get_current_game(&self, player_id: Uuid) -> Result<Option<crate::CurrentGame>, String> {
+        let game_ids = self
+            .player_games
+            .get(&player_id)
+            .ok_or("Player not found")?;
+
+        let current = game_ids.iter().rev().find_map(|game_id| {
+            let game_state = self.repository.get(*game_id)?;
+            if game_state.result.is_some() {
+                return None;
+            }
+
+            let (player, remaining_moves, countdown) = if game_state.player1.id == player_id {
+                (
+                    &game_state.player1,
+                    game_state.game.player1_remaining_moves,
+                    game_state.game.player1_move_increment_countdown,
+                )
+            } else {
+                (
+                    &game_state.player2,
+                    game_state.game.player2_remaining_moves,
+                    game_state.game.player2_move_increment_countdown,
+                )
+            };
+
+            Some(crate::CurrentGame {
+                game_id: *game_id,
+                your_color: player.color,
+                board_version: game_state.version,
+                remaining_moves,
+                next_move_point_in: (self.tick * countdown as u32).as_secs(),
+            })
+        });
+
+        Ok(current)
+    }
+
+    /// The board as a player sees it. Fetching it uses up the player's pending
+    /// capture pulses.
+    ///
+    /// The board is an immutable snapshot shared with the cache. Callers can let
+    /// go of the storage lock before serializing it, and a move made meanwhile
+    /// only swaps in a new snapshot, so they see the board from before or after
+    /// the move, never a mix.
+    pub fn get_fogged_board(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+    ) -> Result<Arc<FoggedBoard>, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+
+        let player_color = if game_state.player1.id == player_id {
+            game_state.player1.color
+        } else if game_state.player2.id == player_id {
+            game_state.player2.color
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+
+        let key = game_state.visibility_key(&player_color, now);
+        let opponent_last_active = game_state.opponent_last_active(&player_color, now);
+        let board = match game_state.fogged_board_cache.get(&player_color) {
+            Some((cached_key, board))
+                if *cached_key == key && board.opponent_last_active == opponent_last_active =>
+            {
+                self.fogged_board_hits.fetch_add(1, Ordering::Relaxed);
+                Arc::clone(board)
+            }
+            // Same view, the opponent's activity has just moved to another
+            // bucket
+            Some((cached_key, board)) if *cached_key == key => {
+                self.fogged_board_hits.fetch_add(1, Ordering::Relaxed);
+                let board = Arc::new(FoggedBoard {
+                    opponent_last_active,
+                    ..FoggedBoard::clone(board)
+                });
+                game_state
+                    .fogged_board_cache
+                    .insert(player_color, (key, Arc::clone(&board)));
+                board
+            }
+            _ => {
+                self.fogged_board_misses.fetch_add(1, Ordering::Relaxed);
+                let board = Arc::new(FoggedBoard::new(
+                    game_state.fogged_slots(&player_color, now),
+                    player_color,
+                    game_state.board.walls(),
+                    opponent_last_active,
+                ));
+                game_state
+                    .fogged_board_cache
+                    .insert(player_color, (key, Arc::clone(&board)));
+                board
+            }
+        };
+        game_state.capture_pulses.remove(&player_color);
+
+        Ok(board)
+    }
+
+    /// The player's board straight from the cache, when the cached snapshot is
+    /// still current and no capture pulses are waiting to be used up. Needs only
+    /// shared access, so board polling can skip the write lock; `None` means
+    /// the caller has to go through [`Self::get_fogged_board`].
+    pub fn cached_fogged_board(&self, game_id: Uuid, player_id: Uuid) -> Option<Arc<FoggedBoard>> {
+        let now = self.clock.now();
+        let game_state = self.repository.get(game_id)?;
+
+        let player_color = if game_state.player1.id == player_id {
+            game_state.player1.color
+        } else if game_state.player2.id == player_id {
+            game_state.player2.color
+        } else {
+            return None;
+        };
+        if game_state.capture_pulses.contains_key(&player_color) {
+            return None;
+        }
+
+        let (cached_key, board) = game_state.fogged_board_cache.get(&player_color)?;
+        if *cached_key != game_state.current_visibility_key(&player_color, now)
+            || board.opponent_last_active != game_state.opponent_last_active(&player_color, now)
+        {
+            return None;
+        }
+        self.fogged_board_hits.fetch_add(1, Ordering::Relaxed);
+        Some(Arc::clone(board))
+    }
+
+    /// Why the player sees each square they see, and which squares in sight of
+    /// their pieces walls hide
+    pub fn explain_fog(
+        &self,
+        game_id: Uuid,
+        player_id: Uuid,
+    ) -> Result<crate::FogExplanation, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+
+        let color = if game_state.player1.id == player_id {
+            game_state.player1.color
+        } else if game_state.player2.id == player_id {
+            game_state.player2.color
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+
+        if !game_state.rules.fog_enabled {
+            return Ok(crate::FogExplanation {
+                game_id,
+                color,
+                fog_enabled: false,
+                visible: squares(game_state.board.all_squares())
+                    .map(|position| crate::VisibleSquare {
+                        position,
+                        sources: Vec::new(),
+                    })
+                    .collect(),
+                hidden_by_walls: Vec::new(),
+            });
+        }
+
+        let visible = game_state.visible_mask(&color, now);
+        let in_sight = game_state
+            .board
+            .pieces()
+            .filter(|(_, slot)| slot.color == color)
+            .fold(Bitboard::EMPTY, |in_sight, (from, slot)| {
+                in_sight | sight(from, slot.piece.default_sight()) & game_state.board.all_squares()
+            });
+
+        Ok(crate::FogExplanation {
+            game_id,
+            color,
+            fog_enabled: true,
+            visible: game_state
+                .vision_sources(&color, now)
+                .into_iter()
+                .map(|(position, sources)| crate::VisibleSquare { position, sources })
+                .collect(),
+            hidden_by_walls: squares(in_sight & !visible).collect(),
+        })
+    }
+
+    /// The whole board as piece codes, ignoring fog
+    pub fn piece_grid(&self, game_id: Uuid) -> Result<Vec<Vec<String>>, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        Ok(game_state.board.piece_codes())
+    }
+
+    /// The board for spectators: everything during the intro reveal and once
+    /// the game is over. Anyone may watch, players included, so while a fog
+    /// game is played no piece is shown at all.
+    pub fn get_spectator_board(&self, game_id: Uuid) -> Result<SpectatorBoard, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        let fogged = game_state.fogged_for_spectators(self.clock.now());
+
+        let visible = if fogged {
+            Bitboard::EMPTY
+        } else {
+            game_state.board.all_squares()
+        };
+
+        Ok(SpectatorBoard {
+            slots: visible_slots(&game_state.board, visible),
+            fogged,
+            walls: game_state.board.walls(),
+        })
+    }
+
+    /// Make a move and write it out. Each try validates the move against the
+    /// latest stored game, so a change another instance made first can't be
+    /// overwritten and a move is only reported as made once it is saved.
+    pub fn make_move(
+        &mut self,
+        game_id: Uuid,
+        move_req: crate::MoveRequest,
+    ) -> Result<crate::MoveResponse, String> {
+        for _ in 0..MOVE_SAVE_ATTEMPTS {
+            self.repository.refresh(game_id);
+            let response = self.apply_move(game_id, move_req)?;
+            if !response.success {
+                return Ok(response);
+            }
+            match self.repository.save(game_id) {
+                // The local copy now holds the other instance's version
+                Err(SaveError::Conflict(_)) => continue,
+                Err(SaveError::Failed(e)) => {
+                    warn!("Move in game {} not saved yet: {}", game_id, e)
+                }
+                Ok(()) => {}
+            }
+
+            if self
+                .repository
+                .get(game_id)
+                .is_some_and(|game_state| game_state.result.is_some())
+            {
+                self.record_finished_game(game_id);
+            }
+            self.flush();
+            self.publish_change(game_id);
+            return Ok(response);
+        }
+
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        let remaining_moves = if game_state.player1.id == move_req.player_id {
+            game_state.game.player1_remaining_moves
+        } else {
+            game_state.game.player2_remaining_moves
+        };
+        Ok(crate::MoveResponse {
+            success: false,
+            message: "The game changed while moving, try again".into(),
+            remaining_moves,
+        })
+    }
+
+    // Validate a move against the local copy of the game and apply it there
+    fn apply_move(
+        &mut self,
+        game_id: Uuid,
+        move_req: crate::MoveRequest,
+    ) -> Result<crate::MoveResponse, String> {
+        if self.paused {
+            return Err("Server paused".to_string());
+        }
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+
+        if game_state.player1.id != move_req.player_id
+            && game_state.player2.id != move_req.player_id
+        {
+            return Err("Player not in this game".to_string());
+        }
+
+        let is_player1 = if game_state.is_solo() {
+            // In practice games the moved piece decides which side is playing
+            let moving_color = game_state.board.slot(move_req.from).map(|slot| slot.color);
+            moving_color.as_ref() != Some(&game_state.player2.color)
+        } else {
+            game_state.player1.id == move_req.player_id
+        };
+        let remaining_moves = if is_player1 {
+            game_state.game.player1_remaining_moves
+        } else {
+            game_state.game.player2_remaining_moves
+        };
+
+        let now = self.clock.now();
+        game_state.mark_player_seen(move_req.player_id, now);
+
+        if game_state.result.is_some() {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "Game is over".into(),
+                remaining_moves,
+            });
+        }
+        if game_state.phase() == GamePhase::Placement {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "Waiting for both players to place their pieces".into(),
+                remaining_moves,
+            });
+        }
+        if game_state.phase() == GamePhase::Setup {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "Waiting for both players to be ready".into(),
+                remaining_moves,
+            });
+        }
+        if let Some(needed) = game_state.opening_grace_points() {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: format!(
+                    "Both players need {} move points before the first move",
+                    needed
+                )
+                .into(),
+                remaining_moves,
+            });
+        }
+
+        let charge = if is_player1 {
+            game_state.game.player1_charge
+        } else {
+            game_state.game.player2_charge
+        };
+        if move_req.use_charge {
+            if game_state.rules.charge_capacity == 0 || charge < game_state.rules.charge_capacity {
+                return Ok(crate::MoveResponse {
+                    success: false,
+                    message: "Charge is not full".into(),
+                    remaining_moves,
+                });
+            }
+        } else if remaining_moves == 0 && game_state.turn.is_none() {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "No moves remaining".into(),
+                remaining_moves: 0,
+            });
+        }
+
+        if game_state.draw_offer.is_some() && game_state.rules.freeze_on_draw_offer {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "A draw offer is pending".into(),
+                remaining_moves,
+            });
+        }
+
+        let last_move_at = if is_player1 {
+            game_state.player1_last_move_at
+        } else {
+            game_state.player2_last_move_at
+        };
+        let min_interval = Duration::from_millis(game_state.rules.min_move_interval_millis);
+        // A charged move ignores the interval
+        if !move_req.use_charge
+            && let Some(last_move_at) = last_move_at
+        {
+            let since_last = now.saturating_duration_since(last_move_at);
+            if since_last < min_interval {
+                return Ok(crate::MoveResponse {
+                    success: false,
+                    message: format!(
+                        "Too soon, wait {}ms before moving again",
+                        (min_interval - since_last).as_millis()
+                    )
+                    .into(),
+                    remaining_moves,
+                });
+            }
+        }
+
+        let player_color = if is_player1 {
+            &game_state.player1.color
+        } else {
+            &game_state.player2.color
+        };
+
+        if game_state.turn.is_some_and(|turn| turn != *player_color) {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "Not your turn".into(),
+                remaining_moves,
+            });
+        }
+
+        if game_state.rules.king_rule == KingRule::Checkmate
+            && game_state
+                .board
+                .holds_king_of(move_req.to, &player_color.opponent())
+        {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "The king can't be captured, only checkmated".into(),
+                remaining_moves,
+            });
+        }
+
+        if game_state.rules.check_rules()
+            && game_state
+                .board
+                .leaves_king_in_check(move_req.from, move_req.to, player_color)
+        {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "Move would leave your king in check".into(),
+                remaining_moves,
+            });
+        }
+
+        if game_state.rules.atomic
+            && game_state
+                .board
+                .blast_hits_own_king(move_req.from, move_req.to, player_color)
+        {
+            return Ok(crate::MoveResponse {
+                success: false,
+                message: "The blast would take out your own king".into(),
+                remaining_moves,
+            });
+        }
+
+        // Validate and execute the move
+        match game_state
+            .board
+            .make_move(move_req.from, move_req.to, player_color)
+        {
+            Ok(captured) => {
+                // Consume a move point, or the whole charge for a charged move.
+                // A turn-based game hands the move over instead.
+                if let Some(turn) = &mut game_state.turn {
+                    *turn = turn.opponent();
+                } else if is_player1 {
+                    if move_req.use_charge {
+                        game_state.game.player1_charge = 0;
+                    } else {
+                        game_state.game.player1_remaining_moves -= 1;
+                        game_state.game.player1_at_cap = false;
+                    }
+                } else if move_req.use_charge {
+                    game_state.game.player2_charge = 0;
+                } else {
+                    game_state.game.player2_remaining_moves -= 1;
+                    game_state.game.player2_at_cap = false;
+                }
+                if is_player1 {
+                    game_state.player1_last_move_at = Some(now);
+                } else {
+                    game_state.player2_last_move_at = Some(now);
+                }
+                game_state.last_activity = now;
+
+                // First blood: the first capture of the game earns a bonus point
+                if captured.is_some()
+                    && game_state.rules.first_blood_bonus
+                    && !game_state.first_blood_awarded
+                {
+                    game_state.first_blood_awarded = true;
+                    let moves = if is_player1 {
+                        &mut game_state.game.player1_remaining_moves
+                    } else {
+                        &mut game_state.game.player2_remaining_moves
+                    };
+                    *moves = std::cmp::min(*moves + 1, game_state.rules.max_stored_moves);
+                }
+
+                let remaining = if is_player1 {
+                    game_state.game.player1_remaining_moves
+                } else {
+                    game_state.game.player2_remaining_moves
+                };
+
+                let moved_piece = game_state
+                    .board
+                    .slot(move_req.to)
+                    .map(|slot| slot.piece)
+                    .unwrap_or_default();
+                if moved_piece == ChestPiece::Scout && game_state.rules.scout_beacon_seconds > 0 {
+                    let expires_at =
+                        now + Duration::from_secs(game_state.rules.scout_beacon_seconds);
+                    let beacons = game_state.beacons.entry(*player_color).or_default();
+                    beacons.retain(|beacon| beacon.position != move_req.to);
+                    beacons.push(Beacon {
+                        position: move_req.to,
+                        expires_at,
+                    });
+                }
+                let exploded = if game_state.rules.atomic && captured.is_some() {
+                    blast_victims(game_state.board.explode(move_req.to))
+                } else {
+                    Vec::new()
+                };
+                game_state.version += 1;
+                game_state.history.push(MoveRecord {
+                    color: *player_color,
+                    piece: moved_piece,
+                    from: move_req.from,
+                    to: move_req.to,
+                    captured: captured.as_ref().map(|slot| slot.piece),
+                    exploded: exploded.clone(),
+                });
+
+                // Moving on withdraws your own pending draw offer
+                if game_state.draw_offer.as_ref() == Some(player_color) {
+                    game_state.draw_offer = None;
+                    game_state.events.push(GameEvent::DrawOfferClosed {
+                        color: *player_color,
+                    });
+                }
+
+                let mut message = Cow::Borrowed("Move successful");
+                if let Some(captured) = captured {
+                    // Capturing the king wins the game
+                    if captured.piece == ChestPiece::King {
+                        game_state.result = Some(GameResult {
+                            winner: Some(*player_color),
+                            reason: GameEndReason::KingCaptured,
+                        });
+                        message = Cow::Borrowed("King captured, you win!");
+                    }
+                    game_state.captured_pieces.push(captured);
+
+                    if game_state.rules.atomic {
+                        game_state
+                            .captured_pieces
+                            .extend(exploded.iter().map(|victim| ExtendedSlot {
+                                piece: victim.piece,
+                                color: victim.color,
+                            }));
+                        let mut destroyed = vec![OccupiedSquare {
+                            row: move_req.to.0,
+                            col: move_req.to.1,
+                            piece: captured.piece,
+                            color: captured.color,
+                        }];
+                        destroyed.extend(exploded.iter().cloned());
+                        game_state.events.push(GameEvent::Explosion {
+                            square: move_req.to,
+                            destroyed,
+                        });
+
+                        // A king caught in the blast ends the game, whoever's
+                        // it was; losing both is a draw
+                        if exploded
+                            .iter()
+                            .any(|victim| victim.piece == ChestPiece::King)
+                        {
+                            let survivor = [PlayerColor::White, PlayerColor::Black]
+                                .into_iter()
+                                .find(|color| game_state.board.find_king(color).is_some());
+                            game_state.result = Some(GameResult {
+                                winner: survivor,
+                                reason: GameEndReason::Explosion,
+                            });
+                            message = Cow::Borrowed(match survivor {
+                                Some(color) if color == *player_color => {
+                                    "The blast took out the king, you win!"
+                                }
+                                Some(_) => "The blast took out your own king",
+                                None => "The blast took out both kings, the game is drawn",
+                            });
+                        }
+                    }
+
+                    if game_state.rules.capture_pulse_radius > 0 {
+                        for color in [&game_state.player1.color, &game_state.player2.color] {
+                            game_state
+                                .capture_pulses
+                                .entry(*color)
+                                .or_default()
+                                .push(move_req.to);
+                        }
+                    }
+                }
+
+                if game_state.result.is_none()
+                    && game_state.rules.king_rule == KingRule::Checkmate
+                    && game_state.board.is_checkmated(&player_color.opponent())
+                {
+                    game_state.result = Some(GameResult {
+                        winner: Some(*player_color),
+                        reason: GameEndReason::Checkmate,
+                    });
+                    message = Cow::Borrowed("Checkmate, you win!");
+                }
+
+                // A double check is still one check
+                if game_state.result.is_none() && game_state.rules.three_check {
+                    let from = game_state.board.checkers(&player_color.opponent());
+                    if !from.is_empty() {
+                        let checks = if is_player1 {
+                            &mut game_state.player1_checks
+                        } else {
+                            &mut game_state.player2_checks
+                        };
+                        *checks += 1;
+                        let checks = *checks;
+                        game_state.events.push(GameEvent::CheckGiven {
+                            color: *player_color,
+                            checks,
+                            from,
+                        });
+                        if checks >= THREE_CHECK_WINS {
+                            game_state.result = Some(GameResult {
+                                winner: Some(*player_color),
+                                reason: GameEndReason::ThreeChecks,
+                            });
+                            message = Cow::Borrowed("Third check, you win!");
+                        }
+                    }
+                }
+
+                if game_state.result.is_none() {
+                    game_state.result = game_state.no_capture_draw_after_move();
+                    if game_state.result.is_some() {
+                        message = Cow::Borrowed("Move successful, the game is drawn");
+                    }
+                }
+
+                if game_state.result.is_none() && game_state.rules.strictness.draw_rules() {
+                    game_state.result = game_state.strict_draw_after_move();
+                    if game_state.result.is_some() {
+                        message = Cow::Borrowed("Move successful, the game is drawn");
+                    }
+                }
+
+                // The tick decides who held the hill long enough
+                if game_state.result.is_none() {
+                    game_state.update_hill(now);
+                }
+
+                if let Some(result) = &game_state.result {
+                    game_state
+                        .events
+                        .push(GameEvent::GameOver { result: *result });
+                }
+
+                Ok(crate::MoveResponse {
+                    success: true,
+                    message,
+                    remaining_moves: remaining,
+                })
+            }
+            Err(e) => Ok(crate::MoveResponse {
+                success: false,
+                message: e.to_string().into(),
+                remaining_moves,
+            }),
+        }
+    }
+
+    /// Moves the player could make right now, judged only from what they can see.
+    /// A practice game lists moves for both colors.
+    pub fn get_legal_moves(
+        &mut self,
+        game_id: Uuid,
+        player_id: Uuid,
+    ) -> Result<crate::LegalMovesResponse, String> {
+        let now = self.clock.now();
+        let game_state = self.repository.get_mut(game_id).ok_or("Game not found")?;
+
+        let colors = if game_state.is_solo() && game_state.player1.id == player_id {
+            vec![game_state.player1.color, game_state.player2.color]
+        } else if game_state.player1.id == player_id {
+            vec![game_state.player1.color]
+        } else if game_state.player2.id == player_id {
+            vec![game_state.player2.color]
+        } else {
+            return Err("Player not in this game".to_string());
+        };
+
+        let mut moves = Vec::new();
+        if game_state.result.is_none() {
+            for color in colors {
+                moves.extend(game_state.legal_moves_for(color, now));
+            }
+        }
+
+        Ok(crate::LegalMovesResponse {
+            board_version: game_state.version,
+            moves,
+        })
+    }
+
+    /// Status as seen by `player_id`, or by an outsider when `None`
+    pub fn get_game_status(
+        &self,
+        game_id: Uuid,
+        player_id: Option<Uuid>,
+    ) -> Result<crate::GameStatus, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+
+        if let Some(player_id) = player_id
+            && game_state.player1.id != player_id
+            && game_state.player2.id != player_id
+        {
+            return Err("Player not in this game".to_string());
+        }
+
+        // Under check rules a player is told when their own king is attacked
+        let in_check = player_id
+            .filter(|_| game_state.rules.check_rules())
+            .map(|player_id| {
+                let color = if game_state.player1.id == player_id {
+                    &game_state.player1.color
+                } else {
+                    &game_state.player2.color
+                };
+                game_state.board.is_in_check(color)
+            });
+
+        // Lets a client skip redrawing when a move elsewhere left its view alone
+        let view_hash = player_id.map(|player_id| {
+            let color = if game_state.player1.id == player_id {
+                &game_state.player1.color
+            } else {
+                &game_state.player2.color
+            };
+            view_hash(
+                &game_state.fogged_slots(color, self.clock.now()),
+                &game_state.board.walls(),
+            )
+        });
+
+        // The start position gives away where pieces stood, so spectators wait
+        // like they do for the start board
+        let start_position = game_state
+            .start_position
+            .clone()
+            .filter(|_| player_id.is_some() || !game_state.fogged_for_spectators(self.clock.now()));
+
+        // Every check is announced, so the counts are no secret
+        let three_check = game_state.rules.three_check;
+
+        // With a hidden economy each player only learns their own move points
+        let hidden = game_state.rules.hide_opponent_economy && game_state.result.is_none();
+        let show_player1 = !hidden || player_id == Some(game_state.player1.id);
+        let show_player2 = !hidden || player_id == Some(game_state.player2.id);
+
+        Ok(crate::GameStatus {
+            game_id,
+            player1_moves: show_player1.then_some(game_state.game.player1_remaining_moves),
+            player2_moves: show_player2.then_some(game_state.game.player2_remaining_moves),
+            player1_at_cap: show_player1.then_some(game_state.game.player1_at_cap),
+            player2_at_cap: show_player2.then_some(game_state.game.player2_at_cap),
+            player1_charge: show_player1.then_some(game_state.game.player1_charge),
+            player2_charge: show_player2.then_some(game_state.game.player2_charge),
+            // Only turn-based games have turns; otherwise both players move at will
+            current_turn: game_state
+                .turn
+                .filter(|_| game_state.result.is_none())
+                .map(|turn| {
+                    if game_state.player1.color == turn {
+                        game_state.player1.id
+                    } else {
+                        game_state.player2.id
+                    }
+                }),
+            result: game_state.result,
+            phase: game_state.phase(),
+            in_check,
+            view_hash,
+            back_rank: game_state.back_rank,
+            handicap: game_state.handicap.clone(),
+            start_position,
+            draw_offer: game_state.draw_offer,
+            player1_checks: three_check.then_some(game_state.player1_checks),
+            player2_checks: three_check.then_some(game_state.player2_checks),
+            created_at: Some(format_wall_time(game_state.started_at)),
+            age_seconds: Some(
+                self.clock
+                    .now()
+                    .saturating_duration_since(game_state.created_at)
+                    .as_secs(),
+            ),
+        })
+    }
+
+    /// Count any request from a player in this game as a sign of life
+    pub fn record_presence(&mut self, game_id: Uuid, player_id: Uuid) {
+        let now = self.clock.now();
+        self.with_game_mut(game_id, |game_state| {
+            game_state.mark_player_seen(player_id, now)
+        });
+    }
+
+    /// [`Self::record_presence`] for callers holding only the read lock. The
+    /// sighting is kept aside until the next presence check, and only for
+    /// players of a game still in progress. Returns true when the player had
+    /// been warned for going quiet, so the caller should take the write lock and
+    /// record them there, letting the opponent hear about the return now.
+    pub fn note_presence(&self, game_id: Uuid, player_id: Uuid) -> bool {
+        let Some(Some(warned)) = self.with_game(game_id, |game_state| {
+            game_state
+                .result
+                .is_none()
+                .then(|| game_state.idle_warned(player_id))
+                .flatten()
+        }) else {
+            return false;
+        };
+
+        self.presence.insert((game_id, player_id), self.clock.now());
+        warned
+    }
+
+    // Hand the sightings noted under the read lock to their games
+    fn apply_presence(&mut self) {
+        let keys: Vec<(Uuid, Uuid)> = self.presence.iter().map(|entry| *entry.key()).collect();
+        for key in keys {
+            let Some(((game_id, player_id), seen_at)) = self.presence.remove(&key) else {
+                continue;
+            };
+            self.with_game_mut(game_id, |game_state| {
+                game_state.mark_player_seen(player_id, seen_at)
+            });
+        }
+    }
+
+    /// Ids of games still being played, so callers can work through them one at a time
+    pub fn active_game_ids(&self) -> Vec<Uuid> {
+        self.repository
+            .active_games()
+            .map(|game_state| game_state.game.id)
+            .collect()
+    }
+
+    /// Ids of games still being played that fall in `shard`. A shard of the
+    /// configured count is read straight from the index; any other split of the
+    /// games filters the whole index.
+    pub fn active_game_ids_in(&self, shard: TickShard) -> Vec<Uuid> {
+        let indexed: Vec<Uuid> = if shard.count == self.shard_games.len() as u64 {
+            self.shard_games[shard.index as usize]
+                .iter()
+                .copied()
+                .collect()
+        } else {
+            self.shard_games
+                .iter()
+                .flatten()
+                .copied()
+                .filter(|&game_id| shard.contains(game_id))
+                .collect()
+        };
+        indexed
+            .into_iter()
+            .filter(|&game_id| {
+                self.repository
+                    .get(game_id)
+                    .is_some_and(|game_state| game_state.result.is_none())
+            })
+            .collect()
+    }
+
+    /// Count down move points, play bots and check hills for the games in
+    /// `shard`, working out which games those are once. Returns how many games
+    /// the pass went through, which is what bounds how long it holds the lock.
+    pub fn tick_shard(&mut self, shard: TickShard) -> usize {
+        let game_ids = self.active_game_ids_in(shard);
+        self.increment_moves_for(&game_ids);
+        self.play_bot_moves_for(&game_ids);
+        self.check_hills_for(&game_ids);
+        game_ids.len()
+    }
+
+    /// Ids of games that have a result but are still held in memory
+    pub fn finished_game_ids(&self) -> Vec<Uuid> {
+        self.repository
+            .games()
+            .filter(|game_state| game_state.result.is_some())
+            .map(|game_state| game_state.game.id)
+            .collect()
+    }
+
+    /// Run `f` on one game, returning `None` if there is no such game
+    pub fn with_game<R>(&self, game_id: Uuid, f: impl FnOnce(&GameState) -> R) -> Option<R> {
+        self.repository.get(game_id).map(f)
+    }
+
+    /// Run `f` on one game and save whatever it changed
+    pub fn with_game_mut<R>(
+        &mut self,
+        game_id: Uuid,
+        f: impl FnOnce(&mut GameState) -> R,
+    ) -> Option<R> {
+        self.repository.get_mut(game_id).map(f)
+    }
+
+    /// End the games in `shard` where a king has stayed on the hill for the
+    /// hold time. The earlier of two kings on the hill wins.
+    pub fn check_hills(&mut self, shard: TickShard) {
+        let game_ids = self.active_game_ids_in(shard);
+        self.check_hills_for(&game_ids);
+    }
+
+    fn check_hills_for(&mut self, game_ids: &[Uuid]) {
+        if self.paused {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut controlled = Vec::new();
+        for &game_id in game_ids {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+            if game_state.rules.hill_squares.is_empty() || game_state.phase() != GamePhase::Playing
+            {
+                continue;
+            }
+            game_state.update_hill(now);
+
+            let hold = Duration::from_secs(game_state.rules.hill_hold_seconds);
+            let winner = game_state
+                .hill_holds
+                .iter()
+                .filter(|(_, held)| now.saturating_duration_since(held.since) >= hold)
+                .min_by_key(|(_, held)| held.since)
+                .map(|(color, _)| *color);
+            if let Some(winner) = winner {
+                let result = GameResult {
+                    winner: Some(winner),
+                    reason: GameEndReason::HillControlled,
+                };
+                game_state.events.push(GameEvent::GameOver { result });
+                game_state.result = Some(result);
+                controlled.push(game_id);
+            }
+        }
+
+        for game_id in controlled {
+            info!("King of the hill won game {}", game_id);
+            self.record_finished_game(game_id);
+        }
+    }
+
+    /// Warn about silent players and forfeit those gone for too long
+    pub fn check_presence(&mut self) {
+        self.apply_presence();
+        let now = self.clock.now();
+        let warn_after = self.presence_warning;
+        let mut abandoned = Vec::new();
+
+        for game_id in self.active_game_ids() {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+
+            let abandon_after = Duration::from_secs(game_state.rules.abandon_after_seconds);
+            // Bots are always there
+            let silent_for = |last_seen: std::time::Instant, color: PlayerColor| {
+                if game_state.bot_color == Some(color) {
+                    Duration::ZERO
+                } else {
+                    now.saturating_duration_since(last_seen)
+                }
+            };
+            let silences = [
+                (
+                    game_state.player1.color,
+                    silent_for(game_state.player1_last_seen, game_state.player1.color),
+                ),
+                (
+                    game_state.player2.color,
+                    silent_for(game_state.player2_last_seen, game_state.player2.color),
+                ),
+            ];
+
+            let gone: Vec<PlayerColor> = silences
+                .iter()
+                .filter(|(_, silent)| *silent >= abandon_after)
+                .map(|(color, _)| *color)
+                .collect();
+            if !gone.is_empty() {
+                // With both players gone there is nobody to award the game to
+                let winner = match gone.as_slice() {
+                    [color] => Some(color.opponent()),
+                    _ => None,
+                };
+                let result = GameResult {
+                    winner,
+                    reason: GameEndReason::Abandoned,
+                };
+                game_state.events.push(GameEvent::GameOver { result });
+                game_state.result = Some(result);
+                abandoned.push(game_id);
+                continue;
+            }
+
+            for (is_player1, (color, silent)) in [true, false].into_iter().zip(silences) {
+                let warned = if is_player1 {
+                    &mut game_state.player1_idle_warned
+                } else {
+                    &mut game_state.player2_idle_warned
+                };
+                if silent >= warn_after && !*warned {
+                    *warned = true;
+                    game_state.events.push(GameEvent::PlayerIdle { color });
+                }
+            }
+        }
+
+        for game_id in abandoned {
+            self.record_finished_game(game_id);
+        }
+    }
+
+    pub fn get_events(&self, game_id: Uuid) -> Result<Vec<GameEvent>, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        Ok(game_state.events.clone())
+    }
+
+    pub fn export_game(&self, game_id: Uuid) -> Result<GameArchive, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        let result = game_state.result.ok_or("Game is still in progress")?;
+
+        Ok(GameArchive {
+            game_id,
+            player1: game_state.player1.clone(),
+            player2: game_state.player2.clone(),
+            rules: game_state.rules.clone(),
+            history: game_state.history.clone(),
+            final_board: game_state.board.clone(),
+            start_board: game_state.start_board.clone(),
+            back_rank: game_state.back_rank,
+            handicap: game_state.handicap.clone(),
+            result,
+        })
+    }
+
+    /// The position a game started from, for replaying it from scratch. While a
+    /// fog game is in progress it is only given out when spectators can see
+    /// the whole board, so it can't reveal hidden pieces.
+    pub fn start_board(&self, game_id: Uuid) -> Result<crate::StartBoard, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        if game_state.fogged_for_spectators(self.clock.now()) {
+            return Err("The start board is hidden until the game ends".to_string());
+        }
+
+        Ok(start_board_of(
+            game_id,
+            game_state.start_board.clone(),
+            &game_state.rules,
+        ))
+    }
+
+    /// The moves played after the first `since`, with how many there are in
+    /// all, so a client replaying a game only fetches what is new. Hidden like
+    /// the start board while a fog game is in progress.
+    pub fn move_history(&self, game_id: Uuid, since: usize) -> Result<crate::MoveHistory, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        if game_state.fogged_for_spectators(self.clock.now()) {
+            return Err("The move history is hidden until the game ends".to_string());
+        }
+
+        Ok(history_since(game_id, &game_state.history, since))
+    }
+
+    /// Replay a game's recorded moves from the starting position and check that
+    /// every move was legal and the result matches the stored board
+    pub fn verify_game(&self, game_id: Uuid) -> Result<crate::VerifyGameResponse, String> {
+        let game_state = self.repository.get(game_id).ok_or("Game not found")?;
+        Ok(verify_replay(
+            game_id,
+            &game_state.history,
+            game_state.start_board.clone(),
+            &game_state.board,
+            &game_state.rules,
+        ))
+    }
+
+    /// Where games evicted from memory are kept, if anywhere. Reading it
+    /// touches the disk, so callers let go of the storage lock first.
+    pub fn archive(&self) -> Option<ArchiveStore> {
+        self.archive.clone()
+    }
+
+    /// Run every cleanup pass: end stale games, then evict finished ones
+    pub fn cleanup(&mut self) {
+        self.end_stale_games();
+        self.evict_finished_games();
+        self.expire_spectator_codes();
+        self.expire_lobbies();
+    }
+
+    /// Drop lobbies past their time
+    pub fn expire_lobbies(&mut self) {
+        let now = self.clock.now();
+        self.lobbies.retain(|_, lobby| lobby.expires_at > now);
+    }
+
+    /// Drop spectator codes that have expired or whose game is gone
+    pub fn expire_spectator_codes(&mut self) {
+        let now = self.clock.now();
+        let repository = &self.repository;
+        self.spectator_codes.retain(|_, code| {
+            code.expires_at.is_none_or(|expires_at| expires_at > now)
+                && repository.contains(code.game_id)
+        });
+    }
+
+    /// The game's spectator code, issuing a new one if it has none or the old
+    /// one expired
+    pub fn spectator_code(
+        &mut self,
+        game_id: Uuid,
+    ) -> Result<crate::SpectatorCodeResponse, String> {
+        if !self.repository.contains(game_id) {
+            return Err("Game not found".to_string());
+        }
+
+        let now = self.clock.now();
+        let existing = self.spectator_codes.iter().find(|(_, code)| {
+            code.game_id == game_id && code.expires_at.is_none_or(|expires_at| expires_at > now)
+        });
+        let (code, expires_at) = match existing {
+            Some((code, entry)) => (code.clone(), entry.expires_at),
+            None => {
+                let code = self.issue_spectator_code(game_id);
+                (code.clone(), self.spectator_codes[&code].expires_at)
+            }
+        };
+
+        Ok(crate::SpectatorCodeResponse {
+            code,
+            expires_in_seconds: expires_at
+                .map(|expires_at| expires_at.saturating_duration_since(now).as_secs()),
+        })
+    }
+
+    // Make up a code no other game is using and point it at the game
+    fn issue_spectator_code(&mut self, game_id: Uuid) -> String {
+        let code = loop {
+            let code = random_code();
+            if !self.spectator_codes.contains_key(&code) {
+                break code;
+            }
+        };
+
+        let expires_at = (!self.spectator_code_ttl.is_zero())
+            .then(|| self.clock.now() + self.spectator_code_ttl);
+        self.spectator_codes.insert(
+            code.clone(),
+            SpectatorCode {
+                game_id,
+                expires_at,
+            },
+        );
+        code
+    }
+
+    /// The spectator board of the game a code leads to. Codes are not case
+    /// sensitive.
+    pub fn spectate_by_code(&self, code: &str) -> Result<SpectatorBoard, String> {
+        let entry = self
+            .spectator_codes
+            .get(&code.trim().to_ascii_uppercase())
+            .ok_or("Unknown spectator code")?;
+        if entry
+            .expires_at
+            .is_some_and(|expires_at| expires_at <= self.clock.now())
+        {
+            return Err("Spectator code expired".to_string());
+        }
+        self.get_spectator_board(entry.game_id)
+    }
+
+    /// Games in progress that can be watched by code, newest first
+    pub fn spectatable_games(&self) -> Vec<crate::SpectatableGame> {
+        let now = self.clock.now();
+        let mut games: Vec<(std::time::Instant, crate::SpectatableGame)> = self
+            .spectator_codes
+            .iter()
+            .filter(|(_, code)| code.expires_at.is_none_or(|expires_at| expires_at > now))
+            .filter_map(|(code, entry)| {
+                let game_state = self.repository.get(entry.game_id)?;
+                if game_state.result.is_some() {
+                    return None;
+                }
+                let (white, black) = if game_state.player1.color == PlayerColor::White {
+                    (&game_state.player1, &game_state.player2)
+                } else {
+                    (&game_state.player2, &game_state.player1)
+                };
+                Some((
+                    game_state.created_at,
+                    crate::SpectatableGame {
+                        code: code.clone(),
+                        game_id: entry.game_id,
+                        white_name: white.name.clone(),
+                        black_name: black.name.clone(),
+                        moves: game_state.history.len(),
+                    },
+                ))
+            })
+            .collect();
+        games.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+        games.into_iter().map(|(_, game)| game).collect()
+    }
+
+    /// End games in which nobody has moved for longer than the stale period,
+    /// without a winner
+    pub fn end_stale_games(&mut self) {
+        if self.stale_after.is_zero() || self.paused {
+            return;
+        }
+
+        let now = self.clock.now();
+        let mut stale = Vec::new();
+        for game_id in self.active_game_ids() {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+            let idle_for = now.saturating_duration_since(game_state.last_activity);
+            if idle_for < self.stale_after {
+                continue;
+            }
+
+            let result = GameResult {
+                winner: None,
+                reason: GameEndReason::Inactive,
+            };
+            game_state.events.push(GameEvent::GameOver { result });
+            game_state.result = Some(result);
+            info!(
+                "Ending game {}: no moves for {}s",
+                game_id,
+                idle_for.as_secs()
+            );
+            stale.push(game_id);
+        }
+
+        for game_id in stale {
+            self.record_finished_game(game_id);
+        }
+    }
+
+    /// Write games finished longer than the grace period to the archive and drop
+    /// them from memory. Without an archive finished games are dropped after the
+    /// longer retention period, or kept if that is zero.
+    pub fn evict_finished_games(&mut self) {
+        let keep_for = match &self.archive {
+            Some(_) => self.archive_grace,
+            None if self.finished_retention.is_zero() => return,
+            None => self.finished_retention,
+        };
+
+        let now = self.clock.now();
+        let expired: Vec<(Uuid, Duration)> = self
+            .repository
+            .games()
+            .filter_map(|game_state| {
+                let finished_for = now.saturating_duration_since(game_state.finished_at?);
+                (finished_for >= keep_for).then_some((game_state.game.id, finished_for))
+            })
+            .collect();
+
+        for (game_id, finished_for) in expired {
+            let Ok(record) = self.export_game(game_id) else {
+                continue;
+            };
+            let players = [record.player1.id, record.player2.id];
+            match &self.archive {
+                Some(archive) => {
+                    archive.queue(record);
+                    info!(
+                        "Archived game {}: finished {}s ago",
+                        game_id,
+                        finished_for.as_secs()
+                    );
+                }
+                None => info!(
+                    "Dropped game {}: finished {}s ago and no archive is configured",
+                    game_id,
+                    finished_for.as_secs()
+                ),
+            }
+
+            self.repository.remove(game_id);
+            // Players stay known, so asking for their current game finds none
+            // rather than no player
+            for player_id in players {
+                if let Some(game_ids) = self.player_games.get_mut(&player_id) {
+                    game_ids.retain(|id| *id != game_id);
+                }
+            }
+        }
+    }
+
+    /// Load an exported game for analysis. Imported games are already finished
+    /// and do not touch player accounts. Only games in memory are checked for a
+    /// clash; callers look in the archive first.
+    pub fn import_game(&mut self, mut archive: GameArchive) -> Result<Uuid, String> {
+        if self.repository.contains(archive.game_id) {
+            return Err("Game already exists".to_string());
+        }
+
+        // The rules decide how pawns move, whatever the boards were saved with
+        let pawn_rules = archive.rules.pawn_rules();
+        archive.final_board.set_pawn_rules(pawn_rules);
+        if let Some(start_board) = &mut archive.start_board {
+            start_board.set_pawn_rules(pawn_rules);
+        }
+
+        let captured_pieces = archive
+            .history
+            .iter()
+            .flat_map(|record| {
+                let captured = record.captured.map(|piece| ExtendedSlot {
+                    piece,
+                    color: record.color.opponent(),
+                });
+                let exploded = record.exploded.iter().map(|victim| ExtendedSlot {
+                    piece: victim.piece,
+                    color: victim.color,
+                });
+                captured.into_iter().chain(exploded)
+            })
+            .collect();
+
+        let now = self.clock.now();
+        let game_state = GameState {
+            game: Game {
+                id: archive.game_id,
+                player1_remaining_moves: 0,
+                player1_move_increment_countdown: archive.rules.move_increment_ticks,
+                player2_remaining_moves: 0,
+                player2_move_increment_countdown: archive.rules.move_increment_ticks,
+                player1_at_cap: false,
+                player2_at_cap: false,
+                player1_charge: 0,
+                player2_charge: 0,
+            },
+            board: archive.final_board,
+            version: archive.history.len() as u64,
+            player1: archive.player1,
+            player2: archive.player2,
+            created_at: now,
+            started_at: self.clock.wall_time(),
+            last_activity: now,
+            first_blood_awarded: archive.rules.first_blood_bonus
+                && archive
+                    .history
+                    .iter()
+                    .any(|record| record.captured.is_some()),
+            rules: archive.rules,
+            captured_pieces,
+            history: archive.history,
+            position_counts: HashMap::new(),
+            halfmove_clock: 0,
+            moves_since_capture: 0,
+            result: Some(archive.result),
+            tournament_id: None,
+            draw_offer: None,
+            unrated: false,
+            start_board: archive.start_board,
+            start_position: None,
+            back_rank: archive.back_rank,
+            handicap: archive.handicap,
+            bot_color: None,
+            turn: None,
+            placement_done: true,
+            placements: HashMap::new(),
+            removed_players: BTreeSet::new(),
+            player1_ready: true,
+            player2_ready: true,
+            player1_checks: 0,
+            player2_checks: 0,
+            events: Vec::new(),
+            player1_last_seen: now,
+            player2_last_seen: now,
+            player1_idle_warned: false,
+            player2_idle_warned: false,
+            player1_last_move_at: None,
+            player2_last_move_at: None,
+            finished_at: Some(now),
+            legal_moves_cache: HashMap::new(),
+            fogged_board_cache: HashMap::new(),
+            beacons: HashMap::new(),
+            capture_pulses: HashMap::new(),
+            reveals: HashMap::new(),
+            hill_holds: HashMap::new(),
+        };
+
+        self.repository.insert(game_state);
+        Ok(archive.game_id)
+    }
+
+    pub fn get_player_stats(&self, player_name: &str) -> Result<PlayerStats, String> {
+        self.accounts
+            .get(player_name)
+            .cloned()
+            .ok_or_else(|| "Player not found".to_string())
+    }
+
+    // Update both players' accounts once a game has reached a result
+    fn record_finished_game(&mut self, game_id: Uuid) {
+        let now = self.clock.now();
+        if let Some(game_state) = self.repository.get_mut(game_id)
+            && game_state.result.is_some()
+        {
+            if game_state.finished_at.is_none() {
+                self.active_game_count.fetch_sub(1, Ordering::Relaxed);
+            }
+            game_state.finished_at = Some(now);
+            self.shard_games_of(game_id).remove(&game_id);
+        }
+        self.publish_change(game_id);
+
+        let Some(game_state) = self.repository.get(game_id) else {
+            return;
+        };
+        let Some(result) = &game_state.result else {
+            return;
+        };
+        let duration_seconds = now
+            .saturating_duration_since(game_state.created_at)
+            .as_secs();
+
+        // Every game is logged, whatever its mode
+        if let Some(analytics) = &self.analytics
+            && let Some(record) = GameAnalytics::from_game(game_state, duration_seconds)
+        {
+            let _ = analytics.send(record);
+        }
+
+        // Practice, bot and seeded games don't count towards anyone's record
+        if game_state.is_solo() || game_state.unrated {
+            return;
+        }
+
+        // Ratings move based on both players' ratings before this game
+        let ratings_before = [&game_state.player1, &game_state.player2].map(|player| {
+            self.accounts
+                .get(&player.name)
+                .map_or(BASE_RATING, |stats| stats.rating)
+        });
+
+        let players = [&game_state.player1, &game_state.player2];
+        for (index, player) in players.into_iter().enumerate() {
+            let stats = self.accounts.entry(player.name.clone()).or_default();
+            stats.games_played += 1;
+            stats.season.games_played += 1;
+
+            let score = match &result.winner {
+                Some(winner) if *winner == player.color => {
+                    stats.wins += 1;
+                    stats.season.wins += 1;
+                    stats.current_win_streak += 1;
+                    stats.best_win_streak = stats.best_win_streak.max(stats.current_win_streak);
+                    1.0
+                }
+                Some(_) => {
+                    stats.losses += 1;
+                    stats.season.losses += 1;
+                    stats.current_win_streak = 0;
+                    0.0
+                }
+                None => {
+                    stats.draws += 1;
+                    stats.season.draws += 1;
+                    stats.current_win_streak = 0;
+                    0.5
+                }
+            };
+            stats.rating = updated_rating(ratings_before[index], ratings_before[1 - index], score);
+            stats.color_record.record(player.color, score);
+            let head_to_head = stats
+                .head_to_head
+                .entry(players[1 - index].name.clone())
+                .or_default();
+            head_to_head.games += 1;
+            head_to_head.points += score;
+
+            for achievement in Achievement::ALL {
+                if !stats.achievements.contains(&achievement)
+                    && achievement.is_earned(&player.color, result, game_state, stats)
+                {
+                    stats.achievements.push(achievement);
+                }
+            }
+        }
+
+        if let Some(persist) = &self.persist {
+            for player in [&game_state.player1, &game_state.player2] {
+                if let Some(stats) = self.accounts.get(&player.name) {
+                    let _ = persist.send(PersistEvent::AccountUpdated {
+                        player_name: player.name.clone(),
+                        stats: stats.clone(),
+                    });
+                }
+            }
+            if let Ok(archive) = self.export_game(game_id) {
+                let _ = persist.send(PersistEvent::GameFinished {
+                    archive: Box::new(archive),
+                });
+            }
+        }
+
+        if let Some(webhook) = &self.webhook {
+            let _ = webhook.send(GameSummary {
+                game_id,
+                players: [&game_state.player1, &game_state.player2]
+                    .map(|player| SummaryPlayer {
+                        name: player.name.clone(),
+                        color: player.color,
+                    })
+                    .to_vec(),
+                winner: result.winner,
+                reason: result.reason,
+                duration_seconds,
+                moves: game_state.history.len(),
+            });
+        }
+
+        if let Some(tournament_id) = game_state.tournament_id {
+            self.advance_tournament(tournament_id, game_id);
+        }
+    }
+
+    /// Close the current season: archive every account's rating and season
+    /// stats, squash ratings towards the base and start counting afresh.
+    /// The new state is built aside and swapped in, so the reset applies fully or not at all.
+    pub fn rollover_season(&mut self, next_season: Option<String>) -> Result<String, String> {
+        let next_season =
+            next_season.unwrap_or_else(|| format!("Season {}", self.seasons.len() + 2));
+        if next_season == self.current_season
+            || self.seasons.iter().any(|season| season.name == next_season)
+        {
+            return Err("Season name already used".to_string());
+        }
+
+        let archive = SeasonArchive {
+            name: self.current_season.clone(),
+            entries: self
+                .accounts
+                .iter()
+                .map(|(player_name, stats)| {
+                    (
+                        player_name.clone(),
+                        SeasonEntry {
+                            rating: stats.rating,
+                            stats: stats.season.clone(),
+                        },
+                    )
+                })
+                .collect(),
+        };
+
+        let mut accounts = self.accounts.clone();
+        for stats in accounts.values_mut() {
+            stats.rating = soft_reset(stats.rating);
+            stats.season = SeasonStats::default();
+        }
+
+        if let Some(persist) = &self.persist {
+            let _ = persist.send(PersistEvent::SeasonClosed {
+                archive: Box::new(archive.clone()),
+                current_season: next_season.clone(),
+                accounts: accounts.clone(),
+            });
+        }
+
+        let closed = std::mem::replace(&mut self.current_season, next_season);
+        self.accounts = accounts;
+        self.seasons.push(archive);
+        Ok(closed)
+    }
+
+    /// An account's standing in every closed season they played in
+    pub fn get_player_seasons(
+        &self,
+        player_name: &str,
+    ) -> Result<Vec<crate::PlayerSeason>, String> {
+        if !self.accounts.contains_key(player_name) {
+            return Err("Player not found".to_string());
+        }
+
+        Ok(self
+            .seasons
+            .iter()
+            .filter_map(|season| {
+                season
+                    .entries
+                    .get(player_name)
+                    .map(|entry| crate::PlayerSeason {
+                        season: season.name.clone(),
+                        rating: entry.rating,
+                        stats: entry.stats.clone(),
+                    })
+            })
+            .collect())
+    }
+
+    /// Every rated player, best first, with ties broken the same way every time
+    pub fn standings(&self) -> Vec<StandingsEntry> {
+        standings(&self.accounts)
+    }
+
+    pub fn get_season_leaderboard(&self, season: &str) -> Result<Vec<LeaderboardEntry>, String> {
+        self.seasons
+            .iter()
+            .find(|archive| archive.name == season)
+            .map(|archive| archive.leaderboard())
+            .ok_or_else(|| "Season not found".to_string())
+    }
+
+    pub fn create_tournament(
+        &mut self,
+        name: String,
+        size: usize,
+        strictness: Strictness,
+        format: TournamentFormat,
+    ) -> Result<Uuid, String> {
+        let tournament = Tournament::new(name, size, strictness, format)?;
+        let tournament_id = tournament.id;
+        self.tournaments.insert(tournament_id, tournament);
+        Ok(tournament_id)
+    }
+
+    /// Sign a player up; the bracket starts as soon as the last seat is taken
+    pub fn register_for_tournament(
+        &mut self,
+        tournament_id: Uuid,
+        player_name: String,
+    ) -> Result<crate::TournamentRegistration, String> {
+        let tournament = self
+            .tournaments
+            .get_mut(&tournament_id)
+            .ok_or("Tournament not found")?;
+
+        let player_id = Uuid::new_v4();
+        tournament.register(TournamentPlayer {
+            id: player_id,
+            name: player_name,
+        })?;
+        self.player_games.entry(player_id).or_default();
+
+        if tournament.is_full() {
+            let round = tournament.first_round();
+            self.start_tournament_round(tournament_id, round)?;
+        }
+
+        Ok(crate::TournamentRegistration {
+            tournament_id,
+            player_id,
+        })
+    }
+
+    pub fn get_tournament(&self, tournament_id: Uuid) -> Result<Tournament, String> {
+        self.tournaments
+            .get(&tournament_id)
+            .cloned()
+            .ok_or_else(|| "Tournament not found".to_string())
+    }
+
+    // Create one game per pairing and append them as the next round
+    fn start_tournament_round(
+        &mut self,
+        tournament_id: Uuid,
+        round: RoundPairings,
+    ) -> Result<(), String> {
+        let mut matches = Vec::with_capacity(round.pairings.len());
+        for (player1, player2) in round.pairings {
+            let game_id = self.create_tournament_game(tournament_id, player1, player2)?;
+            matches.push(BracketMatch {
+                player1,
+                player2,
+                game_id,
+                winner: None,
+                finished: false,
+            });
+        }
+
+        let tournament = self
+            .tournaments
+            .get_mut(&tournament_id)
+            .ok_or("Tournament not found")?;
+        tournament.rounds.push(matches);
+        tournament.byes.push(round.bye);
+        Ok(())
+    }
+
+    fn create_tournament_game(
+        &mut self,
+        tournament_id: Uuid,
+        player1: Uuid,
+        player2: Uuid,
+    ) -> Result<Uuid, String> {
+        let tournament = self
+            .tournaments
+            .get(&tournament_id)
+            .ok_or("Tournament not found")?;
+        let now = self.clock.now();
+        let entrant = |player_id: Uuid| {
+            tournament
+                .players
+                .iter()
+                .find(|player| player.id == player_id)
+                .map(|player| QueuedPlayer {
+                    id: player.id,
+                    name: player.name.clone(),
+                    joined_at: now,
+                    mode: self.default_rules.mode,
+                })
+                .ok_or("Player not in tournament")
+        };
+        let (player1, player2) = (entrant(player1)?, entrant(player2)?);
+        let rules = GameRules {
+            strictness: tournament.strictness,
+            ..self.default_rules.clone()
+        };
+
+        let game_id = self.create_game(player1, player2, rules)?;
+        if let Some(game_state) = self.repository.get_mut(game_id) {
+            game_state.tournament_id = Some(tournament_id);
+        }
+        Ok(game_id)
+    }
+
+    // Record the outcome of a finished bracket game and move the tournament along.
+    // A no-show loses by abandonment, which hands the present player a walkover.
+    fn advance_tournament(&mut self, tournament_id: Uuid, game_id: Uuid) {
+        let Some(game_state) = self.repository.get(game_id) else {
+            return;
+        };
+        let Some(result) = &game_state.result else {
+            return;
+        };
+        let winner = result.winner.as_ref().map(|color| {
+            if game_state.player1.color == *color {
+                game_state.player1.id
+            } else {
+                game_state.player2.id
+            }
+        });
+        let (player1, player2) = (game_state.player1.id, game_state.player2.id);
+
+        let Some(tournament) = self.tournaments.get_mut(&tournament_id) else {
+            return;
+        };
+
+        // Elimination needs a winner, so drawn matches are replayed
+        if winner.is_none() && tournament.format == TournamentFormat::SingleElimination {
+            if let Ok(replay_id) = self.create_tournament_game(tournament_id, player1, player2)
+                && let Some(tournament) = self.tournaments.get_mut(&tournament_id)
+                && let Some(bracket_match) = tournament.find_match_mut(game_id)
+            {
+                bracket_match.game_id = replay_id;
+            }
+            return;
+        }
+
+        let Some(bracket_match) = tournament.find_match_mut(game_id) else {
+            return;
+        };
+        bracket_match.winner = winner;
+        bracket_match.finished = true;
+
+        // The next round waits until every game of this one is over
+        if let Some(round) = tournament.next_round() {
+            let _ = self.start_tournament_round(tournament_id, round);
+        } else if let Some(champion) = tournament.decide_champion() {
+            tournament.champion = Some(champion);
+        }
+    }
+
+    /// Write pending game changes to the repository's backing store
+    pub fn flush(&mut self) {
+        if let Err(errors) = self.repository.flush() {
+            for error in errors {
+                warn!("Game changes not saved: {}", error);
+            }
+        }
+    }
+
+    /// Count down to the next move point for every game in `shard`. Each game
+    /// is in exactly one shard, so it is counted down once per full tick.
+    pub fn increment_moves(&mut self, shard: TickShard) {
+        let game_ids = self.active_game_ids_in(shard);
+        self.increment_moves_for(&game_ids);
+    }
+
+    fn increment_moves_for(&mut self, game_ids: &[Uuid]) {
+        if self.paused {
+            return;
+        }
+        // Finished games no longer accrue move points, and games in placement or
+        // setup don't yet. Turn-based games never do.
+        let now = self.clock.now();
+        let mut placed = Vec::new();
+        for &game_id in game_ids {
+            let Some(game_state) = self.repository.get_mut(game_id) else {
+                continue;
+            };
+            // Out of time: whoever hasn't placed keeps the usual setup
+            if game_state.phase() == GamePhase::Placement
+                && now.saturating_duration_since(game_state.created_at)
+                    >= Duration::from_secs(game_state.rules.placement_seconds)
+            {
+                game_state.finish_placement(now);
+                placed.push(game_id);
+            }
+            if game_state.phase() != GamePhase::Playing || game_state.turn.is_some() {
+                continue;
+            }
+
+            // Player 1 move increment
+            if game_state.game.player1_move_increment_countdown > 0 {
+                game_state.game.player1_move_increment_countdown -= 1;
+            } else {
+                if game_state.game.player1_remaining_moves >= game_state.rules.max_stored_moves
+                    && !game_state.game.player1_at_cap
+                {
+                    game_state.game.player1_at_cap = true;
+                    game_state.events.push(GameEvent::MovePointCapped {
+                        color: game_state.player1.color,
+                    });
+                }
+                // Points past the cap go to the charge meter when it is enabled
+                if game_state.game.player1_remaining_moves >= game_state.rules.max_stored_moves {
+                    game_state.game.player1_charge = std::cmp::min(
+                        game_state.game.player1_charge + 1,
+                        game_state.rules.charge_capacity,
+                    );
+                }
+                game_state.game.player1_remaining_moves = std::cmp::min(
+                    game_state.game.player1_remaining_moves + 1,
+                    game_state.rules.max_stored_moves,
+                );
+                game_state.game.player1_move_increment_countdown =
+                    game_state.rules.move_increment_ticks;
+            }
+
+            // Player 2 move increment
+            if game_state.game.player2_move_increment_countdown > 0 {
+                game_state.game.player2_move_increment_countdown -= 1;
+            } else {
+                if game_state.game.player2_remaining_moves >= game_state.rules.max_stored_moves
+                    && !game_state.game.player2_at_cap
+                {
+                    game_state.game.player2_at_cap = true;
+                    game_state.events.push(GameEvent::MovePointCapped {
+                        color: game_state.player2.color,
+                    });
+                }
+                // Points past the cap go to the charge meter when it is enabled
+                if game_state.game.player2_remaining_moves >= game_state.rules.max_stored_moves {
+                    game_state.game.player2_charge = std::cmp::min(
+                        game_state.game.player2_charge + 1,
+                        game_state.rules.charge_capacity,
+                    );
+                }
+                game_state.game.player2_remaining_moves = std::cmp::min(
+                    game_state.game.player2_remaining_moves + 1,
+                    game_state.rules.max_stored_moves,
+                );
+                game_state.game.player2_move_increment_countdown =
+                    game_state.rules.move_increment_ticks;
+            }
+        }
+
+        for game_id in placed {
+            info!("Placement ran out of time in game {}", game_id);
+            self.publish_change(game_id);
+        }
+    }
+}
+
+// The most valuable capture on offer, otherwise a move picked from the game
+// and board version so the bot doesn't always open the same way
+fn choose_bot_move(
+    board: &ExtendedBoard,
+    moves: &[LegalMove],
+    game_id: Uuid,
+    version: u64,
+) -> Option<LegalMove> {
+    let captured_value = |candidate: &LegalMove| {
+        board.slot(candidate.to).map_or(0, |slot| match slot.piece {
+            // Taking the king wins outright
+            ChestPiece::King => u32::MAX,
+            piece => piece.value(),
+        })
+    };
+
+    let best = moves.iter().map(captured_value).max()?;
+    let candidates: Vec<&LegalMove> = moves
+        .iter()
+        .filter(|candidate| captured_value(candidate) == best)
+        .collect();
+    let (high, low) = game_id.as_u64_pair();
+    let pick = (high ^ low ^ version.wrapping_mul(0x9e37_79b9_7f4a_7c15)) % candidates.len() as u64;
+    Some(*candidates[pick as usize])
+}
+
+// Copy the pieces on the given squares, leaving the rest empty
+fn visible_slots(board: &ExtendedBoard, visible: Bitboard) -> Vec<Vec<Option<VisibleSlot>>> {
+    let (rows, cols) = board.dims();
+    let mut slots = vec![vec![None; cols]; rows];
+
+    for (row, col) in squares(visible) {
+        if let Some(piece_info) = board.slot((row, col)) {
+            slots[row][col] = Some(VisibleSlot {
+                piece: piece_info.piece,
+                color: piece_info.color,
+            });
+        }
+    }
+
+    slots
+}
+
+impl QueuedPlayer {
+    /// Rough number of bytes the queue entry takes up in memory
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>() + self.name.capacity()
+    }
+}
+
+impl PlayerStats {
+    /// Rough number of bytes the account takes up in memory
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>() + self.achievements.capacity() * size_of::<Achievement>()
+    }
+}
+
+impl GameState {
+    /// Rough number of bytes the game takes up in memory, counting what its
+    /// collections have allocated
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>()
+            + self.player1.name.capacity()
+            + self.player2.name.capacity()
+            + self
+                .start_board
+                .as_ref()
+                .map_or(0, |_| size_of::<ExtendedBoard>())
+            + self.captured_pieces.capacity() * size_of::<ExtendedSlot>()
+            + self.position_counts.capacity() * size_of::<(u64, u32)>()
+            + self.events_bytes()
+            + self.history_bytes()
+            + self.legal_moves_cache_bytes()
+            + self.fogged_board_cache_bytes()
+            + self
+                .beacons
+                .values()
+                .map(|beacons| beacons.capacity() * size_of::<Beacon>())
+                .sum::<usize>()
+            + self
+                .capture_pulses
+                .values()
+                .map(|pulses| pulses.capacity() * size_of::<(usize, usize)>())
+                .sum::<usize>()
+    }
+
+    pub fn events_bytes(&self) -> usize {
+        self.events.capacity() * size_of::<GameEvent>()
+    }
+
+    pub fn history_bytes(&self) -> usize {
+        self.history.capacity() * size_of::<MoveRecord>()
+    }
+
+    pub fn legal_moves_cache_bytes(&self) -> usize {
+        self.legal_moves_cache
+            .values()
+            .map(|(_, moves)| {
+                size_of::<(PlayerColor, ((u64, usize), Vec<LegalMove>))>()
+                    + moves.capacity() * size_of::<LegalMove>()
+            })
+            .sum()
+    }
+
+    pub fn fogged_board_cache_bytes(&self) -> usize {
+        self.fogged_board_cache.len()
+            * (size_of::<(PlayerColor, ((u64, usize), Arc<FoggedBoard>))>()
+                + size_of::<FoggedBoard>())
+            + self
+                .fogged_board_cache
+                .values()
+                .map(|(_, board)| board.walls.len() * size_of::<(usize, usize)>())
+                .sum::<usize>()
+    }
+
+    /// Sanity checks for a game loaded from outside
+    pub fn validate(&self) -> Result<(), String> {
+        if self.player1.color == self.player2.color {
+            return Err("Both players have the same color".to_string());
+        }
+        if self.version != self.history.len() as u64 {
+            return Err("Board version does not match the move history".to_string());
+        }
+        if self.result.is_none() {
+            for color in [PlayerColor::White, PlayerColor::Black] {
+                let kings = self.board.count(&color, ChestPiece::King);
+                if kings != 1 {
+                    return Err(format!("{:?} must have exactly one king", color));
+                }
+            }
+            self.board.validate_walls()?;
+        }
+        if self.game.player1_remaining_moves > self.rules.max_stored_moves
+            || self.game.player2_remaining_moves > self.rules.max_stored_moves
+        {
+            return Err("Move points above the cap".to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Squares `color` can see: around its own pieces plus any active beacons and
+    /// pending capture pulses, or everything during a bought reveal
+    pub fn visible_mask(&self, color: &PlayerColor, now: std::time::Instant) -> Bitboard {
+        if self.revealing(color, now) {
+            return self.board.all_squares();
+        }
+        let mut visible = self.board.visible_mask(color);
+        if let Some(beacons) = self.beacons.get(color) {
+            for beacon in beacons.iter().filter(|beacon| beacon.expires_at > now) {
+                visible |= bit(beacon.position);
+            }
+        }
+        if let Some(pulses) = self.capture_pulses.get(color) {
+            let radius = self.rules.capture_pulse_radius as usize;
+            for &pulse in pulses {
+                visible |= self.board.visible_from(pulse, radius);
+            }
+        }
+        visible
+    }
+
+    /// The pieces `color` can see, or the whole board when fog is off
+    pub fn fogged_slots(
+        &self,
+        color: &PlayerColor,
+        now: std::time::Instant,
+    ) -> Vec<Vec<Option<VisibleSlot>>> {
+        let visible = if self.rules.fog_enabled {
+            self.visible_mask(color, now)
+        } else {
+            self.board.all_squares()
+        };
+        visible_slots(&self.board, visible)
+    }
+
+    /// Everything that lets `color` see each square it can see, matching
+    /// `visible_mask`
+    pub fn vision_sources(
+        &self,
+        color: &PlayerColor,
+        now: std::time::Instant,
+    ) -> BTreeMap<(usize, usize), Vec<crate::VisionSource>> {
+        let mut sources: BTreeMap<(usize, usize), Vec<crate::VisionSource>> = BTreeMap::new();
+        for (from, slot) in self.board.pieces().filter(|(_, slot)| slot.color == *color) {
+            let range = slot.piece.default_sight();
+            let source = crate::VisionSource::Piece {
+                piece: slot.piece,
+                from,
+                range,
+            };
+            for square in squares(self.board.visible_from(from, range)) {
+                sources.entry(square).or_default().push(source);
+            }
+        }
+        if let Some(beacons) = self.beacons.get(color) {
+            for beacon in beacons.iter().filter(|beacon| beacon.expires_at > now) {
+                sources
+                    .entry(beacon.position)
+                    .or_default()
+                    .push(crate::VisionSource::Beacon {
+                        from: beacon.position,
+                    });
+            }
+        }
+        if let Some(pulses) = self.capture_pulses.get(color) {
+            let range = self.rules.capture_pulse_radius as usize;
+            for &from in pulses {
+                for square in squares(self.board.visible_from(from, range)) {
+                    sources
+                        .entry(square)
+                        .or_default()
+                        .push(crate::VisionSource::CapturePulse { from, range });
+                }
+            }
+        }
+        if self.revealing(color, now) {
+            for square in squares(self.board.all_squares()) {
+                sources
+                    .entry(square)
+                    .or_default()
+                    .push(crate::VisionSource::Reveal);
+            }
+        }
+        sources
+    }
+
+    // Start the hold of each king that stepped onto the hill and drop those
+    // that left it. A king on the hill is announced once its opponent sees it.
+    fn update_hill(&mut self, now: std::time::Instant) {
+        if self.rules.hill_squares.is_empty() {
+            return;
+        }
+
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            let Some(king) = self
+                .board
+                .find_king(&color)
+                .filter(|king| self.rules.hill_squares.contains(king))
+            else {
+                self.hill_holds.remove(&color);
+                continue;
+            };
+
+            let announced = self
+                .hill_holds
+                .get(&color)
+                .is_some_and(|held| held.announced);
+            let seen = announced
+                || !self.rules.fog_enabled
+                || self.visible_mask(&color.opponent(), now).contains(king);
+            let held = self.hill_holds.entry(color).or_insert(HillHold {
+                since: now,
+                announced: false,
+            });
+            if seen && !held.announced {
+                held.announced = true;
+                self.events.push(GameEvent::KingOnHill { color });
+            }
+        }
+    }
+
+    /// Whether `color` has a bought reveal running
+    pub fn revealing(&self, color: &PlayerColor, now: std::time::Instant) -> bool {
+        self.reveals
+            .get(color)
+            .is_some_and(|expires_at| *expires_at > now)
+    }
+
+    /// Seconds since `color`'s opponent last moved, rounded down to an
+    /// activity bucket
+    pub fn opponent_last_active(
+        &self,
+        color: &PlayerColor,
+        now: std::time::Instant,
+    ) -> Option<u64> {
+        let last_move_at = if self.player1.color == *color {
+            self.player2_last_move_at
+        } else {
+            self.player1_last_move_at
+        }?;
+        let seconds = now.saturating_duration_since(last_move_at).as_secs();
+        ACTIVITY_BUCKETS
+            .into_iter()
+            .rev()
+            .find(|bucket| *bucket <= seconds)
+    }
+
+    // What decides what `color` sees: the board version and how many beacons,
+    // capture pulses and reveals it has. Within one version these only ever go
+    // away, so the count is enough; buying a reveal clears the caches instead.
+    // Expired beacons and reveals are dropped first.
+    fn visibility_key(&mut self, color: &PlayerColor, now: std::time::Instant) -> (u64, usize) {
+        if let Some(beacons) = self.beacons.get_mut(color) {
+            beacons.retain(|beacon| beacon.expires_at > now);
+        }
+        if !self.revealing(color, now) {
+            self.reveals.remove(color);
+        }
+        self.current_visibility_key(color, now)
+    }
+
+    // The same key without dropping anything, skipping what has expired instead
+    fn current_visibility_key(&self, color: &PlayerColor, now: std::time::Instant) -> (u64, usize) {
+        (
+            self.version,
+            self.beacons.get(color).map_or(0, |beacons| {
+                beacons
+                    .iter()
+                    .filter(|beacon| beacon.expires_at > now)
+                    .count()
+            }) + self
+                .capture_pulses
+                .get(color)
+                .map_or(0, |pulses| pulses.len())
+                + usize::from(self.revealing(color, now)),
+        )
+    }
+
+    // Legal moves for one color, recomputed only when the board version has moved
+    // on, a beacon has expired or a capture pulse was used up
+    fn legal_moves_for(&mut self, color: PlayerColor, now: std::time::Instant) -> Vec<LegalMove> {
+        let key = self.visibility_key(&color, now);
+        if let Some((cached_key, moves)) = self.legal_moves_cache.get(&color)
+            && *cached_key == key
+        {
+            return moves.clone();
+        }
+
+        // Hidden pieces must not show up as captures or blockers
+        let board = if self.rules.fog_enabled && !self.is_solo() {
+            self.board.fogged_to(self.visible_mask(&color, now))
+        } else {
+            self.board.clone()
+        };
+        let king_protected = self.rules.king_rule == KingRule::Checkmate;
+        let moves: Vec<LegalMove> = board
+            .all_legal_moves(&color, self.rules.check_rules())
+            .into_iter()
+            .filter(|&(_, to)| !(king_protected && board.holds_king_of(to, &color.opponent())))
+            .filter(|&(from, to)| {
+                !(self.rules.atomic && board.blast_hits_own_king(from, to, &color))
+            })
+            .map(|(from, to)| LegalMove { from, to })
+            .collect();
+
+        self.legal_moves_cache.insert(color, (key, moves.clone()));
+        moves
+    }
+
+    /// Update draw bookkeeping for the last recorded move and report a draw if one applies
+    // Count the move towards the no-capture draw, drawing the game once the
+    // limit is reached. The count runs whatever the game's strictness.
+    fn no_capture_draw_after_move(&mut self) -> Option<GameResult> {
+        let last_move = self.history.last()?;
+        if last_move.captured.is_some()
+            || (self.rules.pawn_moves_reset_no_capture_draw && last_move.piece == ChestPiece::Pawn)
+        {
+            self.moves_since_capture = 0;
+        } else {
+            self.moves_since_capture += 1;
+        }
+
+        let limit = self.rules.no_capture_draw_moves;
+        (limit > 0 && self.moves_since_capture >= limit).then_some(GameResult {
+            winner: None,
+            reason: GameEndReason::NoCaptureLimit,
+        })
+    }
+
+    // Whether spectators are kept from seeing the pieces: a fog game in
+    // progress once the intro reveal is over
+    fn fogged_for_spectators(&self, now: std::time::Instant) -> bool {
+        let intro = Duration::from_secs(self.rules.intro_reveal_seconds);
+        let in_intro = now.duration_since(self.created_at) < intro;
+        self.rules.fog_enabled && !in_intro && self.result.is_none()
+    }
+
+    fn strict_draw_after_move(&mut self) -> Option<GameResult> {
+        let last_move = self.history.last()?;
+        if last_move.captured.is_some() || last_move.piece == ChestPiece::Pawn {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+
+        let repetitions = self
+            .position_counts
+            .entry(self.board.position_key())
+            .or_insert(0);
+        *repetitions += 1;
+
+        let reason = if *repetitions >= STRICT_REPETITION_LIMIT {
+            GameEndReason::ThreefoldRepetition
+        } else if self.halfmove_clock >= STRICT_FIFTY_MOVE_LIMIT {
+            GameEndReason::FiftyMoveRule
+        } else if !self.board.has_sufficient_material(&PlayerColor::White)
+            && !self.board.has_sufficient_material(&PlayerColor::Black)
+        {
+            GameEndReason::InsufficientMaterial
+        } else if [PlayerColor::White, PlayerColor::Black]
+            .iter()
+            .any(|color| {
+                !self.board.is_in_check(color) && self.board.all_legal_moves(color, true).is_empty()
+            })
+        {
+            GameEndReason::Stalemate
+        } else {
+            return None;
+        };
+
+        Some(GameResult {
+            winner: None,
+            reason,
+        })
+    }
+
+    pub fn phase(&self) -> GamePhase {
+        if self.result.is_some() {
+            GamePhase::Finished
+        } else if self.rules.placement_seconds > 0 && !self.placement_done {
+            GamePhase::Placement
+        } else if self.rules.require_ready && !(self.player1_ready && self.player2_ready) {
+            GamePhase::Setup
+        } else {
+            GamePhase::Playing
+        }
+    }
+
+    // Check an arrangement covers `color`'s two home ranks exactly, with the
+    // pieces they hold now
+    fn validate_placement(
+        &self,
+        color: PlayerColor,
+        squares: &[OccupiedSquare],
+    ) -> Result<(), String> {
+        let rows = home_rows(color, self.board.dims().0);
+        let mut seen = BTreeSet::new();
+        let mut placed = HashMap::new();
+        for square in squares {
+            if !rows.contains(&square.row) || square.col >= self.board.dims().1 {
+                return Err(format!(
+                    "({}, {}) is not on your home ranks",
+                    square.row, square.col
+                ));
+            }
+            if !seen.insert((square.row, square.col)) {
+                return Err(format!("Two pieces on ({}, {})", square.row, square.col));
+            }
+            *placed.entry(square.piece).or_insert(0) += 1;
+        }
+
+        let mut expected = HashMap::new();
+        for (position, slot) in self.board.pieces() {
+            if slot.color == color && rows.contains(&position.0) {
+                *expected.entry(slot.piece).or_insert(0) += 1;
+            }
+        }
+        if placed != expected {
+            return Err("The arrangement must use exactly your starting pieces".to_string());
+        }
+        Ok(())
+    }
+
+    // Put the submitted arrangements on the board and start play. Without a
+    // submission a side keeps what it started with.
+    fn finish_placement(&mut self, now: std::time::Instant) {
+        let placements = std::mem::take(&mut self.placements);
+        for (color, squares) in &placements {
+            let (rows, cols) = self.board.dims();
+            for row in home_rows(*color, rows) {
+                for col in 0..cols {
+                    if self
+                        .board
+                        .slot((row, col))
+                        .is_some_and(|slot| slot.color == *color)
+                    {
+                        self.board.set_slot((row, col), None);
+                    }
+                }
+            }
+            for square in squares {
+                self.board.set_slot(
+                    (square.row, square.col),
+                    Some(ExtendedSlot {
+                        piece: square.piece,
+                        color: *color,
+                    }),
+                );
+            }
+        }
+        if !placements.is_empty() {
+            self.start_board = Some(self.board.clone());
+            self.position_counts = HashMap::from([(self.board.position_key(), 1)]);
+            self.legal_moves_cache.clear();
+            self.fogged_board_cache.clear();
+        }
+        self.placement_done = true;
+        self.last_activity = now;
+        self.events.push(GameEvent::PlacementOver);
+    }
+
+    /// The move points both players need before the game's first move, while
+    /// either is still short of them. The threshold never exceeds the cap.
+    pub fn opening_grace_points(&self) -> Option<u64> {
+        let needed = self
+            .rules
+            .opening_grace_moves
+            .min(self.rules.max_stored_moves);
+        let short = self.game.player1_remaining_moves < needed
+            || self.game.player2_remaining_moves < needed;
+        // Turn-based games grant no points to wait for
+        (self.history.is_empty() && self.turn.is_none() && short).then_some(needed)
+    }
+
+    /// Whether both sides are controlled by the same player
+    pub fn is_solo(&self) -> bool {
+        self.player1.id == self.player2.id
+    }
+
+    // Whether the player has been warned for going quiet; None for anyone
+    // not playing in this game
+    fn idle_warned(&self, player_id: Uuid) -> Option<bool> {
+        if self.player1.id == player_id {
+            Some(self.player1_idle_warned)
+        } else if self.player2.id == player_id {
+            Some(self.player2_idle_warned)
+        } else {
+            None
+        }
+    }
+
+    fn mark_player_seen(&mut self, player_id: Uuid, now: std::time::Instant) {
+        if self.player1.id == player_id {
+            self.mark_seen(true, now);
+        }
+        if self.player2.id == player_id {
+            self.mark_seen(false, now);
+        }
+    }
+
+    fn mark_seen(&mut self, is_player1: bool, now: std::time::Instant) {
+        let (last_seen, warned, color) = if is_player1 {
+            (
+                &mut self.player1_last_seen,
+                &mut self.player1_idle_warned,
+                &self.player1.color,
+            )
+        } else {
+            (
+                &mut self.player2_last_seen,
+                &mut self.player2_idle_warned,
+                &self.player2.color,
+            )
+        };
+
+        // A sighting noted under the read lock may be older than one since
+        *last_seen = (*last_seen).max(now);
+        if *warned && self.result.is_none() {
+            *warned = false;
+            self.events
+                .push(GameEvent::PlayerReturned { color: *color });
+        }
+    }
+}
+
+impl PlayerColor {
+    pub fn opponent(&self) -> PlayerColor {
+        match self {
+            PlayerColor::White => PlayerColor::Black,
+            PlayerColor::Black => PlayerColor::White,
+        }
+    }
+}
+
+impl Default for PlayerStats {
+    fn default() -> Self {
+        Self {
+            rating: BASE_RATING,
+            season: SeasonStats::default(),
+            games_played: 0,
+            wins: 0,
+            losses: 0,
+            draws: 0,
+            current_win_streak: 0,
+            best_win_streak: 0,
+            achievements: Vec::new(),
+            color_record: ColorRecord::default(),
+            head_to_head: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for GameStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Play one move with a move point
+    fn play(
+        storage: &mut GameStorage,
+        game_id: Uuid,
+        player_id: Uuid,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> crate::MoveResponse {
+        storage
+            .make_move(
+                game_id,
+                crate::MoveRequest {
+                    player_id,
+                    from,
+                    to,
+                    use_charge: false,
+                },
+            )
+            .expect("the game and player exist")
+    }
+
+    // Match two players through the queue, returning the game and their ids
+    fn queue_pair(storage: &mut GameStorage, first: &str, second: &str) -> (Uuid, Uuid, Uuid) {
+        let first = storage
+            .join_queue(first.to_string(), GameMode::Realtime)
+            .unwrap();
+        let second = storage
+            .join_queue(second.to_string(), GameMode::Realtime)
+            .unwrap();
+        let game_id = second.game_id.expect("the second player is matched");
+        (game_id, first.player_id, second.player_id)
+    }
+
+    #[test]
+    fn only_the_first_capture_earns_the_bonus_point() {
+        let mut storage = GameStorage::new();
+        let rules = GameRules {
+            first_blood_bonus: true,
+            ..GameRules::default()
+        };
+        let game = storage
+            .seed_game(
+                "....k...
+                 ....n...
+                 ........
+                 ...p....
+                 ....P...
+                 ........
+                 ........
+                 ....K...",
+                "white".to_string(),
+                "black".to_string(),
+                Some(rules),
+            )
+            .unwrap();
+
+        let first = play(
+            &mut storage,
+            game.game_id,
+            game.white_player_id,
+            (3, 4),
+            (4, 3),
+        );
+        assert!(first.success, "{}", first.message);
+        assert_eq!(first.remaining_moves, 1);
+
+        let second = play(
+            &mut storage,
+            game.game_id,
+            game.black_player_id,
+            (6, 4),
+            (4, 3),
+        );
+        assert!(second.success, "{}", second.message);
+        assert_eq!(second.remaining_moves, 0);
+        assert_eq!(
+            storage.with_game(game.game_id, |game_state| game_state.first_blood_awarded),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn a_loss_resets_the_win_streak_but_not_the_best() {
+        let mut storage = GameStorage::new();
+        for _ in 0..2 {
+            let (_, _, bob) = queue_pair(&mut storage, "ann", "bob");
+            storage.quit(bob).unwrap();
+        }
+        let stats = storage.get_player_stats("ann").unwrap();
+        assert_eq!((stats.current_win_streak, stats.best_win_streak), (2, 2));
+
+        let (_, ann, _) = queue_pair(&mut storage, "ann", "bob");
+        storage.quit(ann).unwrap();
+        let stats = storage.get_player_stats("ann").unwrap();
+        assert_eq!((stats.current_win_streak, stats.best_win_streak), (0, 2));
+        let stats = storage.get_player_stats("bob").unwrap();
+        assert_eq!((stats.current_win_streak, stats.best_win_streak), (1, 1));
+    }
+
+    #[test]
+    fn achievements_are_awarded_once_and_only_when_earned() {
+        // The first player in the queue plays white
+        let black_pawn_taken = |game_state: &mut GameState| {
+            game_state.board.set_slot((6, 4), None);
+            game_state.captured_pieces.push(ExtendedSlot {
+                piece: ChestPiece::Pawn,
+                color: PlayerColor::Black,
+            });
+        };
+        let mut storage = GameStorage::new();
+        let (game_id, _, bob) = queue_pair(&mut storage, "ann", "bob");
+        storage.with_game_mut(game_id, black_pawn_taken);
+        storage.quit(bob).unwrap();
+
+        let stats = storage.get_player_stats("ann").unwrap();
+        assert_eq!(
+            stats.achievements,
+            [Achievement::FirstWin, Achievement::Flawless]
+        );
+        assert!(
+            storage
+                .get_player_stats("bob")
+                .unwrap()
+                .achievements
+                .is_empty()
+        );
+
+        // Ann wins again having lost a piece: nothing new, and nothing twice
+        let (game_id, bob, _) = queue_pair(&mut storage, "bob", "ann");
+        storage.with_game_mut(game_id, black_pawn_taken);
+        storage.quit(bob).unwrap();
+        let stats = storage.get_player_stats("ann").unwrap();
+        assert_eq!(stats.wins, 2);
+        assert_eq!(
+            stats.achievements,
+            [Achievement::FirstWin, Achievement::Flawless]
+        );
+    }
+
+    // Run enough ticks for every game to grant each player one move point
+    fn grant_move_point(storage: &mut GameStorage) {
+        for _ in 0..=GameRules::default().move_increment_ticks {
+            storage.increment_moves(TickShard::ALL);
+        }
+    }
+
+    #[test]
+    fn an_exported_game_imports_into_fresh_storage() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+        for (player_id, from, to) in [
+            (white, (1, 4), (2, 4)),
+            (black, (6, 3), (5, 3)),
+            (white, (2, 4), (3, 4)),
+            (black, (5, 3), (4, 3)),
+            (white, (3, 4), (4, 3)),
+        ] {
+            if !play(&mut storage, game_id, player_id, from, to).success {
+                grant_move_point(&mut storage);
+                let moved = play(&mut storage, game_id, player_id, from, to);
+                assert!(moved.success, "{:?} to {:?}: {}", from, to, moved.message);
+            }
+        }
+        assert!(
+            storage.export_game(game_id).is_err(),
+            "the game is still on"
+        );
+        storage.quit(black).unwrap();
+
+        let exported = storage.export_game(game_id).unwrap();
+        let json = serde_json::to_string(&exported).unwrap();
+        let mut fresh = GameStorage::new();
+        assert_eq!(
+            fresh.import_game(serde_json::from_str(&json).unwrap()),
+            Ok(game_id)
+        );
+
+        let imported = fresh.export_game(game_id).unwrap();
+        assert_eq!(imported.history, exported.history);
+        assert_eq!(imported.history.len(), 5);
+        assert_eq!(imported.final_board, exported.final_board);
+        assert_eq!(imported.result, exported.result);
+        assert_eq!(
+            fresh.move_history(game_id, 0).unwrap().moves,
+            storage.move_history(game_id, 0).unwrap().moves
+        );
+        assert!(fresh.verify_game(game_id).unwrap().valid);
+    }
+
+    // A queued game on a clock the test moves by hand
+    fn game_on_manual_clock() -> (GameStorage, Arc<ManualClock>, Uuid, Uuid, Uuid) {
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new().with_clock(clock.clone());
+        let (game_id, white, black) = queue_pair(&mut storage, "alice", "bob");
+        (storage, clock, game_id, white, black)
+    }
+
+    fn events(storage: &GameStorage, game_id: Uuid) -> Vec<GameEvent> {
+        storage.get_events(game_id).unwrap()
+    }
+
+    #[test]
+    fn a_silent_player_is_warned_once_then_forfeits() {
+        let (mut storage, clock, game_id, _, black) = game_on_manual_clock();
+
+        clock.advance(Duration::from_secs(29));
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+        assert!(events(&storage, game_id).is_empty());
+
+        clock.advance(Duration::from_secs(1));
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+        storage.check_presence();
+        assert_eq!(
+            events(&storage, game_id),
+            vec![GameEvent::PlayerIdle {
+                color: PlayerColor::White
+            }]
+        );
+
+        clock.advance(Duration::from_secs(60));
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+        let result = storage.with_game(game_id, |game_state| game_state.result);
+        assert_eq!(
+            result,
+            Some(Some(GameResult {
+                winner: Some(PlayerColor::Black),
+                reason: GameEndReason::Abandoned,
+            }))
+        );
+    }
+
+    #[test]
+    fn a_player_who_returns_in_the_warning_window_keeps_playing() {
+        let (mut storage, clock, game_id, white, black) = game_on_manual_clock();
+
+        clock.advance(Duration::from_secs(45));
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+
+        clock.advance(Duration::from_secs(30));
+        storage.record_presence(game_id, white);
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+        assert_eq!(
+            events(&storage, game_id),
+            vec![
+                GameEvent::PlayerIdle {
+                    color: PlayerColor::White
+                },
+                GameEvent::PlayerReturned {
+                    color: PlayerColor::White
+                },
+            ]
+        );
+
+        // Counted from the return, not from the start of the game
+        clock.advance(Duration::from_secs(60));
+        storage.record_presence(game_id, black);
+        storage.check_presence();
+        assert_eq!(
+            storage.with_game(game_id, |game_state| game_state.result),
+            Some(None)
+        );
+    }
+
+    #[test]
+    fn a_game_both_players_left_has_no_winner() {
+        let (mut storage, clock, game_id, _, _) = game_on_manual_clock();
+
+        clock.advance(Duration::from_secs(90));
+        storage.check_presence();
+        assert_eq!(
+            storage.with_game(game_id, |game_state| game_state.result),
+            Some(Some(GameResult {
+                winner: None,
+                reason: GameEndReason::Abandoned,
+            }))
+        );
+    }
+
+    #[test]
+    fn sightings_under_the_read_lock_count_only_for_the_games_players() {
+        let (mut storage, clock, game_id, white, black) = game_on_manual_clock();
+
+        // Strangers and unknown games leave nothing behind
+        assert!(!storage.note_presence(game_id, Uuid::new_v4()));
+        assert!(!storage.note_presence(Uuid::new_v4(), white));
+        assert!(storage.presence.is_empty());
+
+        clock.advance(Duration::from_secs(45));
+        assert!(!storage.note_presence(game_id, black));
+        storage.check_presence();
+        assert!(storage.presence.is_empty());
+        assert_eq!(
+            events(&storage, game_id),
+            vec![GameEvent::PlayerIdle {
+                color: PlayerColor::White
+            }]
+        );
+
+        // A warned player asks for the write lock and is back straight away
+        assert!(storage.note_presence(game_id, white));
+        storage.record_presence(game_id, white);
+        assert_eq!(
+            events(&storage, game_id).last(),
+            Some(&GameEvent::PlayerReturned {
+                color: PlayerColor::White
+            })
+        );
+        storage.check_presence();
+        assert_eq!(events(&storage, game_id).len(), 2);
+    }
+
+    #[test]
+    fn repeated_polls_hit_the_cache_and_match_a_fresh_board() {
+        let (mut storage, _, game_id, white, black) = game_on_manual_clock();
+        let counters = |storage: &GameStorage| {
+            (
+                storage.fogged_board_hits.load(Ordering::Relaxed),
+                storage.fogged_board_misses.load(Ordering::Relaxed),
+            )
+        };
+
+        for _ in 0..3 {
+            storage.get_fogged_board(game_id, white).unwrap();
+        }
+        assert_eq!(counters(&storage), (2, 1));
+        // Each player has a cache entry of their own
+        storage.get_fogged_board(game_id, black).unwrap();
+        assert_eq!(counters(&storage), (2, 2));
+
+        assert!(play(&mut storage, game_id, white, (1, 0), (2, 0)).success);
+        let served = storage.get_fogged_board(game_id, white).unwrap();
+        assert_eq!(counters(&storage), (2, 3));
+        storage.with_game_mut(game_id, |game_state| game_state.fogged_board_cache.clear());
+        let fresh = storage.get_fogged_board(game_id, white).unwrap();
+        assert_eq!(served, fresh);
+        assert!(fresh.slots[2][0].is_some());
+    }
+
+    #[test]
+    fn an_evicted_game_is_served_from_the_archive() {
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        let (archive, _queue) = ArchiveStore::open(&dir).unwrap();
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new()
+            .with_clock(clock.clone())
+            .with_archive(archive);
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+        assert!(play(&mut storage, game_id, white, (1, 4), (2, 4)).success);
+        storage.quit(black).unwrap();
+
+        // Kept through the grace period
+        clock.advance(Duration::from_secs(119));
+        storage.evict_finished_games();
+        assert!(storage.with_game(game_id, |_| ()).is_some());
+
+        clock.advance(Duration::from_secs(1));
+        storage.evict_finished_games();
+        assert!(storage.with_game(game_id, |_| ()).is_none());
+        assert!(storage.move_history(game_id, 0).is_err());
+
+        let archived = storage.archive().unwrap().read(game_id).unwrap().unwrap();
+        assert_eq!(archived.move_history(0).total, 1);
+        assert_eq!(
+            archived
+                .clone()
+                .status(Some(black))
+                .unwrap()
+                .result
+                .unwrap()
+                .winner,
+            Some(PlayerColor::White)
+        );
+        assert!(archived.verify().valid);
+        // Both players are still known, just without a game to resume
+        assert!(storage.get_current_game(white).unwrap().is_none());
+        assert!(storage.get_current_game(black).unwrap().is_none());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn legal_moves_are_cached_until_the_board_changes() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, _) = queue_pair(&mut storage, "ann", "bob");
+        let first = storage.get_legal_moves(game_id, white).unwrap();
+        assert!(!first.moves.is_empty());
+
+        // Tamper with the cached list: a second query at the same version
+        // returns it as is instead of recomputing
+        storage.with_game_mut(game_id, |game_state| {
+            let (_, moves) = game_state
+                .legal_moves_cache
+                .get_mut(&PlayerColor::White)
+                .expect("the first query filled the cache");
+            moves.truncate(1);
+        });
+        let second = storage.get_legal_moves(game_id, white).unwrap();
+        assert_eq!(second.board_version, first.board_version);
+        assert_eq!(second.moves, first.moves[..1]);
+
+        assert!(play(&mut storage, game_id, white, (1, 4), (2, 4)).success);
+        let after_move = storage.get_legal_moves(game_id, white).unwrap();
+        assert!(after_move.board_version > first.board_version);
+        assert!(after_move.moves.len() > 1);
+        assert!(after_move.moves.contains(&LegalMove {
+            from: (2, 4),
+            to: (3, 4)
+        }));
+    }
+
+    // Seed `board` on a manual clock with `rules`, both sides holding `moves`
+    // move points
+    fn seeded_on_manual_clock(
+        board: &str,
+        rules: GameRules,
+        moves: u64,
+    ) -> (GameStorage, Arc<ManualClock>, crate::SeededGame) {
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new().with_clock(clock.clone());
+        let game = storage
+            .seed_game(board, "white".to_string(), "black".to_string(), Some(rules))
+            .unwrap();
+        let game_state = storage.repository.get_mut(game.game_id).unwrap();
+        game_state.game.player1_remaining_moves = moves;
+        game_state.game.player2_remaining_moves = moves;
+        (storage, clock, game)
+    }
+
+    #[test]
+    fn a_move_inside_the_minimum_interval_is_too_soon() {
+        let rules = GameRules {
+            min_move_interval_millis: 200,
+            ..GameRules::default()
+        };
+        let (mut storage, clock, game) = seeded_on_manual_clock(
+            "....k...
+             pppppppp
+             ........
+             ........
+             ........
+             ........
+             PPPPPPPP
+             ....K...",
+            rules,
+            3,
+        );
+        let white = game.white_player_id;
+
+        assert!(play(&mut storage, game.game_id, white, (1, 0), (2, 0)).success);
+        clock.advance(Duration::from_millis(150));
+        let too_soon = play(&mut storage, game.game_id, white, (1, 1), (2, 1));
+        assert!(!too_soon.success);
+        assert_eq!(too_soon.message, "Too soon, wait 50ms before moving again");
+        assert_eq!(too_soon.remaining_moves, 2, "a rejected move costs nothing");
+
+        // The other side keeps its own interval
+        let black = play(
+            &mut storage,
+            game.game_id,
+            game.black_player_id,
+            (6, 0),
+            (5, 0),
+        );
+        assert!(black.success, "{}", black.message);
+
+        clock.advance(Duration::from_millis(50));
+        let on_time = play(&mut storage, game.game_id, white, (1, 1), (2, 1));
+        assert!(on_time.success, "{}", on_time.message);
+    }
+
+    #[test]
+    fn a_scout_beacon_shows_its_square_until_it_expires() {
+        let rules = GameRules {
+            scout_beacon_seconds: 10,
+            ..GameRules::default()
+        };
+        let (mut storage, clock, game) = seeded_on_manual_clock(
+            "k.......
+             ........
+             ......p.
+             ........
+             ........
+             ......S.
+             ........
+             K.......",
+            rules,
+            3,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+
+        // The beacon drops on (4, 6), then the scout walks out of sight of it
+        for (from, to) in [((2, 6), (4, 6)), ((4, 6), (4, 4)), ((4, 4), (4, 2))] {
+            let moved = play(&mut storage, game.game_id, white, from, to);
+            assert!(moved.success, "{}", moved.message);
+            clock.advance(Duration::from_secs(1));
+        }
+        let moved = play(&mut storage, game.game_id, black, (5, 6), (4, 6));
+        assert!(moved.success, "{}", moved.message);
+
+        let seen = |storage: &mut GameStorage| {
+            storage.get_fogged_board(game.game_id, white).unwrap().slots[4][6].is_some()
+        };
+        assert!(
+            seen(&mut storage),
+            "the beacon shows the pawn that stepped onto it"
+        );
+        clock.advance(Duration::from_secs(6));
+        assert!(seen(&mut storage), "one second left on the beacon");
+        clock.advance(Duration::from_secs(1));
+        assert!(!seen(&mut storage), "the beacon has expired");
+    }
+
+    #[test]
+    fn moving_withdraws_your_own_draw_offer() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+        storage
+            .respond_to_draw(game_id, white, DrawAction::Offer)
+            .unwrap();
+
+        // The opponent moving leaves the offer open
+        assert!(play(&mut storage, game_id, black, (6, 0), (5, 0)).success);
+        let offer = |storage: &GameStorage| {
+            storage
+                .with_game(game_id, |game_state| game_state.draw_offer)
+                .unwrap()
+        };
+        assert_eq!(offer(&storage), Some(PlayerColor::White));
+
+        assert!(play(&mut storage, game_id, white, (1, 0), (2, 0)).success);
+        assert_eq!(offer(&storage), None);
+        assert!(
+            events(&storage, game_id).contains(&GameEvent::DrawOfferClosed {
+                color: PlayerColor::White
+            })
+        );
+    }
+
+    #[test]
+    fn a_pending_offer_freezes_both_players_when_the_rules_say_so() {
+        let rules = GameRules {
+            freeze_on_draw_offer: true,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "....k...
+             pppppppp
+             ........
+             ........
+             ........
+             ........
+             PPPPPPPP
+             ....K...",
+            rules,
+            1,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+        storage
+            .respond_to_draw(game.game_id, white, DrawAction::Offer)
+            .unwrap();
+
+        for (player_id, from, to) in [(white, (1, 0), (2, 0)), (black, (6, 0), (5, 0))] {
+            let frozen = play(&mut storage, game.game_id, player_id, from, to);
+            assert!(!frozen.success);
+            assert_eq!(frozen.message, "A draw offer is pending");
+        }
+
+        storage
+            .respond_to_draw(game.game_id, black, DrawAction::Decline)
+            .unwrap();
+        assert!(play(&mut storage, game.game_id, white, (1, 0), (2, 0)).success);
+    }
+
+    #[test]
+    fn a_replayed_history_verifies_until_it_is_doctored() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+        for (player_id, from, to) in [
+            (white, (1, 4), (2, 4)),
+            (black, (6, 3), (5, 3)),
+            (white, (2, 4), (3, 4)),
+            (black, (5, 3), (4, 3)),
+            (white, (3, 4), (4, 3)),
+        ] {
+            grant_move_point(&mut storage);
+            let moved = play(&mut storage, game_id, player_id, from, to);
+            assert!(moved.success, "{}", moved.message);
+        }
+        let verified = storage.verify_game(game_id).unwrap();
+        assert!(verified.valid, "{:?}", verified.error);
+        assert_eq!(verified.moves_checked, 5);
+
+        // A bishop can't have made the capture the pawn did
+        let game_state = storage.repository.get_mut(game_id).unwrap();
+        game_state.history[4].from = (0, 5);
+        let doctored = storage.verify_game(game_id).unwrap();
+        assert!(!doctored.valid);
+        assert!(doctored.error.is_some());
+
+        // A legal history no longer matches a board that was tampered with
+        let game_state = storage.repository.get_mut(game_id).unwrap();
+        game_state.history[4].from = (3, 4);
+        game_state.board.set_slot((0, 0), None);
+        let tampered = storage.verify_game(game_id).unwrap();
+        assert!(!tampered.valid);
+    }
+
+    fn result(storage: &GameStorage, game_id: Uuid) -> Option<GameResult> {
+        storage
+            .with_game(game_id, |game_state| game_state.result)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_back_rank_mate_ends_the_game_under_the_checkmate_rule() {
+        let rules = GameRules {
+            king_rule: KingRule::Checkmate,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "......k.
+             .....ppp
+             ........
+             ........
+             ........
+             ........
+             ........
+             R...K...",
+            rules,
+            1,
+        );
+
+        let mate = play(
+            &mut storage,
+            game.game_id,
+            game.white_player_id,
+            (0, 0),
+            (7, 0),
+        );
+        assert_eq!(mate.message, "Checkmate, you win!");
+        assert_eq!(
+            result(&storage, game.game_id),
+            Some(GameResult {
+                winner: Some(PlayerColor::White),
+                reason: GameEndReason::Checkmate,
+            })
+        );
+    }
+
+    #[test]
+    fn only_the_capture_king_rule_lets_the_king_be_taken() {
+        let board = "k.......
+                     ........
+                     ........
+                     ........
+                     ........
+                     ........
+                     ........
+                     R...K...";
+        for king_rule in [KingRule::CaptureKing, KingRule::Checkmate] {
+            let rules = GameRules {
+                king_rule,
+                ..GameRules::default()
+            };
+            let (mut storage, _, game) = seeded_on_manual_clock(board, rules, 1);
+            let taken = play(
+                &mut storage,
+                game.game_id,
+                game.white_player_id,
+                (0, 0),
+                (7, 0),
+            );
+
+            if king_rule == KingRule::CaptureKing {
+                assert!(taken.success, "{}", taken.message);
+                assert_eq!(
+                    result(&storage, game.game_id).map(|result| result.reason),
+                    Some(GameEndReason::KingCaptured)
+                );
+            } else {
+                assert!(!taken.success);
+                assert_eq!(taken.message, "The king can't be captured, only checkmated");
+                assert_eq!(result(&storage, game.game_id), None);
+            }
+        }
+    }
+
+    #[test]
+    fn the_game_leaves_setup_once_both_players_are_ready() {
+        let rules = GameRules {
+            require_ready: true,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "....k...
+             pppppppp
+             ........
+             ........
+             ........
+             ........
+             PPPPPPPP
+             ....K...",
+            rules,
+            1,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+        let phase =
+            |storage: &GameStorage| storage.get_game_status(game.game_id, None).unwrap().phase;
+        assert_eq!(phase(&storage), GamePhase::Setup);
+
+        let early = play(&mut storage, game.game_id, white, (1, 0), (2, 0));
+        assert_eq!(early.message, "Waiting for both players to be ready");
+        assert_eq!(
+            storage.mark_ready(game.game_id, white).unwrap().phase,
+            GamePhase::Setup
+        );
+        assert_eq!(
+            storage.mark_ready(game.game_id, white).unwrap().phase,
+            GamePhase::Setup
+        );
+        assert_eq!(phase(&storage), GamePhase::Setup);
+
+        assert_eq!(
+            storage.mark_ready(game.game_id, black).unwrap().phase,
+            GamePhase::Playing
+        );
+        assert_eq!(phase(&storage), GamePhase::Playing);
+        assert!(play(&mut storage, game.game_id, white, (1, 0), (2, 0)).success);
+    }
+
+    #[test]
+    fn the_active_count_follows_games_starting_and_ending() {
+        let mut storage = GameStorage::new();
+        let active = storage.active_game_count();
+        let count = || active.load(Ordering::Relaxed);
+
+        let (quit_game, _, quitter) = queue_pair(&mut storage, "ann", "bob");
+        let (game_id, white, _) = queue_pair(&mut storage, "cat", "dan");
+        assert_eq!(count(), 2);
+
+        storage.quit(quitter).unwrap();
+        assert_eq!(count(), 1);
+        // Sweeping or reloading games doesn't count the finished one twice
+        storage.evict_finished_games();
+        storage.refresh_game(quit_game);
+        storage.refresh_game(game_id);
+        assert_eq!(count(), 1);
+
+        let seeded = storage
+            .seed_game(
+                "....k...
+                 ........
+                 ........
+                 ........
+                 ........
+                 ........
+                 ....Q...
+                 K.......",
+                "eve".to_string(),
+                "fay".to_string(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(count(), 2);
+        let taken = play(
+            &mut storage,
+            seeded.game_id,
+            seeded.white_player_id,
+            (1, 4),
+            (7, 4),
+        );
+        assert!(taken.success, "{}", taken.message);
+        assert_eq!(count(), 1);
+
+        // Pausing freezes the clock on move points without ending anything,
+        // and the game ticks again once resumed
+        let remaining = |storage: &GameStorage| {
+            storage
+                .with_game(game_id, |game_state| {
+                    game_state.game.player1_remaining_moves
+                })
+                .unwrap()
+        };
+        let before = remaining(&storage);
+        storage.set_paused(true);
+        grant_move_point(&mut storage);
+        assert_eq!(remaining(&storage), before);
+        assert_eq!(count(), 1);
+
+        storage.set_paused(false);
+        grant_move_point(&mut storage);
+        assert_eq!(remaining(&storage), before + 1);
+        assert!(play(&mut storage, game_id, white, (1, 4), (2, 4)).success);
+        assert_eq!(count(), 1);
+    }
+
+    #[test]
+    fn placement_re_lays_the_board_and_its_hash_when_time_runs_out() {
+        let mut config = Config::default();
+        config.rules.placement_seconds = 30;
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::with_config(&config).with_clock(clock.clone());
+        let (game_id, white, _) = queue_pair(&mut storage, "alice", "bob");
+        let phase = |storage: &GameStorage| storage.with_game(game_id, GameState::phase).unwrap();
+        assert_eq!(phase(&storage), GamePhase::Placement);
+        let before = storage
+            .with_game(game_id, |game_state| game_state.board.position_key())
+            .unwrap();
+
+        // White swaps the knight and bishop on the queen's side
+        let pieces = storage
+            .with_game(game_id, |game_state| {
+                game_state
+                    .board
+                    .pieces()
+                    .filter(|(square, slot)| slot.color == PlayerColor::White && square.0 < 2)
+                    .map(|((row, col), slot)| crate::PlacedPiece {
+                        row,
+                        col: match col {
+                            1 if row == 0 => 2,
+                            2 if row == 0 => 1,
+                            col => col,
+                        },
+                        piece: slot.piece,
+                    })
+                    .collect()
+            })
+            .unwrap();
+        storage.submit_placement(game_id, white, pieces).unwrap();
+
+        clock.advance(Duration::from_millis(29_900));
+        storage.increment_moves(TickShard::ALL);
+        assert_eq!(phase(&storage), GamePhase::Placement);
+
+        clock.advance(Duration::from_millis(100));
+        storage.increment_moves(TickShard::ALL);
+        assert_eq!(phase(&storage), GamePhase::Playing);
+        storage.with_game(game_id, |game_state| {
+            let board = &game_state.board;
+            assert_eq!(board.slot((0, 1)).unwrap().piece, ChestPiece::Bishop);
+            assert_eq!(board.slot((0, 2)).unwrap().piece, ChestPiece::Knight);
+            // Black sent nothing and keeps the usual setup
+            assert_eq!(board.slot((7, 6)).unwrap().piece, ChestPiece::Knight);
+            assert_ne!(board.position_key(), before);
+            assert_eq!(board.position_key(), board.zobrist_hash());
+        });
+    }
+
+    #[test]
+    fn placements_are_checked_and_moves_wait_for_both_players() {
+        let mut config = Config::default();
+        config.rules.placement_seconds = 30;
+        let mut storage = GameStorage::with_config(&config);
+        let (game_id, white, black) = queue_pair(&mut storage, "alice", "bob");
+        let home = |storage: &GameStorage, color: PlayerColor| -> Vec<crate::PlacedPiece> {
+            storage
+                .with_game(game_id, |game_state| {
+                    game_state
+                        .board
+                        .pieces()
+                        .filter(|(_, slot)| slot.color == color)
+                        .filter(|(square, _)| home_rows(color, 8).contains(&square.0))
+                        .map(|((row, col), slot)| crate::PlacedPiece {
+                            row,
+                            col,
+                            piece: slot.piece,
+                        })
+                        .collect()
+                })
+                .unwrap()
+        };
+
+        let moved = play(&mut storage, game_id, white, (1, 4), (2, 4));
+        assert!(!moved.success);
+        assert!(
+            moved.message.contains("place their pieces"),
+            "{}",
+            moved.message
+        );
+
+        // Off the home ranks, two on one square, and a piece swapped for another
+        let mut off_home = home(&storage, PlayerColor::White);
+        off_home[0].row = 2;
+        let mut doubled = home(&storage, PlayerColor::White);
+        (doubled[1].row, doubled[1].col) = (doubled[0].row, doubled[0].col);
+        let mut promoted = home(&storage, PlayerColor::White);
+        let pawn = promoted
+            .iter_mut()
+            .find(|placed| placed.piece == ChestPiece::Pawn)
+            .unwrap();
+        pawn.piece = ChestPiece::Queen;
+        for broken in [off_home, doubled, promoted, Vec::new()] {
+            assert!(storage.submit_placement(game_id, white, broken).is_err());
+        }
+        // Nor can either side lay out the other's pieces
+        assert!(
+            storage
+                .submit_placement(game_id, black, home(&storage, PlayerColor::White))
+                .is_err()
+        );
+        assert!(
+            storage
+                .submit_placement(game_id, Uuid::new_v4(), home(&storage, PlayerColor::White))
+                .is_err()
+        );
+
+        let placed = storage
+            .submit_placement(game_id, white, home(&storage, PlayerColor::White))
+            .unwrap();
+        assert_eq!(placed.phase, GamePhase::Placement);
+        assert!(!play(&mut storage, game_id, white, (1, 4), (2, 4)).success);
+
+        // The second arrangement starts play without waiting for the timer
+        let placed = storage
+            .submit_placement(game_id, black, home(&storage, PlayerColor::Black))
+            .unwrap();
+        assert_eq!(placed.phase, GamePhase::Playing);
+        grant_move_point(&mut storage);
+        let moved = play(&mut storage, game_id, white, (1, 4), (2, 4));
+        assert!(moved.success, "{}", moved.message);
+        assert!(
+            storage
+                .submit_placement(game_id, white, home(&storage, PlayerColor::White))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn placement_is_skipped_unless_the_rules_ask_for_it() {
+        assert_eq!(Config::default().rules.placement_seconds, 0);
+        let mut storage = GameStorage::new();
+        let (game_id, white, _) = queue_pair(&mut storage, "alice", "bob");
+        assert_eq!(
+            storage.with_game(game_id, GameState::phase).unwrap(),
+            GamePhase::Playing
+        );
+        assert!(
+            storage
+                .submit_placement(game_id, white, Vec::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn a_two_scout_army_starts_with_both_scouts_in_place() {
+        use ChestPiece::*;
+        let two_scouts = ArmyComposition::from_back_ranks(
+            [Rook, Scout, Bishop, Queen, King, Bishop, Scout, Rook],
+            [Rook, Scout, Bishop, Queen, King, Bishop, Scout, Rook],
+        );
+        let mut config = Config::default();
+        config.rules.army = Some(two_scouts);
+        assert!(config.validate().is_ok());
+        let mut storage = GameStorage::with_config(&config);
+        let (game_id, _, _) = queue_pair(&mut storage, "alice", "bob");
+
+        storage.with_game(game_id, |game_state| {
+            let scouts: Vec<_> = game_state
+                .board
+                .pieces()
+                .filter(|(_, slot)| slot.piece == Scout)
+                .map(|(square, slot)| (square, slot.color))
+                .collect();
+            assert_eq!(
+                scouts,
+                [
+                    ((0, 1), PlayerColor::White),
+                    ((0, 6), PlayerColor::White),
+                    ((7, 1), PlayerColor::Black),
+                    ((7, 6), PlayerColor::Black),
+                ]
+            );
+            assert!(
+                game_state
+                    .board
+                    .pieces()
+                    .all(|(_, slot)| slot.piece != Knight)
+            );
+            assert_eq!(game_state.board.pieces().count(), 32);
+        });
+
+        // Armies without exactly one king, or with pieces off the board or
+        // sharing a square, are refused
+        let mut kingless = ArmyComposition::default();
+        kingless.white.retain(|group| group.piece != King);
+        let mut off_board = ArmyComposition::default();
+        off_board.black[0].squares.push((8, 0));
+        let mut crowded = ArmyComposition::default();
+        crowded.white[0].squares.push((1, 0));
+        for broken in [kingless, off_board, crowded] {
+            assert!(broken.validate().is_err());
+            config.rules.army = Some(broken);
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn the_board_reports_roughly_when_the_opponent_last_moved() {
+        let (mut storage, clock, game) = seeded_on_manual_clock(
+            "....k...
+             pppppppp
+             ........
+             ........
+             ........
+             ........
+             PPPPPPPP
+             ....K...",
+            GameRules::default(),
+            3,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+        let last_active = |storage: &mut GameStorage, player_id| {
+            storage
+                .get_fogged_board(game.game_id, player_id)
+                .unwrap()
+                .opponent_last_active
+        };
+        assert_eq!(last_active(&mut storage, white), None);
+
+        clock.advance(Duration::from_secs(20));
+        assert!(play(&mut storage, game.game_id, black, (6, 0), (5, 0)).success);
+        assert_eq!(last_active(&mut storage, white), Some(0));
+        // Black learns nothing until white moves
+        assert_eq!(last_active(&mut storage, black), None);
+
+        // Only the bucket is given, not the exact seconds
+        clock.advance(Duration::from_secs(7));
+        assert_eq!(last_active(&mut storage, white), Some(5));
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(last_active(&mut storage, white), Some(30));
+    }
+
+    fn hill_game() -> (GameStorage, Arc<ManualClock>, crate::SeededGame) {
+        let rules = GameRules {
+            hill_squares: vec![(3, 3), (3, 4), (4, 3), (4, 4)],
+            ..GameRules::default()
+        };
+        seeded_on_manual_clock(
+            "....k...
+             r.....s.
+             ........
+             ........
+             ........
+             ...K....
+             ........
+             ........",
+            rules,
+            3,
+        )
+    }
+
+    fn hill_announced(storage: &GameStorage, game_id: Uuid) -> bool {
+        events(storage, game_id).iter().any(|event| {
+            matches!(
+                event,
+                GameEvent::KingOnHill {
+                    color: PlayerColor::White
+                }
+            )
+        })
+    }
+
+    #[test]
+    fn a_king_held_on_the_hill_for_three_seconds_wins() {
+        let (mut storage, clock, game) = hill_game();
+        let white = game.white_player_id;
+        assert!(play(&mut storage, game.game_id, white, (2, 3), (3, 3)).success);
+        storage.check_hills(TickShard::ALL);
+        // Black can't see the hill from its back ranks, so nothing is announced
+        assert!(!hill_announced(&storage, game.game_id));
+
+        clock.advance(Duration::from_millis(2_900));
+        storage.check_hills(TickShard::ALL);
+        assert_eq!(result(&storage, game.game_id), None);
+
+        clock.advance(Duration::from_millis(100));
+        storage.check_hills(TickShard::ALL);
+        assert_eq!(
+            result(&storage, game.game_id),
+            Some(GameResult {
+                winner: Some(PlayerColor::White),
+                reason: GameEndReason::HillControlled,
+            })
+        );
+    }
+
+    #[test]
+    fn a_king_chased_off_the_hill_at_two_point_nine_seconds_has_not_won() {
+        let (mut storage, clock, game) = hill_game();
+        let white = game.white_player_id;
+        assert!(play(&mut storage, game.game_id, white, (2, 3), (3, 3)).success);
+        storage.check_hills(TickShard::ALL);
+
+        clock.advance(Duration::from_secs(1));
+        let black = game.black_player_id;
+        assert!(play(&mut storage, game.game_id, black, (6, 0), (6, 3)).success);
+        assert!(play(&mut storage, game.game_id, black, (6, 6), (5, 5)).success);
+        storage.check_hills(TickShard::ALL);
+        assert!(
+            hill_announced(&storage, game.game_id),
+            "the scout spots the king"
+        );
+        clock.advance(Duration::from_millis(1_900));
+        storage.check_hills(TickShard::ALL);
+        assert!(play(&mut storage, game.game_id, white, (3, 3), (2, 4)).success);
+
+        clock.advance(Duration::from_millis(100));
+        storage.check_hills(TickShard::ALL);
+        assert_eq!(result(&storage, game.game_id), None);
+
+        // Stepping back on starts the hold again
+        assert!(play(&mut storage, game.game_id, white, (2, 4), (3, 4)).success);
+        clock.advance(Duration::from_millis(2_900));
+        storage.check_hills(TickShard::ALL);
+        assert_eq!(result(&storage, game.game_id), None);
+        clock.advance(Duration::from_millis(100));
+        storage.check_hills(TickShard::ALL);
+        assert_eq!(
+            result(&storage, game.game_id).map(|result| result.reason),
+            Some(GameEndReason::HillControlled)
+        );
+    }
+
+    #[test]
+    fn the_third_check_wins_and_a_double_check_counts_once() {
+        let rules = GameRules {
+            three_check: true,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "....k...
+             ........
+             ........
+             ........
+             ....B...
+             ........
+             ........
+             K...R...",
+            rules,
+            3,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+        let checks = |storage: &GameStorage| {
+            let status = storage.get_game_status(game.game_id, Some(white)).unwrap();
+            (status.player1_checks, status.player2_checks)
+        };
+        let last_check = |storage: &GameStorage| {
+            events(storage, game.game_id)
+                .into_iter()
+                .filter_map(|event| match event {
+                    GameEvent::CheckGiven {
+                        color,
+                        checks,
+                        mut from,
+                    } => {
+                        from.sort_unstable();
+                        Some((color, checks, from))
+                    }
+                    _ => None,
+                })
+                .next_back()
+        };
+        assert_eq!(checks(&storage), (Some(0), Some(0)));
+
+        // The bishop steps aside for a check from both it and the rook
+        assert!(play(&mut storage, game.game_id, white, (3, 4), (5, 2)).success);
+        assert_eq!(checks(&storage), (Some(1), Some(0)));
+        assert_eq!(
+            last_check(&storage),
+            Some((PlayerColor::White, 1, vec![(0, 4), (5, 2)]))
+        );
+        // The event names the checkers though black's board still hides them
+        let seen = storage.get_fogged_board(game.game_id, black).unwrap();
+        assert!(seen.slots[5][2].is_none() && seen.slots[0][4].is_none());
+
+        assert!(play(&mut storage, game.game_id, black, (7, 4), (7, 5)).success);
+        assert!(play(&mut storage, game.game_id, white, (0, 4), (0, 5)).success);
+        assert_eq!(checks(&storage), (Some(2), Some(0)));
+        assert_eq!(result(&storage, game.game_id), None);
+
+        assert!(play(&mut storage, game.game_id, black, (7, 5), (6, 6)).success);
+        let third = play(&mut storage, game.game_id, white, (0, 5), (0, 6));
+        assert_eq!(third.message, "Third check, you win!");
+        assert_eq!(
+            result(&storage, game.game_id),
+            Some(GameResult {
+                winner: Some(PlayerColor::White),
+                reason: GameEndReason::ThreeChecks,
+            })
+        );
+    }
+
+    #[test]
+    fn a_blast_in_the_corner_takes_scouts_but_spares_pawns() {
+        let rules = GameRules {
+            atomic: true,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "rn..k...
+             sp......
+             .N......
+             ........
+             ........
+             ........
+             ........
+             ....K...",
+            rules,
+            3,
+        );
+        let taken = play(
+            &mut storage,
+            game.game_id,
+            game.white_player_id,
+            (5, 1),
+            (7, 0),
+        );
+        assert!(taken.success, "{}", taken.message);
+
+        let lost = |piece, color, (row, col)| OccupiedSquare {
+            row,
+            col,
+            piece,
+            color,
+        };
+        let mut destroyed = events(&storage, game.game_id)
+            .into_iter()
+            .find_map(|event| match event {
+                GameEvent::Explosion { square, destroyed } => Some((square, destroyed)),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(destroyed.0, (7, 0));
+        // The captured rook comes first, then everything the blast took
+        assert_eq!(
+            destroyed.1.remove(0),
+            lost(ChestPiece::Rook, PlayerColor::Black, (7, 0))
+        );
+        destroyed.1.sort_by_key(|victim| (victim.row, victim.col));
+        assert_eq!(
+            destroyed.1,
+            [
+                lost(ChestPiece::Scout, PlayerColor::Black, (6, 0)),
+                lost(ChestPiece::Knight, PlayerColor::White, (7, 0)),
+                lost(ChestPiece::Knight, PlayerColor::Black, (7, 1)),
+            ]
+        );
+        storage.with_game(game.game_id, |game_state| {
+            let board = &game_state.board;
+            assert_eq!(board.slot((6, 1)).unwrap().piece, ChestPiece::Pawn);
+            assert_eq!(board.pieces().count(), 3);
+            assert_eq!(game_state.history[0].exploded.len(), 3);
+            assert_eq!(board.position_key(), board.zobrist_hash());
+        });
+        assert_eq!(result(&storage, game.game_id), None);
+    }
+
+    #[test]
+    fn a_blast_may_take_the_enemy_king_but_never_your_own() {
+        let rules = GameRules {
+            atomic: true,
+            fog_enabled: false,
+            ..GameRules::default()
+        };
+        let (mut storage, clock, game) = seeded_on_manual_clock(
+            ".......B
+             ........
+             .....r..
+             ....k...
+             R..n....
+             ..K.....
+             ........
+             ........",
+            rules,
+            3,
+        );
+        let white = game.white_player_id;
+        let legal: Vec<LegalMove> = storage
+            .repository
+            .get_mut(game.game_id)
+            .unwrap()
+            .legal_moves_for(PlayerColor::White, clock.now());
+        let offered = |from, to| legal.contains(&LegalMove { from, to });
+
+        // Taking the knight would blow up both kings, and a king can't capture
+        for from in [(3, 0), (2, 2)] {
+            assert!(!offered(from, (3, 3)));
+            let refused = play(&mut storage, game.game_id, white, from, (3, 3));
+            assert!(!refused.success);
+            assert_eq!(refused.message, "The blast would take out your own king");
+        }
+        assert_eq!(result(&storage, game.game_id), None);
+
+        // Taking the rook next to the black king blows it up
+        assert!(offered((7, 7), (5, 5)));
+        let taken = play(&mut storage, game.game_id, white, (7, 7), (5, 5));
+        assert_eq!(taken.message, "The blast took out the king, you win!");
+        assert_eq!(
+            result(&storage, game.game_id),
+            Some(GameResult {
+                winner: Some(PlayerColor::White),
+                reason: GameEndReason::Explosion,
+            })
+        );
+        storage.with_game(game.game_id, |game_state| {
+            assert_eq!(game_state.board.find_king(&PlayerColor::Black), None);
+            assert_eq!(
+                game_state.board.slot((3, 3)).unwrap().piece,
+                ChestPiece::Knight
+            );
+        });
+    }
+
+    #[test]
+    fn atomic_games_are_never_checkmate_games() {
+        let rules = GameRules {
+            atomic: true,
+            king_rule: KingRule::Checkmate,
+            ..GameRules::default()
+        };
+        let mut storage = GameStorage::new();
+        let seeded = storage.seed_game(
+            "....k...
+             ........
+             ........
+             ........
+             ........
+             ........
+             ........
+             ....K...",
+            "white".to_string(),
+            "black".to_string(),
+            Some(rules),
+        );
+        assert_eq!(
+            seeded.err().as_deref(),
+            Some("Atomic games can't be won by checkmate")
+        );
+        assert_eq!(storage.active_game_count().load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn sharded_ticks_regen_points_on_time_and_touch_one_shard_each() {
+        const SHARDS: u64 = 4;
+        const GAMES: usize = 40;
+        let config = Config {
+            tick_shards: SHARDS,
+            ..Config::default()
+        };
+        let mut storage = GameStorage::with_config(&config);
+        let game_ids: Vec<Uuid> = (0..GAMES)
+            .map(|_| queue_pair(&mut storage, "ann", "bob").0)
+            .collect();
+        let (finished, _, quitter) = queue_pair(&mut storage, "cat", "dan");
+        storage.quit(quitter).unwrap();
+        let points = |storage: &GameStorage, game_id| {
+            storage
+                .with_game(game_id, |game_state| {
+                    game_state.game.player1_remaining_moves
+                })
+                .unwrap()
+        };
+
+        let per_point = GameRules::default().move_increment_ticks + 1;
+        let mut ticks = 0;
+        for expected in 2..=5 {
+            for _ in 0..per_point {
+                let mut handled = Vec::new();
+                for index in 0..SHARDS {
+                    let shard = TickShard {
+                        index,
+                        count: SHARDS,
+                    };
+                    let in_shard = storage.active_game_ids_in(shard);
+                    assert!(in_shard.iter().all(|&game_id| shard.contains(game_id)));
+                    assert!(!in_shard.contains(&finished));
+                    handled.push(storage.tick_shard(shard));
+                }
+                ticks += 1;
+                // Every game once per full tick, and no pass over all of them
+                assert_eq!(handled.iter().sum::<usize>(), GAMES);
+                assert!(handled.iter().all(|&games| games < GAMES), "{:?}", handled);
+            }
+            for &game_id in &game_ids {
+                assert_eq!(points(&storage, game_id), expected, "after {} ticks", ticks);
+            }
+        }
+
+        // The same ticks run unsharded give the same points
+        let mut unsharded = GameStorage::new();
+        let game_id = queue_pair(&mut unsharded, "eve", "fay").0;
+        for _ in 0..ticks {
+            unsharded.increment_moves(TickShard::ALL);
+        }
+        assert_eq!(points(&unsharded, game_id), 5);
+    }
+
+    #[test]
+    fn moves_without_a_capture_draw_and_a_capture_starts_the_count_again() {
+        let rules = GameRules {
+            no_capture_draw_moves: 4,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "n...k...
+             ........
+             ........
+             ........
+             ........
+             ..p.....
+             ........
+             .N..K...",
+            rules,
+            5,
+        );
+        let (white, black) = (game.white_player_id, game.black_player_id);
+        let moves = [
+            (white, (0, 1), (1, 3)),
+            (black, (7, 0), (5, 1)),
+            (white, (1, 3), (0, 1)),
+            // Three quiet moves, then the capture
+            (white, (0, 1), (2, 2)),
+            (black, (5, 1), (7, 0)),
+            (white, (2, 2), (0, 1)),
+            (black, (7, 0), (5, 1)),
+        ];
+        for (player_id, from, to) in moves {
+            let moved = play(&mut storage, game.game_id, player_id, from, to);
+            assert!(moved.success, "{:?} to {:?}: {}", from, to, moved.message);
+            assert_eq!(result(&storage, game.game_id), None);
+        }
+        assert_eq!(
+            storage.with_game(game.game_id, |game_state| game_state.moves_since_capture),
+            Some(3)
+        );
+
+        assert!(play(&mut storage, game.game_id, white, (0, 1), (1, 3)).success);
+        assert_eq!(
+            result(&storage, game.game_id),
+            Some(GameResult {
+                winner: None,
+                reason: GameEndReason::NoCaptureLimit,
+            })
+        );
+    }
+
+    #[test]
+    fn no_move_is_taken_until_both_players_have_the_grace_points() {
+        let mut config = Config::default();
+        config.rules.opening_grace_moves = 3;
+        let mut storage = GameStorage::with_config(&config);
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+        let refused = |moved: crate::MoveResponse| {
+            assert!(!moved.success);
+            assert_eq!(
+                moved.message,
+                "Both players need 3 move points before the first move"
+            );
+        };
+
+        refused(play(&mut storage, game_id, white, (1, 4), (2, 4)));
+        grant_move_point(&mut storage);
+        refused(play(&mut storage, game_id, black, (6, 4), (5, 4)));
+
+        // White reaching 3 isn't enough while black is short
+        storage.with_game_mut(game_id, |game_state| {
+            game_state.game.player1_remaining_moves = 3;
+        });
+        refused(play(&mut storage, game_id, white, (1, 4), (2, 4)));
+
+        grant_move_point(&mut storage);
+        let moved = play(&mut storage, game_id, black, (6, 4), (5, 4));
+        assert!(moved.success, "{}", moved.message);
+        assert_eq!(moved.remaining_moves, 2);
+        // The grace only holds back the first move
+        assert!(play(&mut storage, game_id, white, (1, 4), (2, 4)).success);
+    }
+
+    #[test]
+    fn turn_based_games_alternate_and_ignore_the_tick() {
+        let mut storage = GameStorage::new();
+        let first = storage
+            .join_queue("ann".to_string(), GameMode::TurnBased)
+            .unwrap();
+        // A realtime player in the queue isn't matched with a turn-based one
+        let realtime = storage
+            .join_queue("cat".to_string(), GameMode::Realtime)
+            .unwrap();
+        assert!(realtime.game_id.is_none());
+        let second = storage
+            .join_queue("bob".to_string(), GameMode::TurnBased)
+            .unwrap();
+        let game_id = second.game_id.unwrap();
+        let (white, black) = (first.player_id, second.player_id);
+        let turn =
+            |storage: &GameStorage| storage.get_game_status(game_id, None).unwrap().current_turn;
+        let points = |storage: &GameStorage| {
+            storage
+                .with_game(game_id, |game_state| {
+                    (
+                        game_state.game.player1_remaining_moves,
+                        game_state.game.player2_remaining_moves,
+                    )
+                })
+                .unwrap()
+        };
+
+        assert_eq!(turn(&storage), Some(white));
+        let out_of_turn = play(&mut storage, game_id, black, (6, 4), (5, 4));
+        assert!(!out_of_turn.success);
+        assert_eq!(out_of_turn.message, "Not your turn");
+
+        let before = points(&storage);
+        for _ in 0..3 {
+            grant_move_point(&mut storage);
+        }
+        assert_eq!(points(&storage), before);
+
+        for (player_id, other, from, to) in [
+            (white, black, (1, 4), (2, 4)),
+            (black, white, (6, 4), (5, 4)),
+            (white, black, (2, 4), (3, 4)),
+        ] {
+            let moved = play(&mut storage, game_id, player_id, from, to);
+            assert!(moved.success, "{}", moved.message);
+            assert_eq!(turn(&storage), Some(other));
+            let again = play(&mut storage, game_id, player_id, to, from);
+            assert_eq!(again.message, "Not your turn");
+        }
+        assert_eq!(points(&storage), before);
+    }
+
+    #[test]
+    fn a_seeded_random_back_rank_is_recorded_and_reported() {
+        let mut config = Config::default();
+        config.rules.random_back_rank = true;
+        config.rules.back_rank_seed = Some(960);
+        let mut storage = GameStorage::with_config(&config);
+        let (game_id, _, _) = queue_pair(&mut storage, "ann", "bob");
+        let expected = BackRank::random(960);
+
+        let status = storage.get_game_status(game_id, None).unwrap();
+        assert_eq!(status.back_rank, Some(expected));
+        storage.with_game(game_id, |game_state| {
+            assert_eq!(game_state.board.slot((0, 0)).unwrap().piece, expected.0[0]);
+            assert_eq!(game_state.start_board.as_ref(), Some(&game_state.board));
+        });
+    }
+
+    #[test]
+    fn a_bought_reveal_shows_the_board_once_and_must_be_bought_again() {
+        let rules = GameRules {
+            reveal_powerup_cost: 2,
+            reveal_powerup_seconds: 3,
+            ..GameRules::default()
+        };
+        let (mut storage, clock, game) = seeded_on_manual_clock(
+            "...qk...
+             ........
+             ........
+             ........
+             ........
+             ........
+             ........
+             ....K...",
+            rules,
+            3,
+        );
+        let white = game.white_player_id;
+        let sees_queen = |storage: &mut GameStorage| {
+            storage.get_fogged_board(game.game_id, white).unwrap().slots[7][3].is_some()
+        };
+        assert!(!sees_queen(&mut storage));
+
+        let bought = storage.buy_reveal(game.game_id, white).unwrap();
+        assert_eq!((bought.remaining_moves, bought.reveal_seconds), (1, 3));
+        assert!(sees_queen(&mut storage));
+        assert_eq!(
+            storage.buy_reveal(game.game_id, white).err().as_deref(),
+            Some("A reveal is already running")
+        );
+
+        clock.advance(Duration::from_millis(2_999));
+        assert!(sees_queen(&mut storage));
+        clock.advance(Duration::from_millis(1));
+        assert!(!sees_queen(&mut storage));
+
+        // One point left is not enough for another
+        assert_eq!(
+            storage.buy_reveal(game.game_id, white).err().as_deref(),
+            Some("The reveal costs 2 move points")
+        );
+        grant_move_point(&mut storage);
+        assert_eq!(
+            storage
+                .buy_reveal(game.game_id, white)
+                .unwrap()
+                .remaining_moves,
+            0
+        );
+        assert!(sees_queen(&mut storage));
+    }
+
+    #[test]
+    fn a_king_and_pawn_endgame_lobby_is_playable_from_move_one() {
+        let position = "....k...
+                        ........
+                        ........
+                        ........
+                        ........
+                        ....P...
+                        ........
+                        ....K...";
+        let endgame = || Some(crate::StartPosition::Board(position.to_string()));
+        let mut storage = GameStorage::new();
+        assert!(
+            storage
+                .create_lobby("ann".to_string(), endgame(), None, true, GameMode::Realtime)
+                .is_err(),
+            "ranked lobbies keep the standard position"
+        );
+
+        let lobby = storage
+            .create_lobby(
+                "ann".to_string(),
+                endgame(),
+                None,
+                false,
+                GameMode::Realtime,
+            )
+            .unwrap();
+        let joined = storage.join_lobby(&lobby.code, "bob".to_string()).unwrap();
+        let game_id = joined.game_id.unwrap();
+
+        let status = storage
+            .get_game_status(game_id, Some(lobby.player_id))
+            .unwrap();
+        let expected = ExtendedBoard::from_board_string(position).unwrap();
+        assert_eq!(status.start_position, Some(expected.to_board_string()));
+        storage.with_game(game_id, |game_state| {
+            assert_eq!(game_state.board, expected);
+            assert_eq!(game_state.board.pieces().count(), 3);
+        });
+
+        let moved = play(&mut storage, game_id, lobby.player_id, (2, 4), (3, 4));
+        assert!(moved.success, "{}", moved.message);
+        let moved = play(&mut storage, game_id, joined.player_id, (7, 4), (6, 4));
+        assert!(moved.success, "{}", moved.message);
+    }
+
+    #[test]
+    fn a_lobby_lasts_as_long_as_the_configured_ttl() {
+        let config = Config {
+            lobby_ttl_seconds: 60,
+            ..Config::default()
+        };
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::with_config(&config).with_clock(clock.clone());
+        let lobby = storage
+            .create_lobby("ann".to_string(), None, None, false, GameMode::Realtime)
+            .unwrap();
+
+        clock.advance(Duration::from_secs(59));
+        assert!(storage.get_lobby(&lobby.code).is_ok());
+        clock.advance(Duration::from_secs(1));
+        assert!(storage.get_lobby(&lobby.code).is_err());
+        assert!(storage.join_lobby(&lobby.code, "bob".to_string()).is_err());
+    }
+
+    #[test]
+    fn each_handicap_preset_starts_the_game_without_its_piece() {
+        let presets = [
+            (Handicap::NoQueen, ChestPiece::Queen, (0, 3), (7, 3)),
+            (Handicap::MinusRook, ChestPiece::Rook, (0, 0), (7, 0)),
+            (Handicap::MinusKnight, ChestPiece::Knight, (0, 1), (7, 6)),
+            (Handicap::MinusBishop, ChestPiece::Bishop, (0, 2), (7, 2)),
+            (Handicap::PawnOdds, ChestPiece::Pawn, (1, 0), (6, 0)),
+        ];
+        for color in [PlayerColor::White, PlayerColor::Black] {
+            for (handicap, piece, white_square, black_square) in presets.clone() {
+                let mut storage = GameStorage::new();
+                let odds = HandicapOdds { color, handicap };
+                let lobby = storage
+                    .create_lobby(
+                        "ann".to_string(),
+                        None,
+                        Some(odds.clone()),
+                        false,
+                        GameMode::Realtime,
+                    )
+                    .unwrap();
+                let joined = storage.join_lobby(&lobby.code, "bob".to_string()).unwrap();
+                let game_id = joined.game_id.unwrap();
+                let given_up = match color {
+                    PlayerColor::White => white_square,
+                    PlayerColor::Black => black_square,
+                };
+
+                storage.with_game(game_id, |game_state| {
+                    let board = &game_state.board;
+                    assert_eq!(board.slot(given_up), None, "{:?}", odds);
+                    assert_eq!(
+                        board.count(&color, piece) + 1,
+                        board.count(&color.opponent(), piece)
+                    );
+                    assert_eq!(
+                        board.material(&color) + piece.value(),
+                        board.material(&color.opponent())
+                    );
+                    assert_eq!(game_state.start_board.as_ref(), Some(board));
+                    assert_eq!(game_state.position_counts[&board.position_key()], 1);
+                    assert_eq!(game_state.handicap.as_ref(), Some(&odds));
+                    assert!(game_state.unrated);
+                });
+                let status = storage
+                    .get_game_status(game_id, Some(lobby.player_id))
+                    .unwrap();
+                assert_eq!(status.handicap, Some(odds));
+            }
+        }
+
+        // The first board the giving side sees is already missing the piece
+        let mut storage = GameStorage::new();
+        let odds = HandicapOdds {
+            color: PlayerColor::White,
+            handicap: Handicap::NoQueen,
+        };
+        let lobby = storage
+            .create_lobby(
+                "ann".to_string(),
+                None,
+                Some(odds),
+                false,
+                GameMode::Realtime,
+            )
+            .unwrap();
+        let game_id = storage
+            .join_lobby(&lobby.code, "bob".to_string())
+            .unwrap()
+            .game_id
+            .unwrap();
+        let seen = storage.get_fogged_board(game_id, lobby.player_id).unwrap();
+        assert!(seen.slots[0][3].is_none());
+        assert!(seen.slots[0][4].is_some());
+    }
+
+    #[test]
+    fn a_handicap_that_cant_be_given_starts_no_game() {
+        let mut storage = GameStorage::new();
+        let kingless = HandicapOdds {
+            color: PlayerColor::White,
+            handicap: Handicap::Pieces(vec![ChestPiece::King]),
+        };
+        assert!(
+            storage
+                .create_lobby(
+                    "ann".to_string(),
+                    None,
+                    Some(kingless),
+                    false,
+                    GameMode::Realtime
+                )
+                .is_err()
+        );
+
+        // A lobby whose handicap asks for more than the army has fails on
+        // joining, before any game exists
+        let lobby = storage
+            .create_lobby("ann".to_string(), None, None, false, GameMode::Realtime)
+            .unwrap();
+        storage.lobbies.get_mut(&lobby.code).unwrap().handicap = Some(HandicapOdds {
+            color: PlayerColor::White,
+            handicap: Handicap::Pieces(vec![ChestPiece::Queen, ChestPiece::Queen]),
+        });
+        assert!(storage.join_lobby(&lobby.code, "bob".to_string()).is_err());
+        assert_eq!(storage.active_game_count().load(Ordering::Relaxed), 0);
+        assert_eq!(storage.repository.games().count(), 0);
+        assert!(storage.player_games.is_empty());
+        assert_eq!(storage.lobbies[&lobby.code].game_id, None);
+    }
+
+    #[test]
+    fn the_opponents_economy_is_hidden_only_when_the_rules_say_so() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, black) = queue_pair(&mut storage, "ann", "bob");
+
+        let status = storage.get_game_status(game_id, Some(white)).unwrap();
+        assert!(status.player1_moves.is_some() && status.player1_charge.is_some());
+        assert_eq!(status.player2_moves, None);
+        assert_eq!(status.player2_at_cap, None);
+        assert_eq!(status.player2_charge, None);
+        let status = storage.get_game_status(game_id, Some(black)).unwrap();
+        assert_eq!(status.player1_moves, None);
+        assert!(status.player2_moves.is_some());
+
+        storage
+            .with_game_mut(game_id, |game_state| {
+                game_state.rules.hide_opponent_economy = false
+            })
+            .unwrap();
+        let status = storage.get_game_status(game_id, Some(white)).unwrap();
+        assert!(status.player1_moves.is_some());
+        assert!(status.player2_moves.is_some());
+        assert!(status.player2_at_cap.is_some());
+        assert!(status.player2_charge.is_some());
+    }
+
+    #[test]
+    fn a_solo_player_moves_both_colors() {
+        let mut storage = GameStorage::new();
+        let joined = storage
+            .start_solo_game("dev".to_string(), false, GameMode::Realtime)
+            .unwrap();
+        let (game_id, player_id) = (joined.game_id.unwrap(), joined.player_id);
+        grant_move_point(&mut storage);
+
+        assert!(play(&mut storage, game_id, player_id, (1, 4), (2, 4)).success);
+        assert!(play(&mut storage, game_id, player_id, (6, 3), (5, 3)).success);
+
+        // With the fog off the one player sees both armies
+        let board = storage.get_fogged_board(game_id, player_id).unwrap();
+        let pieces = board.slots.iter().flatten().flatten().count();
+        assert_eq!(pieces, 32);
+        let history = storage
+            .with_game(game_id, |game_state| game_state.history.len())
+            .unwrap();
+        assert_eq!(history, 2);
+    }
+
+    #[test]
+    fn queue_waits_are_estimated_per_mode_and_say_when_there_is_no_data() {
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new().with_clock(clock.clone());
+
+        let status = storage.get_queue_status();
+        assert_eq!(status.median_wait_seconds, None);
+        assert_eq!(status.sample_size, 0);
+        assert_eq!(status.message, "No recent matches to estimate from");
+        assert!(
+            status
+                .modes
+                .iter()
+                .all(|mode| mode.median_wait_seconds.is_none())
+        );
+
+        for secs in [10, 30, 20] {
+            storage.record_wait(GameMode::Realtime, Duration::from_secs(secs));
+        }
+        for secs in [4, 8] {
+            storage.record_wait(GameMode::TurnBased, Duration::from_secs(secs));
+        }
+        storage
+            .join_queue("ann".to_string(), GameMode::TurnBased)
+            .unwrap();
+        clock.advance(Duration::from_secs(5));
+
+        let status = storage.get_queue_status();
+        assert_eq!(status.median_wait_seconds, Some(10.0));
+        assert_eq!(status.sample_size, 5);
+        assert_eq!(status.players_waiting, 1);
+        let [realtime, turn_based] = &status.modes[..] else {
+            panic!("one entry per mode");
+        };
+        assert_eq!(realtime.mode, GameMode::Realtime);
+        assert_eq!(realtime.median_wait_seconds, Some(20.0));
+        assert_eq!(realtime.players_waiting, 0);
+        assert_eq!(realtime.longest_wait_seconds, None);
+        assert_eq!(turn_based.median_wait_seconds, Some(6.0));
+        assert_eq!(turn_based.players_waiting, 1);
+        assert_eq!(turn_based.longest_wait_seconds, Some(5));
+
+        // Each mode keeps only its latest samples
+        for _ in 0..WAIT_SAMPLE_WINDOW {
+            storage.record_wait(GameMode::Realtime, Duration::from_secs(1));
+        }
+        let status = storage.get_queue_status();
+        assert_eq!(status.modes[0].sample_size, WAIT_SAMPLE_WINDOW);
+        assert_eq!(status.modes[0].median_wait_seconds, Some(1.0));
+        assert_eq!(status.modes[1].sample_size, 2);
+    }
+
+    #[test]
+    fn a_king_hanging_move_is_only_allowed_when_lenient() {
+        // The white rook is pinned to its king by the black rook
+        let board = "k...r...
+                     ........
+                     ........
+                     ........
+                     ........
+                     ........
+                     ....R...
+                     ....K...";
+        for (strictness, allowed) in [
+            (Strictness::Lenient, true),
+            (Strictness::Standard, false),
+            (Strictness::Strict, false),
+        ] {
+            let rules = GameRules {
+                strictness,
+                ..GameRules::default()
+            };
+            let (mut storage, _, game) = seeded_on_manual_clock(board, rules, 1);
+
+            let response = play(
+                &mut storage,
+                game.game_id,
+                game.white_player_id,
+                (1, 4),
+                (1, 0),
+            );
+            assert_eq!(response.success, allowed, "{:?}", strictness);
+            if !allowed {
+                assert_eq!(response.message, "Move would leave your king in check");
+            }
+        }
+    }
+
+    #[test]
+    fn the_cap_flag_is_raised_once_and_cleared_by_a_move() {
+        let mut storage = GameStorage::new();
+        let (game_id, white, _) = queue_pair(&mut storage, "ann", "bob");
+        let at_cap = |storage: &GameStorage| {
+            storage
+                .get_game_status(game_id, Some(white))
+                .unwrap()
+                .player1_at_cap
+        };
+        let capped_events = |storage: &GameStorage| {
+            events(storage, game_id)
+                .into_iter()
+                .filter(|event| {
+                    *event
+                        == GameEvent::MovePointCapped {
+                            color: PlayerColor::White,
+                        }
+                })
+                .count()
+        };
+        assert_eq!(at_cap(&storage), Some(false));
+
+        for _ in 0..=GameRules::default().max_stored_moves + 2 {
+            grant_move_point(&mut storage);
+        }
+        assert_eq!(at_cap(&storage), Some(true));
+        assert_eq!(capped_events(&storage), 1);
+
+        assert!(play(&mut storage, game_id, white, (1, 0), (2, 0)).success);
+        assert_eq!(at_cap(&storage), Some(false));
+        assert_eq!(capped_events(&storage), 1);
+    }
+
+    #[test]
+    fn bare_kings_and_a_lone_scout_are_drawn_under_strict_rules() {
+        let strict = GameRules {
+            strictness: Strictness::Strict,
+            ..GameRules::default()
+        };
+        let drawn = Some(GameResult {
+            winner: None,
+            reason: GameEndReason::InsufficientMaterial,
+        });
+        let king_move = |board: &str, rules: GameRules| {
+            let (mut storage, _, game) = seeded_on_manual_clock(board, rules, 1);
+            assert!(
+                play(
+                    &mut storage,
+                    game.game_id,
+                    game.white_player_id,
+                    (0, 4),
+                    (0, 3)
+                )
+                .success
+            );
+            result(&storage, game.game_id)
+        };
+
+        let bare_kings = "k.......
+                          ........
+                          ........
+                          ........
+                          ........
+                          ........
+                          ........
+                          ....K...";
+        let king_and_scout = "k.......
+                              ........
+                              ........
+                              ........
+                              ........
+                              ........
+                              ........
+                              S...K...";
+        let king_and_rook = "k.......
+                             ........
+                             ........
+                             ........
+                             ........
+                             ........
+                             ........
+                             R...K...";
+        assert_eq!(king_move(bare_kings, strict.clone()), drawn);
+        assert_eq!(king_move(king_and_scout, strict.clone()), drawn);
+        assert_eq!(king_move(king_and_rook, strict), None);
+        // Only strict rules call the draw
+        assert_eq!(king_move(bare_kings, GameRules::default()), None);
+    }
+
+    #[test]
+    fn spectators_see_the_intro_then_no_pieces_until_the_game_ends() {
+        let (mut storage, clock, game_id, white, _) = game_on_manual_clock();
+        storage
+            .with_game_mut(game_id, |game_state| {
+                game_state.rules.intro_reveal_seconds = 10
+            })
+            .unwrap();
+        let pieces = |board: &SpectatorBoard| board.slots.iter().flatten().flatten().count();
+
+        clock.advance(Duration::from_secs(9));
+        let intro = storage.get_spectator_board(game_id).unwrap();
+        assert!(!intro.fogged);
+        assert_eq!(pieces(&intro), 32);
+
+        // Past the intro not even the pieces both players can see are given
+        // away, as a player could watch their own game
+        clock.advance(Duration::from_secs(1));
+        let playing = storage.get_spectator_board(game_id).unwrap();
+        assert!(playing.fogged);
+        assert_eq!(pieces(&playing), 0);
+
+        storage.quit(white).unwrap();
+        let finished = storage.get_spectator_board(game_id).unwrap();
+        assert!(!finished.fogged);
+        assert_eq!(pieces(&finished), 32);
+    }
+
+    #[test]
+    fn quitting_leaves_the_queue_and_the_game_and_can_be_repeated() {
+        let (mut storage, clock, game_id, white, _) = game_on_manual_clock();
+        // Also waiting for a turn-based game under the same id
+        storage.repository.push_queued(QueuedPlayer {
+            id: white,
+            name: "alice".to_string(),
+            joined_at: clock.now(),
+            mode: GameMode::TurnBased,
+        });
+        assert_eq!(storage.repository.queue_len(), 1);
+
+        let quit = storage.quit(white).unwrap();
+        assert!(quit.left_queue);
+        assert_eq!(quit.resigned_games, vec![game_id]);
+        assert_eq!(storage.repository.queue_len(), 0);
+        assert_eq!(
+            result(&storage, game_id),
+            Some(GameResult {
+                winner: Some(PlayerColor::Black),
+                reason: GameEndReason::Resigned,
+            })
+        );
+
+        let again = storage.quit(white).unwrap();
+        assert!(!again.left_queue);
+        assert!(again.resigned_games.is_empty());
+        assert_eq!(
+            events(&storage, game_id)
+                .iter()
+                .filter(|event| matches!(event, GameEvent::GameOver { .. }))
+                .count(),
+            1
+        );
+        assert!(storage.quit(Uuid::new_v4()).is_err());
+    }
+
+    #[test]
+    fn finished_games_drop_out_of_the_active_ids() {
+        let mut storage = GameStorage::new();
+        let (first, first_white, _) = queue_pair(&mut storage, "ann", "bob");
+        let (second, _, _) = queue_pair(&mut storage, "cat", "dan");
+        let sorted = |mut ids: Vec<Uuid>| {
+            ids.sort();
+            ids
+        };
+        assert_eq!(
+            sorted(storage.active_game_ids()),
+            sorted(vec![first, second])
+        );
+        assert!(storage.finished_game_ids().is_empty());
+
+        storage.quit(first_white).unwrap();
+        assert_eq!(storage.active_game_ids(), vec![second]);
+        assert_eq!(storage.finished_game_ids(), vec![first]);
+        assert_eq!(storage.active_game_ids_in(TickShard::ALL), vec![second]);
+    }
+
+    #[test]
+    fn each_cleanup_pass_evicts_only_once_its_period_is_up() {
+        let config = Config {
+            lobby_ttl_seconds: 60,
+            spectator_code_ttl_seconds: 120,
+            stale_game_seconds: 600,
+            finished_retention_seconds: 300,
+            ..Config::default()
+        };
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::with_config(&config).with_clock(clock.clone());
+        let (game_id, _, _) = queue_pair(&mut storage, "ann", "bob");
+        let lobby = storage
+            .create_lobby("cat".to_string(), None, None, false, GameMode::Realtime)
+            .unwrap();
+        let code = storage.spectator_code(game_id).unwrap().code;
+        let step = |storage: &mut GameStorage, seconds| {
+            clock.advance(Duration::from_secs(seconds));
+            storage.cleanup();
+        };
+
+        step(&mut storage, 59);
+        assert!(storage.get_lobby(&lobby.code).is_ok());
+        step(&mut storage, 1);
+        assert!(storage.get_lobby(&lobby.code).is_err());
+        assert!(storage.spectator_codes.contains_key(&code));
+
+        step(&mut storage, 59);
+        assert!(storage.spectator_codes.contains_key(&code));
+        step(&mut storage, 1);
+        assert!(!storage.spectator_codes.contains_key(&code));
+
+        // Nobody has moved since the game started
+        step(&mut storage, 479);
+        assert_eq!(result(&storage, game_id), None);
+        step(&mut storage, 1);
+        assert_eq!(
+            result(&storage, game_id),
+            Some(GameResult {
+                winner: None,
+                reason: GameEndReason::Inactive,
+            })
+        );
+
+        step(&mut storage, 299);
+        assert!(storage.repository.contains(game_id));
+        step(&mut storage, 1);
+        assert!(!storage.repository.contains(game_id));
+    }
+
+    #[test]
+    fn points_past_the_cap_fill_a_charge_that_buys_a_free_move() {
+        let rules = GameRules {
+            charge_capacity: 2,
+            min_move_interval_millis: 1000,
+            ..GameRules::default()
+        };
+        let max_stored_moves = rules.max_stored_moves;
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "....k...
+             pppppppp
+             ........
+             ........
+             ........
+             ........
+             PPPPPPPP
+             ....K...",
+            rules,
+            max_stored_moves,
+        );
+        let (game_id, white) = (game.game_id, game.white_player_id);
+        let charge = |storage: &GameStorage| {
+            storage
+                .get_game_status(game_id, Some(white))
+                .unwrap()
+                .player1_charge
+        };
+        let charged = |storage: &mut GameStorage, from, to| {
+            storage
+                .make_move(
+                    game_id,
+                    crate::MoveRequest {
+                        player_id: white,
+                        from,
+                        to,
+                        use_charge: true,
+                    },
+                )
+                .unwrap()
+        };
+
+        // A point earned at the cap overflows into the charge
+        grant_move_point(&mut storage);
+        assert_eq!(charge(&storage), Some(1));
+        let not_full = charged(&mut storage, (1, 0), (2, 0));
+        assert!(!not_full.success);
+        assert_eq!(not_full.message, "Charge is not full");
+
+        // The meter stops at its capacity
+        grant_move_point(&mut storage);
+        grant_move_point(&mut storage);
+        assert_eq!(charge(&storage), Some(2));
+
+        // Spending it moves inside the interval without a move point
+        assert!(play(&mut storage, game_id, white, (1, 0), (2, 0)).success);
+        let too_soon = play(&mut storage, game_id, white, (1, 1), (2, 1));
+        assert!(too_soon.message.starts_with("Too soon"));
+        let free = charged(&mut storage, (1, 1), (2, 1));
+        assert!(free.success, "{}", free.message);
+        assert_eq!(free.remaining_moves, max_stored_moves - 1);
+        assert_eq!(charge(&storage), Some(0));
+        assert!(!charged(&mut storage, (1, 2), (2, 2)).success);
+    }
+
+    #[test]
+    fn a_capture_pulse_reveals_the_area_once_then_clears() {
+        let rules = GameRules {
+            capture_pulse_radius: 1,
+            ..GameRules::default()
+        };
+        let (mut storage, _, game) = seeded_on_manual_clock(
+            "k.......
+             ........
+             ......N.
+             ........
+             ......pP
+             ........
+             ........
+             ......RK",
+            rules,
+            3,
+        );
+        let (game_id, black) = (game.game_id, game.black_player_id);
+        let sees = |storage: &mut GameStorage, square: (usize, usize)| {
+            storage.get_fogged_board(game_id, black).unwrap().slots[square.0][square.1].is_some()
+        };
+        assert!(!sees(&mut storage, (0, 6)));
+
+        // The rook takes the pawn, leaving black nothing near the square
+        assert!(play(&mut storage, game_id, game.white_player_id, (0, 6), (3, 6)).success);
+        assert!(storage.cached_fogged_board(game_id, black).is_none());
+        let pulsed = storage.get_fogged_board(game_id, black).unwrap();
+        assert_eq!(
+            pulsed.slots[3][6],
+            Some(VisibleSlot {
+                piece: ChestPiece::Rook,
+                color: PlayerColor::White
+            })
+        );
+        assert!(pulsed.slots[3][7].is_some(), "the pawn beside the capture");
+        assert!(
+            pulsed.slots[5][6].is_none(),
+            "the knight is out of the radius"
+        );
+
+        // The next fetch is back to what black's own pieces see
+        assert!(!sees(&mut storage, (3, 6)));
+        assert!(!sees(&mut storage, (3, 7)));
+    }
+
+    #[test]
+    fn a_games_start_and_age_follow_the_clock_and_survive_a_snapshot() {
+        let (storage, clock, game_id, white, _) = game_on_manual_clock();
+        let started = format_wall_time(clock.wall_time());
+        let status = storage.get_game_status(game_id, Some(white)).unwrap();
+        assert_eq!(status.created_at.as_deref(), Some(started.as_str()));
+        assert_eq!(status.age_seconds, Some(0));
+
+        clock.advance(Duration::from_secs(90));
+        let status = storage.get_game_status(game_id, Some(white)).unwrap();
+        assert_eq!(status.created_at.as_deref(), Some(started.as_str()));
+        assert_eq!(status.age_seconds, Some(90));
+
+        let json = serde_json::to_value(storage.snapshot()).unwrap();
+        assert_eq!(json["games"][0]["started_at"], started.as_str());
+        let game_state: GameState = serde_json::from_value(json["games"][0].clone()).unwrap();
+        let mut restored = GameStorage::new().with_clock(Arc::new(ManualClock::new()));
+        restored.restore(RestoredState {
+            accounts: Default::default(),
+            active_games: vec![game_state],
+            seasons: Vec::new(),
+            current_season: None,
+        });
+        // Timer ages are saved against the real clock, but the start date is
+        // kept exactly as written
+        let status = restored.get_game_status(game_id, Some(white)).unwrap();
+        assert_eq!(status.created_at.as_deref(), Some(started.as_str()));
+    }
+
+    #[test]
+    fn fogged_boards_of_the_same_position_are_equal_until_a_move() {
+        let board = "....k...
+                     pppppppp
+                     ........
+                     ........
+                     ........
+                     ........
+                     PPPPPPPP
+                     ....K...";
+        let (mut first, _, first_game) = seeded_on_manual_clock(board, GameRules::default(), 3);
+        let (mut second, _, second_game) = seeded_on_manual_clock(board, GameRules::default(), 3);
+        let view = |storage: &mut GameStorage, game: &crate::SeededGame| {
+            storage
+                .get_fogged_board(game.game_id, game.white_player_id)
+                .unwrap()
+        };
+
+        let before = view(&mut first, &first_game);
+        let unmoved = view(&mut second, &second_game);
+        assert_eq!(before, unmoved);
+
+        assert!(
+            play(
+                &mut first,
+                first_game.game_id,
+                first_game.white_player_id,
+                (1, 0),
+                (2, 0)
+            )
+            .success
+        );
+        let after = view(&mut first, &first_game);
+        assert_ne!(after, before);
+        assert_ne!(after, unmoved);
+        let distinct: std::collections::HashSet<_> = [before, unmoved, after].into_iter().collect();
+        assert_eq!(distinct.len(), 2);
+    }
+
+    #[test]
+    fn diagnostics_list_the_largest_games_first() {
+        let mut storage = GameStorage::new();
+        let (mut game_ids, mut black_ids) = (Vec::new(), Vec::new());
+        for (n, events) in [10, 3, 30, 0].into_iter().enumerate() {
+            let (game_id, _, black) =
+                queue_pair(&mut storage, &format!("a{}", n), &format!("b{}", n));
+            let game_state = storage.repository.get_mut(game_id).unwrap();
+            game_state.events = vec![
+                GameEvent::PlayerIdle {
+                    color: PlayerColor::White
+                };
+                events
+            ];
+            game_ids.push(game_id);
+            black_ids.push(black);
+        }
+        // A finished game is listed beside the active ones; resigning logs one
+        // more event
+        storage.quit(black_ids[1]).unwrap();
+
+        let diagnostics = storage.diagnostics(2);
+        let largest: Vec<(Uuid, usize)> = diagnostics
+            .largest_games
+            .iter()
+            .map(|game| (game.game_id, game.events + game.history))
+            .collect();
+        assert_eq!(largest, [(game_ids[2], 30), (game_ids[0], 10)]);
+
+        let everything = storage.diagnostics(10);
+        let order: Vec<Uuid> = everything
+            .largest_games
+            .iter()
+            .map(|game| game.game_id)
+            .collect();
+        assert_eq!(order, [game_ids[2], game_ids[0], game_ids[1], game_ids[3]]);
+        assert!(everything.largest_games[2].finished);
+        assert!(
+            everything.largest_games[0].approx_bytes > everything.largest_games[3].approx_bytes
+        );
+        assert_eq!(everything.categories["active_games"].count, 3);
+        assert_eq!(
+            everything.categories["finished_games_awaiting_archive"].count,
+            1
+        );
+        assert_eq!(everything.categories["events"].count, 44);
+    }
+
+    #[test]
+    fn issued_spectator_codes_are_short_and_never_repeat() {
+        let mut storage = GameStorage::new();
+        let (game_id, _, _) = queue_pair(&mut storage, "ann", "bob");
+        let codes: std::collections::HashSet<String> = (0..500)
+            .map(|_| storage.issue_spectator_code(game_id))
+            .collect();
+        assert_eq!(codes.len(), 500);
+        for code in &codes {
+            assert_eq!(code.len(), SPECTATOR_CODE_LEN);
+            assert!(
+                code.bytes().all(|c| SPECTATOR_CODE_ALPHABET.contains(&c)),
+                "{}",
+                code
+            );
+            assert_eq!(storage.spectator_codes[code].game_id, game_id);
+            assert_eq!(storage.spectator_codes[code].expires_at, None);
+        }
+    }
+
+    #[test]
+    fn a_spectator_code_leads_to_its_game_until_it_expires() {
+        let config = Config {
+            spectator_code_ttl_seconds: 60,
+            ..Config::default()
+        };
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::with_config(&config).with_clock(clock.clone());
+        let (first, _, first_black) = queue_pair(&mut storage, "ann", "bob");
+        let (second, _, _) = queue_pair(&mut storage, "cat", "dan");
+        let code = storage.spectator_code(first).unwrap();
+        assert_eq!(code.expires_in_seconds, Some(60));
+        assert_ne!(code.code, storage.spectator_code(second).unwrap().code);
+
+        // Pieces stay hidden while the fog game is played, and codes aren't
+        // case sensitive
+        let watched = storage.spectate_by_code(&code.code).unwrap();
+        assert_eq!(watched, storage.get_spectator_board(first).unwrap());
+        assert!(watched.fogged);
+        assert!(watched.slots.iter().flatten().all(Option::is_none));
+        assert_eq!(
+            storage.spectate_by_code(&code.code.to_lowercase()),
+            Ok(watched)
+        );
+        assert_eq!(
+            storage.spectate_by_code("NOPE22"),
+            Err("Unknown spectator code".to_string())
+        );
+
+        clock.advance(Duration::from_secs(59));
+        let again = storage.spectator_code(first).unwrap();
+        assert_eq!(again.code, code.code);
+        assert_eq!(again.expires_in_seconds, Some(1));
+
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(
+            storage.spectate_by_code(&code.code),
+            Err("Spectator code expired".to_string())
+        );
+        let renewed = storage.spectator_code(first).unwrap();
+        assert_ne!(renewed.code, code.code);
+        assert_eq!(renewed.expires_in_seconds, Some(60));
+        storage.expire_spectator_codes();
+        assert_eq!(
+            storage.spectate_by_code(&code.code),
+            Err("Unknown spectator code".to_string())
+        );
+
+        // Once the game is over the code shows the whole board
+        storage.quit(first_black).unwrap();
+        let finished = storage.spectate_by_code(&renewed.code).unwrap();
+        assert!(!finished.fogged);
+        assert_eq!(finished.slots.iter().flatten().flatten().count(), 32);
+    }
+
+    #[test]
+    fn a_new_bot_game_sees_the_bot_move_within_a_few_ticks() {
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new().with_clock(clock.clone());
+        let joined = storage
+            .start_bot_game("ann".to_string(), true, GameMode::Realtime)
+            .unwrap();
+        let game_id = joined.game_id.unwrap();
+        let rules = GameRules::default();
+        let tick = Duration::from_millis(rules.bot_move_interval_millis / 2);
+        let bot_moves = |storage: &GameStorage| {
+            storage
+                .with_game(game_id, |game_state| {
+                    game_state
+                        .history
+                        .iter()
+                        .filter(|record| Some(record.color) == game_state.bot_color)
+                        .count()
+                })
+                .unwrap()
+        };
+
+        // The human never moves; the bot opens once its first point comes in
+        let bound = rules.move_increment_ticks as usize + 2;
+        let mut ticks = 0;
+        while bot_moves(&storage) == 0 {
+            assert!(ticks < bound, "no bot move after {} ticks", ticks);
+            clock.advance(tick);
+            storage.tick_shard(TickShard::ALL);
+            ticks += 1;
+        }
+
+        // From then on it makes at most one move per interval
+        let mut last = (clock.now(), bot_moves(&storage));
+        for _ in 0..40 {
+            clock.advance(tick);
+            storage.tick_shard(TickShard::ALL);
+            let moves = bot_moves(&storage);
+            if moves > last.1 {
+                assert_eq!(moves, last.1 + 1);
+                assert!(
+                    clock.now() - last.0 >= Duration::from_millis(rules.bot_move_interval_millis)
+                );
+                last = (clock.now(), moves);
+            }
+        }
+        assert!(last.1 > 1, "the bot kept playing");
+
+        // Nothing more once the game is over
+        storage.quit(joined.player_id).unwrap();
+        for _ in 0..10 {
+            clock.advance(tick);
+            storage.tick_shard(TickShard::ALL);
+        }
+        assert_eq!(bot_moves(&storage), last.1);
+    }
+
+    // Needs a Redis server, as the repository's Redis tests do
+    #[cfg(feature = "redis")]
+    #[test]
+    fn a_move_is_judged_against_what_another_instance_saved() {
+        let Ok(url) = std::env::var("REDIS_URL") else {
+            return;
+        };
+        let connect = || {
+            let repository = RedisRepository::connect(&url).expect("REDIS_URL is reachable");
+            GameStorage::new().with_repository(Box::new(repository))
+        };
+        let mut local = GameStorage::new();
+        let (game_id, white, black) = queue_pair(&mut local, "ann", "bob");
+        let mut first = connect();
+        first
+            .repository
+            .insert(local.with_game(game_id, GameState::clone).unwrap());
+        first.flush();
+        let mut second = connect();
+
+        assert!(play(&mut first, game_id, white, (1, 0), (2, 0)).success);
+        // The second instance's copy still has the pawn on (1, 0) and a move
+        // point to spend
+        assert!(!play(&mut second, game_id, white, (1, 0), (2, 0)).success);
+        assert!(play(&mut second, game_id, black, (6, 0), (5, 0)).success);
+
+        first.refresh_game(game_id);
+        let moves = first
+            .with_game(game_id, |game_state| game_state.history.len())
+            .unwrap();
+        assert_eq!(moves, 2, "neither instance's move was lost");
+
+        first.repository.remove(game_id);
+    }
+
+    #[test]
+    fn the_cached_board_is_served_only_while_current() {
+        let (mut storage, _, game_id, white, black) = game_on_manual_clock();
+        assert!(storage.cached_fogged_board(game_id, white).is_none());
+
+        let board = storage.get_fogged_board(game_id, white).unwrap();
+        let cached = storage.cached_fogged_board(game_id, white).unwrap();
+        assert!(Arc::ptr_eq(&board, &cached));
+        assert!(storage.cached_fogged_board(game_id, black).is_none());
+
+        assert!(play(&mut storage, game_id, white, (1, 0), (2, 0)).success);
+        assert!(storage.cached_fogged_board(game_id, white).is_none());
     }
 }
@@ -0,0 +1,263 @@
+use crate::glub_server_config::Config;
+use crate::glub_server_metrics::RouteMetrics;
+use crate::glub_server_storage::GameStorage;
+use axum::Router;
+use axum::body::Body;
+use axum::http::{Method, Request, StatusCode};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tower::ServiceExt;
+use uuid::Uuid;
+
+/// Largest response body the harness reads
+const MAX_BODY_BYTES: usize = 16 * 1024 * 1024;
+
+/// The full router over in-memory storage, driven one request at a time
+/// without a socket. Responses come back as JSON values, since the response
+/// types only serialize.
+pub struct TestServer {
+    router: Router,
+    storage: Arc<RwLock<GameStorage>>,
+}
+
+/// A game started through the queue, with its players by color
+#[derive(Debug, Clone, Copy)]
+pub struct StartedGame {
+    pub game_id: Uuid,
+    pub white_player_id: Uuid,
+    pub black_player_id: Uuid,
+}
+
+impl TestServer {
+    /// A server on empty storage with `config`
+    pub fn new(config: &Config) -> Self {
+        Self::with_storage(GameStorage::with_config(config), config)
+    }
+
+    /// A server on `storage`, with `config` for the routes' own settings such
+    /// as body limits and the admin token
+    pub fn with_storage(storage: GameStorage, config: &Config) -> Self {
+        let storage = Arc::new(RwLock::new(storage));
+        Self {
+            router: crate::build_router(
+                Arc::clone(&storage),
+                Arc::new(config.clone()),
+                RouteMetrics::default(),
+            ),
+            storage,
+        }
+    }
+
+    /// The storage behind the router, for ticking games or inspecting them
+    pub fn storage(&self) -> &Arc<RwLock<GameStorage>> {
+        &self.storage
+    }
+
+    /// The router itself, for requests that need their own headers
+    pub fn router(&self) -> Router {
+        self.router.clone()
+    }
+
+    /// Send one request, with `body` as JSON when given. An empty response
+    /// body comes back as `Value::Null`.
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        let body = match body {
+            Some(body) => {
+                request = request.header("content-type", "application/json");
+                Body::from(body.to_string())
+            }
+            None => Body::empty(),
+        };
+        let request = request.body(body).expect("test requests are well formed");
+
+        let response = self
+            .router
+            .clone()
+            .oneshot(request)
+            .await
+            .expect("the router never fails");
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), MAX_BODY_BYTES)
+            .await
+            .expect("response bodies are readable");
+        let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, value)
+    }
+
+    pub async fn get(&self, uri: &str) -> (StatusCode, Value) {
+        self.request(Method::GET, uri, None).await
+    }
+
+    pub async fn post(&self, uri: &str, body: Value) -> (StatusCode, Value) {
+        self.request(Method::POST, uri, Some(body)).await
+    }
+
+    /// Queue two players so they are matched with each other
+    pub async fn start_game(&self) -> StartedGame {
+        let (_, first) = self
+            .post("/join_queue", json!({ "player_name": "first" }))
+            .await;
+        let (_, second) = self
+            .post("/join_queue", json!({ "player_name": "second" }))
+            .await;
+        let id = |joined: &Value, field: &str| {
+            joined[field]
+                .as_str()
+                .and_then(|id| Uuid::parse_str(id).ok())
+                .unwrap_or_else(|| panic!("join_queue returned no {}: {}", field, joined))
+        };
+        let (first_id, second_id) = (id(&first, "player_id"), id(&second, "player_id"));
+        let game_id = id(&second, "game_id");
+
+        let (_, current) = self
+            .get(&format!("/players/{}/current_game", first_id))
+            .await;
+        let (white_player_id, black_player_id) = if current["game"]["your_color"] == "white" {
+            (first_id, second_id)
+        } else {
+            (second_id, first_id)
+        };
+
+        StartedGame {
+            game_id,
+            white_player_id,
+            black_player_id,
+        }
+    }
+
+    pub async fn make_move(
+        &self,
+        game_id: Uuid,
+        player_id: Uuid,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> (StatusCode, Value) {
+        let body = json!({ "player_id": player_id, "from": from, "to": to });
+        self.post(&format!("/game/{}/move", game_id), body).await
+    }
+
+    /// The board as `player_id` sees it
+    pub async fn board(&self, game_id: Uuid, player_id: Uuid) -> (StatusCode, Value) {
+        self.get(&format!("/game/{}/board/{}", game_id, player_id))
+            .await
+    }
+
+    pub async fn status(&self, game_id: Uuid, player_id: Option<Uuid>) -> (StatusCode, Value) {
+        let uri = match player_id {
+            Some(player_id) => format!("/game/{}/status?player_id={}", game_id, player_id),
+            None => format!("/game/{}/status", game_id),
+        };
+        self.get(&uri).await
+    }
+}
+
+impl Default for TestServer {
+    fn default() -> Self {
+        Self::new(&Config::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn join_move_and_status_through_the_router() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        assert_ne!(game.white_player_id, game.black_player_id);
+
+        let (status, board) = server.board(game.game_id, game.white_player_id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(board["your_color"], "white");
+        assert_eq!(board["slots"][1][4]["piece"], "Pawn");
+
+        let (status, moved) = server
+            .make_move(game.game_id, game.white_player_id, (1, 4), (2, 4))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved["success"], true, "{}", moved);
+
+        let (_, board) = server.board(game.game_id, game.white_player_id).await;
+        assert_eq!(board["slots"][1][4], Value::Null);
+        assert_eq!(board["slots"][2][4]["piece"], "Pawn");
+
+        // The only starting move point is spent
+        let (_, again) = server
+            .make_move(game.game_id, game.white_player_id, (1, 3), (2, 3))
+            .await;
+        assert_eq!(again["success"], false);
+
+        let (status, game_status) = server.status(game.game_id, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(game_status["game_id"], game.game_id.to_string());
+        assert_eq!(game_status["phase"], "playing");
+        assert_eq!(game_status["result"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn a_ten_by_ten_game_is_played_to_the_end() {
+        let mut config = Config::default();
+        config.rules.board_size = 10;
+        let server = TestServer::new(&config);
+        let game = server.start_game().await;
+        let (white, black) = (game.white_player_id, game.black_player_id);
+
+        // Armies keep to the middle eight columns, black on the last two rows
+        let (_, board) = server.board(game.game_id, white).await;
+        assert_eq!(board["dims"], json!([10, 10]));
+        assert_eq!(board["slots"].as_array().map(Vec::len), Some(10));
+        assert_eq!(board["slots"][0][5]["piece"], "King");
+        assert_eq!(board["slots"][0][0], Value::Null);
+        let (_, board) = server.board(game.game_id, black).await;
+        assert_eq!(board["slots"][9][5]["piece"], "King");
+        assert_eq!(board["slots"][8][1]["piece"], "Pawn");
+
+        // Bank the full five move points for both players
+        for _ in 0..15 {
+            server
+                .storage()
+                .write()
+                .await
+                .increment_moves(crate::glub_server_storage::TickShard::ALL);
+        }
+
+        let (_, off_board) = server.make_move(game.game_id, white, (0, 8), (0, 10)).await;
+        assert_eq!(off_board["success"], false);
+
+        // White's queen slips out through the empty first column, which an 8x8
+        // board doesn't have, and takes the king
+        let moves = [
+            (white, (1, 3), (2, 3)),
+            (black, (8, 8), (7, 8)),
+            (white, (0, 4), (4, 0)),
+            (black, (7, 8), (6, 8)),
+            (white, (4, 0), (8, 4)),
+            (black, (6, 8), (5, 8)),
+            (white, (8, 4), (9, 5)),
+        ];
+        for (player_id, from, to) in moves {
+            let (_, moved) = server.make_move(game.game_id, player_id, from, to).await;
+            assert_eq!(moved["success"], true, "{:?} to {:?}: {}", from, to, moved);
+        }
+
+        let (_, game_status) = server.status(game.game_id, None).await;
+        assert_eq!(game_status["phase"], "finished");
+        assert_eq!(game_status["result"]["winner"], "white");
+        assert_eq!(game_status["result"]["reason"], "king_captured");
+    }
+
+    #[tokio::test]
+    async fn unknown_games_are_not_found() {
+        let server = TestServer::default();
+        let (status, _) = server.status(Uuid::new_v4(), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
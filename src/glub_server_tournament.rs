@@ -0,0 +1,617 @@
+use crate::glub_server_storage::Strictness;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use uuid::Uuid;
+
+/// Bracket sizes a single-elimination tournament can be created with
+pub const TOURNAMENT_SIZES: [usize; 3] = [4, 8, 16];
+
+/// Largest field a Swiss tournament accepts
+pub const MAX_SWISS_PLAYERS: usize = 64;
+
+/// Pairing attempts before Swiss pairing falls back to allowing rematches
+const PAIRING_SEARCH_BUDGET: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TournamentFormat {
+    #[default]
+    SingleElimination,
+    /// Everyone plays every round, paired against players on a similar score
+    Swiss { rounds: usize },
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TournamentPlayer {
+    pub id: Uuid,
+    pub name: String,
+}
+
+/// One pairing in the bracket, played out as a single game
+#[derive(Serialize, Clone, Debug)]
+pub struct BracketMatch {
+    pub player1: Uuid,
+    pub player2: Uuid,
+    /// The game currently deciding this match (replaced when a draw forces a replay)
+    pub game_id: Uuid,
+    pub winner: Option<Uuid>,
+    /// Set once the match is decided; a finished match without a winner is a draw
+    pub finished: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Registering,
+    InProgress,
+    Finished,
+}
+
+/// A player's running Swiss score. Scores are kept in half points so draws stay exact.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Standing {
+    pub player_id: Uuid,
+    pub score_halves: u32,
+    /// Sum of the scores of everyone this player has faced
+    pub buchholz_halves: u32,
+    pub had_bye: bool,
+}
+
+/// Pairings for one round, plus the player sitting it out if any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoundPairings {
+    pub pairings: Vec<(Uuid, Uuid)>,
+    pub bye: Option<Uuid>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct Tournament {
+    pub id: Uuid,
+    pub name: String,
+    pub size: usize,
+    pub strictness: Strictness,
+    pub format: TournamentFormat,
+    /// Registered players in seed order
+    pub players: Vec<TournamentPlayer>,
+    pub rounds: Vec<Vec<BracketMatch>>,
+    /// The player sitting out each round, parallel to `rounds`
+    pub byes: Vec<Option<Uuid>>,
+    pub champion: Option<Uuid>,
+}
+
+impl Tournament {
+    /// Rough number of bytes the tournament takes up in memory
+    pub fn approx_bytes(&self) -> usize {
+        size_of::<Self>()
+            + self.name.capacity()
+            + self
+                .players
+                .iter()
+                .map(|player| size_of::<TournamentPlayer>() + player.name.capacity())
+                .sum::<usize>()
+            + self
+                .rounds
+                .iter()
+                .map(|round| {
+                    size_of::<Vec<BracketMatch>>() + round.capacity() * size_of::<BracketMatch>()
+                })
+                .sum::<usize>()
+            + self.byes.capacity() * size_of::<Option<Uuid>>()
+    }
+
+    pub fn new(
+        name: String,
+        size: usize,
+        strictness: Strictness,
+        format: TournamentFormat,
+    ) -> Result<Self, String> {
+        match format {
+            TournamentFormat::SingleElimination => {
+                if !TOURNAMENT_SIZES.contains(&size) {
+                    return Err("Tournament size must be 4, 8 or 16".to_string());
+                }
+            }
+            TournamentFormat::Swiss { rounds } => {
+                if !(2..=MAX_SWISS_PLAYERS).contains(&size) {
+                    return Err("Swiss tournaments take 2 to 64 players".to_string());
+                }
+                if rounds == 0 || rounds >= size {
+                    return Err(
+                        "Swiss rounds must be at least 1 and fewer than players".to_string()
+                    );
+                }
+            }
+        }
+
+        Ok(Self {
+            id: Uuid::new_v4(),
+            name,
+            size,
+            strictness,
+            format,
+            players: Vec::new(),
+            rounds: Vec::new(),
+            byes: Vec::new(),
+            champion: None,
+        })
+    }
+
+    pub fn status(&self) -> TournamentStatus {
+        if self.champion.is_some() {
+            TournamentStatus::Finished
+        } else if self.rounds.is_empty() {
+            TournamentStatus::Registering
+        } else {
+            TournamentStatus::InProgress
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.players.len() == self.size
+    }
+
+    pub fn register(&mut self, player: TournamentPlayer) -> Result<(), String> {
+        if self.status() != TournamentStatus::Registering || self.is_full() {
+            return Err("Tournament registration is closed".to_string());
+        }
+
+        self.players.push(player);
+        Ok(())
+    }
+
+    /// First round pairings
+    pub fn first_round(&self) -> RoundPairings {
+        match self.format {
+            // Seeded 1 vs N, 2 vs N-1, ...
+            TournamentFormat::SingleElimination => {
+                let half = self.players.len() / 2;
+                RoundPairings {
+                    pairings: (0..half)
+                        .map(|seed| {
+                            (
+                                self.players[seed].id,
+                                self.players[self.players.len() - 1 - seed].id,
+                            )
+                        })
+                        .collect(),
+                    bye: None,
+                }
+            }
+            TournamentFormat::Swiss { .. } => {
+                swiss_pairings(&self.standings(), &self.played_pairs())
+            }
+        }
+    }
+
+    /// Pairings for the next round once every match of the current round is decided
+    pub fn next_round(&self) -> Option<RoundPairings> {
+        if !self.round_complete() {
+            return None;
+        }
+
+        match self.format {
+            // Winners of adjacent matches meet, so the bracket shape is preserved
+            TournamentFormat::SingleElimination => {
+                let winners = self.current_round_winners()?;
+                if winners.len() < 2 {
+                    return None;
+                }
+
+                Some(RoundPairings {
+                    pairings: winners.chunks(2).map(|pair| (pair[0], pair[1])).collect(),
+                    bye: None,
+                })
+            }
+            TournamentFormat::Swiss { rounds } => {
+                if self.rounds.len() >= rounds {
+                    return None;
+                }
+
+                Some(swiss_pairings(&self.standings(), &self.played_pairs()))
+            }
+        }
+    }
+
+    /// Whether every match of the latest round has been decided
+    pub fn round_complete(&self) -> bool {
+        self.rounds
+            .last()
+            .is_some_and(|round| round.iter().all(|bracket_match| bracket_match.finished))
+    }
+
+    /// The tournament winner, once no further round will be played
+    pub fn decide_champion(&self) -> Option<Uuid> {
+        if !self.round_complete() || self.next_round().is_some() {
+            return None;
+        }
+
+        match self.format {
+            TournamentFormat::SingleElimination => match self.current_round_winners()?[..] {
+                [champion] => Some(champion),
+                _ => None,
+            },
+            TournamentFormat::Swiss { .. } => {
+                self.standings().first().map(|standing| standing.player_id)
+            }
+        }
+    }
+
+    /// Winners of the latest round, if it is complete
+    pub fn current_round_winners(&self) -> Option<Vec<Uuid>> {
+        self.rounds
+            .last()?
+            .iter()
+            .map(|bracket_match| bracket_match.winner)
+            .collect()
+    }
+
+    pub fn find_match_mut(&mut self, game_id: Uuid) -> Option<&mut BracketMatch> {
+        self.rounds
+            .last_mut()?
+            .iter_mut()
+            .find(|bracket_match| bracket_match.game_id == game_id)
+    }
+
+    /// Every pairing already played, in both orders
+    pub fn played_pairs(&self) -> HashSet<(Uuid, Uuid)> {
+        self.rounds
+            .iter()
+            .flatten()
+            .flat_map(|m| [(m.player1, m.player2), (m.player2, m.player1)])
+            .collect()
+    }
+
+    /// Players ordered by score, then Buchholz, then seed.
+    /// A win or a bye is worth a point and a draw half a point.
+    pub fn standings(&self) -> Vec<Standing> {
+        let mut standings: Vec<Standing> = self
+            .players
+            .iter()
+            .map(|player| Standing {
+                player_id: player.id,
+                score_halves: 0,
+                buchholz_halves: 0,
+                had_bye: self.byes.contains(&Some(player.id)),
+            })
+            .collect();
+        let index_of = |player_id: Uuid| {
+            self.players
+                .iter()
+                .position(|player| player.id == player_id)
+        };
+
+        for bye in self.byes.iter().flatten() {
+            if let Some(index) = index_of(*bye) {
+                standings[index].score_halves += 2;
+            }
+        }
+
+        let finished: Vec<&BracketMatch> = self
+            .rounds
+            .iter()
+            .flatten()
+            .filter(|bracket_match| bracket_match.finished)
+            .collect();
+
+        for bracket_match in &finished {
+            for player_id in [bracket_match.player1, bracket_match.player2] {
+                let points = match bracket_match.winner {
+                    Some(winner) if winner == player_id => 2,
+                    Some(_) => 0,
+                    None => 1,
+                };
+                if let Some(index) = index_of(player_id) {
+                    standings[index].score_halves += points;
+                }
+            }
+        }
+
+        let scores: Vec<u32> = standings.iter().map(|s| s.score_halves).collect();
+        for bracket_match in &finished {
+            if let (Some(first), Some(second)) = (
+                index_of(bracket_match.player1),
+                index_of(bracket_match.player2),
+            ) {
+                standings[first].buchholz_halves += scores[second];
+                standings[second].buchholz_halves += scores[first];
+            }
+        }
+
+        // Stable sort keeps seed order as the final tiebreak
+        standings.sort_by(|a, b| {
+            b.score_halves
+                .cmp(&a.score_halves)
+                .then(b.buchholz_halves.cmp(&a.buchholz_halves))
+        });
+        standings
+    }
+}
+
+/// Pair a Swiss round from the current standings (best first).
+///
+/// With an odd field the lowest-ranked player without a bye sits out. The rest are
+/// paired top-down with the closest-ranked opponent they haven't met, backtracking
+/// when a choice would leave someone without a fresh opponent. Rematches are only
+/// allowed when no rematch-free pairing exists.
+pub fn swiss_pairings(standings: &[Standing], played: &HashSet<(Uuid, Uuid)>) -> RoundPairings {
+    let mut remaining: Vec<Uuid> = standings.iter().map(|s| s.player_id).collect();
+
+    let bye = if remaining.len() % 2 == 1 {
+        let index = standings
+            .iter()
+            .rposition(|standing| !standing.had_bye)
+            .unwrap_or(standings.len() - 1);
+        Some(remaining.remove(index))
+    } else {
+        None
+    };
+
+    let mut budget = PAIRING_SEARCH_BUDGET;
+    let pairings = pair_without_rematches(&remaining, played, &mut budget)
+        .unwrap_or_else(|| remaining.chunks(2).map(|pair| (pair[0], pair[1])).collect());
+
+    RoundPairings { pairings, bye }
+}
+
+fn pair_without_rematches(
+    players: &[Uuid],
+    played: &HashSet<(Uuid, Uuid)>,
+    budget: &mut usize,
+) -> Option<Vec<(Uuid, Uuid)>> {
+    let Some((&first, rest)) = players.split_first() else {
+        return Some(Vec::new());
+    };
+
+    for (index, &opponent) in rest.iter().enumerate() {
+        if played.contains(&(first, opponent)) {
+            continue;
+        }
+
+        // Give up on pathological fields rather than search forever
+        if *budget == 0 {
+            return None;
+        }
+        *budget -= 1;
+
+        let mut others = rest.to_vec();
+        others.remove(index);
+        if let Some(mut pairings) = pair_without_rematches(&others, played, budget) {
+            pairings.insert(0, (first, opponent));
+            return Some(pairings);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::glub_server_bench::splitmix64;
+
+    fn swiss(size: usize, rounds: usize) -> Tournament {
+        let mut tournament = Tournament::new(
+            "swiss".to_string(),
+            size,
+            Strictness::default(),
+            TournamentFormat::Swiss { rounds },
+        )
+        .unwrap();
+        for seed in 0..size {
+            tournament
+                .register(TournamentPlayer {
+                    id: Uuid::new_v4(),
+                    name: format!("player {seed}"),
+                })
+                .unwrap();
+        }
+        tournament
+    }
+
+    fn standing(player_id: Uuid, score_halves: u32, had_bye: bool) -> Standing {
+        Standing {
+            player_id,
+            score_halves,
+            buchholz_halves: 0,
+            had_bye,
+        }
+    }
+
+    #[test]
+    fn swiss_rounds_pair_everyone_once_without_rematches() {
+        let mut state = 7;
+        for size in 2..=20 {
+            let rounds = (size / 2).max(1);
+            let mut tournament = swiss(size, rounds);
+            let mut pairings = Some(tournament.first_round());
+
+            while let Some(round) = pairings {
+                let mut seen: Vec<Uuid> = round
+                    .pairings
+                    .iter()
+                    .flat_map(|&(first, second)| [first, second])
+                    .chain(round.bye)
+                    .collect();
+                seen.sort();
+                seen.dedup();
+                assert_eq!(seen.len(), size, "everyone plays or sits out once");
+                assert_eq!(round.bye.is_some(), size % 2 == 1);
+                if let Some(bye) = round.bye {
+                    assert!(!tournament.byes.contains(&Some(bye)), "a second bye");
+                }
+                let played = tournament.played_pairs();
+                for pair in &round.pairings {
+                    assert!(!played.contains(pair), "a rematch in a field of {size}");
+                }
+
+                tournament.byes.push(round.bye);
+                tournament.rounds.push(
+                    round
+                        .pairings
+                        .iter()
+                        .map(|&(player1, player2)| BracketMatch {
+                            player1,
+                            player2,
+                            game_id: Uuid::new_v4(),
+                            winner: None,
+                            finished: false,
+                        })
+                        .collect(),
+                );
+                // The next round waits for every game of this one
+                assert!(tournament.next_round().is_none());
+                for bracket_match in tournament.rounds.last_mut().unwrap() {
+                    state = splitmix64(state);
+                    bracket_match.winner = match state % 3 {
+                        0 => Some(bracket_match.player1),
+                        1 => Some(bracket_match.player2),
+                        _ => None,
+                    };
+                    bracket_match.finished = true;
+                }
+                pairings = tournament.next_round();
+            }
+
+            assert_eq!(tournament.rounds.len(), rounds);
+            assert!(tournament.decide_champion().is_some());
+        }
+    }
+
+    #[test]
+    fn players_meet_others_on_the_same_score() {
+        let ids: Vec<Uuid> = (0..6).map(|_| Uuid::new_v4()).collect();
+        let standings: Vec<Standing> = ids
+            .iter()
+            .zip([4, 4, 2, 2, 0, 0])
+            .map(|(&id, score)| standing(id, score, false))
+            .collect();
+
+        let round = swiss_pairings(&standings, &HashSet::new());
+        assert_eq!(
+            round.pairings,
+            vec![(ids[0], ids[1]), (ids[2], ids[3]), (ids[4], ids[5])]
+        );
+        assert_eq!(round.bye, None);
+
+        // Leaders who already met drop to the next closest opponent
+        let played = HashSet::from([(ids[0], ids[1]), (ids[1], ids[0])]);
+        let round = swiss_pairings(&standings, &played);
+        assert_eq!(
+            round.pairings,
+            vec![(ids[0], ids[2]), (ids[1], ids[3]), (ids[4], ids[5])]
+        );
+    }
+
+    #[test]
+    fn the_bye_goes_to_the_lowest_player_who_has_not_had_one() {
+        let ids: Vec<Uuid> = (0..5).map(|_| Uuid::new_v4()).collect();
+        let standings: Vec<Standing> = ids
+            .iter()
+            .zip([(4, false), (2, false), (2, false), (2, true), (0, true)])
+            .map(|(&id, (score, had_bye))| standing(id, score, had_bye))
+            .collect();
+
+        let round = swiss_pairings(&standings, &HashSet::new());
+        assert_eq!(round.bye, Some(ids[2]));
+        assert_eq!(round.pairings.len(), 2);
+    }
+
+    #[test]
+    fn standings_break_ties_on_buchholz() {
+        let mut tournament = swiss(4, 2);
+        let ids: Vec<Uuid> = tournament.players.iter().map(|player| player.id).collect();
+        let decided = |player1, player2, winner| BracketMatch {
+            player1,
+            player2,
+            game_id: Uuid::new_v4(),
+            winner: Some(winner),
+            finished: true,
+        };
+        // 0 beats 1, 2 beats 3, then 0 beats 2 and 3 beats 1
+        tournament.rounds = vec![
+            vec![
+                decided(ids[0], ids[1], ids[0]),
+                decided(ids[2], ids[3], ids[2]),
+            ],
+            vec![
+                decided(ids[0], ids[2], ids[0]),
+                decided(ids[3], ids[1], ids[3]),
+            ],
+        ];
+        tournament.byes = vec![None, None];
+
+        let order: Vec<Uuid> = tournament
+            .standings()
+            .iter()
+            .map(|standing| standing.player_id)
+            .collect();
+        // 2 and 3 both have a point, but 2 met the leader and 3 met the last player
+        assert_eq!(order, vec![ids[0], ids[2], ids[3], ids[1]]);
+        assert_eq!(tournament.decide_champion(), Some(ids[0]));
+    }
+
+    #[test]
+    fn a_four_player_bracket_plays_out_with_a_walkover() {
+        use crate::glub_server_clock::ManualClock;
+        use crate::glub_server_storage::GameStorage;
+        use std::sync::Arc;
+        use std::time::Duration;
+
+        let clock = Arc::new(ManualClock::new());
+        let mut storage = GameStorage::new().with_clock(clock.clone());
+        let tournament_id = storage
+            .create_tournament(
+                "club night".to_string(),
+                4,
+                Strictness::default(),
+                TournamentFormat::SingleElimination,
+            )
+            .unwrap();
+        let seeds: Vec<Uuid> = (0..4)
+            .map(|seed| {
+                storage
+                    .register_for_tournament(tournament_id, format!("seed {seed}"))
+                    .unwrap()
+                    .player_id
+            })
+            .collect();
+
+        let tournament = storage.get_tournament(tournament_id).unwrap();
+        assert_eq!(tournament.status(), TournamentStatus::InProgress);
+        let first_round: Vec<(Uuid, Uuid)> = tournament.rounds[0]
+            .iter()
+            .map(|m| (m.player1, m.player2))
+            .collect();
+        assert_eq!(
+            first_round,
+            vec![(seeds[0], seeds[3]), (seeds[1], seeds[2])]
+        );
+        let walkover_game = tournament.rounds[0][1].game_id;
+
+        // Seed 4 resigns; seed 3 never shows up and seed 2 wins by walkover
+        storage.quit(seeds[3]).unwrap();
+        for _ in 0..5 {
+            clock.advance(Duration::from_secs(20));
+            storage.record_presence(walkover_game, seeds[1]);
+            storage.check_presence();
+        }
+
+        let tournament = storage.get_tournament(tournament_id).unwrap();
+        assert_eq!(tournament.rounds[0][0].winner, Some(seeds[0]));
+        assert_eq!(tournament.rounds[0][1].winner, Some(seeds[1]));
+        let [final_match] = &tournament.rounds[1][..] else {
+            panic!("the winners meet in a single final");
+        };
+        assert_eq!(
+            (final_match.player1, final_match.player2),
+            (seeds[0], seeds[1])
+        );
+        assert_eq!(tournament.champion, None);
+
+        storage.quit(seeds[1]).unwrap();
+        let tournament = storage.get_tournament(tournament_id).unwrap();
+        assert_eq!(tournament.rounds.len(), 2);
+        assert_eq!(tournament.champion, Some(seeds[0]));
+        assert_eq!(tournament.status(), TournamentStatus::Finished);
+    }
+}
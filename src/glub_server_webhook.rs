@@ -0,0 +1,179 @@
+use crate::glub_server_storage::{GameEndReason, PlayerColor};
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Delay before the first retry, doubled after every failed attempt
+pub const WEBHOOK_INITIAL_BACKOFF_MILLIS: u64 = 500;
+
+/// What gets posted to the webhook when a game finishes
+#[derive(Serialize, Clone, Debug)]
+pub struct GameSummary {
+    pub game_id: Uuid,
+    pub players: Vec<SummaryPlayer>,
+    /// `None` for a draw
+    pub winner: Option<PlayerColor>,
+    pub reason: GameEndReason,
+    pub duration_seconds: u64,
+    pub moves: usize,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SummaryPlayer {
+    pub name: String,
+    pub color: PlayerColor,
+}
+
+pub type WebhookSender = mpsc::UnboundedSender<GameSummary>;
+
+/// Post every summary received to `url`, giving each attempt `timeout` and each
+/// summary `max_attempts` tries. Every delivery runs on its own task, so a slow
+/// endpoint never holds up later summaries or gameplay.
+pub async fn run_webhook(
+    url: String,
+    timeout: Duration,
+    max_attempts: u32,
+    mut summaries: mpsc::UnboundedReceiver<GameSummary>,
+) {
+    let client = match reqwest::Client::builder().timeout(timeout).build() {
+        Ok(client) => client,
+        Err(e) => {
+            warn!("Webhook disabled, could not build HTTP client: {}", e);
+            return;
+        }
+    };
+
+    while let Some(summary) = summaries.recv().await {
+        tokio::spawn(deliver(client.clone(), url.clone(), max_attempts, summary));
+    }
+}
+
+// Retry failed posts with exponential backoff, then give up
+async fn deliver(client: reqwest::Client, url: String, max_attempts: u32, summary: GameSummary) {
+    let mut backoff = Duration::from_millis(WEBHOOK_INITIAL_BACKOFF_MILLIS);
+
+    for attempt in 1..=max_attempts {
+        let error = match client.post(&url).json(&summary).send().await {
+            Ok(response) if response.status().is_success() => return,
+            Ok(response) => format!("status {}", response.status()),
+            Err(e) => e.to_string(),
+        };
+
+        if attempt == max_attempts {
+            warn!(
+                "Giving up on webhook for game {} after {} attempts: {}",
+                summary.game_id, attempt, error
+            );
+            return;
+        }
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Json, Router, extract::State, http::StatusCode, routing::post};
+    use serde_json::{Value, json};
+    use std::sync::{Arc, Mutex};
+    use tokio::time::Instant;
+
+    type Received = Arc<Mutex<Vec<(Instant, Value)>>>;
+
+    // A local endpoint that answers 500 to the first `failures` posts of each
+    // game and records every post it gets
+    async fn mock_endpoint(failures: usize) -> (String, Received) {
+        let received = Received::default();
+        let app = Router::new()
+            .route(
+                "/",
+                post(
+                    move |State(received): State<Received>, Json(body): Json<Value>| async move {
+                        let mut received = received.lock().unwrap();
+                        let earlier = received
+                            .iter()
+                            .filter(|(_, seen)| seen["game_id"] == body["game_id"])
+                            .count();
+                        received.push((Instant::now(), body));
+                        if earlier < failures {
+                            StatusCode::INTERNAL_SERVER_ERROR
+                        } else {
+                            StatusCode::OK
+                        }
+                    },
+                ),
+            )
+            .with_state(Arc::clone(&received));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (url, received)
+    }
+
+    fn summary() -> GameSummary {
+        GameSummary {
+            game_id: Uuid::new_v4(),
+            players: vec![
+                SummaryPlayer {
+                    name: "ann".to_string(),
+                    color: PlayerColor::White,
+                },
+                SummaryPlayer {
+                    name: "bob".to_string(),
+                    color: PlayerColor::Black,
+                },
+            ],
+            winner: Some(PlayerColor::White),
+            reason: GameEndReason::Abandoned,
+            duration_seconds: 42,
+            moves: 7,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_posts_are_retried_with_a_doubling_backoff() {
+        let (url, received) = mock_endpoint(2).await;
+        let (sender, summaries) = mpsc::unbounded_channel();
+        tokio::spawn(run_webhook(url, Duration::from_secs(5), 4, summaries));
+
+        let sent = summary();
+        sender.send(sent.clone()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while received.lock().unwrap().len() < 3 {
+            assert!(Instant::now() < deadline, "the summary was never delivered");
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        // Delivered on the third try, so nothing more follows
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 3);
+        for (_, body) in &received {
+            assert_eq!(body["game_id"], json!(sent.game_id));
+            assert_eq!(body["players"][1]["name"], "bob");
+            assert_eq!(body["winner"], "white");
+            assert_eq!(body["reason"], "abandoned");
+            assert_eq!(body["moves"], 7);
+        }
+        let first_wait = received[1].0 - received[0].0;
+        let second_wait = received[2].0 - received[1].0;
+        let backoff = Duration::from_millis(WEBHOOK_INITIAL_BACKOFF_MILLIS);
+        assert!(first_wait >= backoff, "{:?}", first_wait);
+        assert!(second_wait >= backoff * 2, "{:?}", second_wait);
+    }
+
+    #[tokio::test]
+    async fn delivery_gives_up_after_the_last_attempt() {
+        let (url, received) = mock_endpoint(usize::MAX).await;
+        let (sender, summaries) = mpsc::unbounded_channel();
+        tokio::spawn(run_webhook(url, Duration::from_secs(5), 2, summaries));
+
+        sender.send(summary()).unwrap();
+        tokio::time::sleep(Duration::from_millis(WEBHOOK_INITIAL_BACKOFF_MILLIS * 3)).await;
+        assert_eq!(received.lock().unwrap().len(), 2);
+    }
+}
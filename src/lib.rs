@@ -0,0 +1,3768 @@
+use axum::{
+    Extension, Json, Router,
+    extract::{DefaultBodyLimit, FromRef, Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tower_http::compression::CompressionLayer;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::timeout::TimeoutLayer;
+use uuid::Uuid;
+
+pub mod glub_server;
+pub mod glub_server_achievements;
+pub mod glub_server_analytics;
+pub mod glub_server_array_board;
+pub mod glub_server_bench;
+pub mod glub_server_bitboard;
+pub mod glub_server_changes;
+pub mod glub_server_cli;
+pub mod glub_server_clock;
+pub mod glub_server_config;
+pub mod glub_server_loadtest;
+pub mod glub_server_logging;
+pub mod glub_server_metrics;
+pub mod glub_server_persistence;
+pub mod glub_server_repository;
+pub mod glub_server_seasons;
+pub mod glub_server_standings;
+pub mod glub_server_storage;
+#[cfg(feature = "test-util")]
+pub mod glub_server_test_server;
+pub mod glub_server_tournament;
+pub mod glub_server_webhook;
+
+use clap::Parser;
+use glub_server_cli::Cli;
+use glub_server_config::{BindAddress, Config};
+use glub_server_metrics::{LatencyHistogram, RouteMetrics, RouteStats, track_metrics};
+use glub_server_seasons::*;
+use glub_server_storage::*;
+use glub_server_tournament::*;
+use tracing::{info, warn};
+
+/// What every handler can reach: the storage, and the settings loaded at
+/// startup
+#[derive(Clone)]
+pub struct AppState {
+    pub storage: Arc<RwLock<GameStorage>>,
+    pub config: Arc<Config>,
+}
+
+impl FromRef<AppState> for Arc<RwLock<GameStorage>> {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.storage)
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        Arc::clone(&state.config)
+    }
+}
+
+/// Bumped whenever a change to the HTTP API would break existing clients
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// What this build supports, for clients probing capabilities
+fn server_features() -> Vec<&'static str> {
+    let mut features = vec![
+        "fog",
+        "walls",
+        "bots",
+        "practice",
+        "tournaments",
+        "seasons",
+        "spectator_codes",
+        "long_polling",
+        "import_export",
+    ];
+    if cfg!(feature = "sqlite") {
+        features.push("sqlite");
+    }
+    if cfg!(feature = "redis") {
+        features.push("redis");
+    }
+    features
+}
+
+/// Run the server as the command line asks: resolve the configuration, then
+/// serve until a shutdown signal, or run one of the one-off modes
+pub async fn run() {
+    let cli = Cli::parse();
+    let config = match cli.resolve() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let printed = toml::to_string_pretty(&config.redacted())
+        .unwrap_or_else(|e| format!("(could not print: {})", e));
+    if cli.print_config {
+        print!("{}", printed);
+        return;
+    }
+    // initialize tracing; the guard keeps the log file writer alive until exit
+    let _log_guard = match glub_server_logging::init_logging(&config) {
+        Ok(guard) => guard,
+        Err(e) => {
+            eprintln!("Cannot open log file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    info!("Configuration:\n{}", printed);
+    serve(Arc::new(config), shutdown_signal()).await;
+}
+
+/// Serve everything `config` asks for until `shutdown` resolves, then drain
+/// open requests and save what has to outlive the process, each step within
+/// the shutdown deadline
+pub async fn serve(config: Arc<Config>, shutdown: impl Future<Output = ()> + Send + 'static) {
+    // Create shared game storage
+    let mut storage = GameStorage::with_config(&config);
+    let mut persistence_writer = None;
+    if let Some(database_url) = &config.database_url {
+        (storage, persistence_writer) = enable_persistence(storage, database_url).await;
+    }
+
+    if let Some(redis_url) = &config.redis_url {
+        storage = enable_redis(storage, redis_url);
+    }
+    let mut archive_writer = None;
+    if let Some(dir) = &config.archive_dir {
+        match glub_server_persistence::ArchiveStore::open(dir) {
+            Ok((archive, queue)) => {
+                archive_writer = Some(tokio::spawn(archive.clone().run_writer(queue)));
+                storage = storage.with_archive(archive);
+            }
+            Err(e) => warn!("Finished games stay in memory, archive unavailable: {}", e),
+        }
+    }
+
+    // Announce finished games to an external service
+    if let Some(url) = &config.webhook_url {
+        let (webhook, summaries) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(glub_server_webhook::run_webhook(
+            url.clone(),
+            Duration::from_secs(config.webhook_timeout_seconds),
+            config.webhook_max_attempts,
+            summaries,
+        ));
+        storage = storage.with_webhook(webhook);
+    }
+
+    // Keep a machine-readable record of every finished game
+    if let Some(analytics) = glub_server_analytics::start_analytics(&config) {
+        storage = storage.with_analytics(analytics);
+    }
+
+    // Pick up where the last shutdown left off
+    let snapshot_path = &config.snapshot_path;
+    if let Some(path) = snapshot_path {
+        match glub_server_persistence::read_snapshot(path) {
+            Ok(restored) => {
+                info!(
+                    "Loaded snapshot with {} active games",
+                    restored.active_games.len()
+                );
+                storage.restore(restored);
+            }
+            Err(e) => warn!("Ignoring unreadable snapshot {}: {}", path.display(), e),
+        }
+    }
+    let active_game_count = storage.active_game_count();
+    let storage = Arc::new(RwLock::new(storage));
+
+    let shutdown_deadline = Duration::from_secs(config.shutdown_deadline_seconds);
+    let (stop_sender, stop) = tokio::sync::watch::channel(false);
+
+    // Start the move increment task
+    let tick_task = tokio::spawn(move_increment_task(
+        Arc::clone(&storage),
+        active_game_count,
+        config.tick(),
+        TickSchedule {
+            shards: config.tick_shards,
+            checkpoint_every: config.ticks_in(config.checkpoint_interval_seconds),
+            idle_every: (config.ticks_in(config.heartbeat_timeout_seconds) / 2).max(1),
+        },
+        stop.clone(),
+    ));
+
+    // On a shutdown signal, turn away new players and start draining
+    let shutdown_storage = Arc::clone(&storage);
+    tokio::spawn(async move {
+        shutdown.await;
+        info!("Shutting down, finishing open requests");
+        {
+            let mut storage = shutdown_storage.write().await;
+            storage.set_maintenance(true);
+            storage.changes().shut_down();
+        }
+        let _ = stop_sender.send(true);
+    });
+
+    // build our application with routes
+    let app = build_router(
+        Arc::clone(&storage),
+        Arc::clone(&config),
+        RouteMetrics::default(),
+    );
+
+    // run our app with hyper on every configured address, all sharing one router
+    let mut servers = tokio::task::JoinSet::new();
+    let mut socket_paths: Vec<std::path::PathBuf> = Vec::new();
+    for address in &config.bind {
+        let listener = match bind_listener(address, &config) {
+            Ok(listener) => listener,
+            Err(e) if config.best_effort_bind => {
+                warn!("Skipping {}: {}", address, e);
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Cannot listen on {}: {}", address, e);
+                std::process::exit(1);
+            }
+        };
+        let shutdown = stopped(stop.clone());
+        match listener {
+            Listener::Tcp(listener) => {
+                let local = listener
+                    .local_addr()
+                    .map_or(address.to_string(), |a| a.to_string());
+                info!("Chess server running on http://{}", local);
+                let server = axum::serve(listener, app.clone()).with_graceful_shutdown(shutdown);
+                servers.spawn(server.into_future());
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, path) => {
+                info!("Chess server running on {}", address);
+                let server = axum::serve(listener, app.clone()).with_graceful_shutdown(shutdown);
+                servers.spawn(server.into_future());
+                socket_paths.push(path);
+            }
+        }
+    }
+    if servers.is_empty() {
+        eprintln!("Could not listen on any address");
+        std::process::exit(1);
+    }
+
+    // Every listener stops taking connections on shutdown, then drains
+    let drained = async {
+        while let Some(result) = servers.join_next().await {
+            if let Ok(Err(e)) = result {
+                warn!("Listener failed: {}", e);
+            }
+        }
+    };
+    let deadline = async {
+        stopped(stop.clone()).await;
+        tokio::time::sleep(shutdown_deadline).await;
+    };
+    tokio::select! {
+        _ = drained => {}
+        _ = deadline => warn!("Shutdown deadline passed, dropping open requests"),
+    }
+    for path in socket_paths {
+        let _ = std::fs::remove_file(path);
+    }
+    let _ = tick_task.await;
+
+    {
+        let mut storage = storage.write().await;
+        storage.flush();
+        storage.checkpoint();
+        storage.stop_persistence();
+    }
+    // The writers exit once everything queued so far is written
+    if let Some(writer) = persistence_writer
+        && tokio::time::timeout(shutdown_deadline, writer)
+            .await
+            .is_err()
+    {
+        warn!("Persistence writer did not finish in time");
+    }
+    if let Some(writer) = archive_writer
+        && tokio::time::timeout(shutdown_deadline, writer)
+            .await
+            .is_err()
+    {
+        warn!("Archive writer did not finish in time");
+    }
+
+    if let Some(path) = snapshot_path {
+        let snapshot = storage.read().await.snapshot();
+        match glub_server_persistence::write_snapshot(path, &snapshot) {
+            Ok(()) => info!(
+                "Saved {} active games to {}",
+                snapshot.games.len(),
+                path.display()
+            ),
+            Err(e) => warn!("Failed to save snapshot: {}", e),
+        }
+    }
+}
+
+/// A bare server on a free local port for load testing: in-memory storage, the
+/// tick task and the router, without persistence or logging. Returns its base
+/// URL and the sender that stops its tick task.
+pub async fn serve_in_process(
+    config: Arc<Config>,
+) -> std::io::Result<(String, tokio::sync::watch::Sender<bool>)> {
+    let storage = GameStorage::with_config(&config);
+    let active_game_count = storage.active_game_count();
+    let storage = Arc::new(RwLock::new(storage));
+    let (stop_sender, stop) = tokio::sync::watch::channel(false);
+
+    tokio::spawn(move_increment_task(
+        Arc::clone(&storage),
+        active_game_count,
+        config.tick(),
+        TickSchedule {
+            shards: config.tick_shards,
+            checkpoint_every: config.ticks_in(config.checkpoint_interval_seconds),
+            idle_every: (config.ticks_in(config.heartbeat_timeout_seconds) / 2).max(1),
+        },
+        stop,
+    ));
+
+    let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0)).await?;
+    let base_url = format!("http://{}", listener.local_addr()?);
+    let app = build_router(storage, config, RouteMetrics::default());
+    tokio::spawn(axum::serve(listener, app).into_future());
+
+    Ok((base_url, stop_sender))
+}
+
+enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, std::path::PathBuf),
+}
+
+// Bind one configured address
+fn bind_listener(address: &BindAddress, config: &Config) -> std::io::Result<Listener> {
+    if let Some(socket_addr) = address.socket_addr(config.port) {
+        // IPv6 sockets are made IPv6-only, so `[::]` can sit beside `0.0.0.0` on
+        // the same port
+        let socket = match socket_addr {
+            std::net::SocketAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+            std::net::SocketAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+        };
+        #[cfg(unix)]
+        socket.set_reuseaddr(true)?;
+        if socket_addr.is_ipv6() {
+            socket2::SockRef::from(&socket).set_only_v6(true)?;
+        }
+        socket.bind(socket_addr)?;
+        return Ok(Listener::Tcp(socket.listen(1024)?));
+    }
+
+    match address {
+        #[cfg(unix)]
+        BindAddress::Unix(path) => Ok(Listener::Unix(
+            bind_unix_socket(path, config.socket_mode)?,
+            path.clone(),
+        )),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "Unix sockets are not supported on this platform",
+        )),
+    }
+}
+
+// Listen on a Unix socket, replacing one left behind by an earlier run. Anything
+// at the path that isn't a socket is left alone.
+#[cfg(unix)]
+fn bind_unix_socket(
+    path: &std::path::Path,
+    mode: u32,
+) -> std::io::Result<tokio::net::UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "path exists and is not a socket",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(listener)
+}
+
+// Resolves once a shutdown has been requested
+async fn stopped(mut stop: tokio::sync::watch::Receiver<bool>) {
+    let _ = stop.wait_for(|stopping| *stopping).await;
+}
+
+// Resolves on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+// Restore saved state from SQLite and write durable changes back to it
+#[cfg(feature = "sqlite")]
+async fn enable_persistence(
+    mut storage: GameStorage,
+    database_url: &str,
+) -> (GameStorage, Option<tokio::task::JoinHandle<()>>) {
+    let store = glub_server_persistence::SqliteStore::connect(database_url)
+        .await
+        .expect("failed to open database");
+    let restored = store.load().await.expect("failed to load saved state");
+    info!(
+        "Restored {} accounts and {} active games",
+        restored.accounts.len(),
+        restored.active_games.len()
+    );
+    storage.restore(restored);
+
+    let (persist, events) = tokio::sync::mpsc::unbounded_channel();
+    let writer = tokio::spawn(store.run_writer(events));
+    (storage.with_persistence(persist), Some(writer))
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn enable_persistence(
+    storage: GameStorage,
+    _database_url: &str,
+) -> (GameStorage, Option<tokio::task::JoinHandle<()>>) {
+    warn!("CHEST_DATABASE_URL is set but the server was built without the sqlite feature");
+    (storage, None)
+}
+
+// Keep games and the queue in Redis, falling back to memory if it can't be reached
+#[cfg(feature = "redis")]
+fn enable_redis(storage: GameStorage, redis_url: &str) -> GameStorage {
+    use glub_server_repository::GameRepository;
+
+    let storage = match glub_server_repository::RedisRepository::connect(redis_url) {
+        Ok(repository) => {
+            info!("Loaded {} games from Redis", repository.games().count());
+            storage.with_repository(Box::new(repository))
+        }
+        Err(e) => {
+            warn!("Redis unreachable, keeping games in memory: {}", e);
+            return storage;
+        }
+    };
+
+    match glub_server_changes::ChangeBus::new().with_redis(redis_url) {
+        Ok(changes) => storage.with_changes(changes),
+        Err(e) => {
+            warn!(
+                "Redis pub/sub unavailable, changes stay on this instance: {}",
+                e
+            );
+            storage
+        }
+    }
+}
+
+#[cfg(not(feature = "redis"))]
+fn enable_redis(storage: GameStorage, _redis_url: &str) -> GameStorage {
+    warn!("CHEST_REDIS_URL is set but the server was built without the redis feature");
+    storage
+}
+
+// All routes with their body limits applied. Oversized bodies get 413 and
+// requests that run too long get 408. Responses are compressed for clients
+// that send Accept-Encoding unless compression is turned off.
+fn build_router(
+    storage: Arc<RwLock<GameStorage>>,
+    config: Arc<Config>,
+    metrics: RouteMetrics,
+) -> Router {
+    let compress_responses = config.compress_responses;
+    let import_routes = Router::new()
+        .route("/import", post(import_game))
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(config.import_body_limit_bytes));
+
+    let routes = Router::new()
+        .route("/", get(root))
+        .route("/health", get(health))
+        .route("/health/live", get(liveness))
+        .route("/health/ready", get(readiness))
+        .route("/version", get(version))
+        .route("/join_queue", post(join_queue))
+        .route("/queue/status", get(get_queue_status))
+        .route("/game/{game_id}/board/{player_id}", get(get_board))
+        .route("/game/{game_id}/spectate", get(get_spectator_board))
+        .route("/game/{game_id}/spectator_code", get(get_spectator_code))
+        .route("/spectate", get(list_spectatable_games))
+        .route("/spectate/{code}", get(spectate_by_code))
+        .route("/game/{game_id}/move", post(make_move))
+        .route("/game/{game_id}/ready", post(mark_ready))
+        .route("/game/{game_id}/placement", post(submit_placement))
+        .route("/game/{game_id}/powerup/reveal", post(buy_reveal))
+        .route("/game/{game_id}/draw", post(respond_to_draw))
+        .route(
+            "/game/{game_id}/legal_moves/{player_id}",
+            get(get_legal_moves),
+        )
+        .route("/game/{game_i}/status", get(get_game_status))
+        .route("/statuses", post(get_game_statuses))
+        .route("/game/{game_id}/events", get(get_game_events))
+        .route("/game/{game_id}/wait", get(wait_for_change))
+        .route("/game/{game_id}/export", get(export_game))
+        .route("/game/{game_id}/start_board", get(get_start_board))
+        .route("/game/{game_id}/history", get(get_move_history))
+        .route("/accounts/{player_name}/stats", get(get_player_stats))
+        .route("/accounts/{player_name}/seasons", get(get_player_seasons))
+        .route("/seasons/{season}/leaderboard", get(get_season_leaderboard))
+        .route("/standings", get(get_standings))
+        .route("/admin/seasons/rollover", post(rollover_season))
+        .route("/admin/metrics", get(get_route_metrics))
+        .route("/admin/diagnostics", get(get_diagnostics))
+        .route("/admin/pause", post(pause_server))
+        .route("/admin/resume", post(resume_server))
+        .route("/admin/game/{game_id}/verify", get(verify_game))
+        .route("/admin/game/{game_id}/grid", get(get_piece_grid))
+        .route("/admin/game/{game_id}/fog/{player_id}", get(explain_fog))
+        .route("/admin/game/from_board", post(seed_game))
+        .route("/admin/game/{game_id}/reassign", post(reassign_player))
+        .route("/players/{player_id}/current_game", get(get_current_game))
+        .route("/lobbies", post(create_lobby))
+        .route("/lobbies/{code}", get(get_lobby))
+        .route("/lobbies/{code}/join", post(join_lobby))
+        .route("/players/{player_id}/quit", post(quit))
+        .route("/tournaments", post(create_tournament))
+        .route("/tournaments/{tournament_id}", get(get_tournament))
+        .route(
+            "/tournaments/{tournament_id}/register",
+            post(register_for_tournament),
+        );
+    #[cfg(test)]
+    let routes = routes.route("/test/stall", post(stall_holding_lock));
+
+    let router = routes
+        .layer(RequestBodyLimitLayer::new(config.body_limit_bytes))
+        .merge(import_routes)
+        // Inside the metrics layer, so timed out requests are still counted
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            Duration::from_secs(config.request_timeout_seconds),
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            metrics.clone(),
+            track_metrics,
+        ))
+        .layer(Extension(metrics))
+        .with_state(AppState { storage, config });
+
+    if compress_responses {
+        router.layer(CompressionLayer::new().gzip(true).br(true))
+    } else {
+        router
+    }
+}
+
+// Never answers and keeps the write lock until dropped, standing in for a
+// handler that wedges
+#[cfg(test)]
+async fn stall_holding_lock(State(storage): State<Arc<RwLock<GameStorage>>>) -> StatusCode {
+    let _storage = storage.write().await;
+    std::future::pending::<()>().await;
+    StatusCode::OK
+}
+
+fn validate_player_name(config: &Config, player_name: &str) -> Result<(), StatusCode> {
+    if player_name.chars().count() > config.max_player_name_len {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+// Coordinates off even the largest board are turned away before any lock is
+// taken; the game's own board checks the rest
+fn validate_square((row, col): (usize, usize)) -> Result<(), StatusCode> {
+    if row >= glub_server::MAX_BOARD_SIZE || col >= glub_server::MAX_BOARD_SIZE {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(())
+}
+
+// Reject requests that don't carry the configured admin token
+fn require_admin(config: &Config, headers: &HeaderMap) -> Result<(), StatusCode> {
+    let Some(expected) = &config.admin_token else {
+        return Err(StatusCode::FORBIDDEN);
+    };
+
+    match headers.get("x-admin-token").and_then(|v| v.to_str().ok()) {
+        Some(token) if token == expected => Ok(()),
+        _ => Err(StatusCode::FORBIDDEN),
+    }
+}
+
+// basic handler that responds with a static string
+async fn root() -> &'static str {
+    "Chess Server with Fog of War - Ready!"
+}
+
+// Join the matchmaking queue
+async fn join_queue(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Json(payload): Json<JoinQueueRequest>,
+) -> Result<Json<JoinQueueResponse>, Response> {
+    validate_player_name(&config, &payload.player_name).map_err(IntoResponse::into_response)?;
+
+    // No new games while the server drains for shutdown
+    let mut storage = storage.write().await;
+    if storage.in_maintenance() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    }
+
+    // Checked under the write lock, so concurrent joins can't overshoot the cap
+    let occupancy = storage.occupancy();
+    if occupancy.is_full() {
+        let body = ServerFullResponse {
+            error: "server_full".to_string(),
+            occupancy,
+        };
+        return Err((StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response());
+    }
+
+    let result = if payload.solo && payload.bot {
+        return Err(StatusCode::BAD_REQUEST.into_response());
+    } else if payload.solo {
+        storage.start_solo_game(
+            payload.player_name,
+            payload.fog.unwrap_or(true),
+            payload.mode,
+        )
+    } else if payload.bot {
+        storage.start_bot_game(
+            payload.player_name,
+            payload.fog.unwrap_or(true),
+            payload.mode,
+        )
+    } else {
+        storage.join_queue(payload.player_name, payload.mode)
+    };
+
+    match result {
+        Ok(response) => Ok(Json(response)),
+        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR.into_response()),
+    }
+}
+
+// Report load for health checks; 503 while draining for shutdown
+async fn health(State(storage): State<Arc<RwLock<GameStorage>>>) -> (StatusCode, Json<Health>) {
+    let storage = storage.read().await;
+    let occupancy = storage.occupancy();
+
+    let (code, status) = if storage.in_maintenance() {
+        (StatusCode::SERVICE_UNAVAILABLE, "draining")
+    } else if storage.is_paused() {
+        (StatusCode::OK, "paused")
+    } else if occupancy.is_full() {
+        (StatusCode::OK, "full")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+
+    (
+        code,
+        Json(Health {
+            status: status.to_string(),
+            utilization: occupancy.utilization(),
+            occupancy,
+        }),
+    )
+}
+
+// The process is up and serving requests
+async fn liveness() -> StatusCode {
+    StatusCode::OK
+}
+
+// Whether this instance should get traffic; 503 naming the failing component
+// otherwise
+async fn readiness(State(storage): State<Arc<RwLock<GameStorage>>>) -> Response {
+    let mut storage = storage.write().await;
+    match storage.readiness() {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(not_ready) => (StatusCode::SERVICE_UNAVAILABLE, Json(not_ready)).into_response(),
+    }
+}
+
+// Crate and protocol version, and the features compiled in
+async fn version() -> Json<VersionInfo> {
+    Json(VersionInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        protocol_version: PROTOCOL_VERSION,
+        features: server_features(),
+    })
+}
+
+// Current queue size and expected wait
+async fn get_queue_status(State(storage): State<Arc<RwLock<GameStorage>>>) -> Json<QueueStatus> {
+    let storage = storage.read().await;
+    Json(storage.get_queue_status())
+}
+
+// Get board state with fog of war applied
+async fn get_board(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path((game_id, player_id)): Path<(String, String)>,
+    Query(query): Query<BoardQuery>,
+) -> Result<Response, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+    let player_id =
+        Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    // Only the snapshot is taken under the lock; serializing happens after.
+    // Polls that find the cached board current are served under the read lock,
+    // unless they bring back a player the opponent was told had gone quiet.
+    let cached = {
+        let storage = storage.read().await;
+        if storage.note_presence(game_id, player_id) {
+            None
+        } else {
+            storage.cached_fogged_board(game_id, player_id)
+        }
+    };
+    let board = if let Some(board) = cached {
+        Ok(board)
+    } else {
+        let mut storage = storage.write().await;
+        storage.record_presence(game_id, player_id);
+        let board = storage.get_fogged_board(game_id, player_id);
+        if board.is_err() && storage.was_removed(game_id, player_id) {
+            return Err(removed_from_game());
+        }
+        board
+    };
+
+    match board {
+        Ok(board) => Ok(match query.format {
+            BoardFormat::Grid => Json(board.as_ref()).into_response(),
+            BoardFormat::Squares => Json(FoggedSquares {
+                squares: occupied_squares(&board.slots),
+                dims: board.dims,
+                your_color: board.your_color,
+                walls: board.walls.clone(),
+                view_hash: board.view_hash.clone(),
+                opponent_last_active: board.opponent_last_active,
+            })
+            .into_response(),
+        }),
+        Err(_) => Err(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+// Get the board as a spectator sees it
+async fn get_spectator_board(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Query(query): Query<BoardQuery>,
+) -> Result<Response, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let board = storage.read().await.get_spectator_board(game_id);
+
+    match board {
+        Ok(board) => Ok(spectator_board_response(board, query.format)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+fn spectator_board_response(board: SpectatorBoard, format: BoardFormat) -> Response {
+    match format {
+        BoardFormat::Grid => Json(board).into_response(),
+        BoardFormat::Squares => Json(SpectatorSquares {
+            squares: occupied_squares(&board.slots),
+            fogged: board.fogged,
+            walls: board.walls,
+        })
+        .into_response(),
+    }
+}
+
+// 410 for a player whose seat was given to someone else, so a client holding
+// on to an old id can tell that apart from a mistyped one
+fn removed_from_game() -> Response {
+    let body = RemovedFromGameResponse {
+        error: "removed_from_game".to_string(),
+        message: "You are no longer part of this game".to_string(),
+    };
+    (StatusCode::GONE, Json(body)).into_response()
+}
+
+// Get the short code spectators can use to find a game
+async fn get_spectator_code(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+) -> Result<Json<SpectatorCodeResponse>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let mut storage = storage.write().await;
+
+    match storage.spectator_code(game_id) {
+        Ok(code) => Ok(Json(code)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// List games in progress with their spectator codes
+async fn list_spectatable_games(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+) -> Json<Vec<SpectatableGame>> {
+    let storage = storage.read().await;
+    Json(storage.spectatable_games())
+}
+
+// Watch a game by its spectator code
+async fn spectate_by_code(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(code): Path<String>,
+    Query(query): Query<BoardQuery>,
+) -> Result<Response, StatusCode> {
+    let board = storage.read().await.spectate_by_code(&code);
+
+    match board {
+        Ok(board) => Ok(spectator_board_response(board, query.format)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// List the moves a player can make on the current board
+async fn get_legal_moves(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path((game_id, player_id)): Path<(String, String)>,
+) -> Result<Json<LegalMovesResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+    let player_id =
+        Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    let mut storage = storage.write().await;
+    storage.record_presence(game_id, player_id);
+
+    match storage.get_legal_moves(game_id, player_id) {
+        Ok(moves) => Ok(Json(moves)),
+        Err(_) if storage.was_removed(game_id, player_id) => Err(removed_from_game()),
+        Err(_) => Err(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+// Make a move
+async fn make_move(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Json(payload): Json<MoveRequest>,
+) -> Result<Json<MoveResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+    validate_square(payload.from).map_err(IntoResponse::into_response)?;
+    validate_square(payload.to).map_err(IntoResponse::into_response)?;
+
+    let mut storage = storage.write().await;
+
+    if storage.is_paused() {
+        let body = ServerPausedResponse {
+            error: "server_paused".to_string(),
+        };
+        return Err((StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response());
+    }
+
+    let player_id = payload.player_id;
+    match storage.make_move(game_id, payload) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) if storage.was_removed(game_id, player_id) => Err(removed_from_game()),
+        Err(err) => {
+            info!("Move error: {:?}", err);
+            Err(StatusCode::BAD_REQUEST.into_response())
+        }
+    }
+}
+
+// Offer, accept or decline a draw
+async fn respond_to_draw(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Json(payload): Json<DrawOfferRequest>,
+) -> Result<Json<DrawOfferResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    let mut storage = storage.write().await;
+    storage.record_presence(game_id, payload.player_id);
+
+    match storage.respond_to_draw(game_id, payload.player_id, payload.action) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) if storage.was_removed(game_id, payload.player_id) => Err(removed_from_game()),
+        Err(_) => Err(StatusCode::BAD_REQUEST.into_response()),
+    }
+}
+
+// Signal that the player is ready to start
+async fn mark_ready(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Json(payload): Json<ReadyRequest>,
+) -> Result<Json<ReadyResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    let mut storage = storage.write().await;
+    storage.record_presence(game_id, payload.player_id);
+
+    match storage.mark_ready(game_id, payload.player_id) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) if storage.was_removed(game_id, payload.player_id) => Err(removed_from_game()),
+        Err(_) => Err(StatusCode::BAD_REQUEST.into_response()),
+    }
+}
+
+// Arrange your home ranks during the placement phase
+async fn submit_placement(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Json(payload): Json<PlacementRequest>,
+) -> Result<Json<PlacementResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    let mut storage = storage.write().await;
+    storage.record_presence(game_id, payload.player_id);
+
+    match storage.submit_placement(game_id, payload.player_id, payload.pieces) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) if storage.was_removed(game_id, payload.player_id) => Err(removed_from_game()),
+        Err(_) => Err(StatusCode::BAD_REQUEST.into_response()),
+    }
+}
+
+// Spend move points to see the whole board for a few seconds
+async fn buy_reveal(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Json(payload): Json<RevealRequest>,
+) -> Result<Json<RevealResponse>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    let mut storage = storage.write().await;
+    storage.record_presence(game_id, payload.player_id);
+
+    match storage.buy_reveal(game_id, payload.player_id) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) if storage.was_removed(game_id, payload.player_id) => Err(removed_from_game()),
+        Err(_) => Err(StatusCode::BAD_REQUEST.into_response()),
+    }
+}
+
+// Open a private lobby, optionally from a custom starting position or with a
+// handicap
+async fn create_lobby(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Json(payload): Json<CreateLobbyRequest>,
+) -> Result<Json<LobbyCreated>, StatusCode> {
+    validate_player_name(&config, &payload.player_name)?;
+
+    let mut storage = storage.write().await;
+    if storage.in_maintenance() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    match storage.create_lobby(
+        payload.player_name,
+        payload.position,
+        payload.handicap,
+        payload.ranked,
+        payload.mode,
+    ) {
+        Ok(created) => Ok(Json(created)),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Look at a lobby before joining it; the host polls it for the game id
+async fn get_lobby(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(code): Path<String>,
+) -> Result<Json<LobbyInfo>, StatusCode> {
+    let storage = storage.read().await;
+
+    match storage.get_lobby(&code) {
+        Ok(lobby) => Ok(Json(lobby)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Join a private lobby by its code, starting the game
+async fn join_lobby(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(code): Path<String>,
+    Json(payload): Json<JoinLobbyRequest>,
+) -> Result<Json<JoinQueueResponse>, Response> {
+    validate_player_name(&config, &payload.player_name).map_err(IntoResponse::into_response)?;
+
+    let mut storage = storage.write().await;
+    if storage.in_maintenance() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE.into_response());
+    }
+
+    // A full server is no reason to tell the guest the lobby is gone
+    let occupancy = storage.occupancy();
+    if occupancy.is_full() {
+        let body = ServerFullResponse {
+            error: "server_full".to_string(),
+            occupancy,
+        };
+        return Err((StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response());
+    }
+
+    match storage.join_lobby(&code, payload.player_name) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) => Err(StatusCode::NOT_FOUND.into_response()),
+    }
+}
+
+// Get game status
+async fn get_game_status(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Query(query): Query<StatusQuery>,
+) -> Result<Json<GameStatus>, Response> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST.into_response())?;
+
+    // Status polls only need the read lock, unless they bring back a player
+    // the opponent was told had gone quiet
+    if let Some(player_id) = query.player_id
+        && storage.read().await.note_presence(game_id, player_id)
+    {
+        storage.write().await.record_presence(game_id, player_id);
+    }
+
+    let archive = {
+        let storage = storage.read().await;
+        match storage.get_game_status(game_id, query.player_id) {
+            Ok(status) => return Ok(Json(status)),
+            Err(_)
+                if query
+                    .player_id
+                    .is_some_and(|player_id| storage.was_removed(game_id, player_id)) =>
+            {
+                return Err(removed_from_game());
+            }
+            Err(_) if holds_game(&storage, game_id) => {
+                return Err(StatusCode::NOT_FOUND.into_response());
+            }
+            Err(_) => storage.archive(),
+        }
+    };
+
+    read_archived(archive, game_id)
+        .await
+        .and_then(|archived| archived.status(query.player_id))
+        .map(Json)
+        .map_err(|_| StatusCode::NOT_FOUND.into_response())
+}
+
+fn holds_game(storage: &GameStorage, game_id: Uuid) -> bool {
+    storage.with_game(game_id, |_| ()).is_some()
+}
+
+// Look up a game evicted from memory. Reads the disk, so it runs on the
+// blocking pool after the caller has let go of the storage lock.
+async fn read_archived(
+    archive: Option<glub_server_persistence::ArchiveStore>,
+    game_id: Uuid,
+) -> Result<GameArchive, String> {
+    let archive = archive.ok_or("Game not found")?;
+    tokio::task::spawn_blocking(move || archive.read(game_id))
+        .await
+        .map_err(|e| e.to_string())??
+        .ok_or_else(|| "Game not found".to_string())
+}
+
+// Answer from the game in memory, or when storage no longer holds it, from
+// its archive
+async fn from_memory_or_archive<T>(
+    storage: &RwLock<GameStorage>,
+    game_id: Uuid,
+    in_memory: impl FnOnce(&GameStorage) -> Result<T, String>,
+    archived: impl FnOnce(GameArchive) -> T,
+) -> Result<T, String> {
+    let archive = {
+        let storage = storage.read().await;
+        if holds_game(&storage, game_id) {
+            return in_memory(&storage);
+        }
+        storage.archive()
+    };
+
+    read_archived(archive, game_id).await.map(archived)
+}
+
+// Status of several games at once, for dashboards; unknown games get an error
+// entry instead of failing the whole request
+async fn get_game_statuses(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Json(payload): Json<BulkStatusRequest>,
+) -> Result<Json<BulkStatusResponse>, StatusCode> {
+    if payload.game_ids.len() > MAX_BULK_STATUS_GAMES {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Games no longer in memory are looked up in the archive after the lock is released
+    let (found, archive) = {
+        let storage = storage.read().await;
+        let found: Vec<(Uuid, Option<Result<GameStatus, String>>)> = payload
+            .game_ids
+            .into_iter()
+            .map(|game_id| {
+                let status = holds_game(&storage, game_id)
+                    .then(|| storage.get_game_status(game_id, payload.player_id));
+                (game_id, status)
+            })
+            .collect();
+        (found, storage.archive())
+    };
+
+    let mut statuses = Vec::with_capacity(found.len());
+    for (game_id, status) in found {
+        let status = match status {
+            Some(status) => status,
+            None => read_archived(archive.clone(), game_id)
+                .await
+                .and_then(|archived| archived.status(payload.player_id)),
+        };
+        statuses.push(match status {
+            Ok(status) => BulkStatusEntry {
+                game_id,
+                status: Some(status),
+                error: None,
+            },
+            Err(e) => BulkStatusEntry {
+                game_id,
+                status: None,
+                error: Some(e),
+            },
+        });
+    }
+
+    Ok(Json(BulkStatusResponse { statuses }))
+}
+
+// Long-poll until the game moves past `since_version` or ends, or the server
+// starts shutting down
+async fn wait_for_change(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Query(query): Query<WaitQuery>,
+) -> Result<Json<WaitResponse>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // Subscribe before the first look so a change in between isn't missed
+    let (mut changes, shutdown, instance) = {
+        let storage = storage.read().await;
+        (
+            storage.changes().subscribe(),
+            storage.changes().subscribe_shutdown(),
+            storage.changes().instance(),
+        )
+    };
+    let timeout = Duration::from_secs(
+        query
+            .timeout_seconds
+            .unwrap_or(config.max_wait_seconds)
+            .min(config.max_wait_seconds),
+    );
+    let deadline = tokio::time::sleep(timeout);
+    tokio::pin!(deadline);
+
+    loop {
+        let (board_version, result) = storage
+            .read()
+            .await
+            .with_game(game_id, |game_state| {
+                (game_state.version, game_state.result)
+            })
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let changed = board_version > query.since_version || result.is_some();
+        if changed {
+            return Ok(Json(WaitResponse {
+                board_version,
+                result,
+                changed,
+                shutting_down: false,
+            }));
+        }
+
+        tokio::select! {
+            _ = &mut deadline => {
+                return Ok(Json(WaitResponse {
+                    board_version,
+                    result,
+                    changed,
+                    shutting_down: false,
+                }));
+            }
+            _ = stopped(shutdown.clone()) => {
+                return Ok(Json(WaitResponse {
+                    board_version,
+                    result,
+                    changed,
+                    shutting_down: true,
+                }));
+            }
+            change = changes.recv() => match change {
+                // Another instance changed the game, so our copy is out of date
+                Ok(change) if change.game_id == game_id && change.origin != instance => {
+                    storage.write().await.refresh_game(game_id);
+                }
+                Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    return Err(StatusCode::SERVICE_UNAVAILABLE);
+                }
+            },
+        }
+    }
+}
+
+// Get the game's event log
+async fn get_game_events(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+) -> Result<Json<Vec<GameEvent>>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let storage = storage.read().await;
+
+    match storage.get_events(game_id) {
+        Ok(events) => Ok(Json(events)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Download a finished game as a self-contained archive
+async fn export_game(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+) -> Result<Json<GameArchive>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    from_memory_or_archive(
+        &storage,
+        game_id,
+        |storage| storage.export_game(game_id),
+        |archive| archive,
+    )
+    .await
+    .map(Json)
+    .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+// Get the position a game started from
+async fn get_start_board(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+) -> Result<Json<StartBoard>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    from_memory_or_archive(
+        &storage,
+        game_id,
+        |storage| storage.start_board(game_id),
+        GameArchive::start_board,
+    )
+    .await
+    .map(Json)
+    .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+// Get the moves played after the first `since`
+async fn get_move_history(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(game_id): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<MoveHistory>, StatusCode> {
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    from_memory_or_archive(
+        &storage,
+        game_id,
+        |storage| storage.move_history(game_id, query.since),
+        |archive| archive.move_history(query.since),
+    )
+    .await
+    .map(Json)
+    .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+// Replay a game's history and check it against the stored board (admin only)
+async fn verify_game(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Path(game_id): Path<String>,
+) -> Result<Json<VerifyGameResponse>, StatusCode> {
+    require_admin(&config, &headers)?;
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    from_memory_or_archive(
+        &storage,
+        game_id,
+        |storage| storage.verify_game(game_id),
+        GameArchive::verify,
+    )
+    .await
+    .map(Json)
+    .map_err(|_| StatusCode::NOT_FOUND)
+}
+
+// The full board as short piece codes such as "wR", for quick rendering (admin only)
+async fn get_piece_grid(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Path(game_id): Path<String>,
+) -> Result<Json<Vec<Vec<String>>>, StatusCode> {
+    require_admin(&config, &headers)?;
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let storage = storage.read().await;
+
+    match storage.piece_grid(game_id) {
+        Ok(grid) => Ok(Json(grid)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Why a player sees each square they see, for reproducing fog reports (admin only)
+async fn explain_fog(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Path((game_id, player_id)): Path<(String, String)>,
+) -> Result<Json<FogExplanation>, StatusCode> {
+    require_admin(&config, &headers)?;
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let player_id = Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let storage = storage.read().await;
+
+    match storage.explain_fog(game_id, player_id) {
+        Ok(explanation) => Ok(Json(explanation)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Give a player's seat to someone new (admin only)
+async fn reassign_player(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Path(game_id): Path<String>,
+    Json(payload): Json<ReassignRequest>,
+) -> Result<Json<ReassignResponse>, StatusCode> {
+    require_admin(&config, &headers)?;
+    let game_id = Uuid::parse_str(&game_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    validate_player_name(&config, &payload.player_name)?;
+
+    let mut storage = storage.write().await;
+
+    match storage.reassign_player(game_id, payload.color, payload.player_name) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Start a game from a board string, for reproducing reported positions (admin only)
+async fn seed_game(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Json(payload): Json<SeedGameRequest>,
+) -> Result<Json<SeededGame>, StatusCode> {
+    require_admin(&config, &headers)?;
+    validate_player_name(&config, &payload.white_name)?;
+    validate_player_name(&config, &payload.black_name)?;
+
+    let mut storage = storage.write().await;
+
+    match storage.seed_game(
+        &payload.board,
+        payload.white_name,
+        payload.black_name,
+        payload.rules,
+    ) {
+        Ok(seeded) => Ok(Json(seeded)),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Load an exported game into storage (admin only)
+async fn import_game(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Json(archive): Json<GameArchive>,
+) -> Result<Json<Uuid>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    // A game already archived can't be imported again
+    let archived = storage.read().await.archive();
+    if read_archived(archived, archive.game_id).await.is_ok() {
+        return Err(StatusCode::CONFLICT);
+    }
+
+    let mut storage = storage.write().await;
+
+    match storage.import_game(archive) {
+        Ok(game_id) => Ok(Json(game_id)),
+        Err(_) => Err(StatusCode::CONFLICT),
+    }
+}
+
+// Get an account's win/loss record and achievements
+async fn get_player_stats(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(player_name): Path<String>,
+) -> Result<Json<PlayerStats>, StatusCode> {
+    let storage = storage.read().await;
+
+    match storage.get_player_stats(&player_name) {
+        Ok(stats) => Ok(Json(stats)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// An account's results in each closed season
+async fn get_player_seasons(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(player_name): Path<String>,
+) -> Result<Json<Vec<PlayerSeason>>, StatusCode> {
+    let storage = storage.read().await;
+
+    match storage.get_player_seasons(&player_name) {
+        Ok(seasons) => Ok(Json(seasons)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Final ratings of a closed season
+async fn get_season_leaderboard(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(season): Path<String>,
+) -> Result<Json<Vec<LeaderboardEntry>>, StatusCode> {
+    let storage = storage.read().await;
+
+    match storage.get_season_leaderboard(&season) {
+        Ok(leaderboard) => Ok(Json(leaderboard)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// All-time standings of every rated player
+async fn get_standings(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+) -> Json<Vec<glub_server_standings::StandingsEntry>> {
+    let storage = storage.read().await;
+    Json(storage.standings())
+}
+
+// Archive the current season and start the next one (admin only)
+async fn rollover_season(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Json(payload): Json<RolloverSeasonRequest>,
+) -> Result<Json<String>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    let mut storage = storage.write().await;
+
+    match storage.rollover_season(payload.next_season) {
+        Ok(closed_season) => Ok(Json(closed_season)),
+        Err(_) => Err(StatusCode::CONFLICT),
+    }
+}
+
+// Freeze every game for maintenance; reads keep working (admin only)
+async fn pause_server(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+) -> Result<Json<PauseState>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    let mut storage = storage.write().await;
+    storage.set_paused(true);
+    info!("Server paused, moves are refused until resumed");
+    Ok(Json(PauseState { paused: true }))
+}
+
+// Let games carry on after a pause (admin only)
+async fn resume_server(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+) -> Result<Json<PauseState>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    let mut storage = storage.write().await;
+    storage.set_paused(false);
+    info!("Server resumed");
+    Ok(Json(PauseState { paused: false }))
+}
+
+// Request counts, error rates and latencies per route
+async fn get_route_metrics(
+    State(config): State<Arc<Config>>,
+    Extension(metrics): Extension<RouteMetrics>,
+    headers: HeaderMap,
+) -> Result<Json<BTreeMap<String, RouteStats>>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    Ok(Json(metrics.snapshot()))
+}
+
+// What storage is holding on to, for tracking down memory growth (admin only)
+async fn get_diagnostics(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    headers: HeaderMap,
+    Query(query): Query<DiagnosticsQuery>,
+) -> Result<Json<Diagnostics>, StatusCode> {
+    require_admin(&config, &headers)?;
+
+    let storage = storage.read().await;
+
+    Ok(Json(storage.diagnostics(query.top.min(100))))
+}
+
+// Find the game a player should resume after a restart
+async fn get_current_game(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(player_id): Path<String>,
+) -> Result<Json<CurrentGameResponse>, StatusCode> {
+    let player_id = Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let storage = storage.read().await;
+
+    match storage.get_current_game(player_id) {
+        Ok(game) => Ok(Json(CurrentGameResponse { game })),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Leave the queue and resign every game in progress
+async fn quit(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(player_id): Path<String>,
+) -> Result<Json<QuitResponse>, StatusCode> {
+    let player_id = Uuid::parse_str(&player_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let mut storage = storage.write().await;
+
+    match storage.quit(player_id) {
+        Ok(response) => Ok(Json(response)),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+// Open a new tournament for registration
+async fn create_tournament(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Json(payload): Json<CreateTournamentRequest>,
+) -> Result<Json<Uuid>, StatusCode> {
+    let mut storage = storage.write().await;
+
+    match storage.create_tournament(
+        payload.name,
+        payload.size,
+        payload.strictness,
+        payload.format,
+    ) {
+        Ok(tournament_id) => Ok(Json(tournament_id)),
+        Err(_) => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+// Take a seat in a tournament
+async fn register_for_tournament(
+    State(config): State<Arc<Config>>,
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(tournament_id): Path<String>,
+    Json(payload): Json<JoinQueueRequest>,
+) -> Result<Json<TournamentRegistration>, StatusCode> {
+    let tournament_id = Uuid::parse_str(&tournament_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+    validate_player_name(&config, &payload.player_name)?;
+
+    // No new games while the server drains for shutdown
+    let mut storage = storage.write().await;
+    if storage.in_maintenance() {
+        return Err(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    match storage.register_for_tournament(tournament_id, payload.player_name) {
+        Ok(registration) => Ok(Json(registration)),
+        Err(_) => Err(StatusCode::CONFLICT),
+    }
+}
+
+// Get the live bracket
+async fn get_tournament(
+    State(storage): State<Arc<RwLock<GameStorage>>>,
+    Path(tournament_id): Path<String>,
+) -> Result<Json<TournamentView>, StatusCode> {
+    let tournament_id = Uuid::parse_str(&tournament_id).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let storage = storage.read().await;
+
+    match storage.get_tournament(tournament_id) {
+        Ok(tournament) => Ok(Json(TournamentView {
+            status: tournament.status(),
+            standings: tournament.standings(),
+            tournament,
+        })),
+        Err(_) => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+/// How often the tick task does its less frequent work, in ticks
+struct TickSchedule {
+    /// Groups of games handled one after another within each tick
+    shards: u64,
+    /// Ticks between checkpoints of active games
+    checkpoint_every: u64,
+    /// Ticks between full ticks while no game is in progress, short enough
+    /// that the heartbeat never goes stale
+    idle_every: u64,
+}
+
+// Task that increments move points every tick, one shard of games at a time
+async fn move_increment_task(
+    storage: Arc<RwLock<GameStorage>>,
+    active_game_count: Arc<AtomicUsize>,
+    tick: Duration,
+    schedule: TickSchedule,
+    mut stop: tokio::sync::watch::Receiver<bool>,
+) {
+    // Each shard gets its own slice of the tick, so every game is still
+    // handled once per tick, always at the same point in it
+    let mut interval = tokio::time::interval(tick / schedule.shards as u32);
+    let mut shard = TickShard {
+        index: schedule.shards - 1,
+        count: schedule.shards,
+    };
+    let mut ticks: u64 = 0;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stop.wait_for(|stopping| *stopping) => return,
+        }
+        shard.index = (shard.index + 1) % shard.count;
+        // Passes over every game only run once a full tick is done
+        let full_tick = shard.is_last();
+        if full_tick {
+            ticks += 1;
+        }
+        let checkpoint_due = full_tick && ticks.is_multiple_of(schedule.checkpoint_every);
+
+        // With no game in progress there are no points to grant or bots to
+        // move, so only sweep now and then instead of taking the write lock
+        // every tick
+        if active_game_count.load(Ordering::Relaxed) == 0
+            && !checkpoint_due
+            && !(full_tick && ticks.is_multiple_of(schedule.idle_every))
+        {
+            continue;
+        }
+
+        let mut storage = storage.write().await;
+        let started = std::time::Instant::now();
+        storage.tick_shard(shard);
+        if full_tick {
+            storage.check_presence();
+            storage.cleanup();
+            storage.flush();
+        }
+        storage.record_heartbeat(started.elapsed());
+
+        if checkpoint_due {
+            storage.checkpoint();
+        }
+    }
+}
+
+// Request/Response types
+#[derive(Deserialize)]
+pub struct JoinQueueRequest {
+    pub player_name: String,
+    /// Skip matchmaking and control both colors in a practice game
+    #[serde(default)]
+    pub solo: bool,
+    /// Skip matchmaking and play white against a bot
+    #[serde(default)]
+    pub bot: bool,
+    /// Whether fog applies in a practice or bot game (defaults to on)
+    pub fog: Option<bool>,
+    /// Realtime by default; matchmaking only pairs players who chose the same
+    #[serde(default)]
+    pub mode: GameMode,
+}
+
+#[derive(Serialize)]
+pub struct JoinQueueResponse {
+    pub player_id: Uuid,
+    pub game_id: Option<Uuid>,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+pub struct QueueStatus {
+    pub players_waiting: usize,
+    pub longest_wait_seconds: Option<u64>,
+    /// Median time-to-match over recent matches, `None` without data
+    pub median_wait_seconds: Option<f64>,
+    pub sample_size: usize,
+    pub message: String,
+    /// The same, for each mode players can queue for
+    pub modes: Vec<ModeQueueStatus>,
+}
+
+#[derive(Serialize)]
+pub struct ModeQueueStatus {
+    pub mode: GameMode,
+    pub players_waiting: usize,
+    pub longest_wait_seconds: Option<u64>,
+    /// `None` until a game in this mode has been matched recently
+    pub median_wait_seconds: Option<f64>,
+    pub sample_size: usize,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+pub struct MoveRequest {
+    pub player_id: Uuid,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    /// Spend a full charge meter instead of a move point
+    #[serde(default)]
+    pub use_charge: bool,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Occupancy {
+    pub active_games: usize,
+    pub max_active_games: usize,
+}
+
+impl Occupancy {
+    pub fn is_full(&self) -> bool {
+        self.active_games >= self.max_active_games
+    }
+
+    /// Share of the game ceiling in use, from 0.0 to 1.0 (higher if tournaments overshoot)
+    pub fn utilization(&self) -> f64 {
+        self.active_games as f64 / self.max_active_games as f64
+    }
+}
+
+#[derive(Serialize)]
+pub struct ServerFullResponse {
+    pub error: String,
+    #[serde(flatten)]
+    pub occupancy: Occupancy,
+}
+
+#[derive(Serialize)]
+pub struct ServerPausedResponse {
+    pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct RemovedFromGameResponse {
+    pub error: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReassignRequest {
+    pub color: PlayerColor,
+    pub player_name: String,
+}
+
+#[derive(Serialize)]
+pub struct ReassignResponse {
+    /// The new player's id; the old one gets 410 Gone from the game
+    pub player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct PauseState {
+    pub paused: bool,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    /// "ok", "full", "paused" or "draining"
+    pub status: String,
+    #[serde(flatten)]
+    pub occupancy: Occupancy,
+    pub utilization: f64,
+}
+
+#[derive(Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    pub protocol_version: u32,
+    pub features: Vec<&'static str>,
+}
+
+#[derive(Deserialize)]
+pub struct SeedGameRequest {
+    /// In the format read by `ExtendedBoard::from_board_string`
+    pub board: String,
+    pub white_name: String,
+    pub black_name: String,
+    /// The server's default rules when omitted
+    pub rules: Option<GameRules>,
+}
+
+#[derive(Serialize)]
+pub struct StartBoard {
+    pub game_id: Uuid,
+    pub board: glub_server::ExtendedBoard,
+    /// Whether the game was seeded from a position of its own rather than the
+    /// standard one
+    pub custom: bool,
+}
+
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    /// Moves already fetched; only those after them are returned
+    #[serde(default)]
+    pub since: usize,
+}
+
+#[derive(Serialize)]
+pub struct MoveHistory {
+    pub game_id: Uuid,
+    /// Moves played in the whole game
+    pub total: usize,
+    pub moves: Vec<MoveRecord>,
+}
+
+#[derive(Serialize)]
+pub struct SeededGame {
+    pub game_id: Uuid,
+    pub white_player_id: Uuid,
+    pub black_player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct VerifyGameResponse {
+    pub game_id: Uuid,
+    pub moves_checked: usize,
+    pub valid: bool,
+    /// The first problem found, when the game does not verify
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FogExplanation {
+    pub game_id: Uuid,
+    pub color: PlayerColor,
+    /// When fog is off every square is visible and no sources are listed
+    pub fog_enabled: bool,
+    /// Every square the player can see, with what lets them see it
+    pub visible: Vec<VisibleSquare>,
+    /// Squares within sight of one of the player's pieces that walls hide
+    pub hidden_by_walls: Vec<(usize, usize)>,
+}
+
+#[derive(Serialize)]
+pub struct VisibleSquare {
+    pub position: (usize, usize),
+    pub sources: Vec<VisionSource>,
+}
+
+/// One reason a square is visible
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum VisionSource {
+    /// Within `range` of one of the player's pieces, with no wall in the way
+    Piece {
+        piece: glub_server::ChestPiece,
+        from: (usize, usize),
+        range: usize,
+    },
+    /// A beacon left behind by a Scout
+    Beacon { from: (usize, usize) },
+    /// Revealed around a capture
+    CapturePulse { from: (usize, usize), range: usize },
+    /// The whole board, while a bought reveal runs
+    Reveal,
+}
+
+#[derive(Deserialize)]
+pub struct DrawOfferRequest {
+    pub player_id: Uuid,
+    pub action: DrawAction,
+}
+
+#[derive(Deserialize)]
+pub struct ReadyRequest {
+    pub player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct ReadyResponse {
+    pub phase: GamePhase,
+}
+
+#[derive(Deserialize)]
+pub struct PlacementRequest {
+    pub player_id: Uuid,
+    /// Every piece on the player's two home ranks
+    pub pieces: Vec<PlacedPiece>,
+}
+
+#[derive(Deserialize)]
+pub struct PlacedPiece {
+    pub row: usize,
+    pub col: usize,
+    pub piece: glub_server::ChestPiece,
+}
+
+#[derive(Serialize)]
+pub struct PlacementResponse {
+    /// Still `placement` until the other player has placed too
+    pub phase: GamePhase,
+}
+
+#[derive(Deserialize)]
+pub struct RevealRequest {
+    pub player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct RevealResponse {
+    /// Move points left after paying for the reveal
+    pub remaining_moves: u64,
+    pub reveal_seconds: u64,
+}
+
+#[derive(Serialize)]
+pub struct DrawOfferResponse {
+    pub draw_offer: Option<PlayerColor>,
+    pub result: Option<GameResult>,
+}
+
+#[derive(Serialize)]
+pub struct MoveResponse {
+    pub success: bool,
+    /// Borrowed for the fixed messages, so an accepted move doesn't allocate one
+    pub message: Cow<'static, str>,
+    pub remaining_moves: u64,
+}
+
+#[derive(Serialize)]
+pub struct CurrentGame {
+    pub game_id: Uuid,
+    pub your_color: PlayerColor,
+    pub board_version: u64,
+    pub remaining_moves: u64,
+    /// Seconds until the next move point is granted
+    pub next_move_point_in: u64,
+}
+
+#[derive(Serialize)]
+pub struct CurrentGameResponse {
+    /// `None` when the player has no game in progress
+    pub game: Option<CurrentGame>,
+}
+
+#[derive(Serialize)]
+pub struct LegalMovesResponse {
+    /// The board version these moves apply to
+    pub board_version: u64,
+    pub moves: Vec<LegalMove>,
+}
+
+#[derive(Serialize)]
+pub struct QuitResponse {
+    /// Whether the player was still waiting for a match
+    pub left_queue: bool,
+    pub resigned_games: Vec<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct RolloverSeasonRequest {
+    /// Name for the season being started; defaults to the next number
+    pub next_season: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct PlayerSeason {
+    pub season: String,
+    pub rating: f64,
+    pub stats: SeasonStats,
+}
+
+#[derive(Deserialize)]
+pub struct CreateTournamentRequest {
+    pub name: String,
+    pub size: usize,
+    #[serde(default)]
+    pub strictness: Strictness,
+    #[serde(default)]
+    pub format: TournamentFormat,
+}
+
+#[derive(Serialize)]
+pub struct TournamentRegistration {
+    pub tournament_id: Uuid,
+    pub player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct TournamentView {
+    pub status: TournamentStatus,
+    pub standings: Vec<Standing>,
+    #[serde(flatten)]
+    pub tournament: Tournament,
+}
+
+/// `?format=squares` asks for the compact board instead of the 8x8 grid
+#[derive(Deserialize)]
+pub struct BoardQuery {
+    #[serde(default)]
+    pub format: BoardFormat,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum BoardFormat {
+    /// Every square, with `null` for empty or hidden ones
+    #[default]
+    Grid,
+    /// Only the occupied visible squares
+    Squares,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FoggedSquares {
+    pub squares: Vec<OccupiedSquare>,
+    pub dims: (usize, usize),
+    pub your_color: PlayerColor,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub walls: BTreeSet<(usize, usize)>,
+    pub view_hash: String,
+    pub opponent_last_active: Option<u64>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SpectatorSquares {
+    pub squares: Vec<OccupiedSquare>,
+    pub fogged: bool,
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub walls: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateLobbyRequest {
+    pub player_name: String,
+    /// Start from this position instead of the standard one; unranked only
+    pub position: Option<StartPosition>,
+    /// Pieces one side gives up, e.g. `{"color": "white", "handicap":
+    /// "no_queen"}`; unranked only, and not with a custom position
+    pub handicap: Option<HandicapOdds>,
+    #[serde(default)]
+    pub ranked: bool,
+    #[serde(default)]
+    pub mode: GameMode,
+}
+
+/// A custom starting position: a board string in the admin seeding format, or
+/// a list of pieces
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StartPosition {
+    Board(String),
+    Pieces(Vec<OccupiedSquare>),
+}
+
+impl StartPosition {
+    pub fn to_board(&self) -> Result<glub_server::ExtendedBoard, String> {
+        let squares = match self {
+            StartPosition::Board(board) => {
+                return glub_server::ExtendedBoard::from_board_string(board);
+            }
+            StartPosition::Pieces(squares) => squares,
+        };
+        let mut board = glub_server::ExtendedBoard::new();
+        for square in squares {
+            let position = (square.row, square.col);
+            if !board.contains(position) {
+                return Err(format!("({}, {}) is off the board", square.row, square.col));
+            }
+            if board.slot(position).is_some() {
+                return Err(format!("Two pieces on ({}, {})", square.row, square.col));
+            }
+            board.set_slot(
+                position,
+                Some(glub_server::ExtendedSlot {
+                    piece: square.piece,
+                    color: square.color,
+                }),
+            );
+        }
+        Ok(board)
+    }
+}
+
+#[derive(Serialize)]
+pub struct LobbyCreated {
+    /// Share this with the player to invite
+    pub code: String,
+    pub player_id: Uuid,
+}
+
+#[derive(Serialize)]
+pub struct LobbyInfo {
+    pub code: String,
+    pub host_name: String,
+    pub ranked: bool,
+    pub mode: GameMode,
+    /// The custom starting position as a board string, if the lobby has one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_position: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handicap: Option<HandicapOdds>,
+    /// Set once the invited player has joined
+    pub game_id: Option<Uuid>,
+}
+
+#[derive(Deserialize)]
+pub struct JoinLobbyRequest {
+    pub player_name: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SpectatorCodeResponse {
+    pub code: String,
+    /// `None` when the code lasts as long as the game
+    pub expires_in_seconds: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SpectatableGame {
+    pub code: String,
+    pub game_id: Uuid,
+    pub white_name: String,
+    pub black_name: String,
+    pub moves: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NotReady {
+    /// "maintenance", "tick" or "repository"
+    pub component: String,
+    pub reason: String,
+}
+
+/// `?top=N` sets how many of the largest games to list
+#[derive(Deserialize)]
+pub struct DiagnosticsQuery {
+    #[serde(default = "default_diagnostics_top")]
+    pub top: usize,
+}
+
+fn default_diagnostics_top() -> usize {
+    10
+}
+
+#[derive(Serialize, Debug)]
+pub struct Diagnostics {
+    /// Keyed by category, such as `active_games` or `queue`
+    pub categories: BTreeMap<String, CategoryUsage>,
+    /// Games with the most events and moves recorded, largest first
+    pub largest_games: Vec<GameFootprint>,
+    pub ticks: TickTimings,
+    /// How often a player's board was served without recomputing their view
+    pub fogged_board_cache: CacheCounters,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct CategoryUsage {
+    pub count: usize,
+    /// Estimated from the sizes of the structs and what their collections have
+    /// allocated; the real figure is higher
+    pub approx_bytes: usize,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameFootprint {
+    pub game_id: Uuid,
+    pub finished: bool,
+    pub events: usize,
+    pub history: usize,
+    pub approx_bytes: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TickTimings {
+    pub last_millis: Option<f64>,
+    pub latency: LatencyHistogram,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CacheCounters {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Deserialize)]
+pub struct WaitQuery {
+    /// Return as soon as the board version is past this
+    #[serde(default)]
+    pub since_version: u64,
+    /// Defaults to the longest wait the server allows
+    pub timeout_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct WaitResponse {
+    pub board_version: u64,
+    pub result: Option<GameResult>,
+    /// False when the wait timed out with nothing new
+    pub changed: bool,
+    /// The server is going away; wait again once it is back or elsewhere
+    pub shutting_down: bool,
+}
+
+#[derive(Deserialize)]
+pub struct StatusQuery {
+    pub player_id: Option<Uuid>,
+}
+
+/// Most games one bulk status request may ask about
+pub const MAX_BULK_STATUS_GAMES: usize = 100;
+
+#[derive(Deserialize)]
+pub struct BulkStatusRequest {
+    pub game_ids: Vec<Uuid>,
+    /// Ask as this player; games they aren't in come back as errors
+    #[serde(default)]
+    pub player_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct BulkStatusResponse {
+    /// In the order the games were asked for
+    pub statuses: Vec<BulkStatusEntry>,
+}
+
+#[derive(Serialize)]
+pub struct BulkStatusEntry {
+    pub game_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<GameStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct GameStatus {
+    pub game_id: Uuid,
+    /// Omitted when hidden from the requester
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player1_moves: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player2_moves: Option<u64>,
+    /// Whether a move point was wasted because the player was at the cap
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player1_at_cap: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player2_at_cap: Option<bool>,
+    /// Progress towards a charged move, out of the game's charge capacity
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player1_charge: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player2_charge: Option<u64>,
+    /// The player to move in a turn-based game in progress
+    pub current_turn: Option<Uuid>,
+    pub result: Option<GameResult>,
+    pub phase: GamePhase,
+    /// Whether the requesting player's king is attacked, when check rules apply
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub in_check: Option<bool>,
+    /// The requesting player's `view_hash`, the same as on their board
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub view_hash: Option<String>,
+    /// The color waiting on an answer to its draw offer
+    pub draw_offer: Option<PlayerColor>,
+    /// Checks each player has given, in three-check games
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player1_checks: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub player2_checks: Option<u32>,
+    /// The shuffled back rank both sides started with, if the game used one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub back_rank: Option<glub_server::BackRank>,
+    /// The material one side gave up, in a handicap game
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handicap: Option<HandicapOdds>,
+    /// The custom position a private game started from, as a board string;
+    /// hidden from spectators while the game is fogged for them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_position: Option<String>,
+    /// When the game started, ISO 8601 in UTC; omitted for archived games
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_seconds: Option<u64>,
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::*;
+    use crate::glub_server_test_server::TestServer;
+    use serde_json::{Value, json};
+
+    #[tokio::test]
+    async fn bodies_over_the_limit_are_rejected_with_413() {
+        let config = Config {
+            body_limit_bytes: 1024,
+            import_body_limit_bytes: 4096,
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+
+        let (status, _) = server
+            .post("/join_queue", json!({ "player_name": "x".repeat(2048) }))
+            .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // The import route has its own, larger limit
+        let archive = json!({ "padding": "x".repeat(2048) });
+        let (status, _) = server.post("/import", archive).await;
+        assert_ne!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let archive = json!({ "padding": "x".repeat(8192) });
+        let (status, _) = server.post("/import", archive).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let (status, _) = server
+            .post("/join_queue", json!({ "player_name": "short" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn evicted_games_are_fetched_from_the_archive() {
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        let (archive, queue) = glub_server_persistence::ArchiveStore::open(&dir).unwrap();
+        tokio::spawn(archive.clone().run_writer(queue));
+        let config = Config {
+            archive_grace_seconds: 0,
+            ..Config::default()
+        };
+        let server = TestServer::with_storage(
+            GameStorage::with_config(&config).with_archive(archive),
+            &config,
+        );
+
+        let game = server.start_game().await;
+        server
+            .make_move(game.game_id, game.white_player_id, (1, 4), (2, 4))
+            .await;
+        server
+            .post(
+                &format!("/players/{}/quit", game.black_player_id),
+                json!({}),
+            )
+            .await;
+        {
+            let mut storage = server.storage().write().await;
+            storage.evict_finished_games();
+            assert!(storage.with_game(game.game_id, |_| ()).is_none());
+        }
+
+        let (status, history) = server.get(&format!("/game/{}/history", game.game_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(history["total"], 1);
+        let (_, game_status) = server
+            .status(game.game_id, Some(game.white_player_id))
+            .await;
+        assert_eq!(game_status["result"]["winner"], "white");
+        let (status, _) = server.get(&format!("/game/{}/export", game.game_id)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, statuses) = server
+            .post(
+                "/statuses",
+                json!({ "game_ids": [game.game_id, Uuid::new_v4()] }),
+            )
+            .await;
+        assert_eq!(statuses["statuses"][0]["status"]["phase"], "finished");
+        assert_eq!(statuses["statuses"][1]["error"], "Game not found");
+        let (status, current) = server
+            .get(&format!("/players/{}/current_game", game.white_player_id))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(current["game"], Value::Null);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn the_version_names_the_crate_protocol_and_features() {
+        let server = TestServer::default();
+        let (status, version) = server.get("/version").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(version["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(version["protocol_version"], PROTOCOL_VERSION);
+        let features = version["features"].as_array().unwrap();
+        assert!(features.contains(&json!("fog")));
+        assert_eq!(
+            features.contains(&json!("sqlite")),
+            cfg!(feature = "sqlite")
+        );
+    }
+
+    #[tokio::test]
+    async fn history_is_gzipped_for_clients_that_accept_it() {
+        use std::io::Read;
+        use tower::ServiceExt;
+
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        for _ in 0..15 {
+            server
+                .storage()
+                .write()
+                .await
+                .increment_moves(crate::glub_server_storage::TickShard::ALL);
+        }
+        // Knights hopping out and back leave a long history behind
+        for _ in 0..5 {
+            for (player_id, from, to) in [
+                (game.white_player_id, (0, 1), (2, 2)),
+                (game.black_player_id, (7, 6), (5, 5)),
+                (game.white_player_id, (2, 2), (0, 1)),
+                (game.black_player_id, (5, 5), (7, 6)),
+            ] {
+                let (_, moved) = server.make_move(game.game_id, player_id, from, to).await;
+                assert_eq!(moved["success"], true, "{}", moved);
+                let mut storage = server.storage().write().await;
+                for _ in 0..3 {
+                    storage.increment_moves(crate::glub_server_storage::TickShard::ALL);
+                }
+            }
+        }
+        // The history stays hidden under fog until the game ends
+        server
+            .post(
+                &format!("/players/{}/quit", game.black_player_id),
+                json!({}),
+            )
+            .await;
+        let uri = format!("/game/{}/history", game.game_id);
+        let (_, plain) = server.get(&uri).await;
+        assert_eq!(plain["total"], 20);
+
+        let request = axum::http::Request::get(&uri)
+            .header("accept-encoding", "gzip")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-encoding"], "gzip");
+        let compressed = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut decompressed = String::new();
+        flate2::read::GzDecoder::new(&compressed[..])
+            .read_to_string(&mut decompressed)
+            .unwrap();
+        assert!(compressed.len() < decompressed.len());
+        assert_eq!(serde_json::from_str::<Value>(&decompressed).unwrap(), plain);
+    }
+
+    #[tokio::test]
+    async fn the_fog_explanation_credits_a_scout_within_its_range() {
+        use tower::ServiceExt;
+
+        let config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let mut storage = GameStorage::with_config(&config);
+        let game = storage
+            .seed_game(
+                ".......k
+                 ........
+                 ........
+                 ........
+                 ...S....
+                 ........
+                 ........
+                 K.......",
+                "white".to_string(),
+                "black".to_string(),
+                None,
+            )
+            .unwrap();
+        let server = TestServer::with_storage(storage, &config);
+
+        let uri = format!("/admin/game/{}/fog/{}", game.game_id, game.white_player_id);
+        let (status, _) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let request = axum::http::Request::get(&uri)
+            .header("x-admin-token", "secret")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let explanation: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(explanation["fog_enabled"], true);
+
+        let scout = json!({ "kind": "piece", "piece": "Scout", "from": [3, 3], "range": 3 });
+        let credited: Vec<(usize, usize)> = explanation["visible"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|square| square["sources"].as_array().unwrap().contains(&scout))
+            .map(|square| serde_json::from_value(square["position"].clone()).unwrap())
+            .collect();
+        let in_range: Vec<(usize, usize)> = (0..8)
+            .flat_map(|row| (0..8).map(move |col| (row, col)))
+            .filter(|&(row, col): &(usize, usize)| {
+                let (dr, dc) = (row.abs_diff(3), col.abs_diff(3));
+                dr * dr + dc * dc <= 9
+            })
+            .collect();
+        assert_eq!(credited, in_range);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn boards_read_during_moves_are_never_half_moved() {
+        let server = Arc::new(TestServer::default());
+        let game = server.start_game().await;
+        const HOPS: usize = 100;
+
+        let mover = {
+            let server = Arc::clone(&server);
+            tokio::spawn(async move {
+                for hop in 0..HOPS {
+                    let (from, to) = if hop % 2 == 0 {
+                        ((0, 1), (2, 2))
+                    } else {
+                        ((2, 2), (0, 1))
+                    };
+                    server
+                        .storage()
+                        .write()
+                        .await
+                        .with_game_mut(game.game_id, |game_state| {
+                            game_state.game.player1_remaining_moves = 1;
+                            game_state.game.player2_remaining_moves = 1;
+                        });
+                    let (_, moved) = server
+                        .make_move(game.game_id, game.white_player_id, from, to)
+                        .await;
+                    assert_eq!(moved["success"], true, "{}", moved);
+                }
+            })
+        };
+
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let mut reads = 0;
+                    loop {
+                        let (_, board) = server.board(game.game_id, game.white_player_id).await;
+                        let piece =
+                            |row: usize, col: usize| board["slots"][row][col]["piece"].clone();
+                        let knights = [piece(0, 1), piece(2, 2)]
+                            .iter()
+                            .filter(|piece| **piece == "Knight")
+                            .count();
+                        assert_eq!(knights, 1, "{}", board);
+                        let own = board["slots"]
+                            .as_array()
+                            .unwrap()
+                            .iter()
+                            .flat_map(|row| row.as_array().unwrap())
+                            .filter(|slot| slot["color"] == "white")
+                            .count();
+                        assert_eq!(own, 16);
+                        reads += 1;
+                        let history = server
+                            .storage()
+                            .read()
+                            .await
+                            .with_game(game.game_id, |game_state| game_state.history.len())
+                            .unwrap();
+                        if history == HOPS {
+                            return reads;
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        mover.await.unwrap();
+        for reader in readers {
+            assert!(reader.await.unwrap() > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn huge_coordinates_are_refused_without_touching_storage() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+
+        // Holding the write lock would stall any request that reached storage
+        let _held = server.storage().write().await;
+        for (from, to) in [
+            ((usize::MAX, usize::MAX), (2, 4)),
+            ((1, 4), (2, usize::MAX)),
+        ] {
+            let (status, _) = tokio::time::timeout(
+                Duration::from_secs(1),
+                server.make_move(game.game_id, game.white_player_id, from, to),
+            )
+            .await
+            .expect("answered without waiting for the lock");
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_move_in_the_fog_leaves_the_view_hash_alone() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        let server = &server;
+        let version = || async {
+            server
+                .storage()
+                .read()
+                .await
+                .with_game(game.game_id, |game_state| game_state.version)
+                .unwrap()
+        };
+        let view_hash = |player_id| async move {
+            let (_, status) = server.status(game.game_id, Some(player_id)).await;
+            let (_, board) = server.board(game.game_id, player_id).await;
+            assert_eq!(status["view_hash"], board["view_hash"]);
+            status["view_hash"].as_str().unwrap().to_string()
+        };
+        let (white_before, black_before) = (
+            view_hash(game.white_player_id).await,
+            view_hash(game.black_player_id).await,
+        );
+        let version_before = version().await;
+
+        // Black's a-pawn steps forward, well out of white's sight
+        let (_, moved) = server
+            .make_move(game.game_id, game.black_player_id, (6, 0), (5, 0))
+            .await;
+        assert_eq!(moved["success"], true, "{}", moved);
+
+        assert!(version().await > version_before);
+        assert_eq!(view_hash(game.white_player_id).await, white_before);
+        assert_ne!(view_hash(game.black_player_id).await, black_before);
+    }
+
+    #[tokio::test]
+    async fn a_seeded_game_starts_from_the_board_it_was_given() {
+        let position = "....k...
+                        .r......
+                        ........
+                        ...S....
+                        ........
+                        ..N.....
+                        PP......
+                        ....K...";
+        let mut storage = GameStorage::new();
+        let game = storage
+            .seed_game(position, "white".to_string(), "black".to_string(), None)
+            .unwrap();
+        let server = TestServer::with_storage(storage, &Config::default());
+        let uri = format!("/game/{}/start_board", game.game_id);
+
+        // Hidden under fog until the game is over
+        let (status, _) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, moved) = server
+            .make_move(game.game_id, game.white_player_id, (2, 2), (4, 1))
+            .await;
+        assert_eq!(moved["success"], true, "{}", moved);
+        server
+            .post(
+                &format!("/players/{}/quit", game.black_player_id),
+                json!({}),
+            )
+            .await;
+
+        let (status, start) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(start["custom"], true);
+        let supplied = glub_server::ExtendedBoard::from_board_string(position).unwrap();
+        assert_eq!(start["board"], serde_json::to_value(&supplied).unwrap());
+    }
+
+    #[tokio::test]
+    async fn a_reassigned_player_is_told_they_were_removed() {
+        use tower::ServiceExt;
+
+        let config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+        let game = server.start_game().await;
+
+        let request = axum::http::Request::post(format!("/admin/game/{}/reassign", game.game_id))
+            .header("x-admin-token", "secret")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from(
+                json!({ "color": "black", "player_name": "stand-in" }).to_string(),
+            ))
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let reassigned: Value = serde_json::from_slice(&body).unwrap();
+        let stand_in = Uuid::parse_str(reassigned["player_id"].as_str().unwrap()).unwrap();
+
+        let (status, removed) = server.board(game.game_id, game.black_player_id).await;
+        assert_eq!(status, StatusCode::GONE);
+        assert_eq!(removed["error"], "removed_from_game");
+        let (status, _) = server
+            .make_move(game.game_id, game.black_player_id, (6, 4), (5, 4))
+            .await;
+        assert_eq!(status, StatusCode::GONE);
+
+        // The new player takes the seat, and an id that never played isn't told it was removed
+        let (status, _) = server.board(game.game_id, stand_in).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = server.board(game.game_id, Uuid::new_v4()).await;
+        assert_ne!(status, StatusCode::GONE);
+    }
+
+    #[tokio::test]
+    async fn a_waiting_client_is_told_when_the_server_shuts_down() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        let uri = format!(
+            "/game/{}/wait?since_version={}&timeout_seconds=30",
+            game.game_id,
+            u64::MAX
+        );
+
+        let shut_down = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            server.storage().read().await.changes().shut_down();
+        };
+        let ((status, waited), ()) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(server.get(&uri), shut_down)
+        })
+        .await
+        .expect("the wait ends with the shutdown, not its own timeout");
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(waited["shutting_down"], true);
+        assert_eq!(waited["changed"], false);
+    }
+
+    #[tokio::test]
+    async fn history_since_an_index_returns_only_the_later_moves() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        let moves = [
+            (game.white_player_id, (1, 0), (2, 0)),
+            (game.black_player_id, (6, 0), (5, 0)),
+            (game.white_player_id, (1, 1), (2, 1)),
+            (game.black_player_id, (6, 1), (5, 1)),
+            (game.white_player_id, (1, 2), (2, 2)),
+        ];
+        for (player_id, from, to) in moves {
+            let mut storage = server.storage().write().await;
+            for _ in 0..3 {
+                storage.increment_moves(crate::glub_server_storage::TickShard::ALL);
+            }
+            drop(storage);
+            let (_, moved) = server.make_move(game.game_id, player_id, from, to).await;
+            assert_eq!(moved["success"], true, "{}", moved);
+        }
+        let uri = format!("/game/{}/history?since=3", game.game_id);
+
+        // Under fog the history waits for the end of the game, however little is asked for
+        let (status, _) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        server
+            .post(
+                &format!("/players/{}/quit", game.black_player_id),
+                json!({}),
+            )
+            .await;
+
+        let (status, history) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(history["total"], 5);
+        let later: Vec<((usize, usize), (usize, usize))> = history["moves"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|record| {
+                (
+                    serde_json::from_value(record["from"].clone()).unwrap(),
+                    serde_json::from_value(record["to"].clone()).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(later, [((6, 1), (5, 1)), ((1, 2), (2, 2))]);
+
+        // Past the end there is nothing new, but the total still comes back
+        let (_, history) = server
+            .get(&format!("/game/{}/history?since=9", game.game_id))
+            .await;
+        assert_eq!(history["total"], 5);
+        assert_eq!(history["moves"], json!([]));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_joins_pair_everyone_exactly_once() {
+        const PLAYERS: usize = 41;
+        let server = Arc::new(TestServer::default());
+        let joins: Vec<_> = (0..PLAYERS)
+            .map(|n| {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let (status, joined) = server
+                        .post("/join_queue", json!({ "player_name": format!("p{}", n) }))
+                        .await;
+                    assert_eq!(status, StatusCode::OK, "{}", joined);
+                    Uuid::parse_str(joined["player_id"].as_str().unwrap()).unwrap()
+                })
+            })
+            .collect();
+        let mut player_ids = Vec::new();
+        for join in joins {
+            player_ids.push(join.await.unwrap());
+        }
+
+        let mut seats: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let mut waiting = 0;
+        for player_id in player_ids {
+            let (_, current) = server
+                .get(&format!("/players/{}/current_game", player_id))
+                .await;
+            match current["game"]["game_id"].as_str() {
+                Some(game_id) => seats
+                    .entry(game_id.to_string())
+                    .or_default()
+                    .push(current["game"]["your_color"].to_string()),
+                None => waiting += 1,
+            }
+        }
+        assert_eq!(seats.len(), PLAYERS / 2);
+        assert_eq!(waiting, PLAYERS % 2);
+        for colors in seats.values_mut() {
+            colors.sort();
+            assert_eq!(colors, &["\"black\"", "\"white\""]);
+        }
+        let storage = server.storage().read().await;
+        assert_eq!(
+            storage.active_game_count().load(Ordering::Relaxed),
+            PLAYERS / 2
+        );
+    }
+
+    #[tokio::test]
+    async fn the_current_game_is_found_empty_or_unknown() {
+        let server = TestServer::default();
+        let current = |player_id: Uuid| {
+            let server = &server;
+            async move {
+                server
+                    .get(&format!("/players/{}/current_game", player_id))
+                    .await
+            }
+        };
+
+        let (status, _) = current(Uuid::new_v4()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let game = server.start_game().await;
+        let (status, found) = current(game.black_player_id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found["game"]["game_id"], json!(game.game_id));
+        assert_eq!(found["game"]["your_color"], "black");
+        assert_eq!(found["game"]["board_version"], 0);
+
+        // Once the game is over the player is known but has nothing to resume
+        let (status, _) = server
+            .request(
+                axum::http::Method::POST,
+                &format!("/players/{}/quit", game.white_player_id),
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, empty) = current(game.black_player_id).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(empty["game"], Value::Null);
+    }
+
+    #[tokio::test]
+    async fn the_squares_format_lists_exactly_the_grids_pieces() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        let uri = format!("/game/{}/board/{}", game.game_id, game.black_player_id);
+
+        let (_, grid) = server.get(&format!("{}?format=grid", uri)).await;
+        let (status, squares) = server.get(&format!("{}?format=squares", uri)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let mut from_grid: Vec<Value> = Vec::new();
+        for (row, slots) in grid["slots"].as_array().unwrap().iter().enumerate() {
+            for (col, slot) in slots.as_array().unwrap().iter().enumerate() {
+                if !slot.is_null() {
+                    from_grid.push(json!({
+                        "row": row,
+                        "col": col,
+                        "piece": slot["piece"],
+                        "color": slot["color"],
+                    }));
+                }
+            }
+        }
+        // Black sees its own army and nothing of white's through the fog
+        assert_eq!(from_grid.len(), 16);
+        assert_eq!(squares["squares"].as_array().unwrap(), &from_grid);
+        for field in ["dims", "your_color", "view_hash"] {
+            assert_eq!(squares[field], grid[field], "{}", field);
+        }
+    }
+
+    #[tokio::test]
+    async fn route_metrics_aggregate_by_pattern_and_count_errors() {
+        use tower::ServiceExt;
+
+        let config = Config {
+            admin_token: Some("secret".into()),
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+        server.get("/version").await;
+        server.get("/version").await;
+        for _ in 0..2 {
+            let (status, _) = server.board(Uuid::new_v4(), Uuid::new_v4()).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+        let (status, _) = server.get("/no/such/route").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let request = axum::http::Request::get("/admin/metrics")
+            .header("x-admin-token", "secret")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let metrics: Value = serde_json::from_slice(&body).unwrap();
+
+        let version = &metrics["GET /version"];
+        assert_eq!(version["requests"], 2);
+        assert_eq!(version["errors"], 0);
+        assert_eq!(version["status_classes"], json!({ "2xx": 2 }));
+        let buckets: u64 = version["latency"]["buckets"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|bucket| bucket["count"].as_u64().unwrap())
+            .sum();
+        assert_eq!(buckets, 2);
+
+        // Both boards land under the one pattern, not their own URLs
+        let board = &metrics["GET /game/{game_id}/board/{player_id}"];
+        assert_eq!(board["requests"], 2);
+        assert_eq!(board["errors"], 2);
+        assert_eq!(board["error_rate"], 1.0);
+        assert_eq!(board["status_classes"], json!({ "4xx": 2 }));
+        // Unmatched URLs are left out
+        let routes: Vec<&String> = metrics.as_object().unwrap().keys().collect();
+        assert_eq!(
+            routes,
+            ["GET /game/{game_id}/board/{player_id}", "GET /version"]
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_joins_never_push_storage_past_the_cap() {
+        const CAP: usize = 5;
+        let config = Config {
+            max_active_games: CAP,
+            ..Config::default()
+        };
+        let server = Arc::new(TestServer::new(&config));
+        let active = server.storage().read().await.active_game_count();
+
+        // Watch the count the whole time the joins are racing
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let watcher = {
+            let done = Arc::clone(&done);
+            tokio::spawn(async move {
+                let mut highest = 0;
+                while !done.load(Ordering::Relaxed) {
+                    highest = highest.max(active.load(Ordering::Relaxed));
+                    tokio::task::yield_now().await;
+                }
+                highest.max(active.load(Ordering::Relaxed))
+            })
+        };
+
+        // Practice games and queue pairs both start games
+        let joins: Vec<_> = (0..40)
+            .map(|n| {
+                let server = Arc::clone(&server);
+                tokio::spawn(async move {
+                    let body = json!({ "player_name": format!("p{}", n), "solo": n % 2 == 0 });
+                    server.post("/join_queue", body).await
+                })
+            })
+            .collect();
+        let mut refused = 0;
+        for join in joins {
+            let (status, body) = join.await.unwrap();
+            if status == StatusCode::SERVICE_UNAVAILABLE {
+                assert_eq!(body["error"], "server_full");
+                assert_eq!(body["active_games"], CAP);
+                assert_eq!(body["max_active_games"], CAP);
+                refused += 1;
+            } else {
+                assert_eq!(status, StatusCode::OK, "{}", body);
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+
+        assert_eq!(watcher.await.unwrap(), CAP);
+        assert!(refused > 0);
+        let storage = server.storage().read().await;
+        assert_eq!(storage.occupancy().active_games, CAP);
+        assert_eq!(storage.active_game_ids().len(), CAP);
+    }
+
+    // One instance's copy of games kept in a store every instance shares, as
+    // the Redis repository keeps them
+    #[derive(Debug, Default)]
+    struct SharedRepository {
+        local: crate::glub_server_repository::InMemoryRepository,
+        shared: Arc<std::sync::Mutex<std::collections::HashMap<Uuid, GameState>>>,
+    }
+
+    impl SharedRepository {
+        fn another_instance(&self) -> Self {
+            Self {
+                local: Default::default(),
+                shared: Arc::clone(&self.shared),
+            }
+        }
+    }
+
+    impl crate::glub_server_repository::GameRepository for SharedRepository {
+        fn get(&self, game_id: Uuid) -> Option<&GameState> {
+            self.local.get(game_id)
+        }
+
+        fn get_mut(&mut self, game_id: Uuid) -> Option<&mut GameState> {
+            self.local.get_mut(game_id)
+        }
+
+        fn insert(&mut self, game_state: GameState) {
+            let shared = game_state.clone();
+            self.shared.lock().unwrap().insert(shared.game.id, shared);
+            self.local.insert(game_state);
+        }
+
+        fn remove(&mut self, game_id: Uuid) -> Option<GameState> {
+            self.shared.lock().unwrap().remove(&game_id);
+            self.local.remove(game_id)
+        }
+
+        fn games(&self) -> Box<dyn Iterator<Item = &GameState> + '_> {
+            self.local.games()
+        }
+
+        fn push_queued(&mut self, player: crate::glub_server_storage::QueuedPlayer) {
+            self.local.push_queued(player)
+        }
+
+        fn pop_queued(
+            &mut self,
+            mode: GameMode,
+        ) -> Option<crate::glub_server_storage::QueuedPlayer> {
+            self.local.pop_queued(mode)
+        }
+
+        fn remove_queued(
+            &mut self,
+            player_id: Uuid,
+        ) -> Option<crate::glub_server_storage::QueuedPlayer> {
+            self.local.remove_queued(player_id)
+        }
+
+        fn queued(
+            &self,
+        ) -> Box<dyn Iterator<Item = &crate::glub_server_storage::QueuedPlayer> + '_> {
+            self.local.queued()
+        }
+
+        fn save(&mut self, game_id: Uuid) -> Result<(), crate::glub_server_repository::SaveError> {
+            if let Some(game_state) = self.local.get(game_id) {
+                self.shared
+                    .lock()
+                    .unwrap()
+                    .insert(game_id, game_state.clone());
+            }
+            Ok(())
+        }
+
+        fn refresh(&mut self, game_id: Uuid) {
+            let stored = self.shared.lock().unwrap().get(&game_id).cloned();
+            if let Some(game_state) = stored {
+                self.local.insert(game_state);
+            }
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn a_move_on_one_instance_wakes_a_waiter_on_the_other() {
+        let repository = SharedRepository::default();
+        let bus = crate::glub_server_changes::ChangeBus::new();
+        let second_storage = GameStorage::new()
+            .with_repository(Box::new(repository.another_instance()))
+            .with_changes(bus.new_instance());
+        let first_storage = GameStorage::new()
+            .with_repository(Box::new(repository))
+            .with_changes(bus);
+        let config = Config::default();
+        let first = TestServer::with_storage(first_storage, &config);
+        let second = Arc::new(TestServer::with_storage(second_storage, &config));
+
+        let game = first.start_game().await;
+        second.storage().write().await.refresh_game(game.game_id);
+        let since_version = second
+            .storage()
+            .read()
+            .await
+            .with_game(game.game_id, |game_state| game_state.version)
+            .unwrap();
+
+        let waiter = {
+            let second = Arc::clone(&second);
+            let uri = format!(
+                "/game/{}/wait?since_version={}&timeout_seconds=10",
+                game.game_id, since_version
+            );
+            tokio::spawn(async move {
+                let started = std::time::Instant::now();
+                let (_, waited) = second.get(&uri).await;
+                (waited, started.elapsed())
+            })
+        };
+        // Let the waiter subscribe before the move is made
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        {
+            let mut storage = first.storage().write().await;
+            for _ in 0..3 {
+                storage.increment_moves(crate::glub_server_storage::TickShard::ALL);
+            }
+        }
+        let (_, moved) = first
+            .make_move(game.game_id, game.white_player_id, (1, 0), (2, 0))
+            .await;
+        assert_eq!(moved["success"], true, "{}", moved);
+
+        let (waited, elapsed) = waiter.await.unwrap();
+        assert_eq!(waited["changed"], true, "{}", waited);
+        assert!(waited["board_version"].as_u64().unwrap() > since_version);
+        assert!(
+            elapsed < Duration::from_secs(10),
+            "woken by the bus, not the timeout"
+        );
+        // The second instance now serves the move from its own copy
+        let (_, board) = second.board(game.game_id, game.white_player_id).await;
+        let (_, first_board) = first.board(game.game_id, game.white_player_id).await;
+        assert_eq!(board, first_board);
+    }
+
+    #[tokio::test]
+    async fn a_paused_server_refuses_moves_and_stops_the_economy_until_resumed() {
+        use tower::ServiceExt;
+
+        let config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let server = &TestServer::new(&config);
+        let game = server.start_game().await;
+        let admin = |uri: &str| {
+            let request = axum::http::Request::post(uri)
+                .header("x-admin-token", "secret")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            server.router().oneshot(request)
+        };
+        let tick = |ticks: usize| async move {
+            let mut storage = server.storage().write().await;
+            for _ in 0..ticks {
+                storage.increment_moves(crate::glub_server_storage::TickShard::ALL);
+            }
+        };
+        let move_points = || async {
+            let (_, status) = server
+                .status(game.game_id, Some(game.white_player_id))
+                .await;
+            (
+                status["player1_moves"].clone(),
+                status["player2_moves"].clone(),
+            )
+        };
+
+        tick(3).await;
+        assert_eq!(
+            admin("/admin/pause").await.unwrap().status(),
+            StatusCode::OK
+        );
+        let points = move_points().await;
+        assert!(!points.0.is_null() || !points.1.is_null());
+        let (status, refused) = server
+            .make_move(game.game_id, game.white_player_id, (1, 4), (2, 4))
+            .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(refused["error"], "server_paused");
+        tick(10).await;
+        assert_eq!(move_points().await, points, "no points accrue while paused");
+        let (status, board) = server.board(game.game_id, game.white_player_id).await;
+        assert_eq!(status, StatusCode::OK, "reads still work: {}", board);
+
+        assert_eq!(
+            admin("/admin/resume").await.unwrap().status(),
+            StatusCode::OK
+        );
+        let (status, moved) = server
+            .make_move(game.game_id, game.white_player_id, (1, 4), (2, 4))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved["success"], true, "{}", moved);
+        tick(10).await;
+        assert_ne!(move_points().await, points, "points accrue again");
+    }
+
+    // Start the whole server on `config` until the returned sender fires
+    fn serve_until_stopped(
+        config: Config,
+    ) -> (
+        tokio::sync::oneshot::Sender<()>,
+        tokio::task::JoinHandle<()>,
+    ) {
+        let (stop, stopped) = tokio::sync::oneshot::channel();
+        let server = tokio::spawn(serve(Arc::new(config), async {
+            let _ = stopped.await;
+        }));
+        (stop, server)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn a_request_completes_over_a_unix_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chest.sock");
+        // A socket left behind by an earlier run is replaced
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+        let config = Config {
+            bind: vec![glub_server_config::BindAddress::Unix(path.clone())],
+            socket_mode: 0o600,
+            ..Config::default()
+        };
+        let (stop, server) = serve_until_stopped(config);
+
+        let mut stream = None;
+        for _ in 0..100 {
+            if let Ok(connected) = tokio::net::UnixStream::connect(&path).await {
+                stream = Some(connected);
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let stream = stream.expect("the server listens on the socket");
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        let request = axum::http::Request::get("/health")
+            .header("host", "localhost")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(axum::body::Body::new(response.into_body()), usize::MAX)
+            .await
+            .unwrap();
+        let health: Value = serde_json::from_slice(&body).unwrap();
+        assert!(health.is_object(), "{}", health);
+        drop(sender);
+
+        // A graceful shutdown takes the socket file with it
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn two_loopback_listeners_serve_the_same_storage() {
+        // Free ports, released again for the server to take
+        let ports: Vec<u16> = (0..2)
+            .map(|_| {
+                std::net::TcpListener::bind("127.0.0.1:0")
+                    .unwrap()
+                    .local_addr()
+                    .unwrap()
+                    .port()
+            })
+            .collect();
+        let config = Config {
+            bind: ports
+                .iter()
+                .map(|&port| {
+                    glub_server_config::BindAddress::Socket(std::net::SocketAddr::from((
+                        [127, 0, 0, 1],
+                        port,
+                    )))
+                })
+                .collect(),
+            ..Config::default()
+        };
+        let (stop, server) = serve_until_stopped(config);
+
+        let client = reqwest::Client::new();
+        let join = |port: u16, player_name: &str| {
+            client
+                .post(format!("http://127.0.0.1:{}/join_queue", port))
+                .json(&json!({ "player_name": player_name }))
+                .send()
+        };
+        let mut first = None;
+        for _ in 0..100 {
+            if let Ok(response) = join(ports[0], "first").await {
+                first = Some(response.json::<Value>().await.unwrap());
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let first = first.expect("the first listener serves requests");
+        assert!(first["game_id"].is_null(), "{}", first);
+
+        // The player waiting behind the first port is matched through the second
+        let second: Value = join(ports[1], "second")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(second["game_id"].is_string(), "{}", second);
+        let current: Value = client
+            .get(format!(
+                "http://127.0.0.1:{}/players/{}/current_game",
+                ports[0],
+                first["player_id"].as_str().unwrap()
+            ))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(current["game"]["game_id"], second["game_id"]);
+
+        // Shutting down stops both
+        stop.send(()).unwrap();
+        server.await.unwrap();
+        for port in ports {
+            assert!(join(port, "late").await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn the_admin_grid_of_a_new_game_shows_the_starting_codes() {
+        use tower::ServiceExt;
+
+        let config = Config {
+            admin_token: Some("secret".to_string()),
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+        let game = server.start_game().await;
+        let uri = format!("/admin/game/{}/grid", game.game_id);
+
+        let (status, _) = server.get(&uri).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let request = axum::http::Request::get(&uri)
+            .header("x-admin-token", "secret")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let grid: Vec<Vec<String>> = serde_json::from_slice(&body).unwrap();
+
+        let rank = |codes: &str| -> Vec<String> { codes.split(' ').map(String::from).collect() };
+        // White's scout replaces the king's knight, black's the queen's knight
+        assert_eq!(grid.len(), 8);
+        assert_eq!(grid[0], rank("wR wN wB wQ wK wB wS wR"));
+        assert_eq!(grid[1], rank("wP wP wP wP wP wP wP wP"));
+        for row in &grid[2..6] {
+            assert_eq!(*row, vec![String::new(); 8]);
+        }
+        assert_eq!(grid[6], rank("bP bP bP bP bP bP bP bP"));
+        assert_eq!(grid[7], rank("bR bS bB bQ bK bB bN bR"));
+    }
+
+    #[tokio::test]
+    async fn a_stalled_request_times_out_and_lets_go_of_the_lock() {
+        let config = Config {
+            request_timeout_seconds: 1,
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+
+        let (status, _) = server.post("/test/stall", json!({})).await;
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+
+        // The next request gets the write lock straight away
+        let (status, joined) = tokio::time::timeout(
+            Duration::from_millis(500),
+            server.post("/join_queue", json!({ "player_name": "after" })),
+        )
+        .await
+        .expect("the stalled handler released the lock");
+        assert_eq!(status, StatusCode::OK, "{}", joined);
+    }
+
+    #[tokio::test]
+    async fn a_games_spectator_code_resolves_to_its_spectator_view() {
+        let server = TestServer::default();
+        let game = server.start_game().await;
+        let other = server.start_game().await;
+
+        let (status, code) = server
+            .get(&format!("/game/{}/spectator_code", game.game_id))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let code = code["code"].as_str().unwrap().to_string();
+
+        let (status, by_code) = server.get(&format!("/spectate/{}", code)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, direct) = server
+            .get(&format!("/game/{}/spectate", game.game_id))
+            .await;
+        assert_eq!(by_code, direct);
+        assert_eq!(by_code["fogged"], true);
+
+        // The listing names the game under the same code
+        let (_, listed) = server.get("/spectate").await;
+        let listed = listed.as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        let entry = listed
+            .iter()
+            .find(|entry| entry["code"] == code.as_str())
+            .unwrap();
+        assert_eq!(entry["game_id"], game.game_id.to_string());
+        assert!(
+            listed
+                .iter()
+                .any(|entry| entry["game_id"] == other.game_id.to_string())
+        );
+
+        let (status, _) = server.get("/spectate/NOPE22").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn joining_a_lobby_on_a_full_server_says_the_server_is_full() {
+        let config = Config {
+            max_active_games: 1,
+            ..Config::default()
+        };
+        let server = TestServer::new(&config);
+        let (status, lobby) = server
+            .post("/lobbies", json!({ "player_name": "host" }))
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let join_uri = format!("/lobbies/{}/join", lobby["code"].as_str().unwrap());
+        let (status, _) = server
+            .post("/lobbies/NOPE22/join", json!({ "player_name": "guest" }))
+            .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let game = server.start_game().await;
+        let (status, full) = server
+            .post(&join_uri, json!({ "player_name": "guest" }))
+            .await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(full["error"], "server_full");
+        assert_eq!(full["active_games"], 1);
+        assert_eq!(full["max_active_games"], 1);
+
+        // The lobby is still there once a game ends
+        server
+            .post(
+                &format!("/players/{}/quit", game.black_player_id),
+                json!({}),
+            )
+            .await;
+        let (status, joined) = server
+            .post(&join_uri, json!({ "player_name": "guest" }))
+            .await;
+        assert_eq!(status, StatusCode::OK, "{}", joined);
+        assert!(joined["game_id"].is_string());
+    }
+
+    #[tokio::test]
+    async fn bulk_statuses_report_each_game_or_its_error() {
+        let server = TestServer::default();
+        let (first, second) = (server.start_game().await, server.start_game().await);
+        let unknown = Uuid::new_v4();
+
+        let (status, statuses) = server
+            .post(
+                "/statuses",
+                json!({ "game_ids": [first.game_id, unknown, second.game_id] }),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        let entries = statuses["statuses"].as_array().unwrap();
+        assert_eq!(entries.len(), 3);
+        for (entry, game_id) in [(&entries[0], first.game_id), (&entries[2], second.game_id)] {
+            assert_eq!(entry["game_id"], json!(game_id));
+            assert_eq!(entry["status"]["phase"], "playing");
+            assert!(entry.get("error").is_none_or(Value::is_null));
+        }
+        assert_eq!(entries[1]["game_id"], json!(unknown));
+        assert_eq!(entries[1]["error"], "Game not found");
+        assert!(entries[1].get("status").is_none_or(Value::is_null));
+    }
+
+    #[tokio::test]
+    async fn a_stuck_tick_fails_readiness_but_not_liveness() {
+        let config = Config::default();
+        let clock = Arc::new(glub_server_clock::ManualClock::new());
+        let storage = GameStorage::with_config(&config).with_clock(clock.clone());
+        let server = TestServer::with_storage(storage, &config);
+
+        let (status, not_ready) = server.get("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(not_ready["component"], "tick");
+
+        server
+            .storage()
+            .write()
+            .await
+            .record_heartbeat(Duration::from_millis(1));
+        assert_eq!(server.get("/health/ready").await.0, StatusCode::OK);
+
+        // The tick task stops beating
+        clock.advance(Duration::from_secs(config.heartbeat_timeout_seconds + 1));
+        let (status, not_ready) = server.get("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(not_ready["component"], "tick");
+        assert_eq!(server.get("/health/live").await.0, StatusCode::OK);
+
+        server.storage().write().await.set_maintenance(true);
+        server
+            .storage()
+            .write()
+            .await
+            .record_heartbeat(Duration::from_millis(1));
+        let (status, not_ready) = server.get("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(not_ready["component"], "maintenance");
+    }
+
+    // Send raw HTTP/1.1 on `stream` and read the reply up to the connection
+    // closing, returning the final status and JSON body
+    #[cfg(unix)]
+    async fn finish_exchange(
+        stream: &mut tokio::net::UnixStream,
+        sent: &str,
+    ) -> (StatusCode, Value) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        stream.write_all(sent.as_bytes()).await.unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).await.unwrap();
+        let (head, body) = reply.rsplit_once("\r\n\r\n").unwrap();
+        let status_line = head.rsplit("HTTP/1.1 ").next().unwrap();
+        let code: u16 = status_line[..3].parse().unwrap();
+        (
+            StatusCode::from_u16(code).unwrap(),
+            serde_json::from_str(body).unwrap_or(Value::Null),
+        )
+    }
+
+    #[cfg(unix)]
+    fn raw_request(method: &str, uri: &str, body: &Value) -> (String, String) {
+        let body = body.to_string();
+        let head = format!(
+            "{} {} HTTP/1.1\r\nhost: localhost\r\ncontent-type: application/json\r\n\
+             content-length: {}\r\nconnection: close\r\n",
+            method,
+            uri,
+            body.len()
+        );
+        (head, body)
+    }
+
+    #[cfg(unix)]
+    async fn unix_request(
+        socket: &std::path::Path,
+        method: &str,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        let mut stream = tokio::net::UnixStream::connect(socket).await.unwrap();
+        let (head, body) = raw_request(method, uri, &body);
+        finish_exchange(&mut stream, &format!("{}\r\n{}", head, body)).await
+    }
+
+    #[cfg(unix)]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn a_shutdown_finishes_the_move_in_flight_and_saves_a_snapshot() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = std::env::temp_dir().join(format!("chest-royale-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let socket = dir.join("server.sock");
+        let snapshot_path = dir.join("snapshot.json");
+        let config = Config {
+            bind: vec![BindAddress::Unix(socket.clone())],
+            snapshot_path: Some(snapshot_path.clone()),
+            shutdown_deadline_seconds: 5,
+            ..Config::default()
+        };
+        let (signal, signalled) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(Arc::new(config), async {
+            let _ = signalled.await;
+        }));
+        while tokio::net::UnixStream::connect(&socket).await.is_err() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let join = |name: &str| {
+            unix_request(
+                &socket,
+                "POST",
+                "/join_queue",
+                json!({ "player_name": name }),
+            )
+        };
+        let (_, first) = join("ann").await;
+        let (_, second) = join("bob").await;
+        let game_id = second["game_id"].as_str().unwrap().to_string();
+        let uri = format!(
+            "/players/{}/current_game",
+            first["player_id"].as_str().unwrap()
+        );
+        let (_, current) = unix_request(&socket, "GET", &uri, Value::Null).await;
+        let white = if current["game"]["your_color"] == "white" {
+            &first["player_id"]
+        } else {
+            &second["player_id"]
+        };
+
+        // The handler asks for the body, so the move is in flight once the
+        // server answers the expectation
+        let mut moving = tokio::net::UnixStream::connect(&socket).await.unwrap();
+        let (head, body) = raw_request(
+            "POST",
+            &format!("/game/{}/move", game_id),
+            &json!({ "player_id": white, "from": (1, 4), "to": (2, 4) }),
+        );
+        moving
+            .write_all(format!("{}expect: 100-continue\r\n\r\n", head).as_bytes())
+            .await
+            .unwrap();
+        let mut interim = [0; 25];
+        moving.read_exact(&mut interim).await.unwrap();
+        assert!(interim.starts_with(b"HTTP/1.1 100"));
+
+        signal.send(()).unwrap();
+        // Listening stops before open requests are drained
+        while tokio::net::UnixStream::connect(&socket).await.is_ok() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let (status, moved) = finish_exchange(&mut moving, &body).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(moved["success"], true, "{}", moved);
+
+        server.await.unwrap();
+        let restored = glub_server_persistence::read_snapshot(&snapshot_path).unwrap();
+        assert_eq!(restored.active_games.len(), 1);
+        assert_eq!(restored.active_games[0].history.len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,27 @@
+// Its own test binary, since counting allocations needs the global allocator
+// and nothing else running alongside
+use chest_royale_server_unhackable_trust::glub_server::{ExtendedBoard, MoveError};
+use chest_royale_server_unhackable_trust::glub_server_storage::PlayerColor;
+
+#[global_allocator]
+static ALLOC: dhat::Alloc = dhat::Alloc;
+
+#[test]
+fn moves_are_checked_and_played_without_allocating() {
+    let _profiler = dhat::Profiler::builder().testing().build();
+    let mut board = ExtendedBoard::new();
+    board.setup_initial_position();
+    // The move and sight tables are built on first use
+    assert!(board.is_square_attacked((2, 0), &PlayerColor::White));
+
+    let before = dhat::HeapStats::get();
+    let played = board.make_move((1, 4), (2, 4), &PlayerColor::White);
+    let rejected = board.make_move((0, 0), (5, 0), &PlayerColor::White);
+    let off_board = board.make_move((0, 0), (usize::MAX, 0), &PlayerColor::White);
+    let after = dhat::HeapStats::get();
+
+    assert_eq!(played, Ok(None));
+    assert!(matches!(rejected, Err(MoveError::InvalidPieceMove { .. })));
+    assert_eq!(off_board, Err(MoveError::InvalidCoordinates));
+    dhat::assert_eq!(after.total_blocks, before.total_blocks);
+}